@@ -0,0 +1,9 @@
+CREATE TABLE `user_settings` (
+  `tg_id` bigint(10) NOT NULL,
+  `notifications` tinyint(1) NOT NULL DEFAULT 1 COMMENT 'whether to notify the user about votes on their strips',
+  `language` varchar(16) NOT NULL DEFAULT 'English',
+  `daily_subscription` tinyint(1) NOT NULL DEFAULT 0 COMMENT 'whether to send the user an omikuji every day',
+  `created_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  `updated_at` timestamp NOT NULL DEFAULT current_timestamp() ON UPDATE current_timestamp(),
+  PRIMARY KEY (`tg_id`)
+) DEFAULT CHARSET=utf8mb4;
\ No newline at end of file
@@ -0,0 +1,10 @@
+CREATE TABLE `users` (
+  `tg_id` bigint(10) NOT NULL,
+  `tg_name` varchar(128) NOT NULL,
+  `tg_username` varchar(32) NULL DEFAULT NULL,
+  `language_code` varchar(16) NULL DEFAULT NULL,
+  `banned` tinyint(1) NOT NULL DEFAULT 0 COMMENT 'banned users are ignored by the bot',
+  `created_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  `updated_at` timestamp NOT NULL DEFAULT current_timestamp() ON UPDATE current_timestamp(),
+  PRIMARY KEY (`tg_id`)
+) DEFAULT CHARSET=utf8mb4;
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE `user_settings` DROP COLUMN `reply_keyboard`;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE `user_settings`
+  ADD COLUMN `reply_keyboard` tinyint(1) NOT NULL DEFAULT 0 COMMENT 'whether to show the main menu as a persistent reply keyboard' AFTER `daily_subscription`;
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE `draws`;
\ No newline at end of file
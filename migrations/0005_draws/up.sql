@@ -0,0 +1,8 @@
+CREATE TABLE `draws` (
+  `id` int(10) UNSIGNED NOT NULL AUTO_INCREMENT,
+  `tg_id` bigint(10) NOT NULL,
+  `omikuji_id` int(10) UNSIGNED NOT NULL,
+  `created_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  PRIMARY KEY (`id`),
+  KEY `created_at` (`created_at`)
+) DEFAULT CHARSET=utf8mb4;
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE `user_settings` DROP COLUMN `voice`;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE `user_settings`
+  ADD COLUMN `voice` tinyint(1) NOT NULL DEFAULT 0 COMMENT 'whether drawn strips are also sent as voice messages' AFTER `reply_keyboard`;
\ No newline at end of file
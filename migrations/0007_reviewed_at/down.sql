@@ -0,0 +1 @@
+ALTER TABLE `omikujis` DROP COLUMN `reviewed_at`;
\ No newline at end of file
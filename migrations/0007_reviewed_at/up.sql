@@ -0,0 +1,2 @@
+ALTER TABLE `omikujis`
+  ADD COLUMN `reviewed_at` timestamp NULL DEFAULT NULL COMMENT 'when a moderator approved or rejected the hidden strip';
\ No newline at end of file
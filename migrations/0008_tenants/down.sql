@@ -0,0 +1,12 @@
+DELETE FROM `user_settings` WHERE `tenant_id` <> 'default';
+ALTER TABLE `user_settings`
+  DROP PRIMARY KEY,
+  DROP COLUMN `tenant_id`,
+  ADD PRIMARY KEY (`tg_id`);
+DELETE FROM `users` WHERE `tenant_id` <> 'default';
+ALTER TABLE `users`
+  DROP PRIMARY KEY,
+  DROP COLUMN `tenant_id`,
+  ADD PRIMARY KEY (`tg_id`);
+ALTER TABLE `draws` DROP COLUMN `tenant_id`;
+ALTER TABLE `omikujis` DROP COLUMN `tenant_id`;
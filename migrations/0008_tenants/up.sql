@@ -0,0 +1,14 @@
+ALTER TABLE `omikujis`
+  ADD COLUMN `tenant_id` varchar(32) NOT NULL DEFAULT 'default' COMMENT 'the bot (shrine) the strip belongs to',
+  ADD KEY `tenant_id` (`tenant_id`);
+ALTER TABLE `draws`
+  ADD COLUMN `tenant_id` varchar(32) NOT NULL DEFAULT 'default',
+  ADD KEY `tenant_id` (`tenant_id`);
+ALTER TABLE `users`
+  ADD COLUMN `tenant_id` varchar(32) NOT NULL DEFAULT 'default',
+  DROP PRIMARY KEY,
+  ADD PRIMARY KEY (`tenant_id`, `tg_id`);
+ALTER TABLE `user_settings`
+  ADD COLUMN `tenant_id` varchar(32) NOT NULL DEFAULT 'default',
+  DROP PRIMARY KEY,
+  ADD PRIMARY KEY (`tenant_id`, `tg_id`);
@@ -0,0 +1,2 @@
+ALTER TABLE `user_settings` DROP COLUMN `pool`;
+ALTER TABLE `omikujis` DROP COLUMN `community_id`;
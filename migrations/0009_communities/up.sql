@@ -0,0 +1,5 @@
+ALTER TABLE `omikujis`
+  ADD COLUMN `community_id` bigint(10) NULL DEFAULT NULL COMMENT 'the group chat the strip was created from, NULL if created privately',
+  ADD KEY `community_id` (`community_id`);
+ALTER TABLE `user_settings`
+  ADD COLUMN `pool` varchar(16) NOT NULL DEFAULT 'Both' COMMENT 'which strips are drawn: Local, Global or Both' AFTER `voice`;
@@ -0,0 +1 @@
+ALTER TABLE `omikujis` DROP COLUMN `anonymous`;
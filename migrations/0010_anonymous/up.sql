@@ -0,0 +1,2 @@
+ALTER TABLE `omikujis`
+  ADD COLUMN `anonymous` tinyint(1) NOT NULL DEFAULT 0 COMMENT 'whether the author is hidden wherever strips are shown';
@@ -0,0 +1,2 @@
+ALTER TABLE `user_settings`
+  ADD COLUMN `credit` tinyint(1) NOT NULL DEFAULT 0 COMMENT 'whether drawn strips of the user end with a "by <name>" line' AFTER `pool`;
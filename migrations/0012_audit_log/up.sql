@@ -0,0 +1,10 @@
+CREATE TABLE `audit_log` (
+  `id` int(10) UNSIGNED NOT NULL AUTO_INCREMENT,
+  `actor` varchar(64) NOT NULL COMMENT 'who did it, e.g. "tg:42" or "web:alice"',
+  `action` varchar(16) NOT NULL COMMENT 'Ban, Approve, Reject, Delete, Threshold or Broadcast',
+  `target` varchar(255) NOT NULL COMMENT 'what it was done to, e.g. "omikuji 12"',
+  `created_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  `tenant_id` varchar(32) NOT NULL DEFAULT 'default',
+  PRIMARY KEY (`id`),
+  KEY `tenant_id` (`tenant_id`)
+) DEFAULT CHARSET=utf8mb4;
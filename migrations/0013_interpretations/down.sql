@@ -0,0 +1 @@
+DROP TABLE `interpretations`;
\ No newline at end of file
@@ -0,0 +1,7 @@
+CREATE TABLE `interpretations` (
+  `tenant_id` varchar(32) NOT NULL DEFAULT 'default',
+  `class` varchar(32) NOT NULL COMMENT 'as serialized from OmikujiClass, e.g. FutureBlessing',
+  `text` text NOT NULL COMMENT 'appended to drawn strips of the class',
+  `updated_at` timestamp NOT NULL DEFAULT current_timestamp() ON UPDATE current_timestamp(),
+  PRIMARY KEY (`tenant_id`, `class`)
+) DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,7 @@
+CREATE TABLE `achievements` (
+  `tenant_id` varchar(32) NOT NULL DEFAULT 'default',
+  `tg_id` bigint(20) NOT NULL,
+  `badge` varchar(32) NOT NULL COMMENT 'as serialized from Badge, e.g. FirstStrip',
+  `created_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  PRIMARY KEY (`tenant_id`, `tg_id`, `badge`)
+) DEFAULT CHARSET=utf8mb4;
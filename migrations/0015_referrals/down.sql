@@ -0,0 +1 @@
+ALTER TABLE `users` DROP COLUMN `referred_by`;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE `users`
+  ADD COLUMN `referred_by` bigint(20) DEFAULT NULL COMMENT 'tg_id of the user whose invite link brought this user to the bot' AFTER `banned`;
@@ -0,0 +1,4 @@
+ALTER TABLE `omikujis`
+  DROP INDEX `expires_at`,
+  DROP COLUMN `archived`,
+  DROP COLUMN `expires_at`;
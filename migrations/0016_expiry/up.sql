@@ -0,0 +1,4 @@
+ALTER TABLE `omikujis`
+  ADD COLUMN `expires_at` timestamp NULL DEFAULT NULL COMMENT 'strips are no longer drawn after this, e.g. for event-specific fortunes',
+  ADD COLUMN `archived` tinyint(1) NOT NULL DEFAULT 0 COMMENT 'set by the archival job once the strip has expired',
+  ADD INDEX `expires_at` (`expires_at`);
@@ -0,0 +1,3 @@
+ALTER TABLE `omikujis`
+  DROP INDEX `quarantined`,
+  DROP COLUMN `quarantined`;
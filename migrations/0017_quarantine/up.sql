@@ -0,0 +1,5 @@
+ALTER TABLE `omikujis`
+  ADD COLUMN `quarantined` tinyint(1) NOT NULL DEFAULT 0 COMMENT 'hidden from draws until an admin restores the strip, set when downvoted to the threshold or held as spam',
+  ADD INDEX `quarantined` (`quarantined`);
+-- Strips hidden by the former vote_count filter
+UPDATE `omikujis` SET `quarantined` = 1 WHERE `vote_count` <= -3;
@@ -0,0 +1,2 @@
+ALTER TABLE `omikujis`
+  DROP COLUMN `draw_count`;
@@ -0,0 +1,5 @@
+ALTER TABLE `omikujis`
+  ADD COLUMN `draw_count` int(10) unsigned NOT NULL DEFAULT 0 COMMENT 'how often the strip has been drawn, kept in sync with the draws table';
+UPDATE `omikujis` SET `draw_count` = (
+  SELECT COUNT(*) FROM `draws` WHERE `draws`.`omikuji_id` = `omikujis`.`id`
+);
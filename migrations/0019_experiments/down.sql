@@ -0,0 +1,4 @@
+ALTER TABLE `draws`
+  DROP INDEX `variant`,
+  DROP COLUMN `vote`,
+  DROP COLUMN `variant`;
@@ -0,0 +1,4 @@
+ALTER TABLE `draws`
+  ADD COLUMN `variant` varchar(32) NULL DEFAULT NULL COMMENT 'draw strategy the user was assigned to by DRAW_EXPERIMENT',
+  ADD COLUMN `vote` tinyint(1) NULL DEFAULT NULL COMMENT 'vote of the user on the drawn strip, +1 or -1',
+  ADD INDEX `variant` (`variant`);
@@ -0,0 +1,11 @@
+CREATE TABLE `comments` (
+  `id` int(10) UNSIGNED NOT NULL AUTO_INCREMENT,
+  `omikuji_id` int(10) UNSIGNED NOT NULL,
+  `tg_id` bigint(20) NOT NULL COMMENT 'who wrote the comment, after drawing the strip',
+  `text` varchar(280) NOT NULL,
+  `created_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  `tenant_id` varchar(32) NOT NULL DEFAULT 'default',
+  PRIMARY KEY (`id`),
+  KEY `omikuji_id` (`omikuji_id`),
+  KEY `tenant_id` (`tenant_id`)
+) DEFAULT CHARSET=utf8mb4;
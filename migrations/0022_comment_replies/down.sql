@@ -0,0 +1,3 @@
+ALTER TABLE `comments`
+  DROP INDEX `parent_id`,
+  DROP COLUMN `parent_id`;
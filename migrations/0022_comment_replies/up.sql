@@ -0,0 +1,3 @@
+ALTER TABLE `comments`
+  ADD COLUMN `parent_id` int(10) UNSIGNED NULL DEFAULT NULL COMMENT 'comment this one replies to, NULL for comments on the strip itself',
+  ADD INDEX `parent_id` (`parent_id`);
@@ -0,0 +1,2 @@
+ALTER TABLE `user_settings`
+  ADD COLUMN `weekly_digest` tinyint(1) NOT NULL DEFAULT 0 COMMENT 'whether the user gets a weekly summary of the draws, votes and comments on their strips' AFTER `credit`;
@@ -0,0 +1,9 @@
+CREATE TABLE `reactions` (
+  `tenant_id` varchar(32) NOT NULL DEFAULT 'default',
+  `tg_id` bigint(20) NOT NULL,
+  `omikuji_id` int(10) unsigned NOT NULL,
+  `reaction` varchar(16) NOT NULL COMMENT 'as serialized from Reaction, e.g. Pray',
+  `created_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  PRIMARY KEY (`tenant_id`, `tg_id`, `omikuji_id`),
+  KEY `omikuji_id` (`omikuji_id`)
+) DEFAULT CHARSET=utf8mb4;
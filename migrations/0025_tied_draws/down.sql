@@ -0,0 +1,2 @@
+ALTER TABLE `draws`
+  DROP COLUMN `tied_at`;
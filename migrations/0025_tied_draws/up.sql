@@ -0,0 +1,2 @@
+ALTER TABLE `draws`
+  ADD COLUMN `tied_at` timestamp NULL DEFAULT NULL COMMENT 'when the user tied the bad fortune at the shrine, hiding the draw from their history' AFTER `vote`;
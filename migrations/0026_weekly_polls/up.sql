@@ -0,0 +1,12 @@
+CREATE TABLE `weekly_polls` (
+  `id` int(10) UNSIGNED NOT NULL AUTO_INCREMENT,
+  `chat` varchar(64) NOT NULL COMMENT 'chat id or @username of the channel the poll was posted in',
+  `message_id` int(11) NOT NULL,
+  `candidates` varchar(255) NOT NULL COMMENT 'ids of the strips in the order of the options, separated by commas',
+  `winner_id` int(10) UNSIGNED DEFAULT NULL,
+  `created_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  `closed_at` timestamp NULL DEFAULT NULL,
+  `tenant_id` varchar(32) NOT NULL DEFAULT 'default',
+  PRIMARY KEY (`id`),
+  KEY `tenant_id` (`tenant_id`)
+) DEFAULT CHARSET=utf8mb4;
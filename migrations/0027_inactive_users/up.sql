@@ -0,0 +1,2 @@
+ALTER TABLE `users`
+  ADD COLUMN `inactive` tinyint(1) NOT NULL DEFAULT 0 COMMENT 'the user blocked the bot, so they are left out of broadcasts until they write to it again' AFTER `banned`;
@@ -0,0 +1,21 @@
+CREATE TABLE `usage_events` (
+  `id` bigint(20) UNSIGNED NOT NULL AUTO_INCREMENT,
+  `command` varchar(32) NOT NULL COMMENT 'e.g. /draw for commands, vote for buttons, message for anything else',
+  `tg_id` bigint(20) NOT NULL,
+  `latency_ms` int(10) UNSIGNED NOT NULL,
+  `success` tinyint(1) NOT NULL,
+  `created_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  `tenant_id` varchar(32) NOT NULL DEFAULT 'default',
+  PRIMARY KEY (`id`),
+  KEY `tenant_id` (`tenant_id`, `created_at`)
+) DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE `usage_daily` (
+  `tenant_id` varchar(32) NOT NULL DEFAULT 'default',
+  `day` date NOT NULL,
+  `command` varchar(32) NOT NULL,
+  `invocations` bigint(20) NOT NULL,
+  `failures` bigint(20) NOT NULL,
+  `total_latency_ms` bigint(20) NOT NULL COMMENT 'divided by invocations for the average latency',
+  PRIMARY KEY (`tenant_id`, `day`, `command`)
+) DEFAULT CHARSET=utf8mb4 COMMENT 'usage events rolled up by day, once the day is over';
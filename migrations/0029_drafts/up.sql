@@ -0,0 +1,7 @@
+CREATE TABLE `drafts` (
+  `tenant_id` varchar(32) NOT NULL DEFAULT 'default',
+  `tg_id` bigint(20) NOT NULL,
+  `draft` text NOT NULL COMMENT 'JSON of the draft, see drafts::encode',
+  `updated_at` timestamp NOT NULL DEFAULT current_timestamp() ON UPDATE current_timestamp(),
+  PRIMARY KEY (`tenant_id`, `tg_id`)
+) DEFAULT CHARSET=utf8mb4 COMMENT 'drafts being written, when DRAFT_STORE=mysql';
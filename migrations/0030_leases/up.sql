@@ -0,0 +1,7 @@
+CREATE TABLE `leases` (
+  `tenant_id` varchar(32) NOT NULL DEFAULT 'default',
+  `name` varchar(32) NOT NULL COMMENT 'e.g. scheduler',
+  `holder` varchar(64) NOT NULL COMMENT 'INSTANCE_ID of the instance holding the lease',
+  `expires_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  PRIMARY KEY (`tenant_id`, `name`)
+) DEFAULT CHARSET=utf8mb4 COMMENT 'leases making one of several instances run a job';
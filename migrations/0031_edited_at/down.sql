@@ -0,0 +1,2 @@
+ALTER TABLE `omikujis`
+  DROP COLUMN `edited_at`;
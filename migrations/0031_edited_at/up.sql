@@ -0,0 +1,2 @@
+ALTER TABLE `omikujis`
+  ADD COLUMN `edited_at` timestamp NULL DEFAULT NULL COMMENT 'when the author last edited the strip, unlike updated_at this is not touched by votes and draws';
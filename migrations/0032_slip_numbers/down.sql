@@ -0,0 +1,3 @@
+ALTER TABLE `omikujis`
+  DROP KEY `omikujis_slip_number`,
+  DROP COLUMN `slip_number`;
@@ -0,0 +1,12 @@
+ALTER TABLE `omikujis`
+  ADD COLUMN `slip_number` int(10) unsigned NULL DEFAULT NULL COMMENT 'number of the slip within the tenant, given once the strip is first drawable and never reused',
+  ADD UNIQUE KEY `omikujis_slip_number` (`tenant_id`, `slip_number`);
+
+-- Strips which are already drawn are numbered in the order they were saved
+UPDATE `omikujis` o
+  JOIN (
+    SELECT `id`, ROW_NUMBER() OVER (PARTITION BY `tenant_id` ORDER BY `id`) AS `number`
+      FROM `omikujis`
+      WHERE `quarantined` = 0
+  ) n ON n.`id` = o.`id`
+  SET o.`slip_number` = n.`number`;
@@ -0,0 +1,10 @@
+CREATE TABLE `trash` (
+  `id` int(10) UNSIGNED NOT NULL AUTO_INCREMENT,
+  `tg_id` bigint(20) NOT NULL COMMENT 'user who deleted the item, and who can restore it',
+  `kind` varchar(16) NOT NULL COMMENT 'Strip or Draft',
+  `content` text NOT NULL COMMENT 'JSON of the strip (see handlers::trash) or of the draft (see drafts::encode)',
+  `deleted_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  `tenant_id` varchar(32) NOT NULL DEFAULT 'default',
+  PRIMARY KEY (`id`),
+  KEY `tg_id` (`tenant_id`, `tg_id`, `deleted_at`)
+) DEFAULT CHARSET=utf8mb4 COMMENT 'strips and drafts deleted by users, restorable for a week';
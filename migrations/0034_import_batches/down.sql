@@ -0,0 +1,4 @@
+ALTER TABLE `omikujis`
+  DROP COLUMN `import_batch`;
+
+DROP TABLE `import_batches`;
@@ -0,0 +1,12 @@
+CREATE TABLE `import_batches` (
+  `id` int(10) UNSIGNED NOT NULL AUTO_INCREMENT,
+  `tg_id` bigint(20) NOT NULL COMMENT 'admin who imported the strips',
+  `source` varchar(2048) NOT NULL COMMENT 'URL the CSV was downloaded from',
+  `created_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  `tenant_id` varchar(32) NOT NULL DEFAULT 'default',
+  PRIMARY KEY (`id`),
+  KEY `tenant_id` (`tenant_id`)
+) DEFAULT CHARSET=utf8mb4 COMMENT 'strips imported together with /importurl';
+
+ALTER TABLE `omikujis`
+  ADD COLUMN `import_batch` int(10) unsigned NULL DEFAULT NULL COMMENT 'import_batches.id of the strips imported together, NULL for strips written in the bot';
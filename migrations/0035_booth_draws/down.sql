@@ -0,0 +1 @@
+DROP TABLE `booth_draws`;
@@ -0,0 +1,11 @@
+CREATE TABLE `booth_draws` (
+  `id` int(10) UNSIGNED NOT NULL AUTO_INCREMENT,
+  `chat_id` bigint(20) NOT NULL COMMENT 'booth chat the strip was drawn in',
+  `tg_id` bigint(20) NOT NULL COMMENT 'user who drew it on behalf of the visitor',
+  `visitor` varchar(64) NOT NULL COMMENT 'name of the visitor, as typed at the booth',
+  `omikuji_id` int(10) UNSIGNED NOT NULL,
+  `created_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  `tenant_id` varchar(32) NOT NULL DEFAULT 'default',
+  PRIMARY KEY (`id`),
+  KEY `created_at` (`tenant_id`, `created_at`)
+) DEFAULT CHARSET=utf8mb4 COMMENT 'strips drawn for visitors at booths, kept apart from the draws of users';
@@ -0,0 +1,4 @@
+DROP TABLE `deferred_messages`;
+ALTER TABLE `user_settings`
+  DROP COLUMN `quiet_start`,
+  DROP COLUMN `quiet_end`;
@@ -0,0 +1,12 @@
+ALTER TABLE `user_settings`
+  ADD COLUMN `quiet_start` time NULL DEFAULT NULL COMMENT 'start of the quiet hours of the user, in which notifications are held back' AFTER `weekly_digest`,
+  ADD COLUMN `quiet_end` time NULL DEFAULT NULL COMMENT 'end of the quiet hours, earlier than the start if they go over midnight' AFTER `quiet_start`;
+CREATE TABLE `deferred_messages` (
+  `id` int(10) UNSIGNED NOT NULL AUTO_INCREMENT,
+  `tg_id` bigint(20) NOT NULL COMMENT 'recipient, who was in their quiet hours',
+  `message` text NOT NULL COMMENT 'the sendMessage request, as JSON',
+  `created_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  `tenant_id` varchar(32) NOT NULL DEFAULT 'default',
+  PRIMARY KEY (`id`),
+  KEY `tg_id` (`tenant_id`, `tg_id`)
+) DEFAULT CHARSET=utf8mb4 COMMENT 'notifications held back during quiet hours, sent once they are over';
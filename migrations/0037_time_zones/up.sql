@@ -0,0 +1,2 @@
+ALTER TABLE `user_settings`
+  ADD COLUMN `time_zone` varchar(64) NULL DEFAULT NULL COMMENT 'IANA time zone of the user, e.g. Asia/Singapore, NULL for the time zone of the server' AFTER `quiet_end`;
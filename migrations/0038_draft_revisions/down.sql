@@ -0,0 +1,2 @@
+ALTER TABLE `drafts`
+  MODIFY COLUMN `updated_at` timestamp NOT NULL DEFAULT current_timestamp() ON UPDATE current_timestamp();
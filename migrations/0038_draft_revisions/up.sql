@@ -0,0 +1,2 @@
+ALTER TABLE `drafts`
+  MODIFY COLUMN `updated_at` timestamp(6) NOT NULL DEFAULT current_timestamp(6) ON UPDATE current_timestamp(6) COMMENT 'revision of the draft, see DraftStore::save_draft_if';
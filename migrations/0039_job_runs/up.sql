@@ -0,0 +1,9 @@
+CREATE TABLE `job_runs` (
+  `tenant_id` varchar(32) NOT NULL DEFAULT 'default',
+  `job` varchar(32) NOT NULL COMMENT 'e.g. daily, weekly_digest, shrine_board',
+  `tg_id` bigint(20) NOT NULL DEFAULT 0 COMMENT 'user the job ran for, 0 for jobs not run per user',
+  `ran_on` date NOT NULL COMMENT 'day the job last ran on, in the time zone of the user',
+  `message_id` int(11) NULL DEFAULT NULL COMMENT 'message the job keeps up to date, e.g. the shrine board',
+  `count` bigint(20) NULL DEFAULT NULL COMMENT 'count shown in that message',
+  PRIMARY KEY (`tenant_id`, `job`, `tg_id`)
+) DEFAULT CHARSET=utf8mb4 COMMENT 'last runs of the scheduled jobs, so that another instance taking over the lease does not run them again';
@@ -0,0 +1,230 @@
+use crate::config::get_booklet_font;
+use crate::error::BotError;
+use crate::models::{Language, Omikuji, OmikujiMessage};
+use crate::tts::speech_text;
+use printpdf::path::PaintMode;
+use printpdf::{
+    IndirectFontRef, Line, LineDashPattern, Mm, PdfDocument, PdfLayerReference, Point, Pt, Rect,
+};
+use ttf_parser::Face;
+
+//
+// Printable booklet of the library, as a PDF with the strips laid out as slips to cut apart
+// The font is read from BOOKLET_FONT, a TrueType font with CJK glyphs (e.g. Noto Sans JP), and
+// embedded in the PDF so that it prints the same everywhere
+//
+
+// A4 portrait, in points
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 36.0;
+// Slips per page, in columns and rows, and the space inside their cut lines
+const COLUMNS: usize = 3;
+const ROWS: usize = 2;
+const PADDING: f32 = 10.0;
+pub const SLIPS_PER_PAGE: usize = COLUMNS * ROWS;
+
+const TITLE_SIZE: f32 = 16.0;
+const TEXT_SIZE: f32 = 9.0;
+const LEADING: f32 = 1.35;
+
+// The font as embedded in the document, and as parsed to measure the text
+struct Font<'a> {
+    face: Face<'a>,
+    reference: IndirectFontRef,
+}
+
+impl Font<'_> {
+    // Letters the font lacks are left out of the PDF, so they take no space either
+    fn char_width(&self, c: char, size: f32) -> f32 {
+        self.face
+            .glyph_index(c)
+            .and_then(|glyph| self.face.glyph_hor_advance(glyph))
+            .map_or(0.0, |advance| {
+                f32::from(advance) * size / f32::from(self.face.units_per_em())
+            })
+    }
+
+    fn text_width(&self, line: &str, size: f32) -> f32 {
+        line.chars().map(|c| self.char_width(c, size)).sum()
+    }
+}
+
+// The approved strips as slips, in the order given, with the font of BOOKLET_FONT
+pub fn render(omikujis: &[Omikuji], language: Language) -> Result<Vec<u8>, BotError> {
+    let path =
+        get_booklet_font().ok_or_else(|| BotError::State("BOOKLET_FONT is not set".to_string()))?;
+    let font = std::fs::read(path.as_str())
+        .map_err(|error| BotError::State(format!("Cannot read the font {}: {}", path, error)))?;
+    render_with(&font, omikujis, language)
+}
+
+pub fn render_with(
+    font: &[u8],
+    omikujis: &[Omikuji],
+    language: Language,
+) -> Result<Vec<u8>, BotError> {
+    let unreadable = |error: &dyn std::fmt::Display| {
+        BotError::State(format!("Cannot use the font of the booklet: {}", error))
+    };
+    let (document, first_page, first_layer) =
+        PdfDocument::new("Omikuji", mm(PAGE_WIDTH), mm(PAGE_HEIGHT), "Slips");
+    let font = Font {
+        face: Face::parse(font, 0).map_err(|error| unreadable(&error))?,
+        reference: document
+            .add_external_font(font)
+            .map_err(|error| unreadable(&error))?,
+    };
+    for (page, chunk) in omikujis.chunks(SLIPS_PER_PAGE).enumerate() {
+        let (page, layer) = if page == 0 {
+            (first_page, first_layer)
+        } else {
+            document.add_page(mm(PAGE_WIDTH), mm(PAGE_HEIGHT), "Slips")
+        };
+        let layer = document.get_page(page).get_layer(layer);
+        for (index, omikuji) in chunk.iter().enumerate() {
+            let message: OmikujiMessage = serde_json::from_str(omikuji.message.as_str())?;
+            draw_slip(&layer, &font, index, omikuji, &message, language);
+        }
+    }
+    document
+        .save_to_bytes()
+        .map_err(|error| BotError::State(format!("Cannot write the booklet: {}", error)))
+}
+
+fn mm(points: f32) -> Mm {
+    Mm::from(Pt(points))
+}
+
+fn draw_slip(
+    layer: &PdfLayerReference,
+    font: &Font,
+    index: usize,
+    omikuji: &Omikuji,
+    message: &OmikujiMessage,
+    language: Language,
+) {
+    let width = (PAGE_WIDTH - 2.0 * MARGIN) / COLUMNS as f32;
+    let height = (PAGE_HEIGHT - 2.0 * MARGIN) / ROWS as f32;
+    let left = MARGIN + (index % COLUMNS) as f32 * width;
+    let top = PAGE_HEIGHT - MARGIN - (index / COLUMNS) as f32 * height;
+    // Dashed cut lines around the slip
+    layer.set_outline_thickness(0.5);
+    layer.set_line_dash_pattern(LineDashPattern {
+        dash_1: Some(3),
+        ..LineDashPattern::default()
+    });
+    layer.add_rect(
+        Rect::new(mm(left), mm(top - height), mm(left + width), mm(top))
+            .with_mode(PaintMode::Stroke),
+    );
+    layer.set_line_dash_pattern(LineDashPattern::default());
+    let inner = width - 2.0 * PADDING;
+    let bottom = top - height + PADDING;
+    let mut y = top - PADDING;
+    if let Some(label) = omikuji.slip_label(language) {
+        y -= TEXT_SIZE;
+        centered(layer, font, label.as_str(), TEXT_SIZE, left, width, y);
+    }
+    if let Some(class) = &message.class {
+        y -= TITLE_SIZE * LEADING;
+        centered(
+            layer,
+            font,
+            class.name(language),
+            TITLE_SIZE,
+            left,
+            width,
+            y,
+        );
+    }
+    y -= TEXT_SIZE;
+    layer.add_line(Line {
+        points: vec![
+            (Point::new(mm(left + PADDING), mm(y)), false),
+            (Point::new(mm(left + width - PADDING), mm(y)), false),
+        ],
+        is_closed: false,
+    });
+    let mut lines = Vec::new();
+    if let Some(poem) = &message.poem {
+        for verse in poem.lines().filter(|verse| !verse.trim().is_empty()) {
+            lines.extend(wrap(
+                font,
+                format!("  {}", verse.trim()).as_str(),
+                inner,
+                TEXT_SIZE,
+            ));
+        }
+        lines.push(String::new());
+    }
+    if let Some(description) = &message.description {
+        lines.extend(wrap(
+            font,
+            speech_text(description).as_str(),
+            inner,
+            TEXT_SIZE,
+        ));
+        lines.push(String::new());
+    }
+    for (section, text) in &message.sections {
+        let text = format!("【{}】{}", section.name(language), speech_text(text));
+        lines.extend(wrap(font, text.as_str(), inner, TEXT_SIZE));
+    }
+    // Whatever does not fit is cut, with an ellipsis to show it
+    let fitting = ((y - bottom) / (TEXT_SIZE * LEADING)).max(0.0) as usize;
+    if lines.len() > fitting {
+        lines.truncate(fitting);
+        if let Some(last) = lines.last_mut() {
+            last.push('…');
+        }
+    }
+    for line in lines {
+        y -= TEXT_SIZE * LEADING;
+        if !line.is_empty() {
+            layer.use_text(line, TEXT_SIZE, mm(left + PADDING), mm(y), &font.reference);
+        }
+    }
+}
+
+fn centered(
+    layer: &PdfLayerReference,
+    font: &Font,
+    line: &str,
+    size: f32,
+    left: f32,
+    width: f32,
+    y: f32,
+) {
+    let x = left + ((width - font.text_width(line, size)) / 2.0).max(0.0);
+    layer.use_text(line, size, mm(x), mm(y), &font.reference);
+}
+
+// Break a text into lines that fit the width, between words where there are spaces and
+// anywhere in Japanese, which is written without them
+fn wrap(font: &Font, text: &str, width: f32, size: f32) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        let mut line_width = 0.0;
+        for c in paragraph.chars() {
+            let c_width = font.char_width(c, size);
+            if line_width + c_width > width && !line.is_empty() {
+                let rest = match line.rfind(' ') {
+                    Some(space) if c.is_ascii_graphic() => line.split_off(space + 1),
+                    _ => String::new(),
+                };
+                lines.push(line.trim_end().to_string());
+                line = rest;
+                line_width = font.text_width(line.as_str(), size);
+                if c == ' ' {
+                    continue;
+                }
+            }
+            line.push(c);
+            line_width += c_width;
+        }
+        lines.push(line);
+    }
+    lines
+}
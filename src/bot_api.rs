@@ -0,0 +1,355 @@
+use crate::error::BotError;
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::cell::{Cell, RefCell};
+use teloxide_core::payloads::{
+    AnswerCallbackQuery, EditMessageReplyMarkup, EditMessageText, PinChatMessage, SendDocument,
+    SendMessage, SendPhoto, SendPoll, SendVoice, SetMyCommands, StopPoll,
+};
+use teloxide_core::requests::{JsonRequest, MultipartRequest, Payload, Request};
+use teloxide_core::types::{Message, Poll, Recipient};
+use teloxide_core::{ApiError, Bot, RequestError};
+
+// The subset of Telegram Bot API used by the handlers
+// Handlers only talk to Telegram through this trait, so that it can be replaced in tests
+#[async_trait(?Send)]
+pub trait BotApi {
+    async fn send_message(&self, request: SendMessage) -> Result<Message, BotError>;
+    async fn send_photo(&self, request: SendPhoto) -> Result<Message, BotError>;
+    async fn send_voice(&self, request: SendVoice) -> Result<(), BotError>;
+    async fn send_document(&self, request: SendDocument) -> Result<(), BotError>;
+    async fn edit_reply_markup(&self, request: EditMessageReplyMarkup) -> Result<(), BotError>;
+    async fn edit_text(&self, request: EditMessageText) -> Result<(), BotError>;
+    async fn answer_callback(&self, request: AnswerCallbackQuery) -> Result<(), BotError>;
+    async fn set_my_commands(&self, request: SetMyCommands) -> Result<(), BotError>;
+    async fn pin_message(&self, request: PinChatMessage) -> Result<(), BotError>;
+    async fn send_poll(&self, request: SendPoll) -> Result<Message, BotError>;
+    // Returns the poll with its final results
+    async fn stop_poll(&self, request: StopPoll) -> Result<Poll, BotError>;
+}
+
+#[async_trait(?Send)]
+impl BotApi for Bot {
+    async fn send_message(&self, request: SendMessage) -> Result<Message, BotError> {
+        Ok(JsonRequest::new(self.clone(), request).send().await?)
+    }
+
+    async fn send_photo(&self, request: SendPhoto) -> Result<Message, BotError> {
+        Ok(MultipartRequest::new(self.clone(), request).send().await?)
+    }
+
+    async fn send_voice(&self, request: SendVoice) -> Result<(), BotError> {
+        MultipartRequest::new(self.clone(), request).send().await?;
+        Ok(())
+    }
+
+    async fn send_document(&self, request: SendDocument) -> Result<(), BotError> {
+        MultipartRequest::new(self.clone(), request).send().await?;
+        Ok(())
+    }
+
+    async fn edit_reply_markup(&self, request: EditMessageReplyMarkup) -> Result<(), BotError> {
+        JsonRequest::new(self.clone(), request).send().await?;
+        Ok(())
+    }
+
+    async fn edit_text(&self, request: EditMessageText) -> Result<(), BotError> {
+        JsonRequest::new(self.clone(), request).send().await?;
+        Ok(())
+    }
+
+    async fn answer_callback(&self, request: AnswerCallbackQuery) -> Result<(), BotError> {
+        JsonRequest::new(self.clone(), request).send().await?;
+        Ok(())
+    }
+
+    async fn set_my_commands(&self, request: SetMyCommands) -> Result<(), BotError> {
+        JsonRequest::new(self.clone(), request).send().await?;
+        Ok(())
+    }
+
+    async fn pin_message(&self, request: PinChatMessage) -> Result<(), BotError> {
+        JsonRequest::new(self.clone(), request).send().await?;
+        Ok(())
+    }
+
+    async fn send_poll(&self, request: SendPoll) -> Result<Message, BotError> {
+        Ok(JsonRequest::new(self.clone(), request).send().await?)
+    }
+
+    async fn stop_poll(&self, request: StopPoll) -> Result<Poll, BotError> {
+        Ok(JsonRequest::new(self.clone(), request).send().await?)
+    }
+}
+
+// Whether a request failed because the user blocked the bot (or deleted their account), in which
+// case retrying is pointless until they write to the bot again
+pub fn is_blocked(error: &BotError) -> bool {
+    matches!(
+        error,
+        BotError::Telegram(RequestError::Api(
+            ApiError::BotBlocked | ApiError::UserDeactivated | ApiError::CantInitiateConversation
+        ))
+    )
+}
+
+//
+// Recording implementation, for tests
+//
+
+// A request as it would have been sent to Telegram
+#[derive(Debug, Clone)]
+pub struct SentRequest {
+    // Bot API method, e.g. "SendMessage"
+    pub method: &'static str,
+    pub body: Value,
+}
+
+impl SentRequest {
+    // Text of a sendMessage request
+    pub fn text(&self) -> Option<&str> {
+        self.body.get("text").and_then(Value::as_str)
+    }
+
+    // Callback data of all inline keyboard buttons attached to the request
+    pub fn callbacks(&self) -> Vec<&str> {
+        self.body
+            .pointer("/reply_markup/inline_keyboard")
+            .and_then(Value::as_array)
+            .map(|rows| {
+                rows.iter()
+                    .filter_map(Value::as_array)
+                    .flatten()
+                    .filter_map(|button| button.get("callback_data").and_then(Value::as_str))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // Labels of all inline keyboard buttons attached to the request
+    pub fn labels(&self) -> Vec<&str> {
+        self.body
+            .pointer("/reply_markup/inline_keyboard")
+            .and_then(Value::as_array)
+            .map(|rows| {
+                rows.iter()
+                    .filter_map(Value::as_array)
+                    .flatten()
+                    .filter_map(|button| button.get("text").and_then(Value::as_str))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+// Records every request instead of sending it
+#[derive(Default)]
+pub struct RecordingApi {
+    pub requests: RefCell<Vec<SentRequest>>,
+    // Voters per option of the polls stopped, as if users had voted
+    pub poll_votes: RefCell<Vec<i32>>,
+    // Users who blocked the bot, messages to them fail like on Telegram
+    pub blocked: RefCell<Vec<i64>>,
+}
+
+impl RecordingApi {
+    // Take all requests recorded so far
+    pub fn take(&self) -> Vec<SentRequest> {
+        self.requests.replace(Vec::new())
+    }
+
+    // Texts of all messages recorded so far, without taking them
+    pub fn texts(&self) -> Vec<String> {
+        self.requests
+            .borrow()
+            .iter()
+            .filter_map(|request| request.text().map(String::from))
+            .collect()
+    }
+
+    fn record<P: Payload + Serialize>(&self, request: P) -> Result<(), BotError> {
+        self.requests.borrow_mut().push(SentRequest {
+            method: P::NAME,
+            body: serde_json::to_value(&request)?,
+        });
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl BotApi for RecordingApi {
+    async fn send_message(&self, request: SendMessage) -> Result<Message, BotError> {
+        if let Recipient::Id(chat_id) = &request.chat_id {
+            if self.blocked.borrow().contains(&chat_id.0) {
+                return Err(RequestError::Api(ApiError::BotBlocked).into());
+            }
+        }
+        // Numbered by the requests so far
+        let message_id = self.requests.borrow().len() + 1;
+        let message = fake_message(&request.chat_id, &request.text, message_id)?;
+        self.record(request)?;
+        Ok(message)
+    }
+
+    async fn send_photo(&self, request: SendPhoto) -> Result<Message, BotError> {
+        let message_id = self.requests.borrow().len() + 1;
+        let caption = request.caption.clone().unwrap_or_default();
+        let message = fake_message(&request.chat_id, &caption, message_id)?;
+        self.record(request)?;
+        Ok(message)
+    }
+
+    async fn send_voice(&self, request: SendVoice) -> Result<(), BotError> {
+        self.record(request)
+    }
+
+    async fn send_document(&self, request: SendDocument) -> Result<(), BotError> {
+        self.record(request)
+    }
+
+    async fn edit_reply_markup(&self, request: EditMessageReplyMarkup) -> Result<(), BotError> {
+        self.record(request)
+    }
+
+    async fn edit_text(&self, request: EditMessageText) -> Result<(), BotError> {
+        self.record(request)
+    }
+
+    async fn answer_callback(&self, request: AnswerCallbackQuery) -> Result<(), BotError> {
+        self.record(request)
+    }
+
+    async fn set_my_commands(&self, request: SetMyCommands) -> Result<(), BotError> {
+        self.record(request)
+    }
+
+    async fn pin_message(&self, request: PinChatMessage) -> Result<(), BotError> {
+        self.record(request)
+    }
+
+    async fn send_poll(&self, request: SendPoll) -> Result<Message, BotError> {
+        let message_id = self.requests.borrow().len() + 1;
+        let message = fake_message(&request.chat_id, &request.question, message_id)?;
+        self.record(request)?;
+        Ok(message)
+    }
+
+    async fn stop_poll(&self, request: StopPoll) -> Result<Poll, BotError> {
+        self.record(request)?;
+        fake_poll(&self.poll_votes.borrow())
+    }
+}
+
+// The message as Telegram would have returned it for a sendMessage (or sendPoll) request
+pub(crate) fn fake_message(
+    chat_id: &Recipient,
+    text: &str,
+    message_id: usize,
+) -> Result<Message, BotError> {
+    let chat_id = match chat_id {
+        Recipient::Id(chat_id) => chat_id.0,
+        Recipient::ChannelUsername(_) => 0,
+    };
+    Ok(serde_json::from_value(json!({
+        "message_id": message_id,
+        "date": 0,
+        "chat": {"id": chat_id, "type": "private"},
+        "text": text,
+    }))?)
+}
+
+// A stopped poll, with the given number of voters per option
+fn fake_poll(votes: &[i32]) -> Result<Poll, BotError> {
+    let options: Vec<Value> = votes
+        .iter()
+        .map(|votes| json!({"text": "", "voter_count": votes}))
+        .collect();
+    Ok(serde_json::from_value(json!({
+        "id": "0",
+        "question": "",
+        "options": options,
+        "is_closed": true,
+        "total_voter_count": votes.iter().sum::<i32>(),
+        "is_anonymous": true,
+        "type": "regular",
+        "allows_multiple_answers": false,
+    }))?)
+}
+
+//
+// Dry run implementation, for shadow testing
+//
+
+// Logs every request instead of sending it, so that new code can be run against real updates
+// without anyone receiving its replies
+#[derive(Default)]
+pub struct DryRunApi {
+    sent: Cell<usize>,
+}
+
+impl DryRunApi {
+    // Number of requests logged so far
+    pub fn sent(&self) -> usize {
+        self.sent.get()
+    }
+
+    fn log<P: Payload + Serialize>(&self, request: &P) -> Result<(), BotError> {
+        self.sent.set(self.sent.get() + 1);
+        println!("Dry run: {} {}", P::NAME, serde_json::to_string(request)?);
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl BotApi for DryRunApi {
+    async fn send_message(&self, request: SendMessage) -> Result<Message, BotError> {
+        self.log(&request)?;
+        fake_message(&request.chat_id, &request.text, self.sent())
+    }
+
+    async fn send_photo(&self, request: SendPhoto) -> Result<Message, BotError> {
+        self.log(&request)?;
+        let caption = request.caption.clone().unwrap_or_default();
+        fake_message(&request.chat_id, &caption, self.sent())
+    }
+
+    async fn send_voice(&self, request: SendVoice) -> Result<(), BotError> {
+        self.log(&request)
+    }
+
+    async fn send_document(&self, request: SendDocument) -> Result<(), BotError> {
+        self.log(&request)
+    }
+
+    async fn edit_reply_markup(&self, request: EditMessageReplyMarkup) -> Result<(), BotError> {
+        self.log(&request)
+    }
+
+    async fn edit_text(&self, request: EditMessageText) -> Result<(), BotError> {
+        self.log(&request)
+    }
+
+    async fn answer_callback(&self, request: AnswerCallbackQuery) -> Result<(), BotError> {
+        self.log(&request)
+    }
+
+    async fn set_my_commands(&self, request: SetMyCommands) -> Result<(), BotError> {
+        self.log(&request)
+    }
+
+    async fn pin_message(&self, request: PinChatMessage) -> Result<(), BotError> {
+        self.log(&request)
+    }
+
+    async fn send_poll(&self, request: SendPoll) -> Result<Message, BotError> {
+        self.log(&request)?;
+        fake_message(&request.chat_id, &request.question, self.sent())
+    }
+
+    // Nobody votes on polls which were never sent
+    async fn stop_poll(&self, request: StopPoll) -> Result<Poll, BotError> {
+        self.log(&request)?;
+        fake_poll(&[])
+    }
+}
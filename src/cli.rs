@@ -0,0 +1,142 @@
+use crate::models::{AuditAction, Language, NewAuditEntry, NewOmikuji, Omikuji, OmikujiMessage};
+use crate::repository::{Repository, HIDE_THRESHOLD};
+use anyhow::{anyhow, Error};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+//
+// Offline maintenance of the library, run from the command line
+//
+
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+// Strips as written by `export` and read by `import`
+// IDs and dates are informative only, imported strips are saved as new ones
+#[derive(Serialize, Deserialize)]
+pub struct LibraryEntry {
+    #[serde(default)]
+    pub id: Option<u32>,
+    pub message: OmikujiMessage,
+    #[serde(default)]
+    pub vote_count: i32,
+    pub tg_id: i64,
+    pub tg_name: String,
+    #[serde(default)]
+    pub community_id: Option<i64>,
+    #[serde(default)]
+    pub anonymous: bool,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub expires_at: Option<String>,
+    #[serde(default)]
+    pub quarantined: bool,
+}
+
+impl LibraryEntry {
+    fn from_omikuji(omikuji: &Omikuji) -> Result<Self, Error> {
+        Ok(LibraryEntry {
+            id: Some(omikuji.id),
+            message: serde_json::from_str(omikuji.message.as_str())?,
+            vote_count: omikuji.vote_count,
+            tg_id: omikuji.tg_id,
+            tg_name: omikuji.tg_name.clone(),
+            community_id: omikuji.community_id,
+            anonymous: omikuji.anonymous,
+            created_at: Some(omikuji.created_at.format(TIME_FORMAT).to_string()),
+            expires_at: omikuji
+                .expires_at
+                .map(|expires_at| expires_at.format(TIME_FORMAT).to_string()),
+            quarantined: omikuji.quarantined,
+        })
+    }
+}
+
+// One line per strip: ID, votes, author and the first line of the strip
+pub async fn list(repository: &dyn Repository) -> Result<(), Error> {
+    for omikuji in repository.all_omikujis().await? {
+        let text = serde_json::from_str::<OmikujiMessage>(omikuji.message.as_str())
+            .map(|message| message.render(Language::English))
+            .unwrap_or_else(|_| String::from("(malformed)"));
+        println!(
+            "{:>6} {:>5} {:<24} {}",
+            omikuji.id,
+            omikuji.vote_count,
+            omikuji.tg_name,
+            text.lines().next().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+pub async fn show(repository: &dyn Repository, id: u32) -> Result<(), Error> {
+    let omikuji = repository
+        .find_omikuji(id)
+        .await?
+        .ok_or_else(|| anyhow!("Omikuji {} not found", id))?;
+    let message: OmikujiMessage = serde_json::from_str(omikuji.message.as_str())?;
+    println!("ID:       {}", omikuji.id);
+    println!("Author:   {} ({})", omikuji.tg_name, omikuji.tg_id);
+    println!("Votes:    {}", omikuji.vote_count);
+    println!("Created:  {}", omikuji.created_at);
+    if omikuji.anonymous {
+        println!("Submitted anonymously");
+    }
+    println!("\n{}", message.render(Language::English));
+    Ok(())
+}
+
+pub async fn delete(repository: &dyn Repository, id: u32) -> Result<(), Error> {
+    if repository.find_omikuji(id).await?.is_none() {
+        return Err(anyhow!("Omikuji {} not found", id));
+    }
+    repository.delete_omikuji(id).await?;
+    repository
+        .record_audit(&NewAuditEntry::new(
+            "cli",
+            AuditAction::Delete,
+            format!("omikuji {}", id),
+        ))
+        .await?;
+    println!("Deleted omikuji {}", id);
+    Ok(())
+}
+
+pub async fn export(repository: &dyn Repository, path: &str) -> Result<(), Error> {
+    let entries = repository
+        .all_omikujis()
+        .await?
+        .iter()
+        .map(LibraryEntry::from_omikuji)
+        .collect::<Result<Vec<LibraryEntry>, Error>>()?;
+    fs::write(path, serde_json::to_vec_pretty(&entries)?)?;
+    println!("Exported {} omikuji strips to {}", entries.len(), path);
+    Ok(())
+}
+
+// The whole file is read (and checked) before anything is saved
+pub async fn import(repository: &dyn Repository, path: &str) -> Result<(), Error> {
+    let entries: Vec<LibraryEntry> = serde_json::from_slice(&fs::read(path)?)?;
+    for entry in &entries {
+        let expires_at = match &entry.expires_at {
+            Some(expires_at) => Some(NaiveDateTime::parse_from_str(expires_at, TIME_FORMAT)?),
+            None => None,
+        };
+        repository
+            .insert_omikuji(&NewOmikuji {
+                message: serde_json::to_string(&entry.message)?.as_str(),
+                tg_id: entry.tg_id,
+                tg_name: entry.tg_name.as_str(),
+                community_id: entry.community_id,
+                vote_count: entry.vote_count,
+                anonymous: entry.anonymous,
+                expires_at,
+                // Exports from before quarantines only had the vote count to go by
+                quarantined: entry.quarantined || entry.vote_count <= HIDE_THRESHOLD,
+            })
+            .await?;
+    }
+    println!("Imported {} omikuji strips from {}", entries.len(), path);
+    Ok(())
+}
@@ -0,0 +1,238 @@
+use crate::models;
+use crate::models::Language;
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+use strum_macros::EnumString;
+use teloxide_core::payloads::setters::*;
+use teloxide_core::payloads::SetMyCommands;
+use teloxide_core::types::{BotCommand, BotCommandScope};
+
+// Registry of all commands recognized by the bot, e.g. `Command::Help` is "/help"
+// This is used for routing messages as well as for generating the command menu
+#[derive(EnumIter, EnumString, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum Command {
+    Start,
+    Draw,
+    BoothDraw,
+    Today,
+    Last,
+    Favorites,
+    Slip,
+    Comments,
+    MyStrips,
+    Trash,
+    New,
+    Stats,
+    Streak,
+    Invites,
+    Help,
+    Current,
+    Cancel,
+    About,
+    Debug,
+    Settings,
+    Language,
+    Quiet,
+    TimeZone,
+    Template,
+    MyData,
+    ForgetMe,
+    AdminStats,
+    Audit,
+    Maintenance,
+    ReloadConfig,
+    SetInterpretation,
+    Review,
+    ImportUrl,
+    ExportPdf,
+    Inspect,
+    Experiment,
+    ModerateComments,
+    Balance,
+}
+
+impl Command {
+    pub fn name(&self) -> String {
+        format!("{:?}", self).to_lowercase()
+    }
+
+    // Name of a command without the bot it is addressed to, e.g. "draw" for "draw@OmikujiBot"
+    // as sent in groups; None if it is addressed to a bot other than `bot_username`
+    pub fn addressed_name<'a>(name: &'a str, bot_username: Option<&str>) -> Option<&'a str> {
+        match name.split_once('@') {
+            Some((_, username))
+                if bot_username.is_some_and(|bot| !bot.eq_ignore_ascii_case(username)) =>
+            {
+                None
+            }
+            Some((name, _)) => Some(name),
+            None => Some(name),
+        }
+    }
+
+    // Admin-only commands are hidden from (and rejected for) everyone else
+    pub fn admin_only(&self) -> bool {
+        matches!(
+            self,
+            Command::AdminStats
+                | Command::Audit
+                | Command::Maintenance
+                | Command::ReloadConfig
+                | Command::SetInterpretation
+                | Command::Review
+                | Command::ImportUrl
+                | Command::ExportPdf
+                | Command::Inspect
+                | Command::Experiment
+                | Command::ModerateComments
+                | Command::Balance
+        )
+    }
+
+    // Commands which only make sense when working on a new omikuji
+    pub fn requires_draft(&self) -> bool {
+        matches!(
+            self,
+            Command::Current | Command::Cancel | Command::Debug | Command::Template
+        )
+    }
+
+    // Commands also offered on the other chat platforms (see frontends), which need neither
+    // buttons nor drafts
+    pub fn shared(&self) -> bool {
+        matches!(
+            self,
+            Command::Draw | Command::Today | Command::Slip | Command::Streak | Command::About
+        )
+    }
+
+    // Commands offered on the quick action keyboard, which sends the label as plain text
+    pub fn quick_action(&self, language: Language) -> Option<&'static str> {
+        // Command::Language shadows the Language enum here, hence the full paths
+        use Command::*;
+        match language {
+            models::Language::English => match self {
+                Draw => Some("🎴 Draw"),
+                New => Some("✍️ Create"),
+                Stats => Some("📊 Stats"),
+                _ => None,
+            },
+            models::Language::Japanese => match self {
+                Draw => Some("🎴 引く"),
+                New => Some("✍️ 作る"),
+                Stats => Some("📊 統計"),
+                _ => None,
+            },
+        }
+    }
+
+    // Find the command a quick action keyboard button belongs to, in any language
+    pub fn from_quick_action(text: &str) -> Option<Command> {
+        Command::iter().find(|command| {
+            Language::iter().any(|language| command.quick_action(language) == Some(text))
+        })
+    }
+
+    pub fn description(&self, language: Language) -> &'static str {
+        use Command::*;
+        match language {
+            models::Language::English => match self {
+                Start => "Draw or save omikuji strips",
+                New => "Create a new omikuji strip",
+                Draw => "Draw an omikuji strip",
+                BoothDraw => "Draw an omikuji strip for a visitor at a booth",
+                Today => "Show your personal fortune of the day",
+                Last => "Show the omikuji strip you drew last again",
+                Favorites => "Read the omikuji strips you saved again",
+                Slip => "Read the omikuji slip of a number, e.g. /slip 12",
+                Comments => "Show the comments on your omikuji strips",
+                MyStrips => "List your omikuji strips with their draws and scores",
+                Trash => "Restore omikuji strips and drafts you deleted recently",
+                Stats => "Show statistics about your omikuji strips",
+                Streak => "Show how many days in a row you have drawn",
+                Invites => "Get your invite link and see who invited the most users",
+                Help => "Show the help message",
+                Current => "Show the omikuji you are working on",
+                Cancel => "Cancel and delete the omikuji you are working on",
+                About => "Show link to this bot's repository",
+                Debug => "Show the raw omikuji you are working on",
+                Settings => "Change your preferences",
+                Language => "Change the language of the bot",
+                Quiet => "Set hours in which you do not get notifications, e.g. /quiet 23:00-08:00",
+                TimeZone => "Set your time zone, e.g. /timezone Asia/Singapore",
+                Template => "Add sections from a template to the omikuji you are working on",
+                MyData => "Download all your data stored by the bot",
+                ForgetMe => "Delete all your data from the bot",
+                AdminStats => "Show statistics about the bot",
+                Audit => "Show recent admin and moderation actions",
+                Maintenance => "Turn maintenance mode on or off",
+                ReloadConfig => "Reload the configuration without restarting",
+                SetInterpretation => "Set the interpretation shown with strips of a class",
+                Review => "Restore or delete quarantined strips",
+                ImportUrl => "Import strips from a CSV file or Google Sheet by link",
+                ExportPdf => "Get the approved strips as a PDF of slips to print",
+                Inspect => "Show the details of an omikuji strip",
+                Experiment => "Compare the draw strategies of the running experiment",
+                ModerateComments => "Show recent comments and delete inappropriate ones",
+                Balance => "Compare the number of strips per class to the target shares",
+            },
+            models::Language::Japanese => match self {
+                Start => "おみくじを引く・作る",
+                New => "新しいおみくじを作る",
+                Draw => "おみくじを引く",
+                BoothDraw => "ブースで来場者のためにおみくじを引く",
+                Today => "今日の運勢を表示する",
+                Last => "最後に引いたおみくじをもう一度表示する",
+                Favorites => "お気に入りのおみくじを読み返す",
+                Slip => "番号のおみくじを読む（例：/slip 12）",
+                Comments => "自分のおみくじへのコメントを表示する",
+                MyStrips => "自分のおみくじの一覧と統計を表示する",
+                Trash => "最近削除したおみくじや下書きを復元する",
+                Stats => "自分のおみくじの統計を表示する",
+                Streak => "連続で引いた日数を表示する",
+                Invites => "招待リンクと招待ランキングを表示する",
+                Help => "ヘルプを表示する",
+                Current => "作成中のおみくじを表示する",
+                Cancel => "作成中のおみくじを取り消す",
+                About => "このボットのリポジトリを表示する",
+                Debug => "作成中のおみくじの生データを表示する",
+                Settings => "設定を変更する",
+                Language => "ボットの言語を変更する",
+                Quiet => "通知を受け取らない時間帯を設定する（例：/quiet 23:00-08:00）",
+                TimeZone => "タイムゾーンを設定する（例：/timezone Asia/Tokyo）",
+                Template => "作成中のおみくじにテンプレートの項目を追加する",
+                MyData => "自分のデータをダウンロードする",
+                ForgetMe => "自分のデータをすべて削除する",
+                AdminStats => "ボットの統計を表示する",
+                Audit => "最近の管理操作を表示する",
+                Maintenance => "メンテナンスモードを切り替える",
+                ReloadConfig => "再起動せずに設定を再読み込みする",
+                SetInterpretation => "運勢ごとの解説を設定する",
+                Review => "非表示のおみくじを復元・削除する",
+                ImportUrl => "CSVやGoogleスプレッドシートのリンクからおみくじを取り込む",
+                ExportPdf => "承認済みのおみくじを印刷用のPDFで受け取る",
+                Inspect => "おみくじの詳細を表示する",
+                Experiment => "抽選方法の実験結果を比較する",
+                ModerateComments => "最近のコメントを確認・削除する",
+                Balance => "運勢ごとのおみくじの数を目標の割合と比べる",
+            },
+        }
+    }
+}
+
+//
+// Command menu shown by Telegram clients
+//
+
+// Build the command list in the given language, with admin-only commands if required
+pub fn set_my_commands(
+    scope: BotCommandScope,
+    language: Language,
+    include_admin: bool,
+) -> SetMyCommands {
+    let commands = Command::iter()
+        .filter(|command| include_admin || !command.admin_only())
+        .map(|command| BotCommand::new(command.name(), command.description(language)));
+    SetMyCommands::new(commands).scope(scope)
+}
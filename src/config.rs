@@ -0,0 +1,596 @@
+use crate::drafts::DraftBackend;
+use crate::frontends::{Platform, Sender};
+use crate::geo::Point;
+use crate::models::DrawStrategy;
+use chrono::Duration;
+use std::collections::BTreeMap;
+use std::env;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{OnceLock, PoisonError, RwLock};
+use strum::IntoEnumIterator;
+use teloxide_core::types::{ChatId, Recipient};
+use url::Url;
+
+// Telegram IDs of admins, configured as a comma-separated list in ADMIN_IDS
+pub fn get_admins() -> Vec<i64> {
+    var("ADMIN_IDS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|admin| admin.trim().parse().ok())
+        .collect()
+}
+
+// Admins are Telegram users, so users of the other platforms never are
+pub(crate) fn is_admin(user: &Sender) -> bool {
+    user.platform == Platform::Telegram && get_admins().contains(&user.id)
+}
+
+// Channel for the daily "omikuji of the day" post, configured in CHANNEL_ID
+// This is either a numeric chat ID or a public @username
+pub fn get_channel() -> Option<Recipient> {
+    parse_recipient(var("CHANNEL_ID").ok()?.as_str())
+}
+
+// Chat or channel for the shrine board, where the bad fortunes tied this month are tallied
+// Configured in SHRINE_BOARD_ID, like CHANNEL_ID
+pub fn get_shrine_board() -> Option<Recipient> {
+    parse_recipient(var("SHRINE_BOARD_ID").ok()?.as_str())
+}
+
+// Chat or channel for the weekly poll on the omikuji of the week, configured in WEEKLY_POLL_ID
+// like CHANNEL_ID
+pub fn get_weekly_poll_chat() -> Option<Recipient> {
+    parse_recipient(var("WEEKLY_POLL_ID").ok()?.as_str())
+}
+
+// Chat id or @username, as written in CHANNEL_ID and the like
+pub fn parse_recipient(recipient: &str) -> Option<Recipient> {
+    let recipient = recipient.trim();
+    match recipient.parse() {
+        Ok(id) => Some(Recipient::Id(ChatId(id))),
+        Err(_) if recipient.starts_with('@') => {
+            Some(Recipient::ChannelUsername(recipient.to_string()))
+        }
+        Err(_) => None,
+    }
+}
+
+// Whether the channel post is pinned, set CHANNEL_PIN=true to enable
+pub fn pin_channel_post() -> bool {
+    var("CHANNEL_PIN").is_ok_and(|pin| pin == "true" || pin == "1")
+}
+
+// Post the top-voted strip of the past week rather than a random one, set CHANNEL_PICK=top
+pub fn channel_picks_top() -> bool {
+    var("CHANNEL_PICK").is_ok_and(|pick| pick == "top")
+}
+
+// Show the number and submission date under drawn strips, set SHOW_STRIP_INFO=true
+pub fn show_strip_info() -> bool {
+    var("SHOW_STRIP_INFO").is_ok_and(|on| on == "true" || on == "1")
+}
+
+// Half-life of votes on leaderboards, so that old strips don't dominate them
+// Configured in VOTE_HALF_LIFE_DAYS (30 days if unset), votes don't decay if set to 0
+pub fn vote_half_life() -> Option<Duration> {
+    let days = var("VOTE_HALF_LIFE_DAYS")
+        .ok()
+        .and_then(|days| days.trim().parse().ok())
+        .unwrap_or(30);
+    (days > 0).then(|| Duration::days(days))
+}
+
+// Domains which strips may link to without being held for moderation, configured as a
+// comma-separated list in ALLOWED_DOMAINS, e.g. "nus.edu.sg,github.com"
+pub fn get_allowed_domains() -> Vec<String> {
+    var("ALLOWED_DOMAINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|domain| domain.trim().to_string())
+        .filter(|domain| !domain.is_empty())
+        .collect()
+}
+
+// Limits on how many strips an author may submit in total, and within 24 hours
+// Configured in QUOTA_TOTAL and QUOTA_DAILY, there is no limit if unset (or 0)
+pub fn get_quota_total() -> Option<usize> {
+    let quota = var("QUOTA_TOTAL").ok()?.trim().parse().ok()?;
+    Some(quota).filter(|quota| *quota > 0)
+}
+
+pub fn get_quota_daily() -> Option<usize> {
+    let quota = var("QUOTA_DAILY").ok()?.trim().parse().ok()?;
+    Some(quota).filter(|quota| *quota > 0)
+}
+
+// Limit on how many strips a user may draw per day, configured in DRAWS_DAILY
+// There is no limit if unset (or 0), users on a long streak may draw twice as many
+pub fn get_draws_daily() -> Option<usize> {
+    let limit = var("DRAWS_DAILY").ok()?.trim().parse().ok()?;
+    Some(limit).filter(|limit| *limit > 0)
+}
+
+// How strips are picked when drawing, configured in DRAW_STRATEGY (Uniform if unset)
+pub fn get_draw_strategy() -> DrawStrategy {
+    var("DRAW_STRATEGY")
+        .ok()
+        .and_then(|strategy| DrawStrategy::from_str(strategy.trim()).ok())
+        .unwrap_or(DrawStrategy::Uniform)
+}
+
+// Draw strategies compared by an experiment, configured as a comma-separated list in
+// DRAW_EXPERIMENT, e.g. "Uniform,Weighted,FairExposure"; this overrides DRAW_STRATEGY
+pub fn get_draw_experiment() -> Vec<DrawStrategy> {
+    var("DRAW_EXPERIMENT")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|strategy| DrawStrategy::from_str(strategy.trim()).ok())
+        .collect()
+}
+
+// Share of FairExposure draws which pick one of the least drawn strips, configured in
+// DRAW_EXPLORATION as a number between 0 and 1 (0.2 if unset)
+pub fn get_draw_exploration() -> f64 {
+    var("DRAW_EXPLORATION")
+        .ok()
+        .and_then(|rate| rate.trim().parse::<f64>().ok())
+        .filter(|rate| (0.0..=1.0).contains(rate))
+        .unwrap_or(0.2)
+}
+
+// Whether pending migrations are run on connect, set AUTO_MIGRATE=false to only run them with
+// `omikuji_bot migrate`
+pub fn auto_migrate() -> bool {
+    var("AUTO_MIGRATE").map_or(true, |migrate| migrate != "false" && migrate != "0")
+}
+
+// Whether replies are only logged instead of sent to Telegram (updates are still received)
+pub fn dry_run() -> bool {
+    var("DRY_RUN").is_ok_and(|on| on == "true" || on == "1")
+}
+
+// Directory where every incoming update is recorded, to be replayed later
+pub fn get_update_log() -> Option<String> {
+    var("RECORD_UPDATES").ok().filter(|dir| !dir.is_empty())
+}
+
+//
+// Reloading
+//
+
+// Settings are read whenever they are used, so reloading the .env file makes changes (e.g. to
+// ADMIN_IDS or quotas) take effect without a restart
+// Only the bot tokens, DATABASE_URL and the HTTP API settings are fixed at startup
+pub fn reload() -> Result<usize, dotenvy::Error> {
+    apply(dotenvy::dotenv_iter()?)
+}
+
+pub fn reload_from<P: AsRef<Path>>(path: P) -> Result<usize, dotenvy::Error> {
+    apply(dotenvy::from_path_iter(path)?)
+}
+
+// Settings of the last reload, which take precedence over the environment the process was started
+// with; the environment itself is not changed, as the other threads read it at the same time
+static RELOADED: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+
+// The whole file is read before it replaces the settings of the previous reload
+fn apply<I>(iter: I) -> Result<usize, dotenvy::Error>
+where
+    I: Iterator<Item = Result<(String, String), dotenvy::Error>>,
+{
+    let settings = iter.collect::<Result<BTreeMap<String, String>, dotenvy::Error>>()?;
+    let count = settings.len();
+    *RELOADED.write().unwrap_or_else(PoisonError::into_inner) = settings;
+    Ok(count)
+}
+
+fn var(key: &str) -> Result<String, env::VarError> {
+    let reloaded = RELOADED.read().unwrap_or_else(PoisonError::into_inner);
+    match reloaded.get(key) {
+        Some(value) => Ok(value.clone()),
+        None => env::var(key),
+    }
+}
+
+// Maintenance mode, where only admins are served; set MAINTENANCE=true to start in it
+// It is switched at runtime with /maintenance, for all bots of the process
+static MAINTENANCE: OnceLock<AtomicBool> = OnceLock::new();
+
+fn maintenance() -> &'static AtomicBool {
+    MAINTENANCE.get_or_init(|| {
+        AtomicBool::new(var("MAINTENANCE").is_ok_and(|on| on == "true" || on == "1"))
+    })
+}
+
+pub fn in_maintenance() -> bool {
+    maintenance().load(Ordering::Relaxed)
+}
+
+pub fn set_maintenance(on: bool) {
+    maintenance().store(on, Ordering::Relaxed);
+}
+
+// Username of the bot (without @), used for deep links back to the bot
+pub fn get_bot_username() -> Option<String> {
+    var("BOT_USERNAME")
+        .ok()
+        .map(|username| username.trim_start_matches('@').to_string())
+}
+
+// Text-to-speech service used for voice messages, configured in TTS_URL (and TTS_TOKEN)
+// Voice messages are not offered if this is not set
+pub fn get_tts_url() -> Option<String> {
+    var("TTS_URL").ok()
+}
+
+pub fn get_tts_token() -> Option<String> {
+    var("TTS_TOKEN").ok()
+}
+
+// Font the strips are rendered in (see render), configured in STRIP_FONT as the path of a TrueType
+// or OpenType font with CJK glyphs; strips are sent as text if this is not set
+#[cfg(feature = "image-render")]
+pub fn get_strip_font() -> Option<String> {
+    var("STRIP_FONT")
+        .ok()
+        .filter(|path| !path.trim().is_empty())
+}
+
+// Font the booklet of /exportpdf is printed in (see booklet), configured in BOOKLET_FONT as the path
+// of a TrueType font with CJK glyphs, which is embedded in the PDF
+pub fn get_booklet_font() -> Option<String> {
+    var("BOOKLET_FONT")
+        .ok()
+        .filter(|path| !path.trim().is_empty())
+}
+
+// Where events (strips created and drawn, votes cast) are published to, configured in EVENT_SINK
+// as mqtt://broker:1883/<topic prefix>, nats://server:4222/<subject prefix> or the URL of a
+// webhook which gets them as JSON; events are not published if this is not set
+pub fn get_event_sink() -> Option<Url> {
+    let sink = Url::parse(var("EVENT_SINK").ok()?.trim()).ok()?;
+    Some(sink).filter(|sink| matches!(sink.scheme(), "mqtt" | "nats" | "http" | "https"))
+}
+
+// Webhooks which get moderation events (strips submitted, approved or reported) as JSON,
+// configured as a comma-separated list of URLs in MODERATION_WEBHOOKS
+// The payloads can be sent to the incoming webhooks of Discord or Slack as they are
+pub fn get_moderation_webhooks() -> Vec<Url> {
+    var("MODERATION_WEBHOOKS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|webhook| Url::parse(webhook.trim()).ok())
+        .filter(|webhook| matches!(webhook.scheme(), "http" | "https"))
+        .collect()
+}
+
+// Chats of booths at events, where /boothdraw draws strips for visitors who don't use the bot
+// Configured as a comma-separated list of chat IDs in BOOTH_CHATS, e.g. the private chat of an
+// account logged in at the booth; chats with a printer in BOOTH_PRINTERS are booths as well
+pub fn is_booth(chat: i64) -> bool {
+    var("BOOTH_CHATS")
+        .unwrap_or_default()
+        .split(',')
+        .any(|booth| booth.trim().parse() == Ok(chat))
+        || get_booth_printer(chat).is_some()
+}
+
+// Receipt printers of booths at events, which print the strips drawn in their booth chat
+// Configured as a comma-separated list of chat=address in BOOTH_PRINTERS,
+// e.g. "-1001234567890=192.168.1.50:9100"
+pub fn get_booth_printers() -> Vec<(i64, String)> {
+    var("BOOTH_PRINTERS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|booth| booth.split_once('='))
+        .filter_map(|(chat, printer)| Some((chat.trim().parse().ok()?, printer.trim().to_string())))
+        .filter(|(_, printer)| !printer.is_empty())
+        .collect()
+}
+
+pub fn get_booth_printer(chat: i64) -> Option<String> {
+    get_booth_printers()
+        .into_iter()
+        .find(|(booth, _)| *booth == chat)
+        .map(|(_, printer)| printer)
+}
+
+// Landmarks shared locations are shown the way to, e.g. the shrines of a campus
+// Configured as a semicolon-separated list of name=latitude,longitude in SHRINE_LOCATIONS,
+// e.g. "Central Library=1.2966,103.7723;University Town=1.3046,103.7735"
+pub fn get_shrine_locations() -> Vec<(String, Point)> {
+    var("SHRINE_LOCATIONS")
+        .unwrap_or_default()
+        .split(';')
+        .filter_map(|shrine| shrine.split_once('='))
+        .filter_map(|(name, point)| Some((name.trim().to_string(), Point::from_str(point).ok()?)))
+        .filter(|(name, _)| !name.is_empty())
+        .collect()
+}
+
+// Address of the embedded HTTP API, e.g. 127.0.0.1:8080, configured in API_ADDR
+// The API is only started if API_TOKEN is set as well
+pub fn get_api_config() -> Option<(SocketAddr, String)> {
+    let addr = var("API_ADDR").ok()?.parse().ok()?;
+    let token = var("API_TOKEN").ok().filter(|token| !token.is_empty())?;
+    Some((addr, token))
+}
+
+// Homeserver and access token of the Matrix frontend, configured in MATRIX_HOMESERVER (e.g.
+// https://matrix.example.org) and MATRIX_TOKEN; the frontend is only started if both are set, and
+// the bot was built with `--features matrix`
+pub fn get_matrix_config() -> Option<(Url, String)> {
+    let homeserver = Url::parse(var("MATRIX_HOMESERVER").ok()?.trim()).ok()?;
+    let token = var("MATRIX_TOKEN").ok().filter(|token| !token.is_empty())?;
+    Some((homeserver, token))
+        .filter(|(homeserver, _)| matches!(homeserver.scheme(), "http" | "https"))
+}
+
+// Bot token of the Discord bridge, configured in DISCORD_TOKEN
+// The bridge is only started if the bot was built with `--features discord`
+pub fn get_discord_token() -> Option<String> {
+    var("DISCORD_TOKEN").ok().filter(|token| !token.is_empty())
+}
+
+// Secret for signing the data of inline keyboard buttons, configured in CALLBACK_SECRET
+// Buttons are sent unsigned (and accepted as they are) if this is not set
+pub fn get_callback_secret() -> Option<String> {
+    var("CALLBACK_SECRET")
+        .ok()
+        .filter(|secret| !secret.is_empty())
+}
+
+// Where drafts are kept between updates, configured in DRAFT_STORE as memory, mysql or redis
+// (memory if unset); running several instances of the bot needs one of the shared stores
+pub fn get_draft_backend() -> DraftBackend {
+    var("DRAFT_STORE")
+        .ok()
+        .and_then(|backend| DraftBackend::from_str(backend.trim()).ok())
+        .unwrap_or(DraftBackend::Memory)
+}
+
+// Redis server of DRAFT_STORE=redis, e.g. redis://127.0.0.1:6379, configured in REDIS_URL
+pub fn get_redis_url() -> Option<String> {
+    var("REDIS_URL").ok().filter(|url| !url.is_empty())
+}
+
+// Name of this instance of the bot, configured in INSTANCE_ID, which tells the holder of a lease
+// (e.g. running the scheduled jobs) when several instances share the database
+// Falls back to the host name and process ID, so this is read once at startup
+pub fn get_instance_id() -> String {
+    var("INSTANCE_ID")
+        .ok()
+        .filter(|id| !id.is_empty())
+        .unwrap_or_else(|| {
+            format!(
+                "{}-{}",
+                var("HOSTNAME").unwrap_or_else(|_| "omikuji".to_string()),
+                std::process::id()
+            )
+        })
+}
+
+// Tenant used when only TELEGRAM_BOT_TOKEN is configured
+pub const DEFAULT_TENANT: &str = "default";
+
+// Bots served by this process, each with its own omikuji library, as (tenant, token) pairs
+// Configured as a comma-separated list of name=token in TENANTS, e.g. "cas=123:abc,go=456:def"
+// Falls back to a single bot with TELEGRAM_BOT_TOKEN
+pub fn get_tenants() -> Vec<(String, String)> {
+    let tenants: Vec<(String, String)> = var("TENANTS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|tenant| tenant.split_once('='))
+        .map(|(name, token)| (name.trim().to_string(), token.trim().to_string()))
+        .filter(|(name, token)| !name.is_empty() && !token.is_empty())
+        .collect();
+    if !tenants.is_empty() {
+        return tenants;
+    }
+    let token = var("TELEGRAM_BOT_TOKEN").expect("Neither TENANTS nor TELEGRAM_BOT_TOKEN set");
+    vec![(DEFAULT_TENANT.to_string(), token)]
+}
+
+//
+// Startup check
+//
+
+// Problems with the configuration, each with a hint how to fix it
+// Checked on startup, so that they are not only noticed once a handler runs into them
+pub fn validate() -> Vec<String> {
+    let mut problems = Vec::new();
+    if var("DATABASE_URL").map_or(true, |url| url.trim().is_empty()) {
+        problems.push(
+            "DATABASE_URL is not set, it should look like mysql://<username>:<password>@<host>:3306/<database_name>"
+                .to_string(),
+        );
+    }
+    let has_tenants = var("TENANTS").is_ok_and(|tenants| tenants.contains('='));
+    if !has_tenants && var("TELEGRAM_BOT_TOKEN").is_err() {
+        problems.push(
+            "Neither TENANTS nor TELEGRAM_BOT_TOKEN is set, get a bot token from @BotFather"
+                .to_string(),
+        );
+    } else {
+        for (tenant, token) in get_tenants() {
+            if !token.contains(':') {
+                problems.push(format!(
+                    "The bot token of {} does not look like <some_digit>:<some_more_digits>",
+                    tenant
+                ));
+            }
+        }
+    }
+    for admin in var("ADMIN_IDS").unwrap_or_default().split(',') {
+        if !admin.trim().is_empty() && admin.trim().parse::<i64>().is_err() {
+            problems.push(format!(
+                "ADMIN_IDS contains \"{}\", which is not a numeric Telegram ID",
+                admin.trim()
+            ));
+        }
+    }
+    for key in [
+        "QUOTA_TOTAL",
+        "QUOTA_DAILY",
+        "DRAWS_DAILY",
+        "VOTE_HALF_LIFE_DAYS",
+    ] {
+        if let Ok(value) = var(key) {
+            if value.trim().parse::<u32>().is_err() {
+                problems.push(format!("{} should be a number, not \"{}\"", key, value));
+            }
+        }
+    }
+    let strategies: Vec<String> = DrawStrategy::iter()
+        .map(|strategy| format!("{:?}", strategy))
+        .collect();
+    if let Ok(strategy) = var("DRAW_STRATEGY") {
+        if DrawStrategy::from_str(strategy.trim()).is_err() {
+            problems.push(format!(
+                "DRAW_STRATEGY should be one of {}, not \"{}\"",
+                strategies.join(", "),
+                strategy
+            ));
+        }
+    }
+    for strategy in var("DRAW_EXPERIMENT").unwrap_or_default().split(',') {
+        if !strategy.trim().is_empty() && DrawStrategy::from_str(strategy.trim()).is_err() {
+            problems.push(format!(
+                "DRAW_EXPERIMENT should only list {}, not \"{}\"",
+                strategies.join(", "),
+                strategy.trim()
+            ));
+        }
+    }
+    if let Ok(backend) = var("DRAFT_STORE") {
+        match DraftBackend::from_str(backend.trim()) {
+            Ok(DraftBackend::Redis) if cfg!(not(feature = "redis")) => problems.push(
+                "DRAFT_STORE is redis, but the bot was built without `--features redis`"
+                    .to_string(),
+            ),
+            Ok(DraftBackend::Redis) if get_redis_url().is_none() => {
+                problems.push("DRAFT_STORE is redis, but REDIS_URL is not set".to_string())
+            }
+            Ok(_) => {}
+            Err(_) => problems.push(format!(
+                "DRAFT_STORE should be one of memory, mysql, redis, not \"{}\"",
+                backend
+            )),
+        }
+    }
+    for booth in var("BOOTH_CHATS").unwrap_or_default().split(',') {
+        if !booth.trim().is_empty() && booth.trim().parse::<i64>().is_err() {
+            problems.push(format!(
+                "BOOTH_CHATS contains \"{}\", which is not a numeric chat ID",
+                booth.trim()
+            ));
+        }
+    }
+    for booth in var("BOOTH_PRINTERS").unwrap_or_default().split(',') {
+        if booth.trim().is_empty() {
+            continue;
+        }
+        let valid = booth.split_once('=').is_some_and(|(chat, printer)| {
+            chat.trim().parse::<i64>().is_ok() && printer.trim().rsplit_once(':').is_some()
+        });
+        if !valid {
+            problems.push(format!(
+                "BOOTH_PRINTERS should list chat=address like -1001234567890=192.168.1.50:9100, \
+                not \"{}\"",
+                booth.trim()
+            ));
+        }
+    }
+    for shrine in var("SHRINE_LOCATIONS").unwrap_or_default().split(';') {
+        if shrine.trim().is_empty() {
+            continue;
+        }
+        let valid = shrine
+            .split_once('=')
+            .is_some_and(|(name, point)| !name.trim().is_empty() && Point::from_str(point).is_ok());
+        if !valid {
+            problems.push(format!(
+                "SHRINE_LOCATIONS should list name=latitude,longitude like \
+                Central Library=1.2966,103.7723, not \"{}\"",
+                shrine.trim()
+            ));
+        }
+    }
+    if let Some(font) = get_booklet_font() {
+        if !Path::new(font.as_str()).is_file() {
+            problems.push(format!(
+                "BOOKLET_FONT should be the path of a TrueType font, \"{}\" is not a file",
+                font
+            ));
+        }
+    }
+    if let Ok(sink) = var("EVENT_SINK") {
+        if !sink.trim().is_empty() && get_event_sink().is_none() {
+            problems.push(format!(
+                "EVENT_SINK should be an mqtt://, nats:// or http(s):// URL, not \"{}\"",
+                sink
+            ));
+        }
+    }
+    for webhook in var("MODERATION_WEBHOOKS").unwrap_or_default().split(',') {
+        let valid = Url::parse(webhook.trim())
+            .is_ok_and(|webhook| matches!(webhook.scheme(), "http" | "https"));
+        if !webhook.trim().is_empty() && !valid {
+            problems.push(format!(
+                "MODERATION_WEBHOOKS should only list http(s):// URLs, not \"{}\"",
+                webhook.trim()
+            ));
+        }
+    }
+    if let Ok(addr) = var("API_ADDR") {
+        if addr.parse::<SocketAddr>().is_err() {
+            problems.push(format!(
+                "API_ADDR should be an address like 127.0.0.1:8080, not \"{}\"",
+                addr
+            ));
+        } else if var("API_TOKEN").map_or(true, |token| token.is_empty()) {
+            problems
+                .push("API_ADDR is set, but the HTTP API needs an API_TOKEN as well".to_string());
+        }
+    }
+    if let Ok(homeserver) = var("MATRIX_HOMESERVER") {
+        let valid = Url::parse(homeserver.trim())
+            .is_ok_and(|homeserver| matches!(homeserver.scheme(), "http" | "https"));
+        if !valid {
+            problems.push(format!(
+                "MATRIX_HOMESERVER should be an http(s):// URL, not \"{}\"",
+                homeserver
+            ));
+        } else if var("MATRIX_TOKEN").map_or(true, |token| token.is_empty()) {
+            problems.push(
+                "MATRIX_HOMESERVER is set, but the Matrix frontend needs a MATRIX_TOKEN as well"
+                    .to_string(),
+            );
+        }
+    }
+    if get_matrix_config().is_some() && cfg!(not(feature = "matrix")) {
+        problems.push(
+            "MATRIX_HOMESERVER is set, but the bot was built without `--features matrix`"
+                .to_string(),
+        );
+    }
+    if get_discord_token().is_some() && cfg!(not(feature = "discord")) {
+        problems.push(
+            "DISCORD_TOKEN is set, but the bot was built without `--features discord`".to_string(),
+        );
+    }
+    problems
+}
+
+// How often connecting to MySQL is tried on startup, configured in DB_CONNECT_ATTEMPTS
+// Containers are often started before the database is ready
+pub fn db_connect_attempts() -> u32 {
+    var("DB_CONNECT_ATTEMPTS")
+        .ok()
+        .and_then(|attempts| attempts.trim().parse().ok())
+        .filter(|attempts| *attempts > 0)
+        .unwrap_or(10)
+}
@@ -0,0 +1,150 @@
+use crate::config::{auto_migrate, db_connect_attempts};
+use crate::error::BotError;
+use anyhow::{anyhow, Error};
+use diesel::migration::MigrationSource;
+use diesel::mysql::Mysql;
+use diesel::prelude::*;
+use diesel_async::async_connection_wrapper::AsyncConnectionWrapper;
+use diesel_async::pooled_connection::bb8::{Pool, PooledConnection};
+use diesel_async::pooled_connection::AsyncDieselConnectionManager;
+use diesel_async::AsyncMysqlConnection;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use std::env;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
+
+//
+// Helper functions for database connection
+//
+
+// Queries are awaited (with diesel-async) on connections from a pool, so that a slow query
+// holds up neither the other updates nor the other frontends
+// Migrations and the maintenance commands run synchronously on a connection of their own, which
+// waits for the queries on a runtime of its own, so it must not be used from async code
+pub type SyncConnection = AsyncConnectionWrapper<AsyncMysqlConnection>;
+
+static MIGRATED: OnceLock<()> = OnceLock::new();
+
+// The pool of connections to DATABASE_URL
+// Pending migrations are run on the first connect of the process, unless AUTO_MIGRATE=false
+// In that case they are run with `omikuji_bot migrate`
+pub async fn establish_connection() -> Database {
+    if auto_migrate() {
+        tokio::task::spawn_blocking(|| {
+            MIGRATED.get_or_init(|| {
+                let mut connection = connect();
+                connection
+                    .run_pending_migrations(MIGRATIONS)
+                    .expect("Failed to run migrations");
+            })
+        })
+        .await
+        .expect("Failed to run migrations");
+    }
+    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let manager = AsyncDieselConnectionManager::<AsyncMysqlConnection>::new(database_url);
+    // Connections are checked before they are handed out, and replaced once MySQL has dropped
+    // them, e.g. after wait_timeout
+    let pool = Pool::builder()
+        .build(manager)
+        .await
+        .expect("Failed to set up the MySQL connection pool");
+    println!("MySQL connection pool is set up");
+    Database { pool }
+}
+
+pub fn connect() -> SyncConnection {
+    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let connection = SyncConnection::establish(&database_url)
+        .unwrap_or_else(|_| panic!("Error connecting to {}", database_url));
+    println!("MySQL connection is established");
+    connection
+}
+
+// Connect to the database server, retrying with exponential backoff, e.g. while it is starting
+// up along with the bot or after it has restarted
+// Returns an error (instead of panicking) once all attempts have failed
+pub fn connect_with_retry() -> Result<SyncConnection, Error> {
+    let database_url = env::var("DATABASE_URL")?;
+    let attempts = db_connect_attempts();
+    let mut delay = Duration::from_secs(1);
+    for attempt in 1..=attempts {
+        match SyncConnection::establish(&database_url) {
+            Ok(connection) => return Ok(connection),
+            Err(e) if attempt < attempts => {
+                println!(
+                    "MySQL is not ready ({}), retrying in {}s ({}/{})",
+                    e,
+                    delay.as_secs(),
+                    attempt,
+                    attempts
+                );
+                thread::sleep(delay);
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            }
+            Err(e) => {
+                return Err(anyhow!(
+                    "Could not connect to MySQL after {} attempts: {}\nCheck DATABASE_URL and that the database server is reachable",
+                    attempts,
+                    e
+                ))
+            }
+        }
+    }
+    Err(anyhow!("DB_CONNECT_ATTEMPTS must be at least 1"))
+}
+
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+// Connections to MySQL, shared by the repositories of all tenants in a frontend
+#[derive(Clone)]
+pub struct Database {
+    pool: Pool<AsyncMysqlConnection>,
+}
+
+impl Database {
+    // Waits for a free connection if all of them are in use
+    pub async fn connection(&self) -> Result<PooledConnection<'_, AsyncMysqlConnection>, BotError> {
+        self.pool
+            .get()
+            .await
+            .map_err(|e| BotError::Database(Box::new(e)))
+    }
+}
+
+//
+// Migrations run from the command line
+//
+
+// Run all pending migrations, printing each of them
+pub fn run_migrations(connection: &mut SyncConnection) -> Result<(), Error> {
+    for version in connection
+        .run_pending_migrations(MIGRATIONS)
+        .map_err(|e| anyhow!(e))?
+    {
+        println!("Ran migration {}", version);
+    }
+    Ok(())
+}
+
+// Revert the latest migration that has been run, returning its name (None if there is none)
+pub fn revert_latest_migration(connection: &mut SyncConnection) -> Result<Option<String>, Error> {
+    let applied = connection.applied_migrations().map_err(|e| anyhow!(e))?;
+    if applied.is_empty() {
+        return Ok(None);
+    }
+    let version = connection
+        .revert_last_migration(MIGRATIONS)
+        .map_err(|e| anyhow!(e))?;
+    // Versions are the part of the directory name before the first '_'
+    let name = MigrationSource::<Mysql>::migrations(&MIGRATIONS)
+        .map_err(|e| anyhow!(e))?
+        .iter()
+        .map(|migration| migration.name().to_string())
+        .find(|name| name.split('_').next() == Some(version.to_string().as_str()))
+        .unwrap_or_else(|| version.to_string());
+    Ok(Some(name))
+}
@@ -0,0 +1,281 @@
+use crate::db::Database;
+use crate::error::BotError;
+use crate::models::OmikujiMessage;
+use crate::repository::{DieselRepository, DraftStore};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use strum_macros::EnumString;
+
+// Where drafts are kept between updates, configured in DRAFT_STORE (memory if unset)
+#[derive(EnumString, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum DraftBackend {
+    // Only in the memory of the process, so each user has to stick to one instance of the bot
+    Memory,
+    // In the drafts table, shared by all instances using the same database
+    MySql,
+    // In a Redis hash, shared by all instances using the same Redis server (see REDIS_URL)
+    // Only available when built with the `redis` feature
+    Redis,
+}
+
+// The draft store of a backend, None for Memory, where the store passed to the handlers is
+// all there is
+pub fn open<'a>(
+    backend: DraftBackend,
+    database: &'a Database,
+    tenant: &str,
+) -> Result<Option<Box<dyn DraftStore + 'a>>, BotError> {
+    Ok(match backend {
+        DraftBackend::Memory => None,
+        DraftBackend::MySql => Some(Box::new(DieselRepository::new(database, tenant))),
+        DraftBackend::Redis => Some(open_redis(tenant)?),
+    })
+}
+
+#[cfg(feature = "redis")]
+fn open_redis(tenant: &str) -> Result<Box<dyn DraftStore>, BotError> {
+    let url = crate::config::get_redis_url()
+        .ok_or_else(|| BotError::State(String::from("REDIS_URL is not set")))?;
+    Ok(Box::new(redis_store::RedisDraftStore::connect(
+        url.as_str(),
+        tenant,
+    )?))
+}
+
+#[cfg(not(feature = "redis"))]
+fn open_redis(_tenant: &str) -> Result<Box<dyn DraftStore>, BotError> {
+    Err(BotError::State(String::from(
+        "DRAFT_STORE=redis needs the bot to be built with `--features redis`",
+    )))
+}
+
+//
+// Keeping the store of the handlers in sync
+//
+
+// With a shared draft store, the draft of the user is loaded before each of their updates and
+// saved after it, so that any instance of the bot can handle their next update
+
+// The stored draft of the user, replacing whatever this instance had for them
+pub async fn load_draft_into(
+    drafts: &dyn DraftStore,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    tg_id: i64,
+) -> Result<(), BotError> {
+    match drafts.load_draft(tg_id).await? {
+        Some(draft) => store.insert(tg_id, draft),
+        None => store.remove(&tg_id),
+    };
+    Ok(())
+}
+
+// The draft of the user as the handlers left it, deleted if they saved or cancelled it
+pub async fn save_draft_from(
+    drafts: &dyn DraftStore,
+    store: &HashMap<i64, OmikujiMessage>,
+    tg_id: i64,
+) -> Result<(), BotError> {
+    match store.get(&tg_id) {
+        Some(draft) => drafts.save_draft(tg_id, draft).await,
+        None => drafts.delete_draft(tg_id).await,
+    }
+}
+
+// The revision each of the loaded drafts was at, by Telegram ID
+pub type Revisions = HashMap<i64, NaiveDateTime>;
+
+// All stored drafts, for jobs looking at every draft (e.g. reminders), along with their revisions
+pub async fn load_drafts(
+    drafts: &dyn DraftStore,
+) -> Result<(HashMap<i64, OmikujiMessage>, Revisions), BotError> {
+    let mut store = HashMap::new();
+    let mut revisions = HashMap::new();
+    for (tg_id, revision, draft) in drafts.load_drafts().await? {
+        store.insert(tg_id, draft);
+        revisions.insert(tg_id, revision);
+    }
+    Ok((store, revisions))
+}
+
+// Only the drafts a job changed are saved, and only those no other instance has saved or
+// deleted since they were loaded (the user is busy with them again, so the change is moot)
+pub async fn save_changed_drafts(
+    drafts: &dyn DraftStore,
+    store: &HashMap<i64, OmikujiMessage>,
+    revisions: &Revisions,
+    changed: &[i64],
+) -> Result<(), BotError> {
+    for tg_id in changed {
+        if let (Some(draft), Some(revision)) = (store.get(tg_id), revisions.get(tg_id)) {
+            drafts.save_draft_if(*tg_id, draft, *revision).await?;
+        }
+    }
+    Ok(())
+}
+
+//
+// Encoding
+//
+
+// Drafts are stored as JSON, along with the fields which saved strips keep in their own columns
+#[derive(Serialize, Deserialize)]
+struct StoredDraft<M> {
+    message: M,
+    community_id: Option<i64>,
+    touched_at: Option<String>,
+    reminded: bool,
+    anonymous: bool,
+    expires_in: Option<i64>,
+    editing: Option<u32>,
+}
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f";
+
+pub fn encode(draft: &OmikujiMessage) -> Result<String, BotError> {
+    Ok(serde_json::to_string(&StoredDraft {
+        message: draft,
+        community_id: draft.community_id,
+        touched_at: draft
+            .touched_at
+            .map(|touched_at| touched_at.format(TIME_FORMAT).to_string()),
+        reminded: draft.reminded,
+        anonymous: draft.anonymous,
+        expires_in: draft.expires_in,
+        editing: draft.editing,
+    })?)
+}
+
+pub fn decode(text: &str) -> Result<OmikujiMessage, BotError> {
+    let stored: StoredDraft<OmikujiMessage> = serde_json::from_str(text)?;
+    let mut draft = stored.message;
+    draft.community_id = stored.community_id;
+    draft.touched_at = match stored.touched_at {
+        Some(touched_at) => Some(
+            NaiveDateTime::parse_from_str(touched_at.as_str(), TIME_FORMAT)
+                .map_err(|e| BotError::State(format!("Draft touched at {}: {}", touched_at, e)))?,
+        ),
+        None => None,
+    };
+    draft.reminded = stored.reminded;
+    draft.anonymous = stored.anonymous;
+    draft.expires_in = stored.expires_in;
+    draft.editing = stored.editing;
+    Ok(draft)
+}
+
+//
+// Redis implementation
+//
+
+#[cfg(feature = "redis")]
+mod redis_store {
+    use super::{decode, encode, TIME_FORMAT};
+    use crate::error::BotError;
+    use crate::models::OmikujiMessage;
+    use crate::repository::DraftStore;
+    use async_trait::async_trait;
+    use chrono::NaiveDateTime;
+    use redis::Commands;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    // Drafts of a tenant are kept in one hash, e.g. "omikuji:default:drafts", by Telegram ID
+    // Their revisions are kept in another, e.g. "omikuji:default:draft_revisions"
+    pub struct RedisDraftStore {
+        connection: RefCell<redis::Connection>,
+        key: String,
+        revisions_key: String,
+    }
+
+    impl RedisDraftStore {
+        pub fn connect(url: &str, tenant: &str) -> Result<Self, BotError> {
+            let client = redis::Client::open(url)?;
+            Ok(RedisDraftStore {
+                connection: RefCell::new(client.get_connection()?),
+                key: format!("omikuji:{}:drafts", tenant),
+                revisions_key: format!("omikuji:{}:draft_revisions", tenant),
+            })
+        }
+    }
+
+    fn new_revision() -> String {
+        chrono::Local::now()
+            .naive_local()
+            .format(TIME_FORMAT)
+            .to_string()
+    }
+
+    #[async_trait(?Send)]
+    impl DraftStore for RedisDraftStore {
+        async fn load_draft(&self, tg_id: i64) -> Result<Option<OmikujiMessage>, BotError> {
+            let text: Option<String> = self.connection.borrow_mut().hget(&self.key, tg_id)?;
+            text.map(|text| decode(text.as_str())).transpose()
+        }
+
+        async fn save_draft(&self, tg_id: i64, draft: &OmikujiMessage) -> Result<(), BotError> {
+            let text = encode(draft)?;
+            redis::pipe()
+                .atomic()
+                .hset(&self.key, tg_id, text)
+                .hset(&self.revisions_key, tg_id, new_revision())
+                .query::<()>(&mut *self.connection.borrow_mut())?;
+            Ok(())
+        }
+
+        async fn delete_draft(&self, tg_id: i64) -> Result<(), BotError> {
+            redis::pipe()
+                .atomic()
+                .hdel(&self.key, tg_id)
+                .hdel(&self.revisions_key, tg_id)
+                .query::<()>(&mut *self.connection.borrow_mut())?;
+            Ok(())
+        }
+
+        // Drafts saved without a revision are left out, as they cannot be updated safely
+        async fn load_drafts(&self) -> Result<Vec<(i64, NaiveDateTime, OmikujiMessage)>, BotError> {
+            let mut connection = self.connection.borrow_mut();
+            let texts: HashMap<i64, String> = connection.hgetall(&self.key)?;
+            let revisions: HashMap<i64, String> = connection.hgetall(&self.revisions_key)?;
+            texts
+                .into_iter()
+                .filter_map(|(tg_id, text)| {
+                    let revision =
+                        NaiveDateTime::parse_from_str(revisions.get(&tg_id)?, TIME_FORMAT).ok()?;
+                    Some((tg_id, revision, text))
+                })
+                .map(|(tg_id, revision, text)| Ok((tg_id, revision, decode(text.as_str())?)))
+                .collect()
+        }
+
+        async fn save_draft_if(
+            &self,
+            tg_id: i64,
+            draft: &OmikujiMessage,
+            revision: NaiveDateTime,
+        ) -> Result<bool, BotError> {
+            // The revision is watched, so that the draft is not replaced if it is saved in between
+            let expected = revision.format(TIME_FORMAT).to_string();
+            let text = encode(draft)?;
+            let saved = redis::transaction(
+                &mut *self.connection.borrow_mut(),
+                &[&self.revisions_key],
+                |connection, pipe| {
+                    let current: Option<String> = connection.hget(&self.revisions_key, tg_id)?;
+                    if current.as_ref() != Some(&expected) {
+                        return Ok(Some(false));
+                    }
+                    Ok(pipe
+                        .hset(&self.key, tg_id, text.as_str())
+                        .ignore()
+                        .hset(&self.revisions_key, tg_id, new_revision())
+                        .ignore()
+                        .query::<Option<()>>(connection)?
+                        .map(|()| true))
+                },
+            )?;
+            Ok(saved)
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use strum_macros::IntoStaticStr;
+use teloxide_core::RequestError;
+use thiserror::Error;
+
+// Everything that can go wrong in the library, by kind, so that the pipeline can tell the user
+// what happened and label metrics without looking at the message
+// The binary (and the CLI) wraps these in anyhow
+#[derive(Error, Debug, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum BotError {
+    // A request to the Bot API failed, including users having blocked the bot (see is_blocked)
+    #[error("Telegram request failed: {0}")]
+    Telegram(#[from] RequestError),
+    // A query failed, in MySQL or the draft store
+    #[error("Database error: {0}")]
+    Database(#[source] Box<dyn std::error::Error + Send + Sync>),
+    // The input of the user cannot be accepted, the message is shown to them
+    #[error("{0}")]
+    Validation(String),
+    // Stored data or the configuration is not what the bot expects, e.g. a strip which cannot be
+    // parsed any more
+    #[error("Unexpected state: {0}")]
+    State(String),
+    // Something the user asked for does not exist (any more), e.g. "Omikuji 3"
+    #[error("{0} cannot be found")]
+    NotFound(String),
+    // The text-to-speech service of TTS_URL failed
+    #[error("Text-to-speech failed: {0}")]
+    Speech(#[from] reqwest::Error),
+    // The Matrix homeserver of MATRIX_HOMESERVER failed
+    #[error("Matrix request failed: {0}")]
+    Matrix(#[source] reqwest::Error),
+}
+
+impl BotError {
+    // Label for metrics and logs, e.g. "not_found"
+    pub fn kind(&self) -> &'static str {
+        self.into()
+    }
+}
+
+impl From<diesel::result::Error> for BotError {
+    fn from(error: diesel::result::Error) -> Self {
+        BotError::Database(Box::new(error))
+    }
+}
+
+#[cfg(feature = "redis")]
+impl From<redis::RedisError> for BotError {
+    fn from(error: redis::RedisError) -> Self {
+        BotError::Database(Box::new(error))
+    }
+}
+
+// JSON is what strips and drafts are stored as, so failing to read it back is a broken state
+impl From<serde_json::Error> for BotError {
+    fn from(error: serde_json::Error) -> Self {
+        BotError::State(error.to_string())
+    }
+}
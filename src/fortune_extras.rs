@@ -0,0 +1,174 @@
+use crate::models::{Language, OmikujiClass};
+use chrono::{Datelike, NaiveDate};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+// Lucky colors, using the traditional Japanese names
+#[derive(EnumIter, Debug, Clone, Copy, PartialEq)]
+pub enum LuckyColor {
+    Red,
+    Blue,
+    Green,
+    Yellow,
+    White,
+    Black,
+    Purple,
+    Pink,
+    Gold,
+    Silver,
+}
+
+impl LuckyColor {
+    pub fn name(&self, language: Language) -> &'static str {
+        use LuckyColor::*;
+        match language {
+            Language::English => match self {
+                Red => "Red",
+                Blue => "Blue",
+                Green => "Green",
+                Yellow => "Yellow",
+                White => "White",
+                Black => "Black",
+                Purple => "Purple",
+                Pink => "Pink",
+                Gold => "Gold",
+                Silver => "Silver",
+            },
+            Language::Japanese => match self {
+                Red => "赤",
+                Blue => "青",
+                Green => "緑",
+                Yellow => "黄",
+                White => "白",
+                Black => "黒",
+                Purple => "紫",
+                Pink => "桃",
+                Gold => "金",
+                Silver => "銀",
+            },
+        }
+    }
+}
+
+// Auspicious directions (方角, hōgaku)
+#[derive(EnumIter, Debug, Clone, Copy, PartialEq)]
+pub enum LuckyDirection {
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+}
+
+impl LuckyDirection {
+    // The direction of a bearing in degrees, clockwise from north
+    pub fn from_bearing(bearing: f64) -> LuckyDirection {
+        let directions: Vec<LuckyDirection> = LuckyDirection::iter().collect();
+        let index = ((bearing + 22.5).rem_euclid(360.0) / 45.0) as usize;
+        directions[index % directions.len()]
+    }
+
+    pub fn name(&self, language: Language) -> &'static str {
+        use LuckyDirection::*;
+        match language {
+            Language::English => match self {
+                North => "North",
+                NorthEast => "Northeast",
+                East => "East",
+                SouthEast => "Southeast",
+                South => "South",
+                SouthWest => "Southwest",
+                West => "West",
+                NorthWest => "Northwest",
+            },
+            Language::Japanese => match self {
+                North => "北",
+                NorthEast => "北東",
+                East => "東",
+                SouthEast => "南東",
+                South => "南",
+                SouthWest => "南西",
+                West => "西",
+                NorthWest => "北西",
+            },
+        }
+    }
+}
+
+// Embellishments appended to a drawn strip
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FortuneExtras {
+    pub number: u8,
+    pub color: LuckyColor,
+    pub direction: LuckyDirection,
+}
+
+impl FortuneExtras {
+    // The same user drawing the same strip on the same day always gets the same extras
+    pub fn generate(tg_id: i64, date: NaiveDate, omikuji_id: u32) -> FortuneExtras {
+        let mut rng = seeded_rng(tg_id, date, Some(omikuji_id));
+        let colors: Vec<LuckyColor> = LuckyColor::iter().collect();
+        let directions: Vec<LuckyDirection> = LuckyDirection::iter().collect();
+        FortuneExtras {
+            number: rng.gen_range(1, 100),
+            color: colors[rng.gen_range(0, colors.len())],
+            direction: directions[rng.gen_range(0, directions.len())],
+        }
+    }
+
+    // Rendered like the sections of a strip
+    pub fn render(&self, language: Language) -> String {
+        let labels = match language {
+            Language::English => ["Lucky number", "Lucky color", "Lucky direction"],
+            Language::Japanese => ["ラッキーナンバー", "ラッキーカラー", "吉方位"],
+        };
+        format!(
+            "*{}*: {}\n*{}*: {}\n*{}*: {}",
+            labels[0],
+            self.number,
+            labels[1],
+            self.color.name(language),
+            labels[2],
+            self.direction.name(language)
+        )
+    }
+}
+
+// Class of the personal fortune of a user for a day, the same however often it is asked for
+pub fn daily_class(tg_id: i64, date: NaiveDate) -> OmikujiClass {
+    pick_class(&mut seeded_rng(tg_id, date, None))
+}
+
+// Class of the fortune of a place (see geo::Point::cell) for a user on a day
+// The cell is seeded like a strip, sharing a seed with the extras of a strip does no harm
+pub fn place_class(tg_id: i64, date: NaiveDate, cell: u32) -> OmikujiClass {
+    pick_class(&mut seeded_rng(tg_id, date, Some(cell)))
+}
+
+fn pick_class(rng: &mut StdRng) -> OmikujiClass {
+    let classes: Vec<OmikujiClass> = OmikujiClass::iter()
+        .filter(|class| class.rank().is_some())
+        .collect();
+    let index = rng.gen_range(0, classes.len());
+    classes
+        .into_iter()
+        .nth(index)
+        .unwrap_or(OmikujiClass::Other)
+}
+
+// Random numbers determined by the user, the day and the strip (if any)
+fn seeded_rng(tg_id: i64, date: NaiveDate, omikuji_id: Option<u32>) -> StdRng {
+    let mut seed = [0u8; 32];
+    seed[..8].copy_from_slice(&tg_id.to_le_bytes());
+    seed[8..12].copy_from_slice(&date.num_days_from_ce().to_le_bytes());
+    if let Some(omikuji_id) = omikuji_id {
+        seed[12..16].copy_from_slice(&omikuji_id.to_le_bytes());
+        seed[16] = 1;
+    }
+    StdRng::from_seed(seed)
+}
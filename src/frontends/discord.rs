@@ -0,0 +1,154 @@
+use crate::commands::Command;
+use crate::db::{establish_connection, Database};
+use crate::error::BotError;
+use crate::frontends::{handle, EventKind, IncomingEvent, OutgoingAction, Platform, Sender};
+use crate::models::Language;
+use crate::repository::DieselRepository;
+use serenity::all::{
+    Command as SlashCommand, CommandDataOptionValue, CommandInteraction, CommandOptionType,
+    Context, CreateCommand, CreateCommandOption, CreateInteractionResponse,
+    CreateInteractionResponseMessage, EventHandler, GatewayIntents, Interaction, Ready,
+};
+use serenity::async_trait;
+use serenity::Client;
+use strum::IntoEnumIterator;
+
+//
+// Discord bridge, so that the club's Discord server can draw from the same library as the
+// Telegram bot; built with `--features discord` and started if DISCORD_TOKEN is set
+// Only the shared commands (see Command::shared) are offered there, strips are still written in
+// Telegram
+//
+
+// Discord refuses messages longer than this
+const MAX_MESSAGE_CHARS: usize = 2000;
+
+struct Bridge {
+    // Like the HTTP API, the bridge has a pool of database connections of its own
+    database: Database,
+    tenant: String,
+}
+
+// Connect to Discord and serve the commands until the connection fails for good
+pub async fn serve(token: String, tenant: String) -> Result<(), serenity::Error> {
+    let bridge = Bridge {
+        database: establish_connection().await,
+        tenant,
+    };
+    // Slash commands arrive as interactions, which need no privileged intents
+    let mut client = Client::builder(token, GatewayIntents::empty())
+        .event_handler(bridge)
+        .await?;
+    client.start().await
+}
+
+#[async_trait]
+impl EventHandler for Bridge {
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        println!("Discord bridge is {}", ready.user.name);
+        let commands = Command::iter()
+            .filter(Command::shared)
+            .map(|command| {
+                let slash_command = CreateCommand::new(command.name())
+                    .description(command.description(Language::English))
+                    .description_localized("ja", command.description(Language::Japanese));
+                match option(command) {
+                    Some(option) => slash_command.add_option(option),
+                    None => slash_command,
+                }
+            })
+            .collect();
+        if let Err(e) = SlashCommand::set_global_commands(&ctx.http, commands).await {
+            println!("Failed to register the Discord commands: {}", e);
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        let command = match interaction {
+            Interaction::Command(command) => command,
+            _ => return,
+        };
+        let actions = match self.handle(&command).await {
+            Ok(actions) => actions,
+            Err(e) => {
+                println!("Failed to answer Discord user {}: {}", command.user.id, e);
+                vec![OutgoingAction::Reply {
+                    text: String::from("Sorry, something went wrong. Please try again later."),
+                }]
+            }
+        };
+        // An interaction is answered once, further replies would be follow-ups
+        let text = match actions.into_iter().next() {
+            Some(OutgoingAction::Reply { text }) => discord_markdown(text.as_str()),
+            None => return,
+        };
+        let message = CreateInteractionResponseMessage::new().content(text);
+        let response = CreateInteractionResponse::Message(message);
+        if let Err(e) = command.create_response(&ctx.http, response).await {
+            println!("Failed to answer Discord user {}: {}", command.user.id, e);
+        }
+    }
+}
+
+// Discord has no free text after slash commands, so their arguments are options
+fn option(command: Command) -> Option<CreateCommandOption> {
+    match command {
+        Command::Slip => Some(
+            CreateCommandOption::new(CommandOptionType::Integer, "number", "Number of the slip")
+                .description_localized("ja", "おみくじの番号")
+                .min_int_value(1)
+                .required(true),
+        ),
+        _ => None,
+    }
+}
+
+impl Bridge {
+    // Users are recorded by their Discord ID, which is far above the IDs of Telegram users
+    async fn handle(&self, command: &CommandInteraction) -> Result<Vec<OutgoingAction>, BotError> {
+        let event = IncomingEvent {
+            from: Sender {
+                platform: Platform::Discord,
+                id: command.user.id.get() as i64,
+                name: format!("<@{}>", command.user.id),
+                username: Some(command.user.name.clone()),
+                language_code: Some(command.locale.clone()),
+            },
+            community: command.guild_id.map(|guild| guild.get() as i64),
+            kind: EventKind::Command {
+                name: command.data.name.clone(),
+                // The options as the arguments of the command in Telegram, e.g. "12" of /slip 12
+                args: command
+                    .data
+                    .options
+                    .iter()
+                    .filter_map(|option| match &option.value {
+                        CommandDataOptionValue::Integer(value) => Some(value.to_string()),
+                        CommandDataOptionValue::String(value) => Some(value.clone()),
+                        _ => None,
+                    })
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            },
+        };
+        let database = self.database.clone();
+        let tenant = self.tenant.clone();
+        // The futures of the repository are not Send, so the handlers run on a thread of their
+        // own while the gateway goes on
+        tokio::task::spawn_blocking(move || {
+            let repository = DieselRepository::new(&database, tenant.as_str());
+            tokio::runtime::Handle::current().block_on(handle(&event, &repository))
+        })
+        .await
+        .map_err(|e| BotError::State(format!("Discord command panicked: {}", e)))?
+    }
+}
+
+// Strips are written in the Markdown of Telegram, where *bold* is **bold** on Discord
+pub fn discord_markdown(text: &str) -> String {
+    let text = text.replace('*', "**");
+    if text.chars().count() <= MAX_MESSAGE_CHARS {
+        return text;
+    }
+    text.chars().take(MAX_MESSAGE_CHARS - 1).collect::<String>() + "…"
+}
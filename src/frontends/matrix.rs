@@ -0,0 +1,279 @@
+use crate::db::establish_connection;
+use crate::error::BotError;
+use crate::frontends::{handle, EventKind, IncomingEvent, OutgoingAction, Platform, Sender};
+use crate::repository::{DieselRepository, Repository};
+use crate::tts::speech_text;
+use chrono::Local;
+use pulldown_cmark::{Event, Parser};
+use serde_json::{json, Value};
+use std::cell::Cell;
+use std::time::Duration;
+use url::Url;
+
+//
+// Matrix frontend, so that Matrix rooms can draw from the same library as the Telegram bot
+// The bot talks to its homeserver through the client-server API: it joins the rooms it is
+// invited to and answers the commands of the frontends (e.g. !draw) there
+//
+
+// How long a sync waits on the homeserver for something to happen
+const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+// Pause after a failed sync, e.g. while the homeserver restarts
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+// Only messages are of interest, and no presence or typing notifications
+const SYNC_FILTER: &str = r#"{"presence":{"types":[]},"account_data":{"types":[]},"room":{"timeline":{"types":["m.room.message"]},"ephemeral":{"types":[]},"state":{"types":[]}}}"#;
+
+pub struct MatrixClient {
+    homeserver: Url,
+    token: String,
+    http: reqwest::Client,
+    // Own user ID, e.g. @omikuji:example.org, so that the bot does not read its own messages
+    user_id: String,
+    // Messages are sent with transaction IDs, which must not repeat for the access token
+    session: i64,
+    sent: Cell<u64>,
+}
+
+impl MatrixClient {
+    pub fn new(homeserver: Url, token: String, user_id: String) -> Self {
+        MatrixClient {
+            homeserver,
+            token,
+            http: reqwest::Client::new(),
+            user_id,
+            session: Local::now().timestamp_millis(),
+            sent: Cell::new(0),
+        }
+    }
+
+    // Log in with the access token, which already says whose it is
+    pub async fn connect(homeserver: Url, token: String) -> Result<Self, BotError> {
+        let mut client = MatrixClient::new(homeserver, token, String::new());
+        let whoami = client.endpoint(&["account", "whoami"])?;
+        let whoami: Value = client
+            .call(client.http.get(whoami))
+            .await?
+            .json()
+            .await
+            .map_err(BotError::Matrix)?;
+        client.user_id = whoami
+            .get("user_id")
+            .and_then(Value::as_str)
+            .ok_or_else(|| BotError::State("Matrix homeserver did not say who we are".into()))?
+            .to_string();
+        Ok(client)
+    }
+
+    // Wait for what happened since the last sync (or get the current state, on the first)
+    pub async fn sync(&self, since: Option<&str>) -> Result<Value, BotError> {
+        let mut url = self.endpoint(&["sync"])?;
+        url.query_pairs_mut()
+            .append_pair("filter", SYNC_FILTER)
+            .append_pair("timeout", SYNC_TIMEOUT.as_millis().to_string().as_str());
+        if let Some(since) = since {
+            url.query_pairs_mut().append_pair("since", since);
+        }
+        let request = self.http.get(url).timeout(SYNC_TIMEOUT * 2);
+        self.call(request)
+            .await?
+            .json()
+            .await
+            .map_err(BotError::Matrix)
+    }
+
+    // Join the rooms the bot was invited to
+    // Failures are logged room by room, nothing is tried again as the next sync starts after them
+    pub async fn join_invited(&self, sync: &Value) {
+        if let Some(invites) = sync.pointer("/rooms/invite").and_then(Value::as_object) {
+            for room in invites.keys() {
+                if let Err(e) = self.join(room.as_str()).await {
+                    println!("Failed to join {} on Matrix: {}", room, e);
+                }
+            }
+        }
+    }
+
+    async fn join(&self, room: &str) -> Result<(), BotError> {
+        let join = self.endpoint(&["join", room])?;
+        self.call(self.http.post(join).json(&json!({}))).await?;
+        Ok(())
+    }
+
+    // Answer the commands given in the rooms the bot is in
+    // Like joins, failures are logged command by command, so that they do not drop the others
+    pub async fn answer(&self, sync: &Value, repository: &dyn Repository) {
+        let rooms = match sync.pointer("/rooms/join").and_then(Value::as_object) {
+            Some(rooms) => rooms,
+            None => return,
+        };
+        for (room, joined) in rooms {
+            let events = joined
+                .pointer("/timeline/events")
+                .and_then(Value::as_array)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            for event in events.iter().filter_map(|event| self.incoming(event)) {
+                if let Err(e) = self.answer_event(room.as_str(), &event, repository).await {
+                    println!(
+                        "Failed to answer {} in {} on Matrix: {}",
+                        event.from.name, room, e
+                    );
+                }
+            }
+        }
+    }
+
+    async fn answer_event(
+        &self,
+        room: &str,
+        event: &IncomingEvent,
+        repository: &dyn Repository,
+    ) -> Result<(), BotError> {
+        for action in handle(event, repository).await? {
+            self.perform(room, action).await?;
+        }
+        Ok(())
+    }
+
+    // A command in a message like !draw (or !おみくじ, for Japanese), /draw works as well where
+    // clients let it through
+    fn incoming(&self, event: &Value) -> Option<IncomingEvent> {
+        let sender = event.get("sender").and_then(Value::as_str)?;
+        if event.get("type").and_then(Value::as_str) != Some("m.room.message")
+            || sender == self.user_id
+        {
+            return None;
+        }
+        let body = event.pointer("/content/body").and_then(Value::as_str)?;
+        let (command, args) = body.trim().split_once(' ').unwrap_or((body.trim(), ""));
+        let (name, language_code) = match command.strip_prefix(&['!', '/'][..])? {
+            "おみくじ" => ("draw", Some(String::from("ja"))),
+            name => (name, None),
+        };
+        Some(IncomingEvent {
+            from: Sender {
+                platform: Platform::Matrix,
+                id: matrix_user(sender),
+                name: sender.to_string(),
+                username: None,
+                language_code,
+            },
+            community: None,
+            kind: EventKind::Command {
+                name: name.to_string(),
+                args: args.trim().to_string(),
+            },
+        })
+    }
+
+    async fn perform(&self, room: &str, action: OutgoingAction) -> Result<(), BotError> {
+        let OutgoingAction::Reply { text } = action;
+        // Notices, as bots are not supposed to answer each other's
+        let content = json!({
+            "msgtype": "m.notice",
+            "body": speech_text(text.as_str()),
+            "format": "org.matrix.custom.html",
+            "formatted_body": html(text.as_str()),
+        });
+        self.sent.set(self.sent.get() + 1);
+        let transaction = format!("{}-{}", self.session, self.sent.get());
+        let send = self.endpoint(&[
+            "rooms",
+            room,
+            "send",
+            "m.room.message",
+            transaction.as_str(),
+        ])?;
+        self.call(self.http.put(send).json(&content)).await?;
+        Ok(())
+    }
+
+    // URL of a client-server API endpoint, with room IDs and the like escaped as path segments
+    fn endpoint(&self, path: &[&str]) -> Result<Url, BotError> {
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+            .map_err(|_| BotError::State(format!("{} is no homeserver URL", self.homeserver)))?
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3"].iter().chain(path));
+        Ok(url)
+    }
+
+    async fn call(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, BotError> {
+        request
+            .bearer_auth(self.token.as_str())
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(BotError::Matrix)
+    }
+}
+
+// Users are recorded with an ID derived from their Matrix user ID (FNV-1a), kept negative so
+// that it cannot be taken for a Telegram user
+pub fn matrix_user(user_id: &str) -> i64 {
+    let hash = user_id.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    -((hash >> 1) as i64) - 1
+}
+
+// Strips are written in the Markdown of Telegram, which Matrix clients get as HTML
+// Line breaks are kept as they are, and HTML in a strip is shown rather than interpreted
+fn html(markdown: &str) -> String {
+    let markdown = commonmark(markdown);
+    let events = Parser::new(markdown.as_str()).map(|event| match event {
+        Event::SoftBreak => Event::HardBreak,
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        event => event,
+    });
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events);
+    html
+}
+
+// *bold* of Telegram is **bold** in CommonMark, while _italic_ and escapes are the same
+fn commonmark(markdown: &str) -> String {
+    let mut converted = String::with_capacity(markdown.len());
+    let mut chars = markdown.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                converted.push(c);
+                converted.extend(chars.next());
+            }
+            '*' => converted.push_str("**"),
+            c => converted.push(c),
+        }
+    }
+    converted
+}
+
+// Sync with the homeserver until the process stops
+// Invitations are accepted even if they came in before the start, but draws asked for then are
+// not answered any more
+pub async fn serve(homeserver: Url, token: String, tenant: String) -> Result<(), BotError> {
+    let client = MatrixClient::connect(homeserver, token).await?;
+    println!("Matrix frontend is {}", client.user_id);
+    let database = establish_connection().await;
+    let mut since: Option<String> = None;
+    loop {
+        let sync = match client.sync(since.as_deref()).await {
+            Ok(sync) => sync,
+            Err(e) => {
+                println!("Failed to sync with the Matrix homeserver: {}", e);
+                tokio::time::sleep(RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let repository = DieselRepository::new(&database, tenant.as_str());
+        client.join_invited(&sync).await;
+        if since.is_some() {
+            client.answer(&sync, &repository).await;
+        }
+        since = sync
+            .get("next_batch")
+            .and_then(Value::as_str)
+            .map(String::from);
+    }
+}
@@ -0,0 +1,205 @@
+use crate::bot_api::{fake_message, BotApi};
+use crate::error::BotError;
+use crate::geo::Point;
+use crate::handlers;
+use crate::models::Language;
+use crate::repository::Repository;
+use async_trait::async_trait;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use teloxide_core::payloads::{
+    AnswerCallbackQuery, EditMessageReplyMarkup, EditMessageText, PinChatMessage, SendDocument,
+    SendMessage, SendPhoto, SendPoll, SendVoice, SetMyCommands, StopPoll,
+};
+use teloxide_core::types::{Message, Poll};
+
+#[cfg(feature = "discord")]
+pub mod discord;
+#[cfg(feature = "matrix")]
+pub mod matrix;
+pub mod telegram;
+
+//
+// Chat platforms the library is served on
+// Each frontend translates what happens on its platform into IncomingEvent, which the handlers
+// take without knowing the platform (see handlers::handle)
+// Replies are sent through BotApi, which the other frontends carry out as OutgoingAction
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Telegram,
+    Discord,
+    Matrix,
+}
+
+// The user an event came from
+#[derive(Debug, Clone)]
+pub struct Sender {
+    pub platform: Platform,
+    // Recorded instead of the Telegram ID, e.g. for draws; see the frontends how it is derived
+    pub id: i64,
+    // How the user is called, e.g. the full name on Telegram or a mention on Discord
+    pub name: String,
+    pub username: Option<String>,
+    // Language tag of the client, e.g. "ja", if the platform tells
+    pub language_code: Option<String>,
+}
+
+impl Sender {
+    pub fn language(&self) -> Language {
+        Language::detect(self.language_code.as_deref())
+    }
+}
+
+// Something a user did on one of the platforms
+#[derive(Debug, Clone)]
+pub struct IncomingEvent {
+    pub from: Sender,
+    // Group chat the event happened in, None for private chats
+    pub community: Option<i64>,
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventKind {
+    // A command without its prefix (/ or !), with whatever follows it
+    Command {
+        name: String,
+        args: String,
+    },
+    // Any other text, with the text of the bot's message it replies to (e.g. a prompt)
+    Text {
+        text: String,
+        reply_to: Option<String>,
+    },
+    // None if the platform sent no usable size of the photo
+    Photo {
+        file_id: Option<String>,
+    },
+    Location {
+        point: Point,
+    },
+    // A button pressed, with the chat and ID of the message it is attached to
+    Button {
+        data: String,
+        message: Option<(i64, i32)>,
+    },
+    // Anything else, e.g. a sticker
+    Unsupported,
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventKind::Command { name, args } if args.is_empty() => write!(f, "/{}", name),
+            EventKind::Command { name, args } => write!(f, "/{} {}", name, args),
+            EventKind::Text { text, .. } => write!(f, "{}", text),
+            EventKind::Button { data, .. } => write!(f, "Callback {}", data),
+            _ => write!(f, "Non-text message"),
+        }
+    }
+}
+
+// Something a frontend is asked to do in reply to an event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutgoingAction {
+    // A message to where the event came from, in the Markdown of Telegram which frontends
+    // convert to what their platform understands
+    Reply { text: String },
+}
+
+// Collects what the handlers send in reply to an event from a platform other than Telegram
+// Buttons, voices and the like have no counterpart there, so only texts (and captions) get
+// through
+#[derive(Default)]
+pub struct Replies {
+    actions: RefCell<Vec<OutgoingAction>>,
+}
+
+impl Replies {
+    fn reply(&self, text: String) {
+        self.actions
+            .borrow_mut()
+            .push(OutgoingAction::Reply { text });
+    }
+}
+
+#[async_trait(?Send)]
+impl BotApi for Replies {
+    async fn send_message(&self, request: SendMessage) -> Result<Message, BotError> {
+        let message = fake_message(&request.chat_id, &request.text, self.actions.borrow().len())?;
+        self.reply(request.text);
+        Ok(message)
+    }
+
+    async fn send_photo(&self, request: SendPhoto) -> Result<Message, BotError> {
+        let caption = request.caption.unwrap_or_default();
+        let message = fake_message(&request.chat_id, &caption, self.actions.borrow().len())?;
+        if !caption.is_empty() {
+            self.reply(caption);
+        }
+        Ok(message)
+    }
+
+    async fn send_voice(&self, _request: SendVoice) -> Result<(), BotError> {
+        Ok(())
+    }
+
+    async fn send_document(&self, _request: SendDocument) -> Result<(), BotError> {
+        Ok(())
+    }
+
+    async fn edit_reply_markup(&self, _request: EditMessageReplyMarkup) -> Result<(), BotError> {
+        Ok(())
+    }
+
+    async fn edit_text(&self, request: EditMessageText) -> Result<(), BotError> {
+        self.reply(request.text);
+        Ok(())
+    }
+
+    async fn answer_callback(&self, _request: AnswerCallbackQuery) -> Result<(), BotError> {
+        Ok(())
+    }
+
+    async fn set_my_commands(&self, _request: SetMyCommands) -> Result<(), BotError> {
+        Ok(())
+    }
+
+    async fn pin_message(&self, _request: PinChatMessage) -> Result<(), BotError> {
+        Ok(())
+    }
+
+    async fn send_poll(&self, request: SendPoll) -> Result<Message, BotError> {
+        Err(BotError::State(format!(
+            "Polls cannot be sent outside of Telegram: {}",
+            request.question
+        )))
+    }
+
+    async fn stop_poll(&self, _request: StopPoll) -> Result<Poll, BotError> {
+        Err(BotError::State(String::from(
+            "Polls cannot be stopped outside of Telegram",
+        )))
+    }
+}
+
+// Handle an event from a platform other than Telegram, returning what to reply
+// Drafts are only written on Telegram, so there are none to keep between the events
+pub async fn handle(
+    event: &IncomingEvent,
+    repository: &dyn Repository,
+) -> Result<Vec<OutgoingAction>, BotError> {
+    let replies = Replies::default();
+    let mut store = HashMap::new();
+    match handlers::handle(event, &replies, &mut store, repository).await {
+        Ok(()) => {}
+        // Like in the Telegram pipeline, errors about what the user asked for are told to them
+        Err(BotError::Validation(text)) => replies.reply(text),
+        Err(e @ BotError::NotFound(_)) => replies.reply(format!("{}.", e)),
+        Err(e) => return Err(e),
+    }
+    Ok(replies.actions.into_inner())
+}
@@ -0,0 +1,189 @@
+use crate::bot_api::BotApi;
+use crate::commands::Command;
+use crate::config::get_bot_username;
+use crate::drafts;
+use crate::error::BotError;
+use crate::frontends::{EventKind, IncomingEvent, Platform, Sender};
+use crate::geo::Point;
+use crate::handlers;
+use crate::middleware::Pipeline;
+use crate::models::OmikujiMessage;
+use crate::repository::{DraftStore, Repository};
+use crate::telegram_ext::{full_name, user_id};
+use std::collections::HashMap;
+use teloxide_core::types::{CallbackQuery, Message, UpdateKind, User};
+
+//
+// Telegram frontend, which dispatches the updates polled by the binary (or replayed from a log)
+// to the handlers
+//
+
+pub async fn handle_update(
+    kind: UpdateKind,
+    api: &dyn BotApi,
+    pipeline: &mut Pipeline,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    draft_store: Option<&dyn DraftStore>,
+) -> Result<(), BotError> {
+    let event = match kind {
+        UpdateKind::Message(message) => {
+            // Migrations are not from the user who triggered them, so they skip the pipeline
+            // They move the drafts of everyone in the group, of which only the moved ones are
+            // saved again
+            if let (Some(to), Some(draft_store)) = (message.migrate_to_chat_id(), draft_store) {
+                let (mut stored, revisions) = drafts::load_drafts(draft_store).await?;
+                migration_entry(&message, &mut stored, repository).await?;
+                let moved: Vec<i64> = stored
+                    .iter()
+                    .filter(|(_, draft)| draft.community_id == Some(to.0))
+                    .map(|(tg_id, _)| *tg_id)
+                    .collect();
+                return drafts::save_changed_drafts(draft_store, &stored, &revisions, &moved).await;
+            }
+            if migration_entry(&message, store, repository).await? {
+                return Ok(());
+            }
+            match message_event(&message) {
+                Some(event) => event,
+                None => return Ok(()),
+            }
+        }
+        UpdateKind::CallbackQuery(callback) => callback_event(&callback),
+        _ => {
+            // Unsupported message kind
+            println!("Unsupported update kind received!");
+            return Ok(());
+        }
+    };
+    let tg_id = event.from.id;
+    if let Some(draft_store) = draft_store {
+        drafts::load_draft_into(draft_store, store, tg_id).await?;
+    }
+    pipeline.handle(&event, api, store, repository).await?;
+    if let Some(draft_store) = draft_store {
+        drafts::save_draft_from(draft_store, store, tg_id).await?;
+    }
+    Ok(())
+}
+
+// Entry for a message, without the pipeline of middlewares
+pub async fn message_entry(
+    message: &Message,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    match message_event(message) {
+        Some(event) => handlers::handle(&event, api, store, repository).await,
+        None => Ok(()),
+    }
+}
+
+// Entry for a callback query (from inline keyboard buttons), without the pipeline of middlewares
+pub async fn callback_entry(
+    callback: &CallbackQuery,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    handlers::handle(&callback_event(callback), api, store, repository).await
+}
+
+// Entry for groups upgraded to supergroups, which get a new chat id
+// Returns false if the message is not about a migration
+pub async fn migration_entry(
+    message: &Message,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+) -> Result<bool, BotError> {
+    // Telegram sends a message to both chats, the one to the old group is enough
+    if message.migrate_from_chat_id().is_some() {
+        return Ok(true);
+    }
+    match message.migrate_to_chat_id() {
+        Some(to) => handlers::migrate(message.chat.id.0, to.0, store, repository).await?,
+        None => return Ok(false),
+    }
+    Ok(true)
+}
+
+pub fn sender(user: &User) -> Sender {
+    Sender {
+        platform: Platform::Telegram,
+        id: user_id(user),
+        name: full_name(user),
+        username: user.username.clone(),
+        language_code: user.language_code.clone(),
+    }
+}
+
+// The event of a message, None if there is nobody to reply to (e.g. a channel post) or it is a
+// command for another bot in the same group
+pub fn message_event(message: &Message) -> Option<IncomingEvent> {
+    let from = sender(message.from()?);
+    let kind = if let Some(data) = message.text() {
+        // We consider all messages starting with '/' as a command, as well as quick actions
+        // Arguments follow the command after a space, e.g. "/start draw" from a deep link
+        if let Some(name) = data.strip_prefix('/') {
+            let (name, args) = name.split_once(' ').unwrap_or((name, ""));
+            let name = Command::addressed_name(name, get_bot_username().as_deref())?;
+            EventKind::Command {
+                name: name.to_string(),
+                args: args.to_string(),
+            }
+        } else if let Some(command) = Command::from_quick_action(data) {
+            EventKind::Command {
+                name: command.name(),
+                args: String::new(),
+            }
+        } else {
+            // Only replies to the prompts of the bot are of interest
+            let reply_to = message
+                .reply_to_message()
+                .filter(|replied| replied.from().is_some_and(|user| user.is_bot))
+                .and_then(|replied| replied.text())
+                .map(String::from);
+            EventKind::Text {
+                text: data.to_string(),
+                reply_to,
+            }
+        }
+    } else if let Some(photo) = message.photo() {
+        EventKind::Photo {
+            file_id: photo.first().map(|size| size.file.id.clone()),
+        }
+    } else if let Some(location) = message.location() {
+        EventKind::Location {
+            point: Point::new(location.latitude, location.longitude),
+        }
+    } else {
+        EventKind::Unsupported
+    };
+    // Strips created (and drawn) in a group belong to the community of that group
+    let community = if message.chat.is_private() {
+        None
+    } else {
+        Some(message.chat.id.0)
+    };
+    Some(IncomingEvent {
+        from,
+        community,
+        kind,
+    })
+}
+
+// Buttons are only sent to private chats, so there is no community here
+pub fn callback_event(callback: &CallbackQuery) -> IncomingEvent {
+    IncomingEvent {
+        from: sender(&callback.from),
+        community: None,
+        kind: EventKind::Button {
+            data: callback.data.clone().unwrap_or_default(),
+            message: callback
+                .message
+                .as_ref()
+                .map(|message| (message.chat.id.0, message.id.0)),
+        },
+    }
+}
@@ -0,0 +1,72 @@
+use std::str::FromStr;
+
+// Mean radius of the Earth, in kilometres
+const EARTH_RADIUS: f64 = 6371.0;
+
+// A point on the Earth, in degrees (north and east are positive)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Point {
+    pub fn new(latitude: f64, longitude: f64) -> Point {
+        Point {
+            latitude,
+            longitude,
+        }
+    }
+
+    // Great-circle distance to another point in kilometres, by the haversine formula
+    pub fn distance(&self, to: Point) -> f64 {
+        let (phi1, phi2) = (self.latitude.to_radians(), to.latitude.to_radians());
+        let delta_phi = (to.latitude - self.latitude).to_radians();
+        let delta_lambda = (to.longitude - self.longitude).to_radians();
+        let a = (delta_phi / 2.0).sin().powi(2)
+            + phi1.cos() * phi2.cos() * (delta_lambda / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS * a.sqrt().asin()
+    }
+
+    // Initial bearing of the way to another point, in degrees clockwise from north (0 to 360)
+    pub fn bearing(&self, to: Point) -> f64 {
+        let (phi1, phi2) = (self.latitude.to_radians(), to.latitude.to_radians());
+        let delta_lambda = (to.longitude - self.longitude).to_radians();
+        let y = delta_lambda.sin() * phi2.cos();
+        let x = phi1.cos() * phi2.sin() - phi1.sin() * phi2.cos() * delta_lambda.cos();
+        y.atan2(x).to_degrees().rem_euclid(360.0)
+    }
+
+    // Number of the cell of about a kilometre the point lies in, the same for points close by
+    pub fn cell(&self) -> u32 {
+        let row = (self.latitude * 100.0).round() as i32;
+        let column = (self.longitude * 100.0).round() as i32;
+        (row as u32)
+            .wrapping_mul(36_001)
+            .wrapping_add(column as u32)
+    }
+}
+
+// Points like "1.2966,103.7723", latitude first
+impl FromStr for Point {
+    type Err = ();
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (latitude, longitude) = text.split_once(',').ok_or(())?;
+        let latitude: f64 = latitude.trim().parse().map_err(|_| ())?;
+        let longitude: f64 = longitude.trim().parse().map_err(|_| ())?;
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return Err(());
+        }
+        Ok(Point::new(latitude, longitude))
+    }
+}
+
+// Distances in metres up to a kilometre, e.g. "350 m" or "2.4 km"
+pub fn format_distance(kilometres: f64) -> String {
+    if kilometres < 1.0 {
+        format!("{} m", ((kilometres * 100.0).round() * 10.0) as u32)
+    } else {
+        format!("{:.1} km", kilometres)
+    }
+}
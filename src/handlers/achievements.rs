@@ -0,0 +1,71 @@
+use crate::bot_api::BotApi;
+use crate::error::BotError;
+use crate::handlers::quiet;
+use crate::models::{Badge, NewAchievement};
+use crate::repository::{Repository, ANONYMOUS_ID};
+use teloxide_core::payloads::SendMessage;
+use teloxide_core::types::ChatId;
+
+// Draws needed for Badge::HundredDraws
+const DRAWS_BADGE: usize = 100;
+// Votes a strip needs for Badge::PopularStrip
+const VOTES_BADGE: i32 = 10;
+
+// Award the badges the user has earned but not received yet, and tell them about the new ones
+// Called whenever something the badges depend on changes for the user
+pub(super) async fn evaluate(
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    tg_id: i64,
+) -> Result<(), BotError> {
+    if tg_id == ANONYMOUS_ID {
+        return Ok(());
+    }
+    let omikujis = repository.find_omikujis_by_author(tg_id).await?;
+    let mut earned = Vec::new();
+    if !omikujis.is_empty() {
+        earned.push(Badge::FirstStrip);
+    }
+    if repository.find_draws_by_user(tg_id).await?.len() >= DRAWS_BADGE {
+        earned.push(Badge::HundredDraws);
+    }
+    if omikujis
+        .iter()
+        .any(|omikuji| omikuji.vote_count >= VOTES_BADGE)
+    {
+        earned.push(Badge::PopularStrip);
+    }
+    let winners = repository.find_weekly_winners().await?;
+    if omikujis.iter().any(|omikuji| winners.contains(&omikuji.id)) {
+        earned.push(Badge::OmikujiOfTheWeek);
+    }
+
+    let language = repository.get_user_settings(tg_id).await?.language();
+    for badge in earned {
+        let awarded = repository
+            .award_badge(&NewAchievement {
+                tg_id,
+                badge: format!("{:?}", badge),
+            })
+            .await?;
+        if awarded {
+            // The user might have blocked the bot, so the error is ignored here
+            #[allow(unused_must_use)]
+            {
+                quiet::notify(
+                    api,
+                    repository,
+                    SendMessage::new(
+                        ChatId(tg_id),
+                        format!(
+                            "🏅 You have earned a new badge: {}! (See all your badges in /stats)",
+                            badge.name(language)
+                        ),
+                    ),
+                )
+                .await;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,1246 @@
+use crate::booklet;
+use crate::booklet::SLIPS_PER_PAGE;
+use crate::bot_api::BotApi;
+use crate::commands::set_my_commands;
+use crate::config::{
+    get_admins, get_booklet_font, get_draw_experiment, in_maintenance, is_admin, reload,
+    set_maintenance,
+};
+use crate::error::BotError;
+use crate::events;
+use crate::events::ModerationEvent;
+use crate::frontends::Sender;
+use crate::import::{csv_url, fetch_csv, guess_mapping, parse_csv, strip_from_row, ColumnMapping};
+use crate::keyboard::KeyboardBuilder;
+use crate::models::{
+    AuditAction, Language, NewAuditEntry, NewImportBatch, NewInterpretation, NewOmikuji, Omikuji,
+    OmikujiClass, OmikujiMessage, UsageDay,
+};
+use crate::repository::Repository;
+use crate::signing::sign;
+use crate::telegram_ext::{escape_markdown, ApiExtension, MARKDOWN};
+use chrono::{Duration, Local, NaiveDate};
+use std::collections::HashMap;
+use std::str::FromStr;
+use strum::IntoEnumIterator;
+use teloxide_core::payloads::setters::*;
+use teloxide_core::payloads::{SendDocument, SendMessage};
+use teloxide_core::types::{BotCommandScope, ChatId, ForceReply, InlineKeyboardButton, InputFile};
+use url::Url;
+
+// Publish the command list so that Telegram clients can show a command menu
+// Admins get their own list (including admin-only commands) in their preferred language
+pub async fn register_commands(
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    for language in Language::iter() {
+        let request = set_my_commands(BotCommandScope::Default, language, false);
+        if language == Language::English {
+            // Used as the fallback for users of all other languages
+            api.set_my_commands(request).await?;
+        } else {
+            api.set_my_commands(request.language_code(language.code()))
+                .await?;
+        }
+    }
+    for admin in get_admins() {
+        let language = repository.get_user_settings(admin).await?.language();
+        let scope = BotCommandScope::Chat {
+            chat_id: ChatId(admin).into(),
+        };
+        let request = set_my_commands(scope, language, true);
+        // This fails if the admin has never talked to the bot, which should not stop the others
+        if let Err(e) = api.set_my_commands(request).await {
+            println!("Failed to register admin commands for {}: {}", admin, e);
+        }
+    }
+    Ok(())
+}
+
+// Summary of the whole library and its usage, rendered as a fixed-width table
+pub(super) async fn admin_stats(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    const TOP_AUTHORS: i64 = 5;
+    const MOST_DRAWN: i64 = 5;
+    const TOP_COMMANDS: usize = 10;
+    let language = repository.get_user_settings(from.id).await?.language();
+    let now = Local::now().naive_local();
+    let week_start = (now - Duration::days(6))
+        .date()
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight is a valid time");
+
+    let classes = repository.count_omikujis_by_class().await?;
+    let total: i64 = classes.iter().map(|(_, count)| count).sum();
+    let mut text = String::from("*Admin statistics*\n```\n");
+    text += format!("{:<24}{:>8}\n", "Total strips", total).as_str();
+    text += format!(
+        "{:<24}{:>8}\n",
+        "Hidden (pending review)",
+        repository.count_hidden_omikujis().await?
+    )
+    .as_str();
+    text += format!(
+        "{:<24}{:>8}\n",
+        "Never drawn",
+        repository.count_undrawn_omikujis().await?
+    )
+    .as_str();
+    text += format!(
+        "{:<24}{:>8}\n",
+        "Active users (24h)",
+        repository
+            .count_active_users(now - Duration::days(1))
+            .await?
+    )
+    .as_str();
+    let booth_draws = repository.count_booth_draws(week_start).await?;
+    if booth_draws > 0 {
+        text += format!("{:<24}{:>8}\n", "Booth draws (7 days)", booth_draws).as_str();
+    }
+
+    text += "\nStrips per class\n";
+    for (class, count) in classes {
+        let name = class
+            .and_then(|class| OmikujiClass::from_str(class.as_str()).ok())
+            .map(|class| class.name(language))
+            .unwrap_or("(none)");
+        text += format!("  {:<22}{:>8}\n", name, count).as_str();
+    }
+
+    // Days without any draw are shown as well
+    text += "\nDraws per day\n";
+    let draws = repository.count_draws_by_day(week_start).await?;
+    for days in (0..7).rev() {
+        let day = (now - Duration::days(days)).date();
+        let count = draws
+            .iter()
+            .find(|(stored, _)| *stored == day)
+            .map(|(_, count)| *count)
+            .unwrap_or(0);
+        text += format!("  {:<22}{:>8}\n", day.format("%Y-%m-%d"), count).as_str();
+    }
+
+    // Busiest commands and buttons, with a bar per day to show the trend
+    text += "\nUsage (7 days)         calls errors  avg ms\n";
+    let usage = repository.usage_by_day(week_start.date()).await?;
+    let mut commands: Vec<(String, i64)> = Vec::new();
+    for total in &usage {
+        match commands
+            .iter_mut()
+            .find(|(command, _)| *command == total.command)
+        {
+            Some((_, calls)) => *calls += total.invocations,
+            None => commands.push((total.command.clone(), total.invocations)),
+        }
+    }
+    commands.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    for (command, calls) in commands.into_iter().take(TOP_COMMANDS) {
+        let days: Vec<&UsageDay> = usage
+            .iter()
+            .filter(|total| total.command == command)
+            .collect();
+        let per_day: Vec<i64> = (0..7)
+            .rev()
+            .map(|days_ago| {
+                let day = (now - Duration::days(days_ago)).date();
+                days.iter()
+                    .filter(|total| total.day == day)
+                    .map(|total| total.invocations)
+                    .sum()
+            })
+            .collect();
+        let errors: i64 = days.iter().map(|total| total.failures).sum();
+        let latency: i64 = days.iter().map(|total| total.total_latency_ms).sum();
+        text += format!(
+            "  {:<12}{} {:>6}{:>7}{:>8}\n",
+            command,
+            sparkline(&per_day),
+            calls,
+            errors,
+            latency / calls.max(1)
+        )
+        .as_str();
+    }
+
+    text += "\nTop authors\n";
+    for (tg_name, count) in repository.top_authors(TOP_AUTHORS).await? {
+        text += format!("  {:<22}{:>8}\n", tg_name, count).as_str();
+    }
+
+    text += "\nMost drawn strips\n";
+    for (omikuji_id, count) in repository.most_drawn_omikujis(MOST_DRAWN).await? {
+        text += format!("  {:<22}{:>8}\n", format!("#{}", omikuji_id), count).as_str();
+    }
+    text += "```";
+    api.send_text(from, text.as_str()).await?;
+    Ok(())
+}
+
+// One bar per value, as high as the value relative to the largest, blank for nothing
+fn sparkline(values: &[i64]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let max = values.iter().copied().max().unwrap_or(0).max(1);
+    values
+        .iter()
+        .map(|value| match value {
+            0 => ' ',
+            value => BARS[((value * 8 - 1) / max).clamp(0, 7) as usize],
+        })
+        .collect()
+}
+
+// Entry for rolling up usage events, called regularly by the main loop
+// Events of the previous days are rolled up into daily totals once a day
+pub async fn usage_entry(
+    repository: &dyn Repository,
+    last_run: &mut Option<NaiveDate>,
+) -> Result<(), BotError> {
+    let today = Local::now().naive_local().date();
+    if *last_run == Some(today) {
+        return Ok(());
+    }
+    *last_run = Some(today);
+    let midnight = today
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight is a valid time");
+    let rolled_up = repository.aggregate_usage(midnight).await?;
+    if rolled_up > 0 {
+        println!("Rolled up {} usage events", rolled_up);
+    }
+    Ok(())
+}
+
+// Page through the audit log, most recent entries first
+// The payload is the page number; callbacks are not filtered like commands, so admins are checked here
+pub(super) async fn audit(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    const PAGE_SIZE: i64 = 10;
+    if !is_admin(from) {
+        api.send_text(from, "This is only available to admins.")
+            .await?;
+        return Ok(());
+    }
+    let page = match payload.parse::<i64>() {
+        Ok(page) if page >= 0 => page,
+        _ => {
+            api.send_text(from, "Malformed callback request.").await?;
+            return Ok(());
+        }
+    };
+    // One more entry is fetched to know whether there is an older page
+    let mut entries = repository
+        .find_audit_entries(page * PAGE_SIZE, PAGE_SIZE + 1)
+        .await?;
+    let has_older = entries.len() as i64 > PAGE_SIZE;
+    entries.truncate(PAGE_SIZE as usize);
+    if entries.is_empty() {
+        api.send_text(from, "The audit log is empty.").await?;
+        return Ok(());
+    }
+    let mut text = format!("*Audit log* (page {})\n```\n", page + 1);
+    for entry in entries {
+        text += format!(
+            "{} {} {} {}\n",
+            entry.created_at.format("%Y-%m-%d %H:%M"),
+            entry.actor,
+            entry.action,
+            entry.target
+        )
+        .as_str();
+    }
+    text += "```";
+    let mut keyboard = KeyboardBuilder::new().columns(2);
+    if page > 0 {
+        keyboard = keyboard.button("« Newer", format!("audit/{}", page - 1));
+    }
+    if has_older {
+        keyboard = keyboard.button("Older »", format!("audit/{}", page + 1));
+    }
+    api.send_message(
+        SendMessage::new(ChatId(from.id), text)
+            .parse_mode(MARKDOWN)
+            .reply_markup(keyboard.build()),
+    )
+    .await?;
+    Ok(())
+}
+
+// Switch maintenance mode with "/maintenance on" or "/maintenance off", or show whether it is on
+pub(super) async fn maintenance(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    argument: &str,
+) -> Result<(), BotError> {
+    let on = match argument.trim() {
+        "on" => true,
+        "off" => false,
+        _ => {
+            let state = if in_maintenance() { "on" } else { "off" };
+            api.send_text(
+                from,
+                format!(
+                    "Maintenance mode is {}. Use `/maintenance on` or `/maintenance off` to change it.",
+                    state
+                )
+                .as_str(),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    set_maintenance(on);
+    let actor = format!("tg:{}", from.id);
+    repository
+        .record_audit(&NewAuditEntry::new(
+            actor.as_str(),
+            AuditAction::Maintenance,
+            String::from(if on { "on" } else { "off" }),
+        ))
+        .await?;
+    api.send_text(
+        from,
+        if on {
+            "Maintenance mode is on. Only admins are served until it is turned off."
+        } else {
+            "Maintenance mode is off."
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+// Reload the .env file, and the command menus since the list of admins may have changed
+pub(super) async fn reload_config(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let count = match reload() {
+        Ok(count) => count,
+        Err(e) => {
+            api.send_text(
+                from,
+                format!("Failed to reload the configuration: {}", e).as_str(),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    register_commands(api, repository).await?;
+    api.send_text(
+        from,
+        format!("Configuration reloaded ({} settings).", count).as_str(),
+    )
+    .await?;
+    Ok(())
+}
+
+// "/setinterpretation <class> <text>" sets the text appended to drawn strips of the class,
+// e.g. "/setinterpretation FutureBlessing Good luck is yet to come", without a text it is removed
+pub(super) async fn set_interpretation(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    argument: &str,
+) -> Result<(), BotError> {
+    let (class, text) = argument
+        .trim()
+        .split_once(' ')
+        .unwrap_or((argument.trim(), ""));
+    let class = match OmikujiClass::parse(class) {
+        Some(class) => format!("{:?}", class),
+        None => {
+            let classes: Vec<String> = OmikujiClass::iter()
+                .map(|class| format!("{:?}", class))
+                .collect();
+            api.send_text(
+                from,
+                format!(
+                    "Usage: /setinterpretation <class> <text>\nClasses: {}",
+                    classes.join(", ")
+                )
+                .as_str(),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    let text = text.trim();
+    repository
+        .set_interpretation(&NewInterpretation {
+            class: class.as_str(),
+            text,
+        })
+        .await?;
+    let actor = format!("tg:{}", from.id);
+    repository
+        .record_audit(&NewAuditEntry::new(
+            actor.as_str(),
+            AuditAction::Interpretation,
+            class.clone(),
+        ))
+        .await?;
+    let reply = if text.is_empty() {
+        format!("The interpretation of {} has been removed.", class)
+    } else {
+        format!("The interpretation of {} has been saved.", class)
+    };
+    api.send_text(from, reply.as_str()).await?;
+    Ok(())
+}
+
+// Strips shown per page of the multi-select list of /review, one bit each in the selection
+const SELECT_PAGE_SIZE: usize = 8;
+// The prompt the column mapping is a reply to tells which CSV to import, so no state is kept
+const IMPORT_PROMPT: &str = "📥 Import from ";
+// Rows which cannot be imported are listed up to this many, the rest are only counted
+const IMPORT_ISSUES_SHOWN: usize = 10;
+
+// "/review hidden" lists the quarantined strips, each with buttons to restore or delete it
+// Larger batches (e.g. after an import) are better handled with the buttons below the count
+pub(super) async fn review(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    argument: &str,
+) -> Result<(), BotError> {
+    const LIMIT: usize = 10;
+    if argument.trim() != "hidden" {
+        api.send_text(from, "Usage: /review hidden").await?;
+        return Ok(());
+    }
+    let omikujis = repository.find_quarantined_omikujis().await?;
+    if omikujis.is_empty() {
+        api.send_text(from, "There are no quarantined strips.")
+            .await?;
+        return Ok(());
+    }
+    let mut by_author: HashMap<i64, usize> = HashMap::new();
+    for omikuji in &omikujis {
+        *by_author.entry(omikuji.tg_id).or_insert(0) += 1;
+    }
+    let pending = omikujis
+        .iter()
+        .filter(|omikuji| omikuji.reviewed_at.is_none())
+        .count();
+    let mut keyboard = KeyboardBuilder::new().button("Select strips", "review/select/0/0");
+    if pending > 0 {
+        keyboard = keyboard.button(
+            format!("Reject all pending ({})", pending),
+            "review/reject_pending",
+        );
+    }
+    api.send_message(
+        SendMessage::new(
+            ChatId(from.id),
+            format!(
+                "{} quarantined strips, showing the first {}:",
+                omikujis.len(),
+                omikujis.len().min(LIMIT)
+            ),
+        )
+        .reply_markup(keyboard.build()),
+    )
+    .await?;
+    for omikuji in omikujis.iter().take(LIMIT) {
+        let text = serde_json::from_str::<OmikujiMessage>(omikuji.message.as_str())
+            .map(|message| message.render(Language::English))
+            .unwrap_or_else(|_| omikuji.message.clone());
+        let mut keyboard = KeyboardBuilder::new()
+            .columns(2)
+            .button("Restore", format!("review/restore/{}", omikuji.id))
+            .button(
+                "Delete permanently",
+                format!("review/delete/{}", omikuji.id),
+            );
+        if by_author[&omikuji.tg_id] > 1 {
+            keyboard = keyboard.button(
+                format!("Restore all {} by this author", by_author[&omikuji.tg_id]),
+                format!("review/author/{}", omikuji.tg_id),
+            );
+        }
+        api.send_message(
+            SendMessage::new(
+                ChatId(from.id),
+                format!(
+                    "#{} ({} votes, {})\n\n{}",
+                    omikuji.id,
+                    omikuji.vote_count,
+                    review_status(omikuji),
+                    text
+                ),
+            )
+            .reply_markup(keyboard.build()),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+// Buttons of /review, where `payload` is "restore/<id>" or "delete/<id>" for a single strip,
+// "author/<tg_id>" or "reject_pending" for a batch, and "select/<after>/<selection>" or
+// "restore_selected" and "delete_selected" (with the same arguments) for the multi-select list
+pub(super) async fn review_action(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    if !is_admin(from) {
+        api.send_text(from, "This is only available to admins.")
+            .await?;
+        return Ok(());
+    }
+    let (action, argument) = payload.split_once('/').unwrap_or((payload, ""));
+    match action {
+        "author" => return restore_author(from, api, repository, argument).await,
+        "reject_pending" => return reject_pending(from, api, repository).await,
+        "select" | "restore_selected" | "delete_selected" => {
+            return review_selection(from, api, repository, action, argument).await
+        }
+        _ => {}
+    }
+    let omikuji_id = match argument.parse::<u32>() {
+        Ok(omikuji_id) => omikuji_id,
+        Err(_) => {
+            api.send_text(from, "Malformed callback request.").await?;
+            return Ok(());
+        }
+    };
+    if repository.find_omikuji(omikuji_id).await?.is_none() {
+        api.send_text(from, "Requested omikuji cannot be found.")
+            .await?;
+        return Ok(());
+    }
+    let (audit_action, reply) = match action {
+        "restore" => {
+            repository.review_omikuji(omikuji_id, true).await?;
+            (AuditAction::Approve, "has been restored")
+        }
+        "delete" => {
+            repository.delete_omikuji(omikuji_id).await?;
+            (AuditAction::Delete, "has been deleted permanently")
+        }
+        _ => {
+            api.send_text(from, "Malformed callback request.").await?;
+            return Ok(());
+        }
+    };
+    record_reviews(repository, from, audit_action, &[omikuji_id]).await?;
+    api.send_text(from, format!("Omikuji #{} {}.", omikuji_id, reply).as_str())
+        .await?;
+    Ok(())
+}
+
+// Restore all the quarantined strips of an author at once
+async fn restore_author(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    argument: &str,
+) -> Result<(), BotError> {
+    let author_id = match argument.parse::<i64>() {
+        Ok(author_id) => author_id,
+        Err(_) => {
+            api.send_text(from, "Malformed callback request.").await?;
+            return Ok(());
+        }
+    };
+    let omikuji_ids: Vec<u32> = repository
+        .find_quarantined_omikujis()
+        .await?
+        .iter()
+        .filter(|omikuji| omikuji.tg_id == author_id)
+        .map(|omikuji| omikuji.id)
+        .collect();
+    if omikuji_ids.is_empty() {
+        api.send_text(from, "This author has no quarantined strips.")
+            .await?;
+        return Ok(());
+    }
+    let restored = repository.review_omikujis(&omikuji_ids, true).await?;
+    record_reviews(repository, from, AuditAction::Approve, &omikuji_ids).await?;
+    api.send_text(
+        from,
+        format!("{} strips of this author have been restored.", restored).as_str(),
+    )
+    .await?;
+    Ok(())
+}
+
+// Reject all the strips which have not been reviewed yet, they stay quarantined
+async fn reject_pending(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let omikuji_ids: Vec<u32> = repository
+        .find_pending_omikujis()
+        .await?
+        .iter()
+        .map(|omikuji| omikuji.id)
+        .collect();
+    if omikuji_ids.is_empty() {
+        api.send_text(from, "There are no pending strips.").await?;
+        return Ok(());
+    }
+    let rejected = repository.review_omikujis(&omikuji_ids, false).await?;
+    record_reviews(repository, from, AuditAction::Reject, &omikuji_ids).await?;
+    api.send_text(
+        from,
+        format!(
+            "{} pending strips have been rejected. They stay quarantined, \
+            so they can still be restored with /review hidden.",
+            rejected
+        )
+        .as_str(),
+    )
+    .await?;
+    Ok(())
+}
+
+// A page of quarantined strips (those after the ID `after`) with a checkbox each
+// The selection is a bitmask over the page, carried along by the buttons
+async fn review_selection(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    action: &str,
+    argument: &str,
+) -> Result<(), BotError> {
+    let parsed = argument.split_once('/').and_then(|(after, selection)| {
+        Some((after.parse::<u32>().ok()?, selection.parse::<u8>().ok()?))
+    });
+    let (after, selection) = match parsed {
+        Some(parsed) => parsed,
+        None => {
+            api.send_text(from, "Malformed callback request.").await?;
+            return Ok(());
+        }
+    };
+    let page = || async {
+        let omikujis: Vec<Omikuji> = repository
+            .find_quarantined_omikujis()
+            .await?
+            .into_iter()
+            .filter(|omikuji| omikuji.id > after)
+            .take(SELECT_PAGE_SIZE)
+            .collect();
+        Ok::<_, BotError>(omikujis)
+    };
+    let omikujis = page().await?;
+    let selected: Vec<u32> = omikujis
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| selection & (1 << index) != 0)
+        .map(|(_, omikuji)| omikuji.id)
+        .collect();
+    let mut selection = selection;
+    if action != "select" {
+        let reply = if selected.is_empty() {
+            "Nothing is selected, tap the strips first.".to_string()
+        } else if action == "restore_selected" {
+            let restored = repository.review_omikujis(&selected, true).await?;
+            record_reviews(repository, from, AuditAction::Approve, &selected).await?;
+            format!("{} strips have been restored.", restored)
+        } else {
+            let deleted = repository.delete_omikujis(&selected).await?;
+            record_reviews(repository, from, AuditAction::Delete, &selected).await?;
+            format!("{} strips have been deleted permanently.", deleted)
+        };
+        api.send_text(from, reply.as_str()).await?;
+        if !selected.is_empty() {
+            selection = 0;
+        }
+    }
+    // Restored and deleted strips leave the page, so it is fetched again after a batch
+    let omikujis = if selected.is_empty() {
+        omikujis
+    } else {
+        page().await?
+    };
+    if omikujis.is_empty() {
+        let keyboard = KeyboardBuilder::new()
+            .button("« First page", "review/select/0/0")
+            .build();
+        let text = if after == 0 {
+            "There are no quarantined strips."
+        } else {
+            "There are no more quarantined strips."
+        };
+        api.send_message(SendMessage::new(ChatId(from.id), text).reply_markup(keyboard))
+            .await?;
+        return Ok(());
+    }
+    let mut text = "Tap the strips to select them:\n".to_string();
+    let mut keyboard = KeyboardBuilder::new().columns(4);
+    for (index, omikuji) in omikujis.iter().enumerate() {
+        let class = serde_json::from_str::<OmikujiMessage>(omikuji.message.as_str())
+            .ok()
+            .and_then(|message| message.class)
+            .map_or("?", |class| class.name(Language::English));
+        text += format!(
+            "\n#{} {} by {} ({} votes, {})",
+            omikuji.id,
+            class,
+            omikuji.tg_name,
+            omikuji.vote_count,
+            review_status(omikuji)
+        )
+        .as_str();
+        let bit = 1 << index;
+        let mark = if selection & bit != 0 { "☑" } else { "☐" };
+        keyboard = keyboard.button(
+            format!("{} #{}", mark, omikuji.id),
+            format!("review/select/{}/{}", after, selection ^ bit),
+        );
+    }
+    let mut navigation = Vec::new();
+    if after > 0 {
+        navigation.push(InlineKeyboardButton::callback(
+            "« First page",
+            sign("review/select/0/0".to_string()),
+        ));
+    }
+    let last = omikujis.last().map_or(after, |omikuji| omikuji.id);
+    if omikujis.len() == SELECT_PAGE_SIZE && has_more(repository, last).await? {
+        navigation.push(InlineKeyboardButton::callback(
+            "Next »",
+            sign(format!("review/select/{}/0", last)),
+        ));
+    }
+    if !navigation.is_empty() {
+        keyboard = keyboard.extra_row(navigation);
+    }
+    keyboard = keyboard.extra_row(vec![
+        InlineKeyboardButton::callback(
+            "Restore selected",
+            sign(format!("review/restore_selected/{}/{}", after, selection)),
+        ),
+        InlineKeyboardButton::callback(
+            "Delete selected",
+            sign(format!("review/delete_selected/{}/{}", after, selection)),
+        ),
+    ]);
+    api.send_message(SendMessage::new(ChatId(from.id), text).reply_markup(keyboard.build()))
+        .await?;
+    Ok(())
+}
+
+// Whether there are quarantined strips after the given ID, for the next page
+async fn has_more(repository: &dyn Repository, after: u32) -> Result<bool, BotError> {
+    Ok(repository
+        .find_quarantined_omikujis()
+        .await?
+        .iter()
+        .any(|omikuji| omikuji.id > after))
+}
+
+fn review_status(omikuji: &Omikuji) -> &'static str {
+    if omikuji.reviewed_at.is_some() {
+        "rejected before"
+    } else {
+        "pending"
+    }
+}
+
+// One audit entry per strip, so that batches can be traced like single reviews
+async fn record_reviews(
+    repository: &dyn Repository,
+    from: &Sender,
+    action: AuditAction,
+    omikuji_ids: &[u32],
+) -> Result<(), BotError> {
+    let actor = format!("tg:{}", from.id);
+    for omikuji_id in omikuji_ids {
+        repository
+            .record_audit(&NewAuditEntry::new(
+                actor.as_str(),
+                action,
+                format!("omikuji {}", omikuji_id),
+            ))
+            .await?;
+        if action == AuditAction::Approve {
+            events::notify_moderators(ModerationEvent::Approved {
+                omikuji_id: *omikuji_id,
+                moderator: from.name.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+// "/importurl <link>" downloads a CSV (or a Google Sheet shared by link) and asks which of its
+// columns hold which part of the strips
+pub(super) async fn import_url(
+    from: &Sender,
+    api: &dyn BotApi,
+    argument: &str,
+) -> Result<(), BotError> {
+    let url = match csv_url(argument) {
+        Some(url) => url,
+        None => {
+            api.send_text(
+                from,
+                "Usage: /importurl <link to a CSV file or a Google Sheet>",
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    let rows = match fetch_rows(&url).await {
+        Ok(rows) => rows,
+        Err(BotError::Validation(message)) => {
+            api.send_text(from, message.as_str()).await?;
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+    let mut text = format!(
+        "{}{}\n{} rows below the header, in these columns:\n",
+        IMPORT_PROMPT,
+        url,
+        rows.len() - 1
+    );
+    for (index, name) in rows[0].iter().enumerate() {
+        text += format!("\n{}. {}", index + 1, name).as_str();
+    }
+    let example = guess_mapping(&rows[0]).to_string();
+    text += format!(
+        "\n\nReply to this message with the columns to import, e.g.\n{}\n\
+        Each section takes the column of its name and the column of its text.",
+        example
+    )
+    .as_str();
+    let reply = ForceReply::new().input_field_placeholder(example);
+    api.send_message(SendMessage::new(ChatId(from.id), text).reply_markup(reply))
+        .await?;
+    Ok(())
+}
+
+// Import the rows of the CSV if the message is a reply to the prompt of /importurl, return
+// Ok(false) if it is not
+// The strips are saved as pending (quarantined), to be approved with /review
+pub(super) async fn capture_import(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    replied: Option<&str>,
+    data: &str,
+) -> Result<bool, BotError> {
+    let link = replied
+        .and_then(|replied| replied.strip_prefix(IMPORT_PROMPT))
+        .and_then(|rest| rest.lines().next());
+    let url = match link.and_then(csv_url) {
+        Some(url) if is_admin(from) => url,
+        _ => return Ok(false),
+    };
+    let mapping = match ColumnMapping::parse(data) {
+        Some(mapping) => mapping,
+        None => {
+            api.send_text(
+                from,
+                "Please reply with the columns to import, e.g. \
+                class=1 description=2 sections=3:4,5:6",
+            )
+            .await?;
+            return Ok(true);
+        }
+    };
+    let rows = match fetch_rows(&url).await {
+        Ok(rows) => rows,
+        Err(BotError::Validation(message)) => {
+            api.send_text(from, message.as_str()).await?;
+            return Ok(true);
+        }
+        Err(e) => return Err(e),
+    };
+    let columns = rows[0].len();
+    let highest = mapping
+        .sections
+        .iter()
+        .flat_map(|(name, text)| [*name, *text])
+        .chain(mapping.description)
+        .fold(mapping.class, usize::max);
+    if highest > columns {
+        api.send_text(
+            from,
+            format!("The CSV only has {} columns.", columns).as_str(),
+        )
+        .await?;
+        return Ok(true);
+    }
+    let mut messages = Vec::new();
+    let mut problems = Vec::new();
+    // Rows are numbered like in a spreadsheet, the header being row 1
+    for (index, row) in rows.iter().enumerate().skip(1) {
+        match strip_from_row(row, &mapping) {
+            Ok(omikuji_message) => messages.push(serde_json::to_string(&omikuji_message)?),
+            Err(issues) => {
+                for issue in issues {
+                    problems.push(format!("Row {}: {}", index + 1, issue));
+                }
+            }
+        }
+    }
+    let skipped = rows.len() - 1 - messages.len();
+    let mut text = if messages.is_empty() {
+        String::from("None of the rows can be imported.")
+    } else {
+        let name = from.name.clone();
+        // The admin is not the author of the strips, so they are not credited
+        let omikujis: Vec<NewOmikuji> = messages
+            .iter()
+            .map(|message| NewOmikuji {
+                message: message.as_str(),
+                tg_id: from.id,
+                tg_name: name.as_str(),
+                community_id: None,
+                vote_count: 0,
+                anonymous: true,
+                expires_at: None,
+                quarantined: true,
+            })
+            .collect();
+        let batch_id = repository
+            .import_omikujis(
+                &NewImportBatch {
+                    tg_id: from.id,
+                    source: url.as_str(),
+                },
+                &omikujis,
+            )
+            .await?;
+        let actor = format!("tg:{}", from.id);
+        repository
+            .record_audit(&NewAuditEntry::new(
+                actor.as_str(),
+                AuditAction::Import,
+                format!("import batch {} ({} strips)", batch_id, omikujis.len()),
+            ))
+            .await?;
+        format!(
+            "{} strips have been imported as batch #{}. \
+            They are pending until approved with /review hidden.",
+            omikujis.len(),
+            batch_id
+        )
+    };
+    if skipped > 0 {
+        text += format!("\n\n{} rows have been skipped:", skipped).as_str();
+        for problem in problems.iter().take(IMPORT_ISSUES_SHOWN) {
+            text += format!("\n• {}", problem).as_str();
+        }
+        if problems.len() > IMPORT_ISSUES_SHOWN {
+            text += format!("\n… and {} more", problems.len() - IMPORT_ISSUES_SHOWN).as_str();
+        }
+    }
+    api.send_text(from, text.as_str()).await?;
+    Ok(true)
+}
+
+// The rows of the CSV, the first one being the header
+async fn fetch_rows(url: &Url) -> Result<Vec<Vec<String>>, BotError> {
+    let rows = parse_csv(fetch_csv(url).await?.as_str());
+    if rows.len() < 2 {
+        return Err(BotError::Validation(String::from(
+            "The CSV has no rows below its header.",
+        )));
+    }
+    Ok(rows)
+}
+
+// "/exportpdf" sends the approved strips as a PDF of slips, to be printed and cut apart
+pub(super) async fn export_pdf(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let language = repository.get_user_settings(from.id).await?.language();
+    let now = Local::now().naive_local();
+    let mut omikujis: Vec<Omikuji> = repository
+        .all_omikujis()
+        .await?
+        .into_iter()
+        .filter(|omikuji| !omikuji.quarantined && !omikuji.is_expired(now))
+        .collect();
+    if omikujis.is_empty() {
+        api.send_text(from, "There are no approved strips to export.")
+            .await?;
+        return Ok(());
+    }
+    // In the order of their slip numbers, which are printed on them
+    omikujis.sort_by_key(|omikuji| {
+        (
+            omikuji.slip_number.is_none(),
+            omikuji.slip_number,
+            omikuji.id,
+        )
+    });
+    if get_booklet_font().is_none() {
+        api.send_text(
+            from,
+            "The booklet needs a TrueType font with Japanese glyphs, set BOOKLET_FONT to its path.",
+        )
+        .await?;
+        return Ok(());
+    }
+    let pdf = booklet::render(&omikujis, language)?;
+    let pages = omikujis.len().div_ceil(SLIPS_PER_PAGE);
+    let document = InputFile::memory(pdf).file_name("omikuji.pdf");
+    api.send_document(
+        SendDocument::new(ChatId(from.id), document).caption(format!(
+            "{} omikuji slips on {} pages, ready to print.",
+            omikujis.len(),
+            pages
+        )),
+    )
+    .await?;
+    Ok(())
+}
+
+// "/inspect <id>" shows everything stored about a saved strip, including its raw JSON
+pub(super) async fn inspect(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    argument: &str,
+) -> Result<(), BotError> {
+    let omikuji_id = match argument.trim().trim_start_matches('#').parse::<u32>() {
+        Ok(omikuji_id) => omikuji_id,
+        Err(_) => {
+            api.send_text(from, "Usage: /inspect <id>").await?;
+            return Ok(());
+        }
+    };
+    let omikuji = match repository.find_omikuji(omikuji_id).await? {
+        Some(omikuji) => omikuji,
+        None => {
+            api.send_text(from, "Requested omikuji cannot be found.")
+                .await?;
+            return Ok(());
+        }
+    };
+    let status = omikuji.status(Local::now().naive_local());
+    let raw = serde_json::from_str::<serde_json::Value>(omikuji.message.as_str())
+        .and_then(|message| serde_json::to_string_pretty(&message))
+        .unwrap_or_else(|_| omikuji.message.clone());
+    let expires = omikuji
+        .expires_at
+        .map_or("never".to_string(), |expires_at| expires_at.to_string());
+    let text = format!(
+        "#{}\nAuthor: {} ({}){}\nVotes: {}\nCreated: {}\nExpires: {}\nStatus: {}\nDrawn: {} times\n\n{}",
+        omikuji.id,
+        escape_markdown(omikuji.tg_name.as_str()),
+        omikuji.tg_id,
+        if omikuji.anonymous { ", anonymous" } else { "" },
+        omikuji.vote_count,
+        omikuji.created_at,
+        expires,
+        status,
+        omikuji.draw_count,
+        escape_markdown(raw.as_str())
+    );
+    api.send_text(from, text.as_str()).await?;
+    Ok(())
+}
+
+// Engagement per variant of the draw experiment: how many of the drawn strips were voted on
+pub(super) async fn experiment(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let results = repository.experiment_results().await?;
+    if results.is_empty() {
+        api.send_text(
+            from,
+            "No draws have been recorded for an experiment yet. Set DRAW_EXPERIMENT to start one.",
+        )
+        .await?;
+        return Ok(());
+    }
+    let variants: Vec<String> = get_draw_experiment()
+        .iter()
+        .map(|strategy| format!("{:?}", strategy))
+        .collect();
+    let mut text = String::from("*Draw experiment*\n```\n");
+    text += format!(
+        "{:<14}{:>7}{:>7}{:>7}{:>7}{:>8}\n",
+        "Variant", "Users", "Draws", "Up", "Down", "Voted"
+    )
+    .as_str();
+    for result in results {
+        let voted = if result.draws > 0 {
+            (result.upvotes + result.downvotes) * 100 / result.draws
+        } else {
+            0
+        };
+        text += format!(
+            "{:<14}{:>7}{:>7}{:>7}{:>7}{:>7}%\n",
+            result.variant, result.users, result.draws, result.upvotes, result.downvotes, voted
+        )
+        .as_str();
+    }
+    text += "```";
+    if variants.is_empty() {
+        text += "\nThe experiment is not running (DRAW_EXPERIMENT is not set).";
+    } else {
+        text += format!("\nRunning with {}.", variants.join(", ")).as_str();
+    }
+    api.send_text(from, text.as_str()).await?;
+    Ok(())
+}
+
+// Recent comments, each with a button to delete it
+pub(super) async fn moderate_comments(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    const LIMIT: i64 = 10;
+    let comments = repository.find_recent_comments(LIMIT).await?;
+    if comments.is_empty() {
+        api.send_text(from, "There are no comments.").await?;
+        return Ok(());
+    }
+    for comment in comments {
+        let keyboard = KeyboardBuilder::new()
+            .button("Delete", format!("delete_comment/{}", comment.id))
+            .build();
+        api.send_message(
+            SendMessage::new(
+                ChatId(from.id),
+                format!(
+                    "Comment {} on #{} by {} ({})\n\n{}",
+                    comment.id,
+                    comment.omikuji_id,
+                    comment.tg_id,
+                    comment.created_at.format("%Y-%m-%d %H:%M"),
+                    comment.text
+                ),
+            )
+            .reply_markup(keyboard),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+// Button of /moderatecomments, where `payload` is the id of the comment
+pub(super) async fn delete_comment(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    if !is_admin(from) {
+        api.send_text(from, "This is only available to admins.")
+            .await?;
+        return Ok(());
+    }
+    let comment_id = match payload.parse::<u32>() {
+        Ok(comment_id) => comment_id,
+        Err(_) => {
+            api.send_text(from, "Malformed callback request.").await?;
+            return Ok(());
+        }
+    };
+    if !repository.delete_comment(comment_id).await? {
+        api.send_text(from, "This comment has been deleted already.")
+            .await?;
+        return Ok(());
+    }
+    let actor = format!("tg:{}", from.id);
+    repository
+        .record_audit(&NewAuditEntry::new(
+            actor.as_str(),
+            AuditAction::Delete,
+            format!("comment {}", comment_id),
+        ))
+        .await?;
+    api.send_text(
+        from,
+        format!("Comment {} has been deleted.", comment_id).as_str(),
+    )
+    .await?;
+    Ok(())
+}
+
+// Strips per class compared to the target shares, so that moderators know what to ask for
+pub(super) async fn balance(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let language = repository.get_user_settings(from.id).await?.language();
+    let counts = repository.count_omikujis_by_class().await?;
+    let total: i64 = counts.iter().map(|(_, count)| count).sum();
+    if total == 0 {
+        api.send_text(from, "There are no strips yet.").await?;
+        return Ok(());
+    }
+    let mut text = String::from("*Balance of the classes*\n```\n");
+    text += format!(
+        "{:<24}{:>7}{:>7}{:>8}\n",
+        "Class", "Strips", "Share", "Target"
+    )
+    .as_str();
+    let mut lacking = Vec::new();
+    for class in OmikujiClass::iter() {
+        let name = format!("{:?}", class);
+        // Strips without a known class count as Other
+        let count: i64 = counts
+            .iter()
+            .filter(|(stored, _)| {
+                let stored = stored
+                    .as_deref()
+                    .filter(|stored| OmikujiClass::from_str(stored).is_ok())
+                    .unwrap_or("Other");
+                stored == name
+            })
+            .map(|(_, count)| *count)
+            .sum();
+        let share = count * 100 / total;
+        let (target, flag) = match class.target_share() {
+            // Classes with less than half their target share are flagged
+            Some(target) if share * 2 < target as i64 => {
+                lacking.push(class.name(language));
+                (format!("{}%", target), " !")
+            }
+            Some(target) => (format!("{}%", target), ""),
+            None => (String::from("-"), ""),
+        };
+        text += format!(
+            "{:<24}{:>7}{:>6}%{:>8}{}\n",
+            class.name(language),
+            count,
+            share,
+            target,
+            flag
+        )
+        .as_str();
+    }
+    text += "```";
+    if lacking.is_empty() {
+        text += "\nAll classes are well represented.";
+    } else {
+        text += format!(
+            "\nUnder-represented: {}. Consider asking for submissions of these classes.",
+            lacking.join(", ")
+        )
+        .as_str();
+    }
+    api.send_text(from, text.as_str()).await?;
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use crate::bot_api::BotApi;
+use crate::config::vote_half_life;
+use crate::error::BotError;
+use crate::frontends::Sender;
+use crate::handlers::create;
+use crate::handlers::trash;
+use crate::handlers::trash::TRASH_DAYS;
+use crate::keyboard::KeyboardBuilder;
+use crate::models::{Language, Omikuji, OmikujiMessage};
+use crate::repository::Repository;
+use crate::scoring::score;
+use crate::telegram_ext::{ApiExtension, HashMapExtension};
+use chrono::Local;
+use std::collections::HashMap;
+use teloxide_core::payloads::setters::*;
+use teloxide_core::payloads::SendMessage;
+use teloxide_core::types::ChatId;
+
+// Strips listed by /mystrips, with three buttons each
+const LIST_LIMIT: usize = 10;
+
+// The strips of the user, most recent first, with how they are doing and buttons to manage them
+pub(super) async fn my_strips(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let language = repository.get_user_settings(from.id).await?.language();
+    let omikujis = repository.find_omikujis_by_author(from.id).await?;
+    if omikujis.is_empty() {
+        api.send_text(
+            from,
+            "You have not written any omikuji strips yet. Create one with /new!",
+        )
+        .await?;
+        return Ok(());
+    }
+    let now = Local::now().naive_local();
+    let mut text = String::from("Your omikuji strips, latest first:\n");
+    let mut keyboard = KeyboardBuilder::new().columns(3);
+    for omikuji in omikujis.iter().rev().take(LIST_LIMIT) {
+        text += format!(
+            "\n#{} {}: drawn {} times, {} votes (score {:.1}), {}{}",
+            omikuji.id,
+            class_name(omikuji, language),
+            omikuji.draw_count,
+            omikuji.vote_count,
+            score(omikuji, now, vote_half_life()),
+            omikuji.status(now),
+            if omikuji.anonymous { ", anonymous" } else { "" }
+        )
+        .as_str();
+        let anonymity = if omikuji.anonymous {
+            format!("👤 Credit #{}", omikuji.id)
+        } else {
+            format!("🕶 Hide name #{}", omikuji.id)
+        };
+        keyboard = keyboard
+            .button(
+                format!("✏️ Edit #{}", omikuji.id),
+                format!("mystrip/edit/{}", omikuji.id),
+            )
+            .button(
+                format!("🗑 Delete #{}", omikuji.id),
+                format!("mystrip/delete/{}", omikuji.id),
+            )
+            .button(anonymity, format!("mystrip/anonymous/{}", omikuji.id));
+    }
+    if omikujis.len() > LIST_LIMIT {
+        text += format!(
+            "\n\nShowing the latest {} of your {} strips.",
+            LIST_LIMIT,
+            omikujis.len()
+        )
+        .as_str();
+    }
+    api.send_message(SendMessage::new(ChatId(from.id), text).reply_markup(keyboard.build()))
+        .await?;
+    Ok(())
+}
+
+// Buttons of /mystrips, where `payload` is the action and the id of the strip, e.g. "edit/3"
+pub(super) async fn strip_action(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    let (action, omikuji_id) = match payload.split_once('/') {
+        Some((action, omikuji_id)) => match omikuji_id.parse::<u32>() {
+            Ok(omikuji_id) => (action, omikuji_id),
+            Err(_) => {
+                api.send_text(from, "Malformed callback request.").await?;
+                return Ok(());
+            }
+        },
+        None => {
+            api.send_text(from, "Malformed callback request.").await?;
+            return Ok(());
+        }
+    };
+    // Strips of other authors are treated as missing, whatever the buttons say
+    let omikuji = match repository.find_omikuji(omikuji_id).await? {
+        Some(omikuji) if omikuji.tg_id == from.id => omikuji,
+        _ => {
+            api.send_text(from, "Requested omikuji cannot be found.")
+                .await?;
+            return Ok(());
+        }
+    };
+    match action {
+        "edit" => edit(from, api, store, repository, &omikuji).await?,
+        "delete" => {
+            let keyboard = KeyboardBuilder::new()
+                .button(
+                    "Yes, delete it",
+                    format!("mystrip/confirm_delete/{}", omikuji_id),
+                )
+                .button("Keep it", format!("mystrip/keep/{}", omikuji_id))
+                .build();
+            let text = format!(
+                "Delete your omikuji strip #{}? You can restore it from /trash for {} days, but \
+                its comments and reactions are deleted with it.",
+                omikuji_id, TRASH_DAYS
+            );
+            api.send_message(SendMessage::new(ChatId(from.id), text).reply_markup(keyboard))
+                .await?;
+        }
+        "confirm_delete" => {
+            let trash_id = trash::trash_strip(repository, &omikuji).await?;
+            let keyboard = KeyboardBuilder::new()
+                .button("Undo", format!("trash/restore/{}", trash_id))
+                .build();
+            let text = format!("Your omikuji strip #{} has been deleted.", omikuji_id);
+            api.send_message(SendMessage::new(ChatId(from.id), text).reply_markup(keyboard))
+                .await?;
+        }
+        "keep" => {
+            let text = format!("OK, your omikuji strip #{} is kept.", omikuji_id);
+            api.send_text(from, text.as_str()).await?;
+        }
+        "anonymous" => {
+            repository
+                .set_anonymous(omikuji_id, !omikuji.anonymous)
+                .await?;
+            let text = if omikuji.anonymous {
+                format!(
+                    "Omikuji strip #{} is credited to you again, if you turned on credits in \
+                    /settings.",
+                    omikuji_id
+                )
+            } else {
+                format!("Omikuji strip #{} is now anonymous.", omikuji_id)
+            };
+            api.send_text(from, text.as_str()).await?;
+        }
+        _ => {
+            api.send_text(from, "Malformed callback request.").await?;
+        }
+    }
+    Ok(())
+}
+
+// Load a saved strip as a draft, to be saved over the original with the creation wizard
+async fn edit(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    omikuji: &Omikuji,
+) -> Result<(), BotError> {
+    if store.get_user_data(from).is_some() {
+        api.send_text(
+            from,
+            "You have to complete your previous strip before editing another one.",
+        )
+        .await?;
+        return Ok(());
+    }
+    let mut omikuji_message: OmikujiMessage = serde_json::from_str(omikuji.message.as_str())?;
+    omikuji_message.community_id = omikuji.community_id;
+    omikuji_message.touched_at = Some(Local::now().naive_local());
+    omikuji_message.anonymous = omikuji.anonymous;
+    omikuji_message.editing = Some(omikuji.id);
+    let language = repository.get_user_settings(from.id).await?.language();
+    let text = format!(
+        "You are editing your omikuji strip #{}:\n\n{}\n\n\
+        Saving replaces the strip, /cancel keeps it as it is.",
+        omikuji.id,
+        omikuji_message.render(language)
+    );
+    store.insert(from.id, omikuji_message);
+    api.send_text(from, text.as_str()).await?;
+    create::resume(from, api, store, repository).await
+}
+
+fn class_name(omikuji: &Omikuji, language: Language) -> &'static str {
+    serde_json::from_str::<OmikujiMessage>(omikuji.message.as_str())
+        .ok()
+        .and_then(|message| message.class)
+        .map_or("?", |class| class.name(language))
+}
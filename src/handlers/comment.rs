@@ -0,0 +1,317 @@
+use crate::bot_api::BotApi;
+use crate::config::get_allowed_domains;
+use crate::error::BotError;
+use crate::frontends::Sender;
+use crate::handlers::{note_failure, quiet};
+use crate::keyboard::KeyboardBuilder;
+use crate::models::{Comment, NewComment};
+use crate::repository::{Repository, ANONYMOUS_ID};
+use crate::sanitize::sanitize;
+use crate::spam::find_spam;
+use crate::telegram_ext::ApiExtension;
+use teloxide_core::payloads::setters::*;
+use teloxide_core::payloads::SendMessage;
+use teloxide_core::types::{ChatId, ForceReply};
+
+// Comments are kept short, like the strips they are left on
+const MAX_COMMENT_LENGTH: usize = 280;
+// The prompt the comment is a reply to tells which strip it is for, so no state is kept
+const COMMENT_PROMPT: &str = "💬 Comment on omikuji #";
+// Likewise, comments and replies are delivered starting with their id, to be replied to in turn
+const DELIVERY_PREFIXES: [&str; 2] = ["💬 Comment #", "💬 Reply #"];
+const THANK_YOU: &str = "🙏 Thank you for your comment!";
+
+// Buttons of drawn strips, where `payload` is the id of the strip
+pub(super) async fn comment(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    let omikuji_id = match payload.parse::<u32>() {
+        Ok(omikuji_id) => omikuji_id,
+        Err(_) => {
+            api.send_text(from, "Malformed callback request.").await?;
+            return Ok(());
+        }
+    };
+    if !can_comment(from, api, repository, omikuji_id).await? {
+        return Ok(());
+    }
+    let text = format!(
+        "{}{}\nReply to this message with your comment (up to {} characters).",
+        COMMENT_PROMPT, omikuji_id, MAX_COMMENT_LENGTH
+    );
+    let reply = ForceReply::new().input_field_placeholder(String::from("Your comment"));
+    api.send_message(SendMessage::new(ChatId(from.id), text).reply_markup(reply))
+        .await?;
+    Ok(())
+}
+
+// Save a reply to the comment prompt as a comment, or a reply to a delivered comment as an
+// answer to it, return Ok(false) if the message is neither
+pub(super) async fn capture(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    replied: Option<&str>,
+    data: &str,
+) -> Result<bool, BotError> {
+    let replied = match replied {
+        Some(replied) => replied,
+        None => return Ok(false),
+    };
+    if let Some(omikuji_id) = parse_id(replied, &[COMMENT_PROMPT]) {
+        add_comment(from, api, repository, omikuji_id, data).await?;
+    } else if let Some(comment_id) = parse_id(replied, &DELIVERY_PREFIXES) {
+        add_reply(from, api, repository, comment_id, data).await?;
+    } else {
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+// Buttons of delivered comments, answering with a canned thank-you, where `payload` is the id
+// of the comment
+pub(super) async fn thank(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    match payload.parse::<u32>() {
+        Ok(comment_id) => add_reply(from, api, repository, comment_id, THANK_YOU).await,
+        Err(_) => {
+            api.send_text(from, "Malformed callback request.").await?;
+            Ok(())
+        }
+    }
+}
+
+// Comments on the strips of the user, most recent first
+pub(super) async fn comments(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    const LIMIT: i64 = 20;
+    // The replies of the author are part of the threads, but not news to them
+    let comments: Vec<Comment> = repository
+        .find_comments_for_author(from.id, LIMIT)
+        .await?
+        .into_iter()
+        .filter(|comment| comment.tg_id != from.id)
+        .collect();
+    if comments.is_empty() {
+        api.send_text(from, "There are no comments on your omikuji strips yet.")
+            .await?;
+        return Ok(());
+    }
+    let mut text = String::from("Comments on your omikuji strips:\n");
+    for comment in comments {
+        text += format!(
+            "\n#{} ({}): {}",
+            comment.omikuji_id,
+            comment.created_at.format("%Y-%m-%d"),
+            comment.text
+        )
+        .as_str();
+    }
+    api.send_message(SendMessage::new(ChatId(from.id), text))
+        .await?;
+    Ok(())
+}
+
+async fn add_comment(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    omikuji_id: u32,
+    data: &str,
+) -> Result<(), BotError> {
+    if !can_comment(from, api, repository, omikuji_id).await? {
+        return Ok(());
+    }
+    let text = match check_text(from, api, data).await? {
+        Some(text) => text,
+        None => return Ok(()),
+    };
+    let comment_id = repository
+        .insert_comment(&NewComment {
+            omikuji_id,
+            tg_id: from.id,
+            text: text.as_str(),
+            parent_id: None,
+        })
+        .await?;
+    api.send_text(from, "Thank you! Your comment has been sent to the author.")
+        .await?;
+    if let Some(omikuji) = repository.find_omikuji(omikuji_id).await? {
+        if omikuji.tg_id != from.id && omikuji.tg_id != ANONYMOUS_ID {
+            let text = format!(
+                "💬 Comment #{} on your omikuji slip #{}:\n\n{}",
+                comment_id, omikuji.id, text
+            );
+            deliver(api, repository, omikuji.tg_id, text, Some(comment_id)).await?;
+        }
+    }
+    Ok(())
+}
+
+// Only the one a comment was delivered to can answer it, which keeps the thread between two
+async fn add_reply(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    comment_id: u32,
+    data: &str,
+) -> Result<(), BotError> {
+    let comment = match repository.find_comment(comment_id).await? {
+        Some(comment) if recipient(repository, &comment).await? == Some(from.id) => comment,
+        _ => {
+            api.send_text(from, "This conversation is no longer available.")
+                .await?;
+            return Ok(());
+        }
+    };
+    let text = match check_text(from, api, data).await? {
+        Some(text) => text,
+        None => return Ok(()),
+    };
+    let reply_id = repository
+        .insert_comment(&NewComment {
+            omikuji_id: comment.omikuji_id,
+            tg_id: from.id,
+            text: text.as_str(),
+            parent_id: Some(comment.id),
+        })
+        .await?;
+    api.send_text(from, "Your reply has been sent.").await?;
+    let text = format!(
+        "💬 Reply #{} about omikuji slip #{}:\n\n{}",
+        reply_id, comment.omikuji_id, text
+    );
+    deliver(api, repository, comment.tg_id, text, None).await?;
+    Ok(())
+}
+
+// Who a comment was meant for: the author of the strip, or the one it replies to
+async fn recipient(
+    repository: &dyn Repository,
+    comment: &Comment,
+) -> Result<Option<i64>, BotError> {
+    let recipient = match comment.parent_id {
+        Some(parent_id) => repository
+            .find_comment(parent_id)
+            .await?
+            .map(|parent| parent.tg_id),
+        None => repository
+            .find_omikuji(comment.omikuji_id)
+            .await?
+            .map(|omikuji| omikuji.tg_id),
+    };
+    Ok(recipient.filter(|tg_id| *tg_id != ANONYMOUS_ID))
+}
+
+// Send a comment or reply, without telling who wrote it
+// Comments on strips get a thank-you button for the author
+async fn deliver(
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    to: i64,
+    text: String,
+    thank: Option<u32>,
+) -> Result<(), BotError> {
+    if !repository.get_user_settings(to).await?.notifications {
+        return Ok(());
+    }
+    let text = format!(
+        "{}\n\nReply to this message to answer anonymously. \
+        (You can turn off these notifications in /settings)",
+        text
+    );
+    let mut message = SendMessage::new(ChatId(to), text);
+    if let Some(comment_id) = thank {
+        let keyboard = KeyboardBuilder::new()
+            .button("🙏 Say thanks", format!("thank/{}", comment_id))
+            .build();
+        message = message.reply_markup(keyboard);
+    }
+    // The recipient might have blocked the bot, which is only noted here
+    if let Err(e) = quiet::notify(api, repository, message).await {
+        note_failure(repository, to, &e).await?;
+    }
+    Ok(())
+}
+
+// Cleaned-up text of a comment or reply, or None (after telling the user) if it is not allowed
+async fn check_text(
+    from: &Sender,
+    api: &dyn BotApi,
+    data: &str,
+) -> Result<Option<String>, BotError> {
+    let text = match sanitize(data) {
+        Some(text) if text.chars().count() <= MAX_COMMENT_LENGTH => text,
+        Some(_) => {
+            api.send_text(
+                from,
+                format!(
+                    "Comments are limited to {} characters, please make it shorter.",
+                    MAX_COMMENT_LENGTH
+                )
+                .as_str(),
+            )
+            .await?;
+            return Ok(None);
+        }
+        None => {
+            api.send_text(from, "Your comment cannot be empty.").await?;
+            return Ok(None);
+        }
+    };
+    if find_spam(text.as_str(), &get_allowed_domains()).is_some() {
+        api.send_text(
+            from,
+            "Links, mentions and phone numbers are not allowed in comments.",
+        )
+        .await?;
+        return Ok(None);
+    }
+    Ok(Some(text))
+}
+
+// Id following one of the prefixes at the start of a message of the bot
+fn parse_id(text: &str, prefixes: &[&str]) -> Option<u32> {
+    let rest = prefixes
+        .iter()
+        .find_map(|prefix| text.strip_prefix(prefix))?;
+    let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse().ok()
+}
+
+// Only strips the user has drawn can be commented on
+async fn can_comment(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    omikuji_id: u32,
+) -> Result<bool, BotError> {
+    if repository.find_omikuji(omikuji_id).await?.is_none() {
+        api.send_text(from, "Requested omikuji cannot be found.")
+            .await?;
+        return Ok(false);
+    }
+    let drawn = repository
+        .find_draws_by_user(from.id)
+        .await?
+        .iter()
+        .any(|draw| draw.omikuji_id == omikuji_id);
+    if !drawn {
+        api.send_text(
+            from,
+            "You can only comment on omikuji strips you have drawn.",
+        )
+        .await?;
+    }
+    Ok(drawn)
+}
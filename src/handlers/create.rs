@@ -0,0 +1,1203 @@
+use crate::bot_api::BotApi;
+use crate::config::{get_allowed_domains, get_quota_daily, get_quota_total};
+use crate::error::BotError;
+use crate::events;
+use crate::events::{Event, ModerationEvent};
+use crate::frontends::Sender;
+use crate::handlers::achievements;
+use crate::handlers::note_failure;
+use crate::handlers::quiet;
+use crate::handlers::trash;
+use crate::handlers::trash::TRASH_DAYS;
+use crate::keyboard::{EnumExtension, KeyboardBuilder};
+use crate::models;
+use crate::models::DraftStep;
+use crate::models::Language;
+use crate::models::OmikujiClass;
+use crate::models::OmikujiMessage;
+use crate::models::OmikujiSection;
+use crate::models::OmikujiTemplate;
+use crate::repository::Repository;
+use crate::sanitize::sanitize;
+use crate::signing::sign;
+use crate::spam::find_spam;
+use crate::telegram_ext::{split_message, ApiExtension, HashMapExtension, MARKDOWN};
+use crate::validate::{check_strip, check_text, render_issues, Field, Issue};
+use chrono::{Duration, Local, NaiveDateTime};
+use std::collections::HashMap;
+use std::str::FromStr;
+use teloxide_core::payloads::setters::*;
+use teloxide_core::payloads::{SendMessage, SendPhoto};
+use teloxide_core::types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup, InputFile};
+
+// Number of buttons shown at a time by the class and section pickers
+const PICKER_PAGE_SIZE: usize = 8;
+
+fn class_picker(language: Language, page: usize) -> InlineKeyboardMarkup {
+    OmikujiClass::to_keyboard("class", language)
+        .columns(2)
+        .paginate("class", PICKER_PAGE_SIZE, page)
+        .build()
+}
+
+// A "save" button is offered once the strip has at least one complete section, and a poem can
+// be added to strips without one
+fn section_picker(
+    language: Language,
+    page: usize,
+    can_save: bool,
+    can_add_poem: bool,
+) -> InlineKeyboardMarkup {
+    let mut builder = OmikujiSection::to_keyboard("section", language)
+        .columns(2)
+        .paginate("section", PICKER_PAGE_SIZE, page);
+    if can_add_poem {
+        builder = builder.extra_row(vec![InlineKeyboardButton::callback(
+            "📜 Add a poem",
+            sign(String::from("poem")),
+        )]);
+    }
+    if can_save {
+        builder = builder.extra_row(vec![InlineKeyboardButton::callback(
+            "Just save what is done!",
+            sign(String::from("ask_photo")),
+        )]);
+    }
+    builder
+        .extra_row(wizard_row(DraftStep::Sections, false))
+        .build()
+}
+
+// Back (and skip) buttons of the wizard, i.e. "back/<step>" and "skip/<step>"
+// The step is sent along, so that buttons of an earlier prompt are not taken for the current one
+fn wizard_row(step: DraftStep, can_skip: bool) -> Vec<InlineKeyboardButton> {
+    let mut row = vec![InlineKeyboardButton::callback(
+        "« Back",
+        sign(format!("back/{:?}", step)),
+    )];
+    if can_skip {
+        row.push(InlineKeyboardButton::callback(
+            "Skip »",
+            sign(format!("skip/{:?}", step)),
+        ));
+    }
+    row
+}
+
+// Every prompt of the wizard starts with the progress, e.g. "Step 3/4: add sections"
+fn prompt(step: DraftStep, text: &str) -> String {
+    format!("{}\n{}", step.progress(), text)
+}
+
+// Page requested by a picker's navigation button, i.e. payload "page:<n>"
+fn picker_page(payload: &str) -> Option<usize> {
+    payload
+        .strip_prefix("page:")
+        .and_then(|page| page.parse().ok())
+}
+
+// Check whether the author has used up their quota, counted from the strips already stored
+// Return the message to show in that case
+async fn quota_reached(
+    repository: &dyn Repository,
+    tg_id: i64,
+) -> Result<Option<String>, BotError> {
+    let strips = repository.find_omikujis_by_author(tg_id).await?;
+    if let Some(total) = get_quota_total() {
+        if strips.len() >= total {
+            return Ok(Some(format!(
+                "You have submitted {} omikuji strips, which is the most one author can submit. \
+                Thank you for all of them!",
+                strips.len()
+            )));
+        }
+    }
+    if let Some(daily) = get_quota_daily() {
+        let since = Local::now().naive_local() - Duration::days(1);
+        let mut recent: Vec<NaiveDateTime> = strips
+            .iter()
+            .map(|omikuji| omikuji.created_at)
+            .filter(|created_at| *created_at > since)
+            .collect();
+        if recent.len() >= daily {
+            // A submission is allowed again once enough of the recent strips are a day old
+            recent.sort();
+            let next = recent[recent.len() - daily] + Duration::days(1);
+            return Ok(Some(format!(
+                "You can submit up to {} omikuji strips a day. \
+                You will be able to submit again after {}, and your strip is kept until then.",
+                daily,
+                next.format("%Y-%m-%d %H:%M")
+            )));
+        }
+    }
+    Ok(None)
+}
+
+// Text which is empty after sanitizing (e.g. only spaces or invisible characters) is not stored
+async fn reject_empty(from: &Sender, api: &dyn BotApi) -> Result<(), BotError> {
+    api.send_text(
+        from,
+        "Your message looks empty once spaces and invisible characters are removed. \
+        Please type some text.",
+    )
+    .await
+}
+
+// Ask for the description of the first section without one, e.g. added by a template
+// Return Ok(false) if all sections have been filled in
+async fn ask_next_section(
+    from: &Sender,
+    api: &dyn BotApi,
+    omikuji_message: &OmikujiMessage,
+) -> Result<bool, BotError> {
+    match omikuji_message
+        .sections
+        .iter()
+        .find(|(_, description)| description.is_empty())
+    {
+        Some((section, _)) => {
+            let reply = format!("OK. Type your description for section {:?} below!", section);
+            let keyboard = KeyboardBuilder::new()
+                .extra_row(wizard_row(DraftStep::Sections, true))
+                .build();
+            api.send_message(
+                SendMessage::new(ChatId(from.id), prompt(DraftStep::Sections, reply.as_str()))
+                    .reply_markup(keyboard),
+            )
+            .await?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+// Tell the user why a text they sent was not taken, so that they can send it again
+// Issues are sent as plain text, since they may quote the Markdown marks which are the problem
+async fn reject_issues(
+    from: &Sender,
+    api: &dyn BotApi,
+    subject: &str,
+    issues: &[Issue],
+) -> Result<(), BotError> {
+    let text = format!("{}\nPlease send it again.", render_issues(subject, issues));
+    api.send_message(SendMessage::new(ChatId(from.id), text))
+        .await?;
+    Ok(())
+}
+
+async fn ask_class(from: &Sender, api: &dyn BotApi, language: Language) -> Result<(), BotError> {
+    api.send_message(
+        SendMessage::new(
+            ChatId(from.id),
+            prompt(
+                DraftStep::Class,
+                "Ok. Select a class from below! \
+                (Tip: You can use /current to check the omikuji you are working on)",
+            ),
+        )
+        .reply_markup(class_picker(language, 0)),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn ask_description(from: &Sender, api: &dyn BotApi) -> Result<(), BotError> {
+    let keyboard = KeyboardBuilder::new()
+        .extra_row(wizard_row(DraftStep::Description, true))
+        .build();
+    api.send_message(
+        SendMessage::new(
+            ChatId(from.id),
+            prompt(
+                DraftStep::Description,
+                "Sure! Can you write a brief description for it (simple Markdown can be used)?",
+            ),
+        )
+        .reply_markup(keyboard),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn ask_sections(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    omikuji_message: &OmikujiMessage,
+    text: &str,
+    page: usize,
+    can_save: bool,
+) -> Result<(), BotError> {
+    let language = repository.get_user_settings(from.id).await?.language();
+    let can_add_poem = omikuji_message.poem.is_none();
+    api.send_message(
+        SendMessage::new(ChatId(from.id), prompt(DraftStep::Sections, text))
+            .reply_markup(section_picker(language, page, can_save, can_add_poem)),
+    )
+    .await?;
+    Ok(())
+}
+
+// Entry for periodic jobs, called regularly by the main loop
+// Users are reminded (once) of drafts untouched for REMINDER_MINUTES, or after their quiet hours
+// Returns the users who were reminded, whose drafts have changed
+pub async fn reminder_entry(
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+) -> Result<Vec<i64>, BotError> {
+    const REMINDER_MINUTES: i64 = 10;
+    let now = Local::now().naive_local();
+    let mut reminded = Vec::new();
+    for (tg_id, omikuji_message) in store.iter_mut() {
+        let stalled = omikuji_message
+            .touched_at
+            .is_some_and(|touched_at| now - touched_at >= Duration::minutes(REMINDER_MINUTES));
+        if !stalled || omikuji_message.reminded {
+            continue;
+        }
+        omikuji_message.reminded = true;
+        reminded.push(*tg_id);
+        let keyboard = KeyboardBuilder::new()
+            .columns(2)
+            .button("Resume", "resume")
+            .button("Cancel", "cancel")
+            .build();
+        let message = SendMessage::new(
+            ChatId(*tg_id),
+            "You have an omikuji strip which is not finished yet. Do you want to continue?",
+        )
+        .reply_markup(keyboard);
+        // A user might have blocked the bot, which should not stop the others
+        if let Err(e) = quiet::notify(api, repository, message).await {
+            println!("Failed to send reminder to {}: {}", tg_id, e);
+            note_failure(repository, *tg_id, &e).await?;
+        }
+    }
+    Ok(reminded)
+}
+
+// Continue a draft from where the user left it, e.g. after a reminder
+pub(super) async fn resume(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let omikuji_message = match store.get_user_data(from) {
+        Some(omikuji_message) => omikuji_message,
+        None => {
+            api.send_text(
+                from,
+                "You don't have an omikuji you are currently working on.",
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    match omikuji_message.step() {
+        DraftStep::Class => {
+            let language = repository.get_user_settings(from.id).await?.language();
+            ask_class(from, api, language).await?;
+        }
+        DraftStep::Description => ask_description(from, api).await?,
+        _ => {
+            if !ask_next_section(from, api, omikuji_message).await? {
+                let can_save = !omikuji_message.sections.is_empty();
+                let text = "Do you want to add a new section or just save?";
+                ask_sections(from, api, repository, omikuji_message, text, 0, can_save).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Buttons for what can be done next with the draft, following the step it is at
+fn draft_actions(omikuji_message: &OmikujiMessage) -> InlineKeyboardMarkup {
+    let pending = omikuji_message
+        .sections
+        .iter()
+        .any(|(_, description)| description.is_empty());
+    let mut builder = KeyboardBuilder::new().columns(2);
+    if omikuji_message.step() != DraftStep::Sections || pending {
+        builder = builder.button("✏️ Continue", "resume");
+    } else {
+        builder = builder.button("➕ Add section", "section/page:0");
+        if !omikuji_message.sections.is_empty() {
+            builder = builder
+                .button("📷 Set photo", "ask_photo")
+                .button("💾 Save", "save");
+        }
+    }
+    builder.button("🗑 Cancel", "cancel").build()
+}
+
+// Show the draft the way it will be drawn (photo included), with buttons for the next steps
+pub(super) async fn current(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let omikuji_message = match store.get_user_data(from) {
+        Some(omikuji_message) => omikuji_message,
+        None => {
+            api.send_text(
+                from,
+                "You don't have an omikuji you are currently working on.",
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    let language = repository.get_user_settings(from.id).await?.language();
+    if let Some(photo) = &omikuji_message.photo {
+        api.send_photo(SendPhoto::new(
+            ChatId(from.id),
+            InputFile::file_id(photo.clone()),
+        ))
+        .await?;
+    }
+    let text = format!(
+        "This is what you are currently working on:\n\n{}",
+        omikuji_message.render(language)
+    );
+    // Long drafts are sent in several messages, with the buttons attached to the last one
+    let mut chunks = split_message(text.as_str());
+    let last = chunks.pop().unwrap_or_default();
+    for chunk in chunks {
+        api.send_message(SendMessage::new(ChatId(from.id), chunk).parse_mode(MARKDOWN))
+            .await?;
+    }
+    api.send_message(
+        SendMessage::new(ChatId(from.id), last)
+            .parse_mode(MARKDOWN)
+            .reply_markup(draft_actions(omikuji_message)),
+    )
+    .await?;
+    Ok(())
+}
+
+// Delete the draft once the user has confirmed it, payload being "confirm" then
+// The draft is moved to the trash, from where it can be restored for TRASH_DAYS
+pub(super) async fn cancel(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    let omikuji_message = match store.get_user_data(from) {
+        Some(omikuji_message) => omikuji_message,
+        None => {
+            api.send_text(
+                from,
+                "You don't have an omikuji you are currently working on.",
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    if payload != "confirm" {
+        let keyboard = KeyboardBuilder::new()
+            .columns(2)
+            .button("Yes, delete it", "cancel/confirm")
+            .button("Keep it", "resume")
+            .build();
+        api.send_message(
+            SendMessage::new(
+                ChatId(from.id),
+                "Do you really want to delete the omikuji strip you are working on?",
+            )
+            .reply_markup(keyboard),
+        )
+        .await?;
+        return Ok(());
+    }
+    let trash_id = trash::trash_draft(repository, from.id, omikuji_message).await?;
+    store.delete_user_data(from);
+    let keyboard = KeyboardBuilder::new()
+        .button("Undo", format!("trash/restore/{}", trash_id))
+        .build();
+    api.send_message(
+        SendMessage::new(
+            ChatId(from.id),
+            format!(
+                "Fine. I have deleted your work-in-progress omikuji, \
+                which you can restore from /trash for {} days. \
+                You can start a new one by calling /start !",
+                TRASH_DAYS
+            ),
+        )
+        .reply_markup(keyboard),
+    )
+    .await?;
+    Ok(())
+}
+
+// Print out the current strip, saved strips are shown to admins with /inspect
+pub(super) async fn debug(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+) -> Result<(), BotError> {
+    if let Some(omikuji_message) = store.get_user_data(from) {
+        api.send_text(from, format!("{:?}", omikuji_message).as_str())
+            .await?;
+    } else {
+        api.send_text(from, "No omikuji strip stored.").await?;
+    }
+    Ok(())
+}
+
+// Classes can be typed instead of picked, by any of their names (e.g. 大吉, daikichi or
+// Great Blessing), which is captured while the draft has no class yet
+pub(super) async fn update_class(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<bool, BotError> {
+    match store.get_user_data(from) {
+        Some(omikuji_message) if omikuji_message.class.is_none() => {}
+        _ => return Ok(false),
+    }
+    if OmikujiClass::parse(payload).is_some() {
+        class(from, api, store, repository, payload).await?;
+        return Ok(true);
+    }
+    let language = repository.get_user_settings(from.id).await?.language();
+    let text = format!(
+        "There is no class called \"{}\". Select one from below, or type its name, \
+        e.g. Great Blessing or 大吉.",
+        payload
+    );
+    api.send_message(
+        SendMessage::new(ChatId(from.id), prompt(DraftStep::Class, text.as_str()))
+            .reply_markup(class_picker(language, 0)),
+    )
+    .await?;
+    Ok(true)
+}
+
+// Check if the user need to update the description
+pub(super) async fn update_description(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<bool, BotError> {
+    if let Some(omikuji_message) = store.get_user_data(from) {
+        if omikuji_message.description.is_none() {
+            let description = match sanitize(payload) {
+                Some(description) => description,
+                None => {
+                    reject_empty(from, api).await?;
+                    return Ok(true);
+                }
+            };
+            let issues = check_text(Field::Description, description.as_str());
+            if !issues.is_empty() {
+                reject_issues(from, api, "Your description", &issues).await?;
+                return Ok(true);
+            }
+            omikuji_message.description = Some(description);
+            if !ask_next_section(from, api, omikuji_message).await? {
+                let text = "Nice. Now, select the first section below.";
+                ask_sections(from, api, repository, omikuji_message, text, 0, false).await?;
+            }
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// Capture the poem the user has been asked for, see `poem`
+pub(super) async fn update_poem(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<bool, BotError> {
+    let omikuji_message = match store.get_user_data(from) {
+        Some(omikuji_message) if omikuji_message.poem.as_deref() == Some("") => omikuji_message,
+        _ => return Ok(false),
+    };
+    let poem = match sanitize(payload) {
+        Some(poem) => poem,
+        None => {
+            reject_empty(from, api).await?;
+            return Ok(true);
+        }
+    };
+    let issues = check_text(Field::Poem, poem.as_str());
+    if !issues.is_empty() {
+        reject_issues(from, api, "Your poem", &issues).await?;
+        return Ok(true);
+    }
+    omikuji_message.poem = Some(poem);
+    if !ask_next_section(from, api, omikuji_message).await? {
+        let can_save = !omikuji_message.sections.is_empty();
+        let text = "What a poem! Do you want to add a new section or just save?";
+        ask_sections(from, api, repository, omikuji_message, text, 0, can_save).await?;
+    }
+    Ok(true)
+}
+
+// Check if the user has a pending omikuji which is yet to be submitted
+// Return Ok(true) if an omikuji strip is updated or anything wrong occurred
+pub(super) async fn update_section(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<bool, BotError> {
+    if let Some(omikuji_message) = store.get_user_data(from) {
+        // Determine which part this message is updating
+        // Sections are filled in order, since a template may add several at once
+        let section = omikuji_message
+            .sections
+            .iter_mut()
+            .find(|(_, description)| description.is_empty());
+        let (name, description) = match section {
+            Some((section, description)) => (section.name(Language::English), description),
+            None => {
+                // We don't modify a section if it already has description
+                api.send_text(
+                    from,
+                    "You will need to select a section type before entering any description!",
+                )
+                .await?;
+                return Ok(true);
+            }
+        };
+        match sanitize(payload) {
+            Some(payload) => {
+                let issues = check_text(Field::Section(name.to_string()), payload.as_str());
+                if !issues.is_empty() {
+                    reject_issues(from, api, "Your section", &issues).await?;
+                    return Ok(true);
+                }
+                description.push_str(payload.as_str())
+            }
+            None => {
+                reject_empty(from, api).await?;
+                return Ok(true);
+            }
+        }
+        if !ask_next_section(from, api, omikuji_message).await? {
+            let text = "Sure. Do you want to add a new section or just save?";
+            ask_sections(from, api, repository, omikuji_message, text, 0, true).await?;
+        }
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+pub(super) async fn new(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    community: Option<i64>,
+) -> Result<(), BotError> {
+    if store.get_user_data(from).is_some() {
+        api.send_text(
+            from,
+            "You have to complete your previous strip before creating a new one.",
+        )
+        .await?;
+        return Ok(());
+    }
+    store.new_user_data(from, community);
+
+    let language = repository.get_user_settings(from.id).await?.language();
+    ask_class(from, api, language).await
+}
+
+// Update the class of the omikuji strip
+pub(super) async fn class(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    if let Some(omikuji_message) = store.get_user_data(from) {
+        if omikuji_message.class.is_some() {
+            api.send_text(from, "You have already set the class of this strip.")
+                .await?;
+            return Ok(());
+        }
+        if let Some(page) = picker_page(payload) {
+            let language = repository.get_user_settings(from.id).await?.language();
+            api.send_message(
+                SendMessage::new(
+                    ChatId(from.id),
+                    prompt(DraftStep::Class, "Select a class from below!"),
+                )
+                .reply_markup(class_picker(language, page)),
+            )
+            .await?;
+            return Ok(());
+        }
+        if let Some(class) = OmikujiClass::parse(payload) {
+            ask_description(from, api).await?;
+            if let OmikujiClass::Other = class {
+                api.send_text(
+                    from,
+                    "Since you choose `Other` for the class, \
+                probably you want to name your class in the description as well?",
+                )
+                .await?;
+            }
+            omikuji_message.class = Some(class);
+        } else {
+            api.send_text(from, "Malformed callback request.").await?;
+        }
+    } else {
+        api.send_text(
+            from,
+            "You have to create a new omikuji strip before calling `class` callback.",
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+pub(super) async fn section(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    if let Some(omikuji_message) = store.get_user_data(from) {
+        if omikuji_message.class.is_none() {
+            api.send_text(
+                from,
+                "You have to choose a class before create a new section!",
+            )
+            .await?;
+            return Ok(());
+        }
+        if omikuji_message.description.is_none() {
+            api.send_text(
+                from,
+                "You have to enter brief description before create a new section!",
+            )
+            .await?;
+            return Ok(());
+        }
+        let section_count = omikuji_message.sections.len();
+        if section_count != 0 {
+            let (_, description) = &omikuji_message.sections[section_count - 1];
+            if description.is_empty() {
+                api.send_text(
+                    from,
+                    "You have to type the description for the previous section first",
+                )
+                .await?;
+                return Ok(());
+            }
+        }
+        if let Some(page) = picker_page(payload) {
+            let text = "Select a section from below!";
+            ask_sections(
+                from,
+                api,
+                repository,
+                omikuji_message,
+                text,
+                page,
+                section_count != 0,
+            )
+            .await?;
+            return Ok(());
+        }
+
+        if let Ok(section) = OmikujiSection::from_str(payload) {
+            omikuji_message.sections.push((section, String::new()));
+            ask_next_section(from, api, omikuji_message).await?;
+        } else {
+            api.send_text(from, "Malformed callback request.").await?;
+        }
+    } else {
+        api.send_text(
+            from,
+            "You have to create a new omikuji strip before calling `section` callback.",
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+// Ask for a poem (waka), which traditional omikuji have above the fortune
+// The poem is left empty until the user has typed it, and payload "none" drops it again
+pub(super) async fn poem(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    let omikuji_message = match store.get_user_data(from) {
+        Some(omikuji_message) if omikuji_message.step() == DraftStep::Sections => omikuji_message,
+        _ => {
+            api.send_text(
+                from,
+                "You have to enter the class and the description before adding a poem.",
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    if payload == "none" {
+        if omikuji_message.poem.as_deref() == Some("") {
+            omikuji_message.poem = None;
+        }
+        if !ask_next_section(from, api, omikuji_message).await? {
+            let can_save = !omikuji_message.sections.is_empty();
+            let text = "Sure. Do you want to add a new section or just save?";
+            ask_sections(from, api, repository, omikuji_message, text, 0, can_save).await?;
+        }
+        return Ok(());
+    }
+    if omikuji_message.poem.is_some() {
+        api.send_text(from, "Your strip has a poem already.")
+            .await?;
+        return Ok(());
+    }
+    omikuji_message.poem = Some(String::new());
+    let keyboard = KeyboardBuilder::new()
+        .button("No poem after all", "poem/none")
+        .build();
+    api.send_message(
+        SendMessage::new(
+            ChatId(from.id),
+            prompt(
+                DraftStep::Sections,
+                "Lovely. Type your poem below, one verse per line! \
+                It is shown in italics above the description.",
+            ),
+        )
+        .reply_markup(keyboard),
+    )
+    .await?;
+    Ok(())
+}
+
+// Add the sections of a template to the strip, or show the templates if none is given
+// Sections which are already on the strip are not added again
+pub(super) async fn template(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    let omikuji_message = match store.get_user_data(from) {
+        Some(omikuji_message) => omikuji_message,
+        None => {
+            api.send_text(
+                from,
+                "You have to create a new omikuji strip before choosing a template.",
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    if payload.is_empty() {
+        let language = repository.get_user_settings(from.id).await?.language();
+        let keyboard = OmikujiTemplate::to_keyboard("template", language).build();
+        api.send_message(
+            SendMessage::new(ChatId(from.id), "Select a template from below!")
+                .reply_markup(keyboard),
+        )
+        .await?;
+        return Ok(());
+    }
+    let template = match OmikujiTemplate::from_str(payload) {
+        Ok(template) => template,
+        Err(_) => {
+            api.send_text(from, "Malformed callback request.").await?;
+            return Ok(());
+        }
+    };
+    for section in template.sections() {
+        if !omikuji_message
+            .sections
+            .iter()
+            .any(|(existing, _)| *existing == section)
+        {
+            omikuji_message.sections.push((section, String::new()));
+        }
+    }
+    if omikuji_message.class.is_none() || omikuji_message.description.is_none() {
+        api.send_text(
+            from,
+            "Template added! Once the class and the description are set, \
+            you will be asked for each section in turn.",
+        )
+        .await?;
+        return Ok(());
+    }
+    if !ask_next_section(from, api, omikuji_message).await? {
+        api.send_text(
+            from,
+            "All sections of this template are on your strip already.",
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+// The last step shows a preview of the strip, where it can also be made anonymous
+pub(super) async fn ask_photo(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+) -> Result<(), BotError> {
+    let omikuji_message = match store.get_user_data(from) {
+        Some(omikuji_message) => omikuji_message,
+        None => {
+            api.send_text(
+                from,
+                "You have to create a new omikuji strip before calling `ask_photo` callback.",
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    // Strips edited from before the checks (or from templates) may not pass them
+    let issues = check_strip(omikuji_message);
+    if !issues.is_empty() {
+        let keyboard = KeyboardBuilder::new()
+            .extra_row(wizard_row(DraftStep::Photo, false))
+            .build();
+        let text = format!(
+            "{}\nGo back to fix it, or /cancel.",
+            render_issues("Your strip", &issues)
+        );
+        api.send_message(SendMessage::new(ChatId(from.id), text).reply_markup(keyboard))
+            .await?;
+        return Ok(());
+    }
+    api.send_text(
+        from,
+        format!(
+            "Here is a preview of your strip:\n\n{}",
+            omikuji_message.render(Language::English)
+        )
+        .as_str(),
+    )
+    .await?;
+    let anonymous = if omikuji_message.anonymous {
+        "On"
+    } else {
+        "Off"
+    };
+    let expiry = match omikuji_message.expires_in {
+        Some(days) => format!("Expires: after {} days", days),
+        None => String::from("Expires: never"),
+    };
+    let keyboard = KeyboardBuilder::new()
+        .button("No, just save it!", "save")
+        .button(format!("Submit anonymously: {}", anonymous), "anonymous")
+        .button(expiry, "expiry")
+        .extra_row(wizard_row(DraftStep::Photo, false))
+        .build();
+    api.send_message(SendMessage::new(ChatId(from.id), prompt(DraftStep::Photo,
+        "Do you want to upload an image of your omikuji strip? Just send me a photo if you want to! \
+        (Just send normally and don't choose the 'send without compression')")).reply_markup(keyboard)).await?;
+    Ok(())
+}
+
+// Toggle whether the author's name is hidden for the strip being created
+pub(super) async fn toggle_anonymous(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+) -> Result<(), BotError> {
+    if let Some(omikuji_message) = store.get_user_data(from) {
+        omikuji_message.anonymous = !omikuji_message.anonymous;
+    }
+    ask_photo(from, api, store).await
+}
+
+// Cycle through the expiry options for the strip being created, e.g. for event-specific fortunes
+pub(super) async fn toggle_expiry(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+) -> Result<(), BotError> {
+    const EXPIRY_OPTIONS: [Option<i64>; 4] = [None, Some(1), Some(7), Some(30)];
+    if let Some(omikuji_message) = store.get_user_data(from) {
+        let current = EXPIRY_OPTIONS
+            .iter()
+            .position(|option| *option == omikuji_message.expires_in)
+            .unwrap_or(0);
+        omikuji_message.expires_in = EXPIRY_OPTIONS[(current + 1) % EXPIRY_OPTIONS.len()];
+    }
+    ask_photo(from, api, store).await
+}
+
+// Go back to the previous step of the wizard, where `payload` is the step the button was shown at
+// Going back from a section asks for the last section entered again
+pub(super) async fn back(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    let omikuji_message = match store.get_user_data(from) {
+        Some(omikuji_message) => omikuji_message,
+        None => {
+            api.send_text(
+                from,
+                "You have to create a new omikuji strip before calling `back` callback.",
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    let current = omikuji_message.step();
+    let is_complete = omikuji_message
+        .sections
+        .iter()
+        .all(|(_, description)| !description.is_empty());
+    match DraftStep::from_str(payload) {
+        Ok(DraftStep::Description) if current == DraftStep::Description => {
+            omikuji_message.class = None;
+            let language = repository.get_user_settings(from.id).await?.language();
+            ask_class(from, api, language).await?;
+        }
+        Ok(DraftStep::Sections) if current == DraftStep::Sections => {
+            let last_filled = omikuji_message
+                .sections
+                .iter_mut()
+                .rev()
+                .find(|(_, description)| !description.is_empty());
+            match last_filled {
+                Some((_, description)) => {
+                    description.clear();
+                    ask_next_section(from, api, omikuji_message).await?;
+                }
+                None => {
+                    omikuji_message.description = None;
+                    ask_description(from, api).await?;
+                }
+            }
+        }
+        Ok(DraftStep::Photo) if current == DraftStep::Sections && is_complete => {
+            let text = "Sure. Do you want to add a new section or just save?";
+            ask_sections(from, api, repository, omikuji_message, text, 0, true).await?;
+        }
+        Ok(_) => {
+            api.send_text(
+                from,
+                "This button is out of date. You can use /current to check where you are.",
+            )
+            .await?;
+        }
+        Err(_) => {
+            api.send_text(from, "Malformed callback request.").await?;
+        }
+    }
+    Ok(())
+}
+
+// Skip the description, or the section being asked for, where `payload` is the current step
+pub(super) async fn skip(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    let omikuji_message = match store.get_user_data(from) {
+        Some(omikuji_message) => omikuji_message,
+        None => {
+            api.send_text(
+                from,
+                "You have to create a new omikuji strip before calling `skip` callback.",
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    let current = omikuji_message.step();
+    let pending = omikuji_message
+        .sections
+        .iter()
+        .position(|(_, description)| description.is_empty());
+    match (DraftStep::from_str(payload), pending) {
+        (Ok(DraftStep::Description), _) if current == DraftStep::Description => {
+            omikuji_message.description = Some(String::new());
+        }
+        (Ok(DraftStep::Sections), Some(index)) if current == DraftStep::Sections => {
+            omikuji_message.sections.remove(index);
+        }
+        (Ok(_), _) => {
+            api.send_text(
+                from,
+                "This button is out of date. You can use /current to check where you are.",
+            )
+            .await?;
+            return Ok(());
+        }
+        (Err(_), _) => {
+            api.send_text(from, "Malformed callback request.").await?;
+            return Ok(());
+        }
+    }
+    if !ask_next_section(from, api, omikuji_message).await? {
+        if omikuji_message.sections.is_empty() {
+            let text = "Nice. Now, select the first section below.";
+            ask_sections(from, api, repository, omikuji_message, text, 0, false).await?;
+        } else {
+            let text = "Sure. Do you want to add a new section or just save?";
+            ask_sections(from, api, repository, omikuji_message, text, 0, true).await?;
+        }
+    }
+    Ok(())
+}
+
+pub(super) async fn save(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    photo: Option<String>,
+) -> Result<(), BotError> {
+    if let Some(omikuji_message) = store.get_user_data(from) {
+        let section_count = omikuji_message.sections.len();
+        if section_count != 0 {
+            let (_, description) = &omikuji_message.sections[section_count - 1];
+            // Check whether last section's description is filled in
+            if !description.is_empty() {
+                let issues = check_strip(omikuji_message);
+                if !issues.is_empty() {
+                    let text = render_issues("Your strip", &issues);
+                    api.send_message(SendMessage::new(ChatId(from.id), text))
+                        .await?;
+                    return Ok(());
+                }
+                // A photo uploaded before a failed attempt is kept when retrying
+                if photo.is_some() {
+                    omikuji_message.photo = photo;
+                }
+                // Edits replace the original strip, so they do not count towards the quota
+                if let Some(omikuji_id) = omikuji_message.editing {
+                    let text = omikuji_message.render(Language::English);
+                    let spam = find_spam(text.as_str(), &get_allowed_domains());
+                    let j = serde_json::to_string(omikuji_message)?;
+                    repository
+                        .update_omikuji(omikuji_id, j.as_str(), spam.is_some())
+                        .await?;
+                    repository
+                        .set_anonymous(omikuji_id, omikuji_message.anonymous)
+                        .await?;
+                    store.delete_user_data(from);
+                    let mut reply = format!("Your omikuji strip #{} has been updated.", omikuji_id);
+                    if spam.is_some() {
+                        reply += " Since it contains a link, a mention or a phone number, \
+                            it will be drawn again once a moderator has approved it.";
+                    }
+                    api.send_text(from, reply.as_str()).await?;
+                    return Ok(());
+                }
+                if let Some(reply) = quota_reached(repository, from.id).await? {
+                    let keyboard = KeyboardBuilder::new().button("Try again", "save").build();
+                    api.send_message(
+                        SendMessage::new(ChatId(from.id), reply).reply_markup(keyboard),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+                // Strips with links, mentions or phone numbers go to the moderation queue
+                let text = omikuji_message.render(Language::English);
+                let spam = find_spam(text.as_str(), &get_allowed_domains());
+                let j = serde_json::to_string(omikuji_message)?;
+                let tg_name = from.name.clone();
+                let result = repository
+                    .insert_omikuji(&models::NewOmikuji {
+                        message: j.as_str(),
+                        tg_id: from.id,
+                        tg_name: &tg_name,
+                        community_id: omikuji_message.community_id,
+                        vote_count: 0,
+                        anonymous: omikuji_message.anonymous,
+                        expires_at: omikuji_message
+                            .expires_in
+                            .map(|days| Local::now().naive_local() + Duration::days(days)),
+                        quarantined: spam.is_some(),
+                    })
+                    .await;
+                let omikuji_id = match result {
+                    Ok(omikuji_id) => omikuji_id,
+                    Err(e) => {
+                        // The insertion has been rolled back, so the draft is kept for another try
+                        println!("Failed to save omikuji for {}: {}", from.id, e);
+                        let keyboard = KeyboardBuilder::new().button("Try again", "save").build();
+                        api.send_message(
+                            SendMessage::new(
+                                ChatId(from.id),
+                                "Sorry, your omikuji strip could not be saved. Nothing has been \
+                                stored, and you can try again.",
+                            )
+                            .reply_markup(keyboard),
+                        )
+                        .await?;
+                        return Ok(());
+                    }
+                };
+                events::publish(Event::StripCreated {
+                    omikuji_id,
+                    class: omikuji_message
+                        .class
+                        .as_ref()
+                        .map(|class| format!("{:?}", class)),
+                    pending: spam.is_some(),
+                });
+                // Moderators are not told who submitted a strip anonymously either
+                events::notify_moderators(ModerationEvent::Submitted {
+                    omikuji_id,
+                    author: (!omikuji_message.anonymous).then(|| tg_name.clone()),
+                    pending: spam.is_some(),
+                    strip: text.clone(),
+                });
+                store.delete_user_data(from);
+                if let Some(spam) = spam {
+                    println!("Omikuji from {} held for moderation: {:?}", from.id, spam);
+                    api.send_text(
+                        from,
+                        "Your omikuji strip has been saved. Since it contains a link, a mention \
+                        or a phone number, it will be drawn once a moderator has approved it.",
+                    )
+                    .await?;
+                } else {
+                    api.send_text(
+                        from,
+                        "Nice! Your omikuji strip has been saved into our database.",
+                    )
+                    .await?;
+                }
+                achievements::evaluate(api, repository, from.id).await?;
+                return Ok(());
+            }
+        }
+    }
+    api.send_text(
+        from,
+        "You have to have a complete omikuji strip before executing `save`.",
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,942 @@
+use crate::bot_api::BotApi;
+use crate::config::{
+    channel_picks_top, get_booth_printer, get_bot_username, get_channel, get_draw_experiment,
+    get_draw_exploration, get_draw_strategy, get_draws_daily, get_shrine_locations, get_tts_url,
+    is_booth, pin_channel_post, show_strip_info,
+};
+use crate::error::BotError;
+use crate::events;
+use crate::events::Event;
+use crate::fortune_extras::{daily_class, place_class, FortuneExtras, LuckyDirection};
+use crate::frontends::Sender;
+use crate::geo::{format_distance, Point};
+use crate::handlers::stats::{streak, STREAK_UNLOCK};
+use crate::handlers::{achievements, last_runs, note_failure};
+use crate::keyboard::KeyboardBuilder;
+use crate::models::Language;
+use crate::models::{
+    DrawPool, DrawStrategy, JobRun, NewBoothDraw, NewDraw, NewFavorite, Omikuji, OmikujiClass,
+    OmikujiMessage, Reaction, UserSettings,
+};
+use crate::printer;
+#[cfg(feature = "image-render")]
+use crate::render;
+use crate::repository::Repository;
+use crate::sanitize::sanitize;
+use crate::signing::sign;
+use crate::telegram_ext::{escape_markdown, split_message, ApiExtension, MARKDOWN};
+use crate::time_zones::nearest_time_zone;
+use crate::tts::{speech_text, synthesize};
+use chrono::{Duration, Local, NaiveTime, Timelike};
+use rand::{thread_rng, Rng};
+use strum::IntoEnumIterator;
+use teloxide_core::payloads::setters::*;
+use teloxide_core::payloads::{PinChatMessage, SendMessage, SendPhoto, SendVoice};
+use teloxide_core::types::{
+    ChatId, InlineKeyboardButton, InlineKeyboardMarkup, InputFile, Message, Recipient,
+};
+use url::Url;
+
+// Scheduled jobs posting the omikuji of the day to the channel and sending it to subscribers
+const CHANNEL_JOB: &str = "daily_channel";
+const DAILY_JOB: &str = "daily";
+
+// Entry for periodic jobs, called regularly by the main loop
+// Daily omikuji are sent once a day, after DAILY_HOUR in the time zone of each subscriber (or
+// after their quiet hours), while the channel follows the time of the server
+pub async fn daily_entry(api: &dyn BotApi, repository: &dyn Repository) -> Result<(), BotError> {
+    const DAILY_HOUR: u32 = 8;
+    let now = Local::now().naive_local();
+    if now.hour() >= DAILY_HOUR
+        && last_runs(repository, CHANNEL_JOB).await?.get(&0) != Some(&now.date())
+    {
+        repository
+            .record_job_run(&JobRun::new(CHANNEL_JOB, 0, now.date()))
+            .await?;
+        // The channel is independent from the subscribers, so its errors are only logged
+        if let Err(e) = post_to_channel(api, repository).await {
+            println!("Failed to post the omikuji of the day: {}", e);
+        }
+    }
+    let sent = last_runs(repository, DAILY_JOB).await?;
+    for settings in repository.get_daily_subscribers().await? {
+        let subscriber = settings.tg_id;
+        let local = settings.local_now();
+        if local.hour() < DAILY_HOUR
+            || sent.get(&subscriber) == Some(&local.date())
+            || settings.is_quiet(local.time())
+        {
+            continue;
+        }
+        repository
+            .record_job_run(&JobRun::new(DAILY_JOB, subscriber, local.date()))
+            .await?;
+        let to = ChatId(subscriber);
+        // A subscriber might have blocked the bot, which should not stop the others
+        let result = async {
+            api.send_message(SendMessage::new(
+                to,
+                "Good morning! Here is your daily omikuji.",
+            ))
+            .await?;
+            send_random_omikuji(to, api, repository, &settings, None).await
+        }
+        .await;
+        match result {
+            Ok(Some(_)) => {}
+            // The pool picked by this subscriber may be empty, unlike those of the others
+            Ok(None) => {}
+            Err(e) => {
+                println!("Failed to send daily omikuji to {}: {}", subscriber, e);
+                note_failure(repository, subscriber, &e).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Entry for the archival job, called regularly by the main loop
+pub async fn archive_entry(repository: &dyn Repository) -> Result<(), BotError> {
+    let archived = repository
+        .archive_expired(Local::now().naive_local())
+        .await?;
+    if archived > 0 {
+        println!("Archived {} expired omikuji strips", archived);
+    }
+    Ok(())
+}
+
+// Draw an omikuji, from the pool the user picked for the community the command was sent in
+pub(super) async fn draw(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    community: Option<i64>,
+) -> Result<(), BotError> {
+    let settings = repository.get_user_settings(from.id).await?;
+    let language = settings.language();
+    if let Some(limit) = get_draws_daily() {
+        // The day starts at midnight where the user is, as for the daily omikuji, while the
+        // draws are recorded in the time of the server
+        let local = settings.local_now();
+        let today = Local::now().naive_local() - (local - local.date().and_time(NaiveTime::MIN));
+        let drawn = repository.count_draws_by_user(from.id, today).await? as usize;
+        // Users on a long streak have unlocked extra draws
+        let streak = streak(repository, from.id).await?;
+        let limit = if streak >= STREAK_UNLOCK {
+            limit * 2
+        } else {
+            limit
+        };
+        if drawn >= limit {
+            api.send_text(
+                from,
+                "You have drawn enough omikuji for today. Come back tomorrow!",
+            )
+            .await?;
+            return Ok(());
+        }
+    }
+    let drawn = send_random_omikuji(ChatId(from.id), api, repository, &settings, community).await?;
+    if let (Some(omikuji), Some(community)) = (&drawn, community) {
+        print_at_booth(omikuji, community, language)?;
+    }
+    match drawn {
+        // A great curse is not read out before the user chose to reveal it
+        Some(omikuji) if settings.voice && get_tts_url().is_some() && !is_concealed(&omikuji) => {
+            // The strip has been sent already, so a failing TTS service is only logged
+            if let Err(e) = send_voice(ChatId(from.id), api, &omikuji, language).await {
+                println!("Failed to send voice message to {}: {}", from.id, e);
+            }
+        }
+        Some(_) => {}
+        None => {
+            api.send_text(from, "Oops! Our omikuji library is empty.")
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+// Draw a strip in a booth chat for a visitor, e.g. "/boothdraw Hanako"
+// Booth draws skip the daily limit, and are recorded as draws of the visitor rather than of the
+// user at the booth, so that they don't count towards the streak and badges of the latter
+pub(super) async fn booth_draw(
+    from: &Sender,
+    chat: ChatId,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    community: Option<i64>,
+    visitor: &str,
+) -> Result<(), BotError> {
+    const MAX_VISITOR_CHARS: usize = 64;
+    if !is_booth(chat.0) {
+        api.send_text(from, "/boothdraw only works in the chats of booths.")
+            .await?;
+        return Ok(());
+    }
+    let visitor: String = sanitize(visitor)
+        .unwrap_or_default()
+        .chars()
+        .take(MAX_VISITOR_CHARS)
+        .collect();
+    if visitor.is_empty() {
+        api.send_message(SendMessage::new(
+            chat,
+            "Who is drawing? Send their name along, e.g. /boothdraw Hanako",
+        ))
+        .await?;
+        return Ok(());
+    }
+    let settings = repository.get_user_settings(from.id).await?;
+    let language = settings.language();
+    let omikuji =
+        match pick_omikuji(repository, get_draw_strategy(), settings.pool(), community).await? {
+            Some(omikuji) => omikuji,
+            None => {
+                api.send_message(SendMessage::new(
+                    chat,
+                    "Oops! Our omikuji library is empty.",
+                ))
+                .await?;
+                return Ok(());
+            }
+        };
+    repository
+        .record_booth_draw(&NewBoothDraw {
+            chat_id: chat.0,
+            tg_id: from.id,
+            visitor: visitor.clone(),
+            omikuji_id: omikuji.id,
+        })
+        .await?;
+    events::publish(Event::StripDrawn {
+        omikuji_id: omikuji.id,
+        class: events::class_of(&omikuji),
+        booth: true,
+    });
+    // The visitor is at the booth to read it, so even a great curse is shown (and printed) as is
+    let header = format!(
+        "🎐 {} draws a omikuji strip:\n\n",
+        escape_markdown(visitor.as_str())
+    );
+    let keyboard = KeyboardBuilder::new().build();
+    send_omikuji(
+        chat.into(),
+        api,
+        repository,
+        &omikuji,
+        language,
+        header.as_str(),
+        "",
+        keyboard,
+    )
+    .await?;
+    if let Some(printer) = get_booth_printer(chat.0) {
+        printer::enqueue(printer.as_str(), printer::receipt(&omikuji, language)?);
+    }
+    Ok(())
+}
+
+// Print a strip drawn in a booth chat, if the booth has a printer
+// A great curse is not printed, as the drawer may choose to tie it unread
+fn print_at_booth(omikuji: &Omikuji, chat: i64, language: Language) -> Result<(), BotError> {
+    if let Some(printer) = get_booth_printer(chat) {
+        if !is_concealed(omikuji) {
+            printer::enqueue(printer.as_str(), printer::receipt(omikuji, language)?);
+        }
+    }
+    Ok(())
+}
+
+// Show the strip drawn last again (without drawing a new one), e.g. after clearing the chat
+pub(super) async fn last(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let language = repository.get_user_settings(from.id).await?.language();
+    let draw = match repository.find_last_draw(from.id).await? {
+        Some(draw) => draw,
+        None => {
+            api.send_text(from, "You have not drawn any omikuji yet. Try /draw!")
+                .await?;
+            return Ok(());
+        }
+    };
+    // Strips hidden or archived since are not shown again, as they could not be drawn now
+    let now = Local::now().naive_local();
+    let omikuji = match repository.find_omikuji(draw.omikuji_id).await? {
+        Some(omikuji) if !omikuji.quarantined && !omikuji.is_expired(now) => omikuji,
+        _ => {
+            api.send_text(from, "Your last omikuji strip is no longer available.")
+                .await?;
+            return Ok(());
+        }
+    };
+    // Drawing again goes through /draw, so the daily limit still applies
+    let keyboard = strip_keyboard(omikuji.id)
+        .button("Draw again", "draw")
+        .extra_row(reaction_row(repository, omikuji.id).await?)
+        .build();
+    let header = format!(
+        "Your last omikuji strip, drawn on {}:\n\n",
+        draw.created_at.format("%Y-%m-%d %H:%M")
+    );
+    // The extras are those of the day it was drawn
+    let extras = FortuneExtras::generate(from.id, draw.created_at.date(), omikuji.id);
+    send_omikuji(
+        ChatId(from.id).into(),
+        api,
+        repository,
+        &omikuji,
+        language,
+        header.as_str(),
+        extras.render(language).as_str(),
+        keyboard,
+    )
+    .await?;
+    Ok(())
+}
+
+// A strip by its slip number, like asking for a numbered slip at a shrine, where `argument`
+// is the number (e.g. "12")
+pub(super) async fn slip(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    argument: &str,
+) -> Result<(), BotError> {
+    let number = match argument.trim().parse::<u32>() {
+        Ok(number) if number > 0 => number,
+        _ => {
+            api.send_text(from, "Please give the number of the slip, e.g. /slip 12.")
+                .await?;
+            return Ok(());
+        }
+    };
+    // Hidden and expired strips keep their numbers, but are not shown any more
+    let now = chrono::Local::now().naive_local();
+    let omikuji = match repository.find_omikuji_by_slip(number).await? {
+        Some(omikuji) if !omikuji.quarantined && !omikuji.is_expired(now) => omikuji,
+        _ => {
+            api.send_text(
+                from,
+                format!("There is no omikuji slip number {}.", number).as_str(),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    // Not drawn, so it can only be saved, not voted or commented on
+    let keyboard = KeyboardBuilder::new()
+        .button("⭐ Save to favorites", format!("favorite/{}", omikuji.id))
+        .build();
+    let language = repository.get_user_settings(from.id).await?.language();
+    send_omikuji(
+        ChatId(from.id).into(),
+        api,
+        repository,
+        &omikuji,
+        language,
+        "",
+        "",
+        keyboard,
+    )
+    .await?;
+    Ok(())
+}
+
+// Reveal a great curse the user was asked to confirm, payload being the id of the strip
+// The pending strip is the last draw of the user, so nothing else needs to be remembered
+pub(super) async fn reveal(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    let omikuji_id = match payload.parse::<u32>() {
+        Ok(omikuji_id) => omikuji_id,
+        Err(_) => {
+            api.send_text(from, "Malformed callback request.").await?;
+            return Ok(());
+        }
+    };
+    let omikuji = match repository.find_last_draw(from.id).await? {
+        Some(draw) if draw.omikuji_id == omikuji_id => repository.find_omikuji(omikuji_id).await?,
+        _ => None,
+    };
+    let omikuji = match omikuji {
+        Some(omikuji) => omikuji,
+        None => {
+            api.send_text(
+                from,
+                "This omikuji strip is no longer waiting to be revealed.",
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    let language = repository.get_user_settings(from.id).await?.language();
+    send_drawn(ChatId(from.id), api, repository, &omikuji, language).await
+}
+
+// Tie a bad fortune to the rack at the shrine, which leaves it out of the history of the user,
+// payload being the id of the strip
+pub(super) async fn tie(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    let omikuji_id = match payload.parse::<u32>() {
+        Ok(omikuji_id) => omikuji_id,
+        Err(_) => {
+            api.send_text(from, "Malformed callback request.").await?;
+            return Ok(());
+        }
+    };
+    let omikuji = match repository.find_omikuji(omikuji_id).await? {
+        Some(omikuji) => omikuji,
+        None => {
+            api.send_text(from, "Requested omikuji cannot be found.")
+                .await?;
+            return Ok(());
+        }
+    };
+    let omikuji_message: OmikujiMessage = serde_json::from_str(omikuji.message.as_str())?;
+    if !omikuji_message.class.is_some_and(|class| class.is_curse()) {
+        api.send_text(from, "Only bad fortunes are tied at the shrine.")
+            .await?;
+        return Ok(());
+    }
+    let text = if repository.tie_draw(from.id, omikuji_id).await? {
+        "🪢 You tied the bad fortune to the rack at the shrine and left it behind. \
+        It no longer shows up in your history."
+    } else {
+        "There is no bad fortune of yours left to tie here."
+    };
+    api.send_text(from, text).await?;
+    Ok(())
+}
+
+// Save a drawn strip to the favorites of the user, payload being the id of the strip
+pub(super) async fn favorite(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    let omikuji_id = match payload.parse::<u32>() {
+        Ok(omikuji_id) => omikuji_id,
+        Err(_) => {
+            api.send_text(from, "Malformed callback request.").await?;
+            return Ok(());
+        }
+    };
+    if repository.find_omikuji(omikuji_id).await?.is_none() {
+        api.send_text(from, "Requested omikuji cannot be found.")
+            .await?;
+        return Ok(());
+    }
+    let saved = repository
+        .add_favorite(&NewFavorite {
+            tg_id: from.id,
+            omikuji_id,
+        })
+        .await?;
+    let text = if saved {
+        "Saved to your favorites. Read them again with /favorites."
+    } else {
+        "This omikuji strip is already in your favorites."
+    };
+    api.send_text(from, text).await?;
+    Ok(())
+}
+
+// Show the saved strips one at a time, most recently saved first, payload being the page
+pub(super) async fn favorites(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    let page = match payload.parse::<i64>() {
+        Ok(page) if page >= 0 => page,
+        _ => {
+            api.send_text(from, "Malformed callback request.").await?;
+            return Ok(());
+        }
+    };
+    let total = repository.count_favorites(from.id).await?;
+    if total == 0 {
+        api.send_text(
+            from,
+            "You have no favorites yet. Save a strip with ⭐ after drawing it!",
+        )
+        .await?;
+        return Ok(());
+    }
+    // Fall back to the last page if favorites have been removed since the buttons were sent
+    let page = page.min(total - 1);
+    let omikuji = match repository.find_favorites(from.id, page, 1).await?.pop() {
+        Some(omikuji) => omikuji,
+        None => {
+            api.send_text(from, "Requested omikuji cannot be found.")
+                .await?;
+            return Ok(());
+        }
+    };
+    let mut keyboard = KeyboardBuilder::new().columns(2);
+    if page > 0 {
+        keyboard = keyboard.button("« Newer", format!("favorites/{}", page - 1));
+    }
+    if page + 1 < total {
+        keyboard = keyboard.button("Older »", format!("favorites/{}", page + 1));
+    }
+    let header = format!("⭐ Favorite {} of {}:\n\n", page + 1, total);
+    let language = repository.get_user_settings(from.id).await?.language();
+    send_omikuji(
+        ChatId(from.id).into(),
+        api,
+        repository,
+        &omikuji,
+        language,
+        header.as_str(),
+        "",
+        keyboard.build(),
+    )
+    .await?;
+    Ok(())
+}
+
+// Personal fortune of the day, which does not need any strip in the library
+pub(super) async fn today(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let language = repository.get_user_settings(from.id).await?.language();
+    let date = Local::now().naive_local().date();
+    let class = daily_class(from.id, date);
+    let mut text = format!(
+        "Your fortune for {}:\n\n*{}*",
+        date.format("%Y-%m-%d"),
+        class.name(language)
+    );
+    if let Some(interpretation) = repository
+        .find_interpretation(format!("{:?}", class).as_str())
+        .await?
+    {
+        text += format!("\n\n{}", interpretation).as_str();
+    }
+    // Strip 0 never exists, so these differ from the extras of any drawn strip
+    let extras = FortuneExtras::generate(from.id, date, 0);
+    text += format!("\n\n{}", extras.render(language)).as_str();
+    api.send_message(SendMessage::new(ChatId(from.id), text).parse_mode(MARKDOWN))
+        .await?;
+    Ok(())
+}
+
+// Easter egg for a location shared with the bot: the fortune of the place for the day, with the
+// way to the nearest of the landmarks in SHRINE_LOCATIONS (if any)
+// The time zone of the place is only offered, as the user may just be passing through
+pub(super) async fn place(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    point: Point,
+) -> Result<(), BotError> {
+    let settings = repository.get_user_settings(from.id).await?;
+    let language = settings.language();
+    let class = place_class(from.id, settings.local_now().date(), point.cell());
+    let mut text = format!(
+        "⛩ The kami of this place have read your fortune:\n\n*{}*",
+        class.name(language)
+    );
+    let nearest = get_shrine_locations()
+        .into_iter()
+        .map(|(name, shrine)| (point.distance(shrine), name, shrine))
+        .min_by(|(a, _, _), (b, _, _)| a.total_cmp(b));
+    if let Some((distance, name, shrine)) = nearest {
+        text += format!(
+            "\n\nThe nearest shrine is {}, {} to the {}.",
+            name,
+            format_distance(distance),
+            LuckyDirection::from_bearing(point.bearing(shrine)).name(language)
+        )
+        .as_str();
+    }
+    let mut message = SendMessage::new(ChatId(from.id), text).parse_mode(MARKDOWN);
+    match nearest_time_zone(point) {
+        Some(time_zone) if settings.time_zone() != Some(time_zone) => {
+            let keyboard = KeyboardBuilder::new()
+                .button(
+                    format!("Use {} as my time zone", time_zone.name()),
+                    format!("timezone/{}", time_zone.name()),
+                )
+                .build();
+            message = message.reply_markup(keyboard);
+        }
+        _ => {}
+    }
+    api.send_message(message).await?;
+    Ok(())
+}
+
+// Read a strip out as a voice message
+async fn send_voice(
+    to: ChatId,
+    api: &dyn BotApi,
+    omikuji: &Omikuji,
+    language: Language,
+) -> Result<(), BotError> {
+    let omikuji_message: OmikujiMessage = serde_json::from_str(omikuji.message.as_str())?;
+    let text = speech_text(omikuji_message.render(language).as_str());
+    let voice = synthesize(text.as_str(), language).await?;
+    api.send_voice(SendVoice::new(
+        to,
+        InputFile::memory(voice).file_name("omikuji.ogg"),
+    ))
+    .await
+}
+
+// Pick the strip to draw according to the strategy
+pub async fn pick_omikuji(
+    repository: &dyn Repository,
+    strategy: DrawStrategy,
+    pool: DrawPool,
+    community: Option<i64>,
+) -> Result<Option<Omikuji>, BotError> {
+    match strategy {
+        DrawStrategy::Uniform => repository.random_omikuji(pool, community).await,
+        // Epsilon-greedy: mostly a uniform draw, otherwise one of the least drawn strips
+        DrawStrategy::FairExposure if thread_rng().gen_bool(get_draw_exploration()) => {
+            repository.least_drawn_omikuji(pool, community).await
+        }
+        DrawStrategy::FairExposure => repository.random_omikuji(pool, community).await,
+        DrawStrategy::Weighted => repository.weighted_omikuji(pool, community).await,
+        DrawStrategy::Balanced => repository.balanced_omikuji(pool, community).await,
+    }
+}
+
+// Draw strategy of a user, with the name of the variant if an experiment is running
+// Users are split evenly (and always the same way) between the variants of DRAW_EXPERIMENT
+pub fn assign_strategy(tg_id: i64) -> (DrawStrategy, Option<String>) {
+    let variants = get_draw_experiment();
+    if variants.is_empty() {
+        return (get_draw_strategy(), None);
+    }
+    let strategy = variants[tg_id.rem_euclid(variants.len() as i64) as usize];
+    (strategy, Some(format!("{:?}", strategy)))
+}
+
+// Send a random omikuji strip with voting buttons, return Ok(None) if the library is empty
+// A great curse is only announced, and sent once the user confirms they want to read it
+pub(super) async fn send_random_omikuji(
+    to: ChatId,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    settings: &UserSettings,
+    community: Option<i64>,
+) -> Result<Option<Omikuji>, BotError> {
+    let language = settings.language();
+    let (strategy, variant) = assign_strategy(to.0);
+    let omikuji = pick_omikuji(repository, strategy, settings.pool(), community).await?;
+    if let Some(omikuji) = omikuji {
+        repository
+            .record_draw(&NewDraw {
+                tg_id: to.0,
+                omikuji_id: omikuji.id,
+                variant,
+            })
+            .await?;
+        events::publish(Event::StripDrawn {
+            omikuji_id: omikuji.id,
+            class: events::class_of(&omikuji),
+            booth: false,
+        });
+        if is_concealed(&omikuji) {
+            let keyboard = KeyboardBuilder::new()
+                .button("Reveal it", format!("reveal/{}", omikuji.id))
+                .button("🪢 Tie it unread", format!("tie/{}", omikuji.id))
+                .build();
+            api.send_message(
+                SendMessage::new(
+                    to,
+                    "⚠️ The strip you drew carries an ominous fortune. \
+                    Are you sure you want to know?",
+                )
+                .reply_markup(keyboard),
+            )
+            .await?;
+        } else {
+            send_drawn(to, api, repository, &omikuji, language).await?;
+        }
+        achievements::evaluate(api, repository, to.0).await?;
+        return Ok(Some(omikuji));
+    }
+    Ok(None)
+}
+
+// Send a strip just drawn, with the extras of the day and the streak of the user
+async fn send_drawn(
+    to: ChatId,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    omikuji: &Omikuji,
+    language: Language,
+) -> Result<(), BotError> {
+    let omikuji_message: OmikujiMessage = serde_json::from_str(omikuji.message.as_str())?;
+    let mut keyboard = strip_keyboard(omikuji.id);
+    if omikuji_message
+        .class
+        .as_ref()
+        .is_some_and(|class| class.is_curse())
+    {
+        keyboard = keyboard.button("🪢 Tie the bad fortune", format!("tie/{}", omikuji.id));
+    }
+    let keyboard = keyboard
+        .extra_row(reaction_row(repository, omikuji.id).await?)
+        .build();
+    // Tell the drawer how lucky they were compared to the other classes
+    let header = match omikuji_message.class.and_then(|class| class.better_than()) {
+        Some(percent) if percent > 0 => format!(
+            "You draw a omikuji strip, better than {}% of possible fortunes:\n\n",
+            percent
+        ),
+        _ => String::from("You draw a omikuji strip:\n\n"),
+    };
+    let extras = FortuneExtras::generate(to.0, Local::now().naive_local().date(), omikuji.id);
+    let mut footer = extras.render(language);
+    let streak = streak(repository, to.0).await?;
+    if streak > 1 {
+        footer += format!("\n\n🔥 {}-day streak", streak).as_str();
+    }
+    send_omikuji(
+        to.into(),
+        api,
+        repository,
+        omikuji,
+        language,
+        header.as_str(),
+        footer.as_str(),
+        keyboard,
+    )
+    .await?;
+    Ok(())
+}
+
+// Whether a drawn strip waits for the user to confirm before it is revealed
+fn is_concealed(omikuji: &Omikuji) -> bool {
+    serde_json::from_str::<OmikujiMessage>(omikuji.message.as_str())
+        .ok()
+        .and_then(|message| message.class)
+        .is_some_and(|class| matches!(class, OmikujiClass::GreatCurse))
+}
+
+// Buttons shared by the drawn strips, where more can be added
+fn strip_keyboard(omikuji_id: u32) -> KeyboardBuilder {
+    KeyboardBuilder::new()
+        .columns(2)
+        .button("This slip is well written", format!("vote/+{}", omikuji_id))
+        .button("I feel insulted :(", format!("vote/-{}", omikuji_id))
+        .button("⭐ Save to favorites", format!("favorite/{}", omikuji_id))
+        .button("💬 Comment", format!("comment/{}", omikuji_id))
+}
+
+// One button per reaction, showing how many users chose it so far
+async fn reaction_row(
+    repository: &dyn Repository,
+    omikuji_id: u32,
+) -> Result<Vec<InlineKeyboardButton>, BotError> {
+    let counts = repository.count_reactions(omikuji_id).await?;
+    Ok(Reaction::iter()
+        .map(|reaction| {
+            let name = format!("{:?}", reaction);
+            let label = match counts.iter().find(|(counted, _)| *counted == name) {
+                Some((_, count)) => format!("{} {}", reaction.emoji(), count),
+                None => String::from(reaction.emoji()),
+            };
+            InlineKeyboardButton::callback(label, sign(format!("react/{}/{}", omikuji_id, name)))
+        })
+        .collect())
+}
+
+// Send a strip (and its photo), return the last message sent
+// With the image-render feature, the strip is sent as an image of omikuji paper, unless it
+// cannot be rendered (see render::render_strip)
+#[allow(clippy::too_many_arguments)]
+async fn send_omikuji(
+    to: Recipient,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    omikuji: &Omikuji,
+    language: Language,
+    header: &str,
+    footer: &str,
+    keyboard: InlineKeyboardMarkup,
+) -> Result<Message, BotError> {
+    let omikuji_message: OmikujiMessage = serde_json::from_str(omikuji.message.as_str())?;
+    if let Some(photo) = &omikuji_message.photo {
+        api.send_photo(SendPhoto::new(
+            to.clone(),
+            InputFile::file_id(photo.clone()),
+        ))
+        .await?;
+    }
+    let text = strip_text(repository, omikuji, language, header, footer).await?;
+    #[cfg(feature = "image-render")]
+    if let Some(png) = render::render_strip(text.as_str())? {
+        return api
+            .send_photo(
+                SendPhoto::new(to, InputFile::memory(png).file_name("omikuji.png"))
+                    .reply_markup(keyboard),
+            )
+            .await;
+    }
+
+    // Long strips are sent in several messages, with the buttons attached to the last one
+    let mut chunks = split_message(text.as_str());
+    let last = chunks.pop().unwrap_or_default();
+    for chunk in chunks {
+        api.send_message(SendMessage::new(to.clone(), chunk).parse_mode(MARKDOWN))
+            .await?;
+    }
+    api.send_message(
+        SendMessage::new(to, last)
+            .parse_mode(MARKDOWN)
+            .reply_markup(keyboard),
+    )
+    .await
+}
+
+// Text of a strip as sent, in Markdown, with everything shown along with it
+pub async fn strip_text(
+    repository: &dyn Repository,
+    omikuji: &Omikuji,
+    language: Language,
+    header: &str,
+    footer: &str,
+) -> Result<String, BotError> {
+    let omikuji_message: OmikujiMessage = serde_json::from_str(omikuji.message.as_str())?;
+    let mut text = String::from(header);
+    if let Some(label) = omikuji.slip_label(language) {
+        text += format!("{}\n\n", label).as_str();
+    }
+    text += omikuji_message.render(language).as_str();
+    if !footer.is_empty() {
+        text += format!("\n\n{}", footer).as_str();
+    }
+    if let Some(class) = &omikuji_message.class {
+        let class = format!("{:?}", class);
+        if let Some(interpretation) = repository.find_interpretation(class.as_str()).await? {
+            text += format!("\n\n{}", interpretation).as_str();
+        }
+    }
+    // Authors can opt in to be credited, unless the strip itself was submitted anonymously
+    if !omikuji.anonymous {
+        if let Some(name) = repository.credit_name(omikuji.tg_id).await? {
+            text += format!("\n\nby {}", escape_markdown(name.as_str())).as_str();
+        }
+    }
+    if show_strip_info() {
+        text += format!("\n\n{}", omikuji.info(language)).as_str();
+    }
+    Ok(text)
+}
+
+// Draw for a user of another chat platform (see frontends), in the language of their client
+pub(super) async fn draw_elsewhere(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let header = format!("{} draws a omikuji strip:\n\n", from.name);
+    let text = draw_text(repository, from.id, from.language(), header.as_str())
+        .await?
+        .unwrap_or_else(|| String::from("Oops! Our omikuji library is empty."));
+    api.send_text(from, text.as_str()).await?;
+    Ok(())
+}
+
+// Draw a strip for a user of another chat platform (see frontends), from the global
+// pool and without streaks or daily limits, which are only kept for Telegram users
+// Returns the text of the strip in Markdown, or None if the library is empty
+async fn draw_text(
+    repository: &dyn Repository,
+    drawer: i64,
+    language: Language,
+    header: &str,
+) -> Result<Option<String>, BotError> {
+    let omikuji = pick_omikuji(repository, get_draw_strategy(), DrawPool::Global, None).await?;
+    let omikuji = match omikuji {
+        Some(omikuji) => omikuji,
+        None => return Ok(None),
+    };
+    repository
+        .record_draw(&NewDraw {
+            tg_id: drawer,
+            omikuji_id: omikuji.id,
+            variant: None,
+        })
+        .await?;
+    events::publish(Event::StripDrawn {
+        omikuji_id: omikuji.id,
+        class: events::class_of(&omikuji),
+        booth: false,
+    });
+    Ok(Some(
+        strip_text(repository, &omikuji, language, header, "").await?,
+    ))
+}
+
+// Post the "omikuji of the day" to the channel configured in CHANNEL_ID, if any
+// Channel posts have no voting buttons, but a deep link to draw from the bot instead
+pub async fn post_to_channel(
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let channel = match get_channel() {
+        Some(channel) => channel,
+        None => return Ok(()),
+    };
+    let mut omikuji = None;
+    if channel_picks_top() {
+        let since = Local::now().naive_local() - Duration::days(7);
+        omikuji = repository.top_omikuji(since).await?;
+    }
+    let omikuji = match omikuji {
+        Some(omikuji) => omikuji,
+        None => match repository.random_omikuji(DrawPool::Global, None).await? {
+            Some(omikuji) => omikuji,
+            None => return Ok(()),
+        },
+    };
+    let mut keyboard = KeyboardBuilder::new().build();
+    if let Some(username) = get_bot_username() {
+        let link = format!("https://t.me/{}?start=draw", username);
+        keyboard = keyboard.append_row(vec![InlineKeyboardButton::url(
+            "Draw your own omikuji",
+            Url::parse(link.as_str())
+                .map_err(|e| BotError::State(format!("BOT_USERNAME in {}: {}", link, e)))?,
+        )]);
+    }
+    let header = "Omikuji of the day:\n\n";
+    let message = send_omikuji(
+        channel.clone(),
+        api,
+        repository,
+        &omikuji,
+        Language::English,
+        header,
+        "",
+        keyboard,
+    )
+    .await?;
+    if pin_channel_post() {
+        api.pin_message(PinChatMessage::new(channel, message.id))
+            .await?;
+    }
+    Ok(())
+}
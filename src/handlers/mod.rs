@@ -0,0 +1,490 @@
+use crate::bot_api::{is_blocked, BotApi};
+use crate::commands::Command;
+use crate::config::{get_channel, get_shrine_board, get_weekly_poll_chat, is_admin};
+use crate::error::BotError;
+use crate::frontends::{EventKind, IncomingEvent, Platform, Sender};
+use crate::keyboard::KeyboardBuilder;
+use crate::models::OmikujiMessage;
+use crate::repository::Repository;
+use crate::signing;
+use crate::telegram_ext::{ApiExtension, HashMapExtension};
+use chrono::NaiveDate;
+use std::collections::HashMap;
+use std::str::FromStr;
+use strum::IntoEnumIterator;
+use teloxide_core::payloads::setters::*;
+use teloxide_core::payloads::{EditMessageReplyMarkup, SendMessage};
+use teloxide_core::types::{
+    ChatId, KeyboardButton, KeyboardMarkup, KeyboardRemove, MessageId, Recipient,
+};
+
+pub mod achievements;
+pub mod admin;
+pub mod author;
+pub mod comment;
+pub mod create;
+pub mod draw;
+pub mod poll;
+pub mod quiet;
+pub mod settings;
+pub mod shrine;
+pub mod stats;
+pub mod trash;
+pub mod vote;
+
+//
+// Functions for handling client-side inputs
+//
+
+// Entry for all events, from any of the frontends
+pub async fn handle(
+    event: &IncomingEvent,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let from = &event.from;
+    match &event.kind {
+        EventKind::Command { name, args } => {
+            command(event, name, args, api, store, repository).await?
+        }
+        // Only the shared commands are offered on the other platforms, without drafts to write
+        _ if from.platform != Platform::Telegram => {}
+        EventKind::Text { text, reply_to } => {
+            message(from, api, store, repository, text, reply_to.as_deref()).await?
+        }
+        EventKind::Photo {
+            file_id: Some(file_id),
+        } => create::save(from, api, store, repository, Some(file_id.clone())).await?,
+        EventKind::Photo { file_id: None } => api.send_text(from, "Malformed image").await?,
+        EventKind::Location { point } => draw::place(from, api, repository, *point).await?,
+        EventKind::Button { data, message } => {
+            button(from, api, store, repository, data, *message).await?
+        }
+        EventKind::Unsupported => {
+            api.send_text(from, "Sorry, this kind of message is yet to be supported.")
+                .await?
+        }
+    }
+    Ok(())
+}
+
+async fn command(
+    event: &IncomingEvent,
+    name: &str,
+    argument: &str,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let from = &event.from;
+    let community = event.community;
+    let elsewhere = from.platform != Platform::Telegram;
+    let command = match Command::from_str(name) {
+        Ok(command) if (!command.admin_only() || is_admin(from)) && !elsewhere => command,
+        Ok(command) if command.shared() && elsewhere => command,
+        // Rooms on the other platforms may have bots of their own, whose commands are none of
+        // our business
+        _ if elsewhere => return Ok(()),
+        _ => {
+            let text = format!("Command /{} is not recognized.", name);
+            api.send_text(from, text.as_str()).await?;
+            return Ok(());
+        }
+    };
+    match command {
+        Command::Help => help(from, api, store, repository).await?,
+        Command::Start if argument == "draw" => {
+            draw::draw(from, api, repository, community).await?
+        }
+        Command::Start if argument.starts_with("ref_") => {
+            start_referred(from, api, repository, argument).await?
+        }
+        Command::Start => start(from, api, repository).await?,
+        Command::New => create::new(from, api, store, repository, community).await?,
+        Command::Draw if elsewhere => draw::draw_elsewhere(from, api, repository).await?,
+        Command::Draw => draw::draw(from, api, repository, community).await?,
+        Command::BoothDraw => {
+            // Commands in private chats come from the chat of the user
+            let chat = ChatId(community.unwrap_or(from.id));
+            draw::booth_draw(from, chat, api, repository, community, argument).await?
+        }
+        Command::Today => draw::today(from, api, repository).await?,
+        Command::Last => draw::last(from, api, repository).await?,
+        Command::Favorites => draw::favorites(from, api, repository, "0").await?,
+        Command::Slip => draw::slip(from, api, repository, argument).await?,
+        Command::Comments => comment::comments(from, api, repository).await?,
+        Command::MyStrips => author::my_strips(from, api, repository).await?,
+        Command::Trash => trash::trash(from, api, repository).await?,
+        Command::Stats => stats::stats(from, api, repository).await?,
+        Command::Streak => stats::show_streak(from, api, repository).await?,
+        Command::Invites => stats::invites(from, api, repository).await?,
+        Command::Current => create::current(from, api, store, repository).await?,
+        Command::Cancel => create::cancel(from, api, store, repository, "").await?,
+        Command::About => about(from, api).await?,
+        Command::Debug => create::debug(from, api, store).await?,
+        Command::Settings => settings::settings(from, api, repository).await?,
+        Command::Language => settings::language(from, api, repository).await?,
+        Command::Quiet => quiet::quiet(from, api, repository, argument).await?,
+        Command::TimeZone => settings::time_zone(from, api, repository, argument).await?,
+        Command::Template => create::template(from, api, store, repository, "").await?,
+        Command::MyData => settings::my_data(from, api, store, repository).await?,
+        Command::ForgetMe => settings::forget_me(from, api).await?,
+        Command::AdminStats => admin::admin_stats(from, api, repository).await?,
+        Command::Audit => admin::audit(from, api, repository, "0").await?,
+        Command::Maintenance => admin::maintenance(from, api, repository, argument).await?,
+        Command::ReloadConfig => admin::reload_config(from, api, repository).await?,
+        Command::Review => admin::review(from, api, repository, argument).await?,
+        Command::ImportUrl => admin::import_url(from, api, argument).await?,
+        Command::ExportPdf => admin::export_pdf(from, api, repository).await?,
+        Command::Inspect => admin::inspect(from, api, repository, argument).await?,
+        Command::Experiment => admin::experiment(from, api, repository).await?,
+        Command::Balance => admin::balance(from, api, repository).await?,
+        Command::ModerateComments => admin::moderate_comments(from, api, repository).await?,
+        Command::SetInterpretation => {
+            admin::set_interpretation(from, api, repository, argument).await?
+        }
+    }
+    Ok(())
+}
+
+// Text which is not a command, e.g. a part of the strip being written
+async fn message(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    data: &str,
+    reply_to: Option<&str>,
+) -> Result<(), BotError> {
+    if comment::capture(from, api, repository, reply_to, data).await? {
+        // This message is a reply to the comment prompt, not part of a new omikuji
+        return Ok(());
+    }
+
+    if admin::capture_import(from, api, repository, reply_to, data).await? {
+        // This message is the column mapping of a CSV being imported
+        return Ok(());
+    }
+
+    if create::update_class(from, api, store, repository, data).await? {
+        // This message has been taken as the class of the strip
+        return Ok(());
+    }
+
+    if create::update_description(from, api, store, repository, data).await? {
+        // This message has been captured as a description, so don't do anything else
+        return Ok(());
+    }
+
+    if create::update_poem(from, api, store, repository, data).await? {
+        // This message has been captured as the poem the user was asked for
+        return Ok(());
+    }
+
+    if !create::update_section(from, api, store, repository, data).await? {
+        // Show user a welcome message for text input if no section has been updated
+        api.send_text(
+                from,
+                "Welcome to use NUSCAS's Omikuji Bot!\nTo start, simply type /start. You can also call /help for more information.",
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+// Groups upgraded to supergroups get a new chat id
+// Strips (and drafts) of the group follow it, so that its community stays the same
+pub async fn migrate(
+    from: i64,
+    to: i64,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let moved = repository.migrate_community(from, to).await?;
+    repository.migrate_poll_chat(from, to).await?;
+    for omikuji_message in store.values_mut() {
+        if omikuji_message.community_id == Some(from) {
+            omikuji_message.community_id = Some(to);
+        }
+    }
+    println!(
+        "Group {} was upgraded to supergroup {}, {} omikuji strips moved along",
+        from, to, moved
+    );
+    // Chats configured by id cannot be updated from here
+    let configured = [
+        ("CHANNEL_ID", get_channel()),
+        ("SHRINE_BOARD_ID", get_shrine_board()),
+        ("WEEKLY_POLL_ID", get_weekly_poll_chat()),
+    ];
+    for (key, chat) in configured {
+        if matches!(chat, Some(Recipient::Id(ChatId(id))) if id == from) {
+            println!(
+                "{} still has the old id of the group, change it to {}",
+                key, to
+            );
+        }
+    }
+    Ok(())
+}
+
+// Inline keyboard buttons, whose data is the command and its payload, e.g. "vote/3/+1"
+async fn button(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    command: &str,
+    message: Option<(i64, i32)>,
+) -> Result<(), BotError> {
+    if command.is_empty() {
+        // This callback query contains empty query body - there must be something wrong
+        api.send_text(
+            from,
+            "Callback query has empty body - probably your TG client is lousy!",
+        )
+        .await?;
+        return Ok(());
+    }
+    // We delete the original inline keyboard to prevent it being clicked for 2 times
+    // We will ignore the error generated here
+    if let Some((chat, message_id)) = message {
+        #[allow(unused_must_use)]
+        {
+            api.edit_reply_markup(EditMessageReplyMarkup::new(
+                ChatId(chat),
+                MessageId(message_id),
+            ))
+            .await;
+        }
+    }
+
+    // Buttons are signed (if CALLBACK_SECRET is set), so modified clients cannot make up
+    // their own, e.g. votes on arbitrary strips
+    let command = match signing::verify(command) {
+        Some(command) => command,
+        None => {
+            api.send_text(
+                from,
+                "This button is no longer valid, please use a more recent message.",
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    // The command and the payload (metadata) are separated by the first '/'
+    let (command, payload) = command.split_once('/').unwrap_or((command, ""));
+    match command {
+        // Sequence: from, api, store, repository, payload/photo
+        // Buttons are only sent to private chats, so there is no community here
+        "new" => create::new(from, api, store, repository, None).await?,
+        "draw" => draw::draw(from, api, repository, None).await?,
+        "class" => create::class(from, api, store, repository, payload).await?,
+        "section" => create::section(from, api, store, repository, payload).await?,
+        "template" => create::template(from, api, store, repository, payload).await?,
+        "poem" => create::poem(from, api, store, repository, payload).await?,
+        "ask_photo" => create::ask_photo(from, api, store).await?,
+        "anonymous" => create::toggle_anonymous(from, api, store).await?,
+        "expiry" => create::toggle_expiry(from, api, store).await?,
+        "resume" => create::resume(from, api, store, repository).await?,
+        "cancel" => create::cancel(from, api, store, repository, payload).await?,
+        "back" => create::back(from, api, store, repository, payload).await?,
+        "skip" => create::skip(from, api, store, repository, payload).await?,
+        "save" => create::save(from, api, store, repository, None).await?,
+        "vote" => vote::vote(from, api, repository, payload).await?,
+        "react" => vote::react(from, api, repository, payload).await?,
+        "favorite" => draw::favorite(from, api, repository, payload).await?,
+        "reveal" => draw::reveal(from, api, repository, payload).await?,
+        "tie" => draw::tie(from, api, repository, payload).await?,
+        "favorites" => draw::favorites(from, api, repository, payload).await?,
+        "comment" => comment::comment(from, api, repository, payload).await?,
+        "mystrip" => author::strip_action(from, api, store, repository, payload).await?,
+        "trash" => trash::trash_action(from, api, store, repository, payload).await?,
+        "thank" => comment::thank(from, api, repository, payload).await?,
+        "delete_comment" => admin::delete_comment(from, api, repository, payload).await?,
+        "audit" => admin::audit(from, api, repository, payload).await?,
+        "review" => admin::review_action(from, api, repository, payload).await?,
+        "settings" => settings::toggle_setting(from, api, repository, payload).await?,
+        "language" => settings::set_language(from, api, repository, payload).await?,
+        "timezone" => settings::time_zone(from, api, repository, payload).await?,
+        "forgetme" => settings::confirm_forget_me(from, api, store, repository, payload).await?,
+        _ => {
+            api.send_text(
+                from,
+                format!("Callback query {} is not recognized!", command).as_str(),
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+// The day a scheduled job last ran on, by the user it ran for
+// Runs are kept in the database, so that an instance taking over the lease (or a restarted one)
+// does not run the job again on the same day
+pub(crate) async fn last_runs(
+    repository: &dyn Repository,
+    job: &str,
+) -> Result<HashMap<i64, NaiveDate>, BotError> {
+    Ok(repository
+        .find_job_runs(job)
+        .await?
+        .into_iter()
+        .map(|run| (run.tg_id, run.ran_on))
+        .collect())
+}
+
+// Called when sending to a user failed, so that broadcasts skip users who blocked the bot
+pub(crate) async fn note_failure(
+    repository: &dyn Repository,
+    tg_id: i64,
+    error: &BotError,
+) -> Result<(), BotError> {
+    if is_blocked(error) {
+        repository.set_inactive(tg_id, true).await?;
+    }
+    Ok(())
+}
+
+//
+// Functions for general commands
+//
+
+// Prints out the help message
+// Commands are listed from the command registry, showing only those usable right now
+pub(super) async fn help(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let language = repository.get_user_settings(from.id).await?.language();
+    let has_draft = store.get_user_data(from).is_some();
+    let is_admin = is_admin(from);
+    let mut help_message = String::from("NUSCAS Omikuji Bot\n\n*Available commands:*\n");
+    for command in Command::iter() {
+        if (command.requires_draft() && !has_draft) || (command.admin_only() && !is_admin) {
+            continue;
+        }
+        help_message += format!(
+            "- /{} - {}\n",
+            command.name(),
+            command.description(language)
+        )
+        .as_str();
+    }
+    if !has_draft {
+        help_message +=
+            "\nMore commands will be available when you are working on a new omikuji.\n";
+    }
+    help_message += "\nYou may use [Telegram Markdown](https://sourceforge.net/p/telegram/wiki/markdown_syntax/) \
+        to format your message as well when entering descriptions.";
+    api.send_text(from, help_message.as_str()).await?;
+    Ok(())
+}
+
+// Welcome a new user with the main menu
+pub(super) async fn start(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    main_menu(
+        from,
+        api,
+        repository,
+        "Welcome to use NUSCAS's Omikuji Bot!",
+    )
+    .await
+}
+
+// "/start ref_<tg_id>" from an invite link, the referrer is credited for users who are new to the bot
+pub(super) async fn start_referred(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    argument: &str,
+) -> Result<(), BotError> {
+    let referrer = argument.trim_start_matches("ref_").parse::<i64>().ok();
+    if let Some(referrer) = referrer {
+        let tg_id = from.id;
+        // Users who have drawn or written anything already found the bot on their own
+        let is_new = repository.find_draws_by_user(tg_id).await?.is_empty()
+            && repository.find_omikujis_by_author(tg_id).await?.is_empty();
+        if referrer != tg_id
+            && is_new
+            && repository.find_user(referrer).await?.is_some()
+            && repository.set_referrer(tg_id, referrer).await?
+        {
+            // The referrer might have blocked the bot, so the error is ignored here
+            #[allow(unused_must_use)]
+            {
+                quiet::notify(
+                    api,
+                    repository,
+                    SendMessage::new(
+                        ChatId(referrer),
+                        "Someone has just joined through your invite link! (See how many you \
+                        have invited in /invites)",
+                    ),
+                )
+                .await;
+            }
+        }
+    }
+    start(from, api, repository).await
+}
+
+// Show the main menu, either as inline buttons or as a persistent quick action keyboard
+pub(super) async fn main_menu(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    greeting: &str,
+) -> Result<(), BotError> {
+    let settings = repository.get_user_settings(from.id).await?;
+    if settings.reply_keyboard {
+        let buttons = Command::iter()
+            .filter_map(|command| command.quick_action(settings.language()))
+            .map(KeyboardButton::new);
+        let keyboard = KeyboardMarkup::new(vec![buttons]).resize_keyboard(true);
+        api.send_message(
+            SendMessage::new(
+                ChatId(from.id),
+                format!(
+                    "{}\nPick what you want to do from the keyboard below!",
+                    greeting
+                ),
+            )
+            .reply_markup(keyboard),
+        )
+        .await?;
+        return Ok(());
+    }
+    // Also reset the quick action keyboard, in case it was turned off
+    api.send_message(
+        SendMessage::new(ChatId(from.id), greeting).reply_markup(KeyboardRemove::new()),
+    )
+    .await?;
+    let keyboard = KeyboardBuilder::new()
+        .columns(2)
+        .button("Create new Omikuji", "new")
+        .button("Draw an Omikuji slip", "draw")
+        .build();
+    api.send_message(
+        SendMessage::new(ChatId(from.id), "Pick what you want to do!").reply_markup(keyboard),
+    )
+    .await?;
+    Ok(())
+}
+
+pub(super) async fn about(from: &Sender, api: &dyn BotApi) -> Result<(), BotError> {
+    api.send_text(
+        from,
+        "This is a bot used for storing and drawing Omikuji strips, written by @FSGMHoward.\n\
+        Source code can be found on https://github.com/fsgmhoward/omikuji_bot",
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,148 @@
+use crate::bot_api::BotApi;
+use crate::config::{get_weekly_poll_chat, parse_recipient};
+use crate::error::BotError;
+use crate::handlers::achievements;
+use crate::models::{Language, NewWeeklyPoll, Omikuji, OmikujiMessage, WeeklyPoll};
+use crate::repository::Repository;
+use chrono::{Datelike, Duration, Local, Timelike, Weekday};
+use teloxide_core::payloads::{SendMessage, SendPoll, StopPoll};
+use teloxide_core::types::{MessageId, Recipient};
+
+// Telegram allows up to 10 options, fewer keep the poll readable
+const MAX_OPTIONS: usize = 5;
+// Telegram limits options to 100 characters
+const MAX_OPTION_LENGTH: usize = 100;
+
+// Entry for the weekly poll job, called regularly by the main loop
+// Polls are run on Mondays after POLL_HOUR (local time)
+pub async fn poll_entry(api: &dyn BotApi, repository: &dyn Repository) -> Result<(), BotError> {
+    const POLL_HOUR: u32 = 12;
+    let chat = match get_weekly_poll_chat() {
+        Some(chat) => chat,
+        None => return Ok(()),
+    };
+    let now = Local::now().naive_local();
+    if now.weekday() != Weekday::Mon || now.hour() < POLL_HOUR {
+        return Ok(());
+    }
+    // The chat is independent from the users, so its errors are only logged
+    if let Err(e) = weekly_poll_round(api, repository, chat).await {
+        println!("Failed to run the weekly poll: {}", e);
+    }
+    Ok(())
+}
+
+// Close the poll of the past week, crowning its winner, and open one among the top strips of
+// this week
+// The open poll is kept in the database, so a round is only run once a day, even after restarts
+pub async fn weekly_poll_round(
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    chat: Recipient,
+) -> Result<(), BotError> {
+    let now = Local::now().naive_local();
+    if let Some(poll) = repository.find_open_weekly_poll().await? {
+        if poll.created_at.date() == now.date() {
+            return Ok(());
+        }
+        close_poll(api, repository, &poll).await?;
+    }
+    let candidates = repository
+        .top_omikujis(now - Duration::days(7), MAX_OPTIONS)
+        .await?;
+    // A poll needs at least two options
+    if candidates.len() < 2 {
+        return Ok(());
+    }
+    let mut options = Vec::new();
+    for omikuji in &candidates {
+        options.push(option_text(omikuji)?);
+    }
+    let message = api
+        .send_poll(SendPoll::new(
+            chat.clone(),
+            "🏆 Omikuji of the week: which of this week's strips do you like best?",
+            options,
+        ))
+        .await?;
+    let ids: Vec<String> = candidates
+        .iter()
+        .map(|omikuji| omikuji.id.to_string())
+        .collect();
+    repository
+        .insert_weekly_poll(&NewWeeklyPoll {
+            chat: recipient_key(&chat).as_str(),
+            message_id: message.id.0,
+            candidates: ids.join(",").as_str(),
+        })
+        .await?;
+    Ok(())
+}
+
+// The option with the most votes wins, the better ranked strip on ties
+async fn close_poll(
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    poll: &WeeklyPoll,
+) -> Result<(), BotError> {
+    let chat = parse_recipient(poll.chat.as_str());
+    let mut options = Vec::new();
+    if let Some(chat) = &chat {
+        // The poll might have been deleted, which leaves the week without a winner
+        match api
+            .stop_poll(StopPoll::new(chat.clone(), MessageId(poll.message_id)))
+            .await
+        {
+            Ok(result) => options = result.options,
+            Err(e) => println!("Failed to stop the weekly poll {}: {}", poll.id, e),
+        }
+    }
+    let mut winner: Option<(u32, i32)> = None;
+    for (omikuji_id, option) in poll.candidate_ids().into_iter().zip(options) {
+        if option.voter_count > winner.map_or(0, |(_, votes)| votes) {
+            winner = Some((omikuji_id, option.voter_count));
+        }
+    }
+    repository
+        .close_weekly_poll(poll.id, winner.map(|(omikuji_id, _)| omikuji_id))
+        .await?;
+    let (omikuji_id, votes) = match winner {
+        Some(winner) => winner,
+        None => return Ok(()),
+    };
+    if let (Some(chat), Some(omikuji)) = (chat, repository.find_omikuji(omikuji_id).await?) {
+        let text = format!(
+            "🏆 The omikuji of the week is #{}, with {} votes:\n\n{}",
+            omikuji.id,
+            votes,
+            option_text(&omikuji)?
+        );
+        api.send_message(SendMessage::new(chat, text)).await?;
+        achievements::evaluate(api, repository, omikuji.tg_id).await?;
+    }
+    Ok(())
+}
+
+// Class and description of a strip, cut to fit a poll option
+fn option_text(omikuji: &Omikuji) -> Result<String, BotError> {
+    let message: OmikujiMessage = serde_json::from_str(omikuji.message.as_str())?;
+    let mut text = format!("#{}", omikuji.id);
+    if let Some(class) = &message.class {
+        text += format!(" {}", class.name(Language::English)).as_str();
+    }
+    if let Some(description) = &message.description {
+        text += format!(": {}", description).as_str();
+    }
+    if text.chars().count() > MAX_OPTION_LENGTH {
+        text = text.chars().take(MAX_OPTION_LENGTH - 1).collect::<String>() + "…";
+    }
+    Ok(text)
+}
+
+// The chat as written in WEEKLY_POLL_ID, to be parsed again when the poll is closed
+fn recipient_key(chat: &Recipient) -> String {
+    match chat {
+        Recipient::Id(id) => id.0.to_string(),
+        Recipient::ChannelUsername(username) => username.clone(),
+    }
+}
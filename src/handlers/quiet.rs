@@ -0,0 +1,138 @@
+use crate::bot_api::BotApi;
+use crate::error::BotError;
+use crate::frontends::Sender;
+use crate::handlers::note_failure;
+use crate::models::{NewDeferredMessage, UserSettings};
+use crate::repository::Repository;
+use crate::telegram_ext::ApiExtension;
+use chrono::NaiveTime;
+use serde::Deserialize;
+use teloxide_core::payloads::setters::*;
+use teloxide_core::payloads::SendMessage;
+use teloxide_core::types::{ChatId, ParseMode, Recipient, ReplyMarkup};
+
+// Show, set or turn off the quiet hours of the user, e.g. "/quiet 23:00-08:00"
+pub(super) async fn quiet(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    argument: &str,
+) -> Result<(), BotError> {
+    let mut settings = repository.get_user_settings(from.id).await?;
+    let text = match argument.trim() {
+        "" => match (settings.quiet_start, settings.quiet_end) {
+            (Some(start), Some(end)) => format!(
+                "Your quiet hours are from {} to {}. Notifications due then are sent once they \
+                are over. (Turn them off with /quiet off)",
+                start.format("%H:%M"),
+                end.format("%H:%M")
+            ),
+            _ => String::from(
+                "You have no quiet hours. Set them like /quiet 23:00-08:00, and notifications \
+                due then are sent once they are over.",
+            ),
+        },
+        "off" => {
+            settings.quiet_start = None;
+            settings.quiet_end = None;
+            repository.update_user_settings(&settings).await?;
+            String::from("Your quiet hours are turned off.")
+        }
+        argument => match parse_quiet_hours(argument) {
+            Some((start, end)) => {
+                settings.quiet_start = Some(start);
+                settings.quiet_end = Some(end);
+                repository.update_user_settings(&settings).await?;
+                format!(
+                    "Got it, you will not be disturbed from {} to {}.",
+                    start.format("%H:%M"),
+                    end.format("%H:%M")
+                )
+            }
+            None => String::from("Please give your quiet hours like /quiet 23:00-08:00."),
+        },
+    };
+    api.send_text(from, text.as_str()).await?;
+    Ok(())
+}
+
+// Quiet hours like 23:00-08:00 (or 23:00~08:00), which must not start and end at the same time
+fn parse_quiet_hours(text: &str) -> Option<(NaiveTime, NaiveTime)> {
+    let (start, end) = text.split_once(['-', '–', '~', '〜'])?;
+    let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").ok()?;
+    let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").ok()?;
+    Some((start, end)).filter(|(start, end)| start != end)
+}
+
+// Send a notification to a user, or hold it back until their quiet hours are over
+pub(crate) async fn notify(
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    message: SendMessage,
+) -> Result<(), BotError> {
+    if let Recipient::Id(ChatId(tg_id)) = message.chat_id {
+        let settings = repository.get_user_settings(tg_id).await?;
+        return notify_with(api, repository, &settings, message).await;
+    }
+    api.send_message(message).await?;
+    Ok(())
+}
+
+// Like notify, for jobs which have loaded the settings of the recipients already
+pub(crate) async fn notify_with(
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    settings: &UserSettings,
+    message: SendMessage,
+) -> Result<(), BotError> {
+    // Quiet hours are in the time zone of the user
+    if settings.is_quiet(settings.local_now().time()) {
+        repository
+            .defer_message(&NewDeferredMessage {
+                tg_id: settings.tg_id,
+                message: serde_json::to_string(&message)?.as_str(),
+            })
+            .await?;
+        return Ok(());
+    }
+    api.send_message(message).await?;
+    Ok(())
+}
+
+// The parts of a held back sendMessage request which are needed to send it again
+#[derive(Deserialize)]
+struct Deferred {
+    text: String,
+    parse_mode: Option<ParseMode>,
+    reply_markup: Option<ReplyMarkup>,
+}
+
+// Entry for periodic jobs, called regularly by the main loop
+// Notifications held back are sent once the quiet hours of their recipients are over
+pub async fn quiet_entry(api: &dyn BotApi, repository: &dyn Repository) -> Result<(), BotError> {
+    for deferred in repository.find_deferred_messages().await? {
+        let settings = repository.get_user_settings(deferred.tg_id).await?;
+        if settings.is_quiet(settings.local_now().time()) {
+            continue;
+        }
+        // Taken off first, so that a message which cannot be sent is not tried forever
+        repository.delete_deferred_message(deferred.id).await?;
+        let held: Deferred = serde_json::from_str(deferred.message.as_str())?;
+        let mut message = SendMessage::new(ChatId(deferred.tg_id), held.text);
+        if let Some(parse_mode) = held.parse_mode {
+            message = message.parse_mode(parse_mode);
+        }
+        if let Some(reply_markup) = held.reply_markup {
+            message = message.reply_markup(reply_markup);
+        }
+        // A recipient might have blocked the bot in the meantime, which should not stop the others
+        if let Err(e) = api.send_message(message).await {
+            println!(
+                "Failed to send held back message to {}: {}",
+                deferred.tg_id, e
+            );
+            note_failure(repository, deferred.tg_id, &e).await?;
+        }
+    }
+    Ok(())
+}
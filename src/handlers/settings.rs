@@ -0,0 +1,411 @@
+use crate::bot_api::BotApi;
+use crate::config::get_tts_url;
+use crate::error::BotError;
+use crate::frontends::Sender;
+use crate::keyboard::KeyboardBuilder;
+use crate::models::{DrawPool, Language, OmikujiMessage};
+use crate::repository::Repository;
+use crate::telegram_ext::ApiExtension;
+use chrono_tz::Tz;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::str::FromStr;
+use strum::IntoEnumIterator;
+use teloxide_core::payloads::setters::*;
+use teloxide_core::payloads::{SendDocument, SendMessage};
+use teloxide_core::types::{ButtonRequest, ChatId, InputFile, KeyboardButton, KeyboardMarkup};
+
+// Show the settings menu, where each button toggles one of the settings
+pub(super) async fn settings(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let settings = repository.get_user_settings(from.id).await?;
+    let on_off = |value: bool| if value { "On" } else { "Off" };
+    let mut keyboard = KeyboardBuilder::new()
+        .button(
+            format!("Vote notifications: {}", on_off(settings.notifications)),
+            "settings/notifications",
+        )
+        .button(
+            format!("Language: {:?}", settings.language()),
+            "settings/language",
+        )
+        .button(
+            format!("Daily omikuji: {}", on_off(settings.daily_subscription)),
+            "settings/daily",
+        )
+        .button(
+            format!("Quick action keyboard: {}", on_off(settings.reply_keyboard)),
+            "settings/keyboard",
+        )
+        .button(format!("Draw from: {:?}", settings.pool()), "settings/pool")
+        .button(
+            format!("Credit me on my strips: {}", on_off(settings.credit)),
+            "settings/credit",
+        )
+        .button(
+            format!("Weekly digest: {}", on_off(settings.weekly_digest)),
+            "settings/digest",
+        )
+        .button(
+            match (settings.quiet_start, settings.quiet_end) {
+                (Some(start), Some(end)) => format!(
+                    "Quiet hours: {}-{}",
+                    start.format("%H:%M"),
+                    end.format("%H:%M")
+                ),
+                _ => String::from("Quiet hours: Off"),
+            },
+            "settings/quiet",
+        )
+        .button(
+            format!(
+                "Time zone: {}",
+                settings
+                    .time_zone()
+                    .map_or("Same as the bot", |time_zone| time_zone.name())
+            ),
+            "settings/timezone",
+        );
+    if get_tts_url().is_some() {
+        keyboard = keyboard.button(
+            format!("Voice messages: {}", on_off(settings.voice)),
+            "settings/voice",
+        );
+    }
+    api.send_message(
+        SendMessage::new(
+            ChatId(from.id),
+            "Here are your settings. Tap a button to change it.",
+        )
+        .reply_markup(keyboard.build()),
+    )
+    .await?;
+    Ok(())
+}
+
+// Pick a language, or go back to the language of the Telegram client
+pub(super) async fn language(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let settings = repository.get_user_settings(from.id).await?;
+    let mut keyboard = KeyboardBuilder::new();
+    for language in Language::iter() {
+        keyboard = keyboard.button(
+            format!("{:?}", language),
+            format!("language/{:?}", language),
+        );
+    }
+    keyboard = keyboard.button("Same as my Telegram app", "language/auto");
+    api.send_message(
+        SendMessage::new(
+            ChatId(from.id),
+            format!(
+                "The bot is currently in {:?}. Which language do you prefer?",
+                settings.language()
+            ),
+        )
+        .reply_markup(keyboard.build()),
+    )
+    .await?;
+    Ok(())
+}
+
+pub(super) async fn set_language(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    let language = match payload {
+        "auto" => Language::detect(from.language_code.as_deref()),
+        _ => match Language::from_str(payload) {
+            Ok(language) => language,
+            Err(_) => {
+                api.send_text(from, "Malformed callback request.").await?;
+                return Ok(());
+            }
+        },
+    };
+    let mut settings = repository.get_user_settings(from.id).await?;
+    settings.language = format!("{:?}", language);
+    repository.update_user_settings(&settings).await?;
+    api.send_text(from, format!("The bot is now in {:?}.", language).as_str())
+        .await?;
+    Ok(())
+}
+
+pub(super) async fn toggle_setting(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    let mut user_settings = repository.get_user_settings(from.id).await?;
+    match payload {
+        "notifications" => user_settings.notifications = !user_settings.notifications,
+        "daily" => user_settings.daily_subscription = !user_settings.daily_subscription,
+        "voice" => user_settings.voice = !user_settings.voice,
+        "credit" => user_settings.credit = !user_settings.credit,
+        "digest" => user_settings.weekly_digest = !user_settings.weekly_digest,
+        // Quiet hours cannot be toggled, they are set with the command
+        "quiet" => return super::quiet::quiet(from, api, repository, "").await,
+        "timezone" => return time_zone(from, api, repository, "").await,
+        "keyboard" => {
+            user_settings.reply_keyboard = !user_settings.reply_keyboard;
+            repository.update_user_settings(&user_settings).await?;
+            // The keyboard is shown (or removed) together with the main menu
+            return super::main_menu(from, api, repository, "Your main menu has been updated.")
+                .await;
+        }
+        "language" => {
+            // Cycle through all available languages
+            let languages: Vec<Language> = Language::iter().collect();
+            let index = languages
+                .iter()
+                .position(|language| *language == user_settings.language())
+                .unwrap_or(0);
+            user_settings.language = format!("{:?}", languages[(index + 1) % languages.len()]);
+        }
+        "pool" => {
+            let pools: Vec<DrawPool> = DrawPool::iter().collect();
+            let index = pools
+                .iter()
+                .position(|pool| *pool == user_settings.pool())
+                .unwrap_or(0);
+            user_settings.pool = format!("{:?}", pools[(index + 1) % pools.len()]);
+        }
+        _ => {
+            api.send_text(from, "Malformed callback request.").await?;
+            return Ok(());
+        }
+    }
+    repository.update_user_settings(&user_settings).await?;
+    settings(from, api, repository).await
+}
+
+// Show, set or reset the time zone which the daily omikuji, the weekly digest and the quiet
+// hours of the user follow, e.g. "/timezone Asia/Singapore"
+pub(super) async fn time_zone(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    argument: &str,
+) -> Result<(), BotError> {
+    let mut settings = repository.get_user_settings(from.id).await?;
+    let text = match argument.trim() {
+        "" => {
+            let text = match settings.time_zone() {
+                Some(time_zone) => format!(
+                    "Your time zone is {}, where it is now {}. Change it like /timezone \
+                    Asia/Singapore, or follow the time of the bot again with /timezone off.",
+                    time_zone.name(),
+                    settings.local_now().format("%H:%M")
+                ),
+                None => format!(
+                    "You follow the time of the bot, where it is now {}. Set your time zone \
+                    like /timezone Asia/Singapore, or share your location to have it guessed.",
+                    settings.local_now().format("%H:%M")
+                ),
+            };
+            let keyboard =
+                KeyboardMarkup::new(vec![vec![
+                    KeyboardButton::new("Share my location").request(ButtonRequest::Location)
+                ]])
+                .resize_keyboard(true)
+                .one_time_keyboard(true);
+            api.send_message(SendMessage::new(ChatId(from.id), text).reply_markup(keyboard))
+                .await?;
+            return Ok(());
+        }
+        "off" => {
+            settings.time_zone = None;
+            repository.update_user_settings(&settings).await?;
+            String::from("You follow the time of the bot again.")
+        }
+        argument => match Tz::from_str(argument) {
+            Ok(time_zone) => {
+                settings.time_zone = Some(time_zone.name().to_string());
+                repository.update_user_settings(&settings).await?;
+                format!(
+                    "Your time zone is now {}, where it is {}.",
+                    time_zone.name(),
+                    settings.local_now().format("%H:%M")
+                )
+            }
+            Err(_) => String::from(
+                "Unknown time zone. Please give it like /timezone Asia/Singapore, see \
+                https://en.wikipedia.org/wiki/List_of_tz_database_time_zones for all of them.",
+            ),
+        },
+    };
+    api.send_text(from, text.as_str()).await?;
+    Ok(())
+}
+
+// Send everything stored about the user as a JSON file
+pub(super) async fn my_data(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let data = user_data(repository, store, from.id).await?;
+    let document = InputFile::memory(serde_json::to_vec_pretty(&data)?).file_name("mydata.json");
+    api.send_document(
+        SendDocument::new(ChatId(from.id), document)
+            .caption("Here is everything we have stored about you."),
+    )
+    .await?;
+    Ok(())
+}
+
+// Everything stored about the user, as sent by /mydata
+// Votes are part of the draws they were cast on
+pub async fn user_data(
+    repository: &dyn Repository,
+    store: &HashMap<i64, OmikujiMessage>,
+    tg_id: i64,
+) -> Result<Value, BotError> {
+    let format_time = |time: chrono::NaiveDateTime| time.format("%Y-%m-%dT%H:%M:%S").to_string();
+    let user = repository.find_user(tg_id).await?.map(|user| {
+        json!({
+            "name": user.tg_name,
+            "username": user.tg_username,
+            "language_code": user.language_code,
+            "banned": user.banned,
+            "referred_by": user.referred_by,
+            "created_at": format_time(user.created_at),
+            "updated_at": format_time(user.updated_at),
+        })
+    });
+    let settings = repository.get_user_settings(tg_id).await?;
+    let mut omikujis = Vec::new();
+    for omikuji in repository.find_omikujis_by_author(tg_id).await? {
+        omikujis.push(json!({
+            "id": omikuji.id,
+            "message": serde_json::from_str::<Value>(omikuji.message.as_str())?,
+            "vote_count": omikuji.vote_count,
+            "anonymous": omikuji.anonymous,
+            "created_at": format_time(omikuji.created_at),
+        }));
+    }
+    let draws: Vec<Value> = repository
+        .find_draws_by_user(tg_id)
+        .await?
+        .into_iter()
+        .map(|draw| {
+            json!({
+                "omikuji_id": draw.omikuji_id,
+                "created_at": format_time(draw.created_at),
+                "vote": draw.vote,
+                "tied_at": draw.tied_at.map(format_time),
+            })
+        })
+        .collect();
+    let comments: Vec<Value> = repository
+        .find_comments_by_user(tg_id)
+        .await?
+        .into_iter()
+        .map(|comment| {
+            json!({
+                "id": comment.id,
+                "omikuji_id": comment.omikuji_id,
+                "parent_id": comment.parent_id,
+                "text": comment.text,
+                "created_at": format_time(comment.created_at),
+            })
+        })
+        .collect();
+    let reactions: Vec<Value> = repository
+        .find_reactions_by_user(tg_id)
+        .await?
+        .into_iter()
+        .map(|(omikuji_id, reaction)| json!({ "omikuji_id": omikuji_id, "reaction": reaction }))
+        .collect();
+    let booth_draws: Vec<Value> = repository
+        .find_booth_draws_by_user(tg_id)
+        .await?
+        .into_iter()
+        .map(|draw| {
+            json!({
+                "chat_id": draw.chat_id,
+                "visitor": draw.visitor,
+                "omikuji_id": draw.omikuji_id,
+                "created_at": format_time(draw.created_at),
+            })
+        })
+        .collect();
+    let favorites: Vec<u32> = repository
+        .find_favorites(tg_id, 0, i64::MAX)
+        .await?
+        .into_iter()
+        .map(|omikuji| omikuji.id)
+        .collect();
+    Ok(json!({
+        "tg_id": tg_id,
+        "user": user,
+        "settings": {
+            "notifications": settings.notifications,
+            "language": settings.language,
+            "daily_subscription": settings.daily_subscription,
+            "reply_keyboard": settings.reply_keyboard,
+            "voice": settings.voice,
+            "pool": settings.pool,
+            "credit": settings.credit,
+            "weekly_digest": settings.weekly_digest,
+            "quiet_start": settings.quiet_start.map(|time| time.format("%H:%M").to_string()),
+            "quiet_end": settings.quiet_end.map(|time| time.format("%H:%M").to_string()),
+            "time_zone": settings.time_zone,
+        },
+        "draft": store.get(&tg_id),
+        "omikujis": omikujis,
+        "draws": draws,
+        "comments": comments,
+        "reactions": reactions,
+        "booth_draws": booth_draws,
+        "favorites": favorites,
+        "badges": repository.find_badges(tg_id).await?,
+    }))
+}
+
+// Ask for a confirmation before deleting all data of the user
+pub(super) async fn forget_me(from: &Sender, api: &dyn BotApi) -> Result<(), BotError> {
+    let keyboard = KeyboardBuilder::new()
+        .columns(2)
+        .button("Yes, forget me", "forgetme/confirm")
+        .button("No, keep my data", "forgetme/cancel")
+        .build();
+    api.send_message(
+        SendMessage::new(
+            ChatId(from.id),
+            "This deletes your settings, your draw history and the omikuji you are working on. \
+            Your saved omikuji strips are kept, but no longer linked to you. Are you sure?",
+        )
+        .reply_markup(keyboard),
+    )
+    .await?;
+    Ok(())
+}
+
+pub(super) async fn confirm_forget_me(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    if payload != "confirm" {
+        api.send_text(from, "OK, nothing has been deleted.").await?;
+        return Ok(());
+    }
+    repository.forget_user(from.id).await?;
+    store.remove(&from.id);
+    api.send_text(from, "Done. Everything we knew about you has been deleted.")
+        .await?;
+    Ok(())
+}
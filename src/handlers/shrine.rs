@@ -0,0 +1,95 @@
+use crate::bot_api::BotApi;
+use crate::config::get_shrine_board;
+use crate::error::BotError;
+use crate::models::JobRun;
+use crate::repository::Repository;
+use chrono::{Datelike, Local, NaiveDate};
+use teloxide_core::payloads::{EditMessageText, SendMessage};
+use teloxide_core::types::{MessageId, Recipient};
+
+// The message on the shrine board is kept as the run of this job, with the month it is for and
+// the bad fortunes tied in that month it shows
+const SHRINE_JOB: &str = "shrine_board";
+
+// Entry for the shrine board job, called regularly by the main loop
+// The board is cleared by posting a new message at the start of each month
+pub async fn shrine_entry(api: &dyn BotApi, repository: &dyn Repository) -> Result<(), BotError> {
+    let shrine = match get_shrine_board() {
+        Some(shrine) => shrine,
+        None => return Ok(()),
+    };
+    let today = Local::now().naive_local().date();
+    let month = today.with_day(1).expect("every month has a first day");
+    let since = month
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight is a valid time");
+    let tally = repository.count_tied_draws(since).await?;
+    // The board is independent from the users, so its errors are only logged
+    if let Err(e) = update_board(api, repository, shrine, month, tally).await {
+        println!("Failed to update the shrine board: {}", e);
+    }
+    Ok(())
+}
+
+async fn update_board(
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    shrine: Recipient,
+    month: NaiveDate,
+    tally: i64,
+) -> Result<(), BotError> {
+    let board = repository
+        .find_job_runs(SHRINE_JOB)
+        .await?
+        .into_iter()
+        .next();
+    match board {
+        Some(JobRun {
+            ran_on,
+            message_id: Some(message_id),
+            count,
+            ..
+        }) if ran_on == month => {
+            if count != Some(tally) {
+                let text = board_text(month, tally);
+                api.edit_text(EditMessageText::new(shrine, MessageId(message_id), text))
+                    .await?;
+                repository
+                    .record_job_run(&JobRun {
+                        message_id: Some(message_id),
+                        count: Some(tally),
+                        ..JobRun::new(SHRINE_JOB, 0, month)
+                    })
+                    .await?;
+            }
+        }
+        _ => {
+            let message = api
+                .send_message(SendMessage::new(shrine, board_text(month, tally)))
+                .await?;
+            repository
+                .record_job_run(&JobRun {
+                    message_id: Some(message.id.0),
+                    count: Some(tally),
+                    ..JobRun::new(SHRINE_JOB, 0, month)
+                })
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+// Only the number of bad fortunes is shown, never who tied them
+fn board_text(month: NaiveDate, tally: i64) -> String {
+    format!(
+        "🪢 Shrine board of {}\n\n{} {} tied this month. \
+        Drew a curse? Tie it here and leave it behind.",
+        month.format("%B %Y"),
+        tally,
+        if tally == 1 {
+            "bad fortune"
+        } else {
+            "bad fortunes"
+        }
+    )
+}
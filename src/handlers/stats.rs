@@ -0,0 +1,240 @@
+use crate::bot_api::BotApi;
+use crate::config::{get_bot_username, get_draws_daily};
+use crate::error::BotError;
+use crate::frontends::Sender;
+use crate::handlers::{last_runs, note_failure, quiet};
+use crate::models::{Badge, JobRun, OmikujiClass, OmikujiMessage};
+use crate::repository::Repository;
+use crate::telegram_ext::ApiExtension;
+use chrono::{Datelike, Duration, Local, NaiveDate, Timelike, Weekday};
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use teloxide_core::payloads::SendMessage;
+use teloxide_core::types::ChatId;
+
+// Streak length from which a user may draw twice as many strips per day
+pub const STREAK_UNLOCK: usize = 7;
+
+const DIGEST_JOB: &str = "weekly_digest";
+
+// Entry for the weekly digest, called regularly by the main loop
+// Digests are sent once a week, on Monday after DIGEST_HOUR in the time zone of each subscriber
+pub async fn weekly_entry(api: &dyn BotApi, repository: &dyn Repository) -> Result<(), BotError> {
+    const DIGEST_HOUR: u32 = 9;
+    let since = Local::now().naive_local() - Duration::days(7);
+    let last_sent = last_runs(repository, DIGEST_JOB).await?;
+    for settings in repository.get_digest_subscribers().await? {
+        let subscriber = settings.tg_id;
+        let local = settings.local_now();
+        if local.weekday() != Weekday::Mon
+            || local.hour() < DIGEST_HOUR
+            || last_sent.get(&subscriber) == Some(&local.date())
+        {
+            continue;
+        }
+        repository
+            .record_job_run(&JobRun::new(DIGEST_JOB, subscriber, local.date()))
+            .await?;
+        let digest = repository.author_digest(subscriber, since).await?;
+        // Authors are not bothered when nothing happened to their strips
+        if digest.draws == 0 && digest.comments == 0 {
+            continue;
+        }
+        let text = format!(
+            "📬 Your week on the omikuji bot\n\n\
+            Your strips were drawn {} times, with {} upvotes and {} downvotes.\n\
+            New comments: {}{}\n\n\
+            (You can turn off the weekly digest in /settings)",
+            digest.draws,
+            digest.upvotes,
+            digest.downvotes,
+            digest.comments,
+            if digest.comments > 0 {
+                " (read them with /comments)"
+            } else {
+                ""
+            }
+        );
+        // A subscriber might have blocked the bot, which should not stop the others
+        if let Err(e) = quiet::notify_with(
+            api,
+            repository,
+            &settings,
+            SendMessage::new(ChatId(subscriber), text),
+        )
+        .await
+        {
+            println!("Failed to send weekly digest to {}: {}", subscriber, e);
+            note_failure(repository, subscriber, &e).await?;
+        }
+    }
+    Ok(())
+}
+
+// Summarize the omikuji strips written by the user, the luck of their draws and their badges
+pub(super) async fn stats(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let omikujis = repository.find_omikujis_by_author(from.id).await?;
+    let luck = average_luck(repository, from.id).await?;
+    let badges = badges(repository, from.id).await?;
+    if omikujis.is_empty() {
+        let mut text = String::from(
+            "You have not written any omikuji strip yet. Tap Create to write your first one!",
+        );
+        if let Some(luck) = luck {
+            text += format!("\n\n{}", luck).as_str();
+        }
+        if let Some(badges) = badges {
+            text += format!("\n\n{}", badges).as_str();
+        }
+        api.send_text(from, text.as_str()).await?;
+        return Ok(());
+    }
+    let votes: i32 = omikujis.iter().map(|omikuji| omikuji.vote_count).sum();
+    let hidden = omikujis
+        .iter()
+        .filter(|omikuji| omikuji.quarantined)
+        .count();
+    let mut text = String::from("*Your omikuji strips*\n");
+    text += format!("Strips written: {}\n", omikujis.len()).as_str();
+    text += format!("Total votes: {:+}\n", votes).as_str();
+    if hidden > 0 {
+        text += format!("Quarantined, waiting for a moderator: {}\n", hidden).as_str();
+    }
+    if let Some(luck) = luck {
+        text += format!("\n{}", luck).as_str();
+    }
+    if let Some(badges) = badges {
+        text += format!("\n{}", badges).as_str();
+    }
+    api.send_text(from, text.as_str()).await?;
+    Ok(())
+}
+
+// Badges awarded to the user, badges unknown to this build are left out
+async fn badges(repository: &dyn Repository, tg_id: i64) -> Result<Option<String>, BotError> {
+    let language = repository.get_user_settings(tg_id).await?.language();
+    let badges: Vec<&str> = repository
+        .find_badges(tg_id)
+        .await?
+        .iter()
+        .filter_map(|badge| Badge::from_str(badge).ok())
+        .map(|badge| badge.name(language))
+        .collect();
+    if badges.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(format!("Badges: {}", badges.join(", "))))
+}
+
+// Average class of the strips drawn by the user, strips of class Other are left out
+async fn average_luck(repository: &dyn Repository, tg_id: i64) -> Result<Option<String>, BotError> {
+    let language = repository.get_user_settings(tg_id).await?.language();
+    let mut ranks = Vec::new();
+    // Tied bad fortunes are left behind at the shrine, and do not weigh on the luck
+    for draw in repository.find_draws_by_user(tg_id).await? {
+        if draw.tied_at.is_some() {
+            continue;
+        }
+        // Strips may have been deleted since
+        if let Some(omikuji) = repository.find_omikuji(draw.omikuji_id).await? {
+            let message: OmikujiMessage = serde_json::from_str(omikuji.message.as_str())?;
+            if let Some(rank) = message.class.and_then(|class| class.rank()) {
+                ranks.push(rank as f64);
+            }
+        }
+    }
+    if ranks.is_empty() {
+        return Ok(None);
+    }
+    let average = ranks.iter().sum::<f64>() / ranks.len() as f64;
+    Ok(OmikujiClass::from_rank(average).map(|class| {
+        format!(
+            "Average luck of your {} draws: {} (better than {}% of possible fortunes)",
+            ranks.len(),
+            class.name(language),
+            class.better_than().unwrap_or_default()
+        )
+    }))
+}
+
+// Number of consecutive days (up to today) the user has drawn on, counted from the draw history
+// A streak is kept until the end of the day after the last draw
+pub async fn streak(repository: &dyn Repository, tg_id: i64) -> Result<usize, BotError> {
+    let dates: BTreeSet<NaiveDate> = repository
+        .find_draws_by_user(tg_id)
+        .await?
+        .iter()
+        .map(|draw| draw.created_at.date())
+        .collect();
+    let mut day = Local::now().naive_local().date();
+    if !dates.contains(&day) {
+        day -= Duration::days(1);
+    }
+    let mut streak = 0;
+    while dates.contains(&day) {
+        streak += 1;
+        day -= Duration::days(1);
+    }
+    Ok(streak)
+}
+
+// Show the current streak, and what it takes to unlock extra draws
+pub(super) async fn show_streak(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let streak = streak(repository, from.id).await?;
+    let mut text = if streak == 0 {
+        String::from("You are not on a streak. Draw an omikuji every day to start one!")
+    } else {
+        format!("🔥 {}-day streak", streak)
+    };
+    if get_draws_daily().is_some() {
+        if streak >= STREAK_UNLOCK {
+            text += "\nYou may draw twice as many strips per day while your streak lasts.";
+        } else {
+            text += format!(
+                "\nKeep it up for {} more days to draw twice as many strips per day.",
+                STREAK_UNLOCK - streak
+            )
+            .as_str();
+        }
+    }
+    api.send_text(from, text.as_str()).await?;
+    Ok(())
+}
+
+// Invite link of the user, with their number of invites and the leaderboard of referrers
+pub(super) async fn invites(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    const LEADERBOARD_SIZE: i64 = 10;
+    let mut text = match get_bot_username() {
+        Some(username) => format!(
+            "Invite your friends with this link:\nhttps://t.me/{}?start=ref_{}\n",
+            username, from.id
+        ),
+        None => String::new(),
+    };
+    text += format!(
+        "Users you have invited: {}\n",
+        repository.count_referrals(from.id).await?
+    )
+    .as_str();
+    let referrers = repository.top_referrers(LEADERBOARD_SIZE).await?;
+    if !referrers.is_empty() {
+        text += "\nTop inviters\n";
+        for (rank, (name, count)) in referrers.iter().enumerate() {
+            text += format!("{}. {}: {}\n", rank + 1, name, count).as_str();
+        }
+    }
+    api.send_text(from, text.as_str()).await?;
+    Ok(())
+}
@@ -0,0 +1,250 @@
+use crate::bot_api::BotApi;
+use crate::drafts;
+use crate::error::BotError;
+use crate::frontends::Sender;
+use crate::handlers::create;
+use crate::keyboard::KeyboardBuilder;
+use crate::models::{Language, NewOmikuji, NewTrashItem, Omikuji, OmikujiMessage, TrashKind};
+use crate::repository::Repository;
+use crate::telegram_ext::{ApiExtension, HashMapExtension};
+use chrono::{Duration, Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use teloxide_core::payloads::setters::*;
+use teloxide_core::payloads::SendMessage;
+use teloxide_core::types::ChatId;
+
+// Deleted strips and drafts can be restored for this many days, then they are gone for good
+pub const TRASH_DAYS: i64 = 7;
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+// A strip as kept in the trash, enough to save it again as a new strip
+// Votes are kept as a score, comments and reactions are deleted with the strip
+#[derive(Serialize, Deserialize)]
+struct DeletedStrip {
+    id: u32,
+    message: OmikujiMessage,
+    tg_name: String,
+    community_id: Option<i64>,
+    vote_count: i32,
+    anonymous: bool,
+    expires_at: Option<String>,
+    quarantined: bool,
+}
+
+// Move a strip of the user to the trash, returns the ID of the item to restore it with
+pub(super) async fn trash_strip(
+    repository: &dyn Repository,
+    omikuji: &Omikuji,
+) -> Result<u32, BotError> {
+    let content = serde_json::to_string(&DeletedStrip {
+        id: omikuji.id,
+        message: serde_json::from_str(omikuji.message.as_str())?,
+        tg_name: omikuji.tg_name.clone(),
+        community_id: omikuji.community_id,
+        vote_count: omikuji.vote_count,
+        anonymous: omikuji.anonymous,
+        expires_at: omikuji
+            .expires_at
+            .map(|expires_at| expires_at.format(TIME_FORMAT).to_string()),
+        quarantined: omikuji.quarantined,
+    })?;
+    let trash_id = repository
+        .insert_trash(&NewTrashItem::new(
+            omikuji.tg_id,
+            TrashKind::Strip,
+            content.as_str(),
+        ))
+        .await?;
+    repository.delete_omikuji(omikuji.id).await?;
+    Ok(trash_id)
+}
+
+// Move the draft of the user to the trash, the caller removes it from the store
+pub(super) async fn trash_draft(
+    repository: &dyn Repository,
+    tg_id: i64,
+    omikuji_message: &OmikujiMessage,
+) -> Result<u32, BotError> {
+    let content = drafts::encode(omikuji_message)?;
+    repository
+        .insert_trash(&NewTrashItem::new(
+            tg_id,
+            TrashKind::Draft,
+            content.as_str(),
+        ))
+        .await
+}
+
+// Entry for the trash job, called regularly by the main loop
+pub async fn trash_entry(repository: &dyn Repository) -> Result<(), BotError> {
+    let before = Local::now().naive_local() - Duration::days(TRASH_DAYS);
+    let purged = repository.purge_trash(before).await?;
+    if purged > 0 {
+        println!("Emptied {} items from the trash", purged);
+    }
+    Ok(())
+}
+
+// The strips and drafts the user deleted recently, with a button to restore each of them
+pub(super) async fn trash(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+) -> Result<(), BotError> {
+    let language = repository.get_user_settings(from.id).await?.language();
+    let since = Local::now().naive_local() - Duration::days(TRASH_DAYS);
+    let items = repository.find_trash(from.id, since).await?;
+    if items.is_empty() {
+        api.send_text(
+            from,
+            "Your trash is empty. Strips and drafts you delete are kept here for a while.",
+        )
+        .await?;
+        return Ok(());
+    }
+    let mut text = format!(
+        "You can restore what you deleted in the last {} days:\n",
+        TRASH_DAYS
+    );
+    // Items which cannot be read any more are left for the trash job
+    let labelled = items.iter().filter_map(|item| {
+        let label = match item.kind()? {
+            TrashKind::Strip => serde_json::from_str::<DeletedStrip>(item.content.as_str())
+                .ok()
+                .map(|strip| {
+                    format!(
+                        "Strip #{} ({})",
+                        strip.id,
+                        class_name(&strip.message, language)
+                    )
+                })?,
+            TrashKind::Draft => drafts::decode(item.content.as_str())
+                .ok()
+                .map(|draft| format!("Draft ({})", class_name(&draft, language)))?,
+        };
+        Some((item, label))
+    });
+    let mut keyboard = KeyboardBuilder::new();
+    for (index, (item, label)) in labelled.enumerate() {
+        text += format!(
+            "\n{}. {}, deleted {}",
+            index + 1,
+            label,
+            item.deleted_at.format("%Y-%m-%d %H:%M")
+        )
+        .as_str();
+        keyboard = keyboard.button(
+            format!("♻️ Restore {}", index + 1),
+            format!("trash/restore/{}", item.id),
+        );
+    }
+    api.send_message(
+        SendMessage::new(ChatId(from.id), text).reply_markup(keyboard.columns(2).build()),
+    )
+    .await?;
+    Ok(())
+}
+
+// Handle the buttons of /trash, payload being e.g. "restore/12"
+pub(super) async fn trash_action(
+    from: &Sender,
+    api: &dyn BotApi,
+    store: &mut HashMap<i64, OmikujiMessage>,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    let trash_id = match payload
+        .strip_prefix("restore/")
+        .and_then(|trash_id| trash_id.parse::<u32>().ok())
+    {
+        Some(trash_id) => trash_id,
+        None => {
+            api.send_text(from, "Malformed callback request.").await?;
+            return Ok(());
+        }
+    };
+    let since = Local::now().naive_local() - Duration::days(TRASH_DAYS);
+    // A draft can only be restored into an empty store, checked before taking it out of the trash
+    let item = repository
+        .find_trash(from.id, since)
+        .await?
+        .into_iter()
+        .find(|item| item.id == trash_id);
+    if item.as_ref().and_then(|item| item.kind()) == Some(TrashKind::Draft)
+        && store.get_user_data(from).is_some()
+    {
+        api.send_text(
+            from,
+            "You have to complete (or /cancel) your current strip before restoring another one.",
+        )
+        .await?;
+        return Ok(());
+    }
+    let item = match item {
+        Some(_) => repository.take_trash(from.id, trash_id).await?,
+        None => None,
+    };
+    let item = match item {
+        Some(item) => item,
+        None => {
+            api.send_text(from, "Sorry, this item cannot be restored any more.")
+                .await?;
+            return Ok(());
+        }
+    };
+    match item.kind() {
+        Some(TrashKind::Strip) => {
+            let strip: DeletedStrip = serde_json::from_str(item.content.as_str())?;
+            let expires_at = match &strip.expires_at {
+                Some(expires_at) => Some(
+                    NaiveDateTime::parse_from_str(expires_at, TIME_FORMAT).map_err(|e| {
+                        BotError::State(format!("Strip expiring at {}: {}", expires_at, e))
+                    })?,
+                ),
+                None => None,
+            };
+            // The strip comes back as a new one, which is why it gets a new ID
+            repository
+                .insert_omikuji(&NewOmikuji {
+                    message: serde_json::to_string(&strip.message)?.as_str(),
+                    tg_id: item.tg_id,
+                    tg_name: strip.tg_name.as_str(),
+                    community_id: strip.community_id,
+                    vote_count: strip.vote_count,
+                    anonymous: strip.anonymous,
+                    expires_at,
+                    quarantined: strip.quarantined,
+                })
+                .await?;
+            api.send_text(
+                from,
+                "Your omikuji strip is back! You can find it in /mystrips.",
+            )
+            .await?;
+        }
+        Some(TrashKind::Draft) => {
+            let mut omikuji_message = drafts::decode(item.content.as_str())?;
+            omikuji_message.touched_at = Some(Local::now().naive_local());
+            omikuji_message.reminded = false;
+            store.insert(from.id, omikuji_message);
+            api.send_text(from, "Your omikuji strip is back!").await?;
+            create::resume(from, api, store, repository).await?;
+        }
+        None => {
+            return Err(BotError::State(format!(
+                "Trash item {} is of unknown kind {}",
+                item.id, item.kind
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn class_name(omikuji_message: &OmikujiMessage, language: Language) -> &'static str {
+    omikuji_message
+        .class
+        .as_ref()
+        .map_or("no fortune yet", |class| class.name(language))
+}
@@ -0,0 +1,138 @@
+use crate::bot_api::BotApi;
+use crate::error::BotError;
+use crate::events;
+use crate::events::{Event, ModerationEvent};
+use crate::frontends::Sender;
+use crate::handlers::{achievements, quiet};
+use crate::models::{NewReaction, Reaction};
+use crate::repository::Repository;
+use crate::telegram_ext::ApiExtension;
+use std::str::FromStr;
+use teloxide_core::payloads::SendMessage;
+use teloxide_core::types::ChatId;
+
+pub(super) async fn vote(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    if payload.len() <= 1 {
+        // Malformed payload - this should be +<id> or -<id>
+        api.send_text(from, "Malformed callback request.").await?;
+        return Ok(());
+    }
+    let omikuji_id = &payload[1..payload.len()];
+    if let Ok(omikuji_id) = omikuji_id.parse::<u32>() {
+        if let Some(omikuji) = repository.find_omikuji(omikuji_id).await? {
+            let is_upvote = payload.as_bytes()[0] == b'+';
+            repository
+                .add_vote(omikuji.id, if is_upvote { 1 } else { -1 })
+                .await?;
+            // Outcome of the draw, for comparing draw strategies
+            repository
+                .record_vote(from.id, omikuji.id, if is_upvote { 1 } else { -1 })
+                .await?;
+            events::publish(Event::VoteCast {
+                omikuji_id: omikuji.id,
+                vote: if is_upvote { 1 } else { -1 },
+            });
+            // Downvotes are the "I feel insulted" button, which moderators may want to look at
+            if !is_upvote && events::enabled() {
+                if let Some(voted) = repository.find_omikuji(omikuji.id).await? {
+                    events::notify_moderators(ModerationEvent::Reported {
+                        omikuji_id: voted.id,
+                        vote_count: voted.vote_count,
+                        quarantined: voted.quarantined,
+                    });
+                }
+            }
+            api.send_text(
+                from,
+                format!(
+                    "Successfully {} the omikuji slip!",
+                    if is_upvote { "upvoted" } else { "downvoted" }
+                )
+                .as_str(),
+            )
+            .await?;
+            if omikuji.tg_id != from.id
+                && repository
+                    .get_user_settings(omikuji.tg_id)
+                    .await?
+                    .notifications
+            {
+                // The author might have blocked the bot, so the error is ignored here
+                #[allow(unused_must_use)]
+                {
+                    quiet::notify(
+                        api,
+                        repository,
+                        SendMessage::new(
+                            ChatId(omikuji.tg_id),
+                            format!(
+                                "Someone has just {} your omikuji slip #{}! \
+                                (You can turn off these notifications in /settings)",
+                                if is_upvote { "upvoted" } else { "downvoted" },
+                                omikuji.id
+                            ),
+                        ),
+                    )
+                    .await;
+                }
+            }
+            achievements::evaluate(api, repository, omikuji.tg_id).await?;
+        } else {
+            api.send_text(from, "Requested omikuji cannot be found.")
+                .await?;
+        }
+    } else {
+        api.send_text(from, "Malformed callback request.").await?;
+    }
+    Ok(())
+}
+
+// Buttons of the reaction row, where `payload` is "<id>/<reaction>", e.g. "1/Pray"
+pub(super) async fn react(
+    from: &Sender,
+    api: &dyn BotApi,
+    repository: &dyn Repository,
+    payload: &str,
+) -> Result<(), BotError> {
+    let parsed = payload.split_once('/').and_then(|(omikuji_id, reaction)| {
+        Some((
+            omikuji_id.parse::<u32>().ok()?,
+            Reaction::from_str(reaction).ok()?,
+        ))
+    });
+    let (omikuji_id, reaction) = match parsed {
+        Some(parsed) => parsed,
+        None => {
+            api.send_text(from, "Malformed callback request.").await?;
+            return Ok(());
+        }
+    };
+    if repository.find_omikuji(omikuji_id).await?.is_none() {
+        api.send_text(from, "Requested omikuji cannot be found.")
+            .await?;
+        return Ok(());
+    }
+    repository
+        .set_reaction(&NewReaction {
+            tg_id: from.id,
+            omikuji_id,
+            reaction: format!("{:?}", reaction),
+        })
+        .await?;
+    api.send_text(
+        from,
+        format!(
+            "You reacted {} to the omikuji slip #{}.",
+            reaction.emoji(),
+            omikuji_id
+        )
+        .as_str(),
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,253 @@
+use crate::error::BotError;
+use crate::models::{OmikujiClass, OmikujiMessage, OmikujiSection};
+use crate::sanitize::sanitize;
+use crate::validate::{check_strip, Issue};
+use std::fmt;
+use url::Url;
+
+//
+// Strips imported by admins from a CSV file, e.g. a Google Sheet shared by link
+//
+
+// Sheets are downloaded as a whole, so larger files are refused rather than read into memory
+const MAX_CSV_BYTES: usize = 1024 * 1024;
+
+// Which columns (counted from 1, like the prompt shows them) hold which part of the strips
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnMapping {
+    pub class: usize,
+    pub description: Option<usize>,
+    // Pairs of the column naming the section and the column with its text
+    pub sections: Vec<(usize, usize)>,
+}
+
+impl ColumnMapping {
+    // Parse a mapping as typed by the admin, e.g. "class=1 description=2 sections=3:4,5:6"
+    pub fn parse(input: &str) -> Option<ColumnMapping> {
+        let mut class = None;
+        let mut description = None;
+        let mut sections = Vec::new();
+        for part in input.split_whitespace() {
+            let (key, value) = part.split_once('=')?;
+            match key.to_lowercase().as_str() {
+                "class" => class = Some(column(value)?),
+                "description" => description = Some(column(value)?),
+                "sections" => {
+                    for pair in value.split(',').filter(|pair| !pair.is_empty()) {
+                        let (name, text) = pair.split_once(':')?;
+                        sections.push((column(name)?, column(text)?));
+                    }
+                }
+                _ => return None,
+            }
+        }
+        Some(ColumnMapping {
+            class: class?,
+            description,
+            sections,
+        })
+    }
+}
+
+impl fmt::Display for ColumnMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "class={}", self.class)?;
+        if let Some(description) = self.description {
+            write!(f, " description={}", description)?;
+        }
+        if !self.sections.is_empty() {
+            let pairs: Vec<String> = self
+                .sections
+                .iter()
+                .map(|(name, text)| format!("{}:{}", name, text))
+                .collect();
+            write!(f, " sections={}", pairs.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+fn column(value: &str) -> Option<usize> {
+    value.parse::<usize>().ok().filter(|column| *column > 0)
+}
+
+// Guess the mapping from the header row, for the example in the prompt
+// Sections are taken in pairs of columns after the description
+pub fn guess_mapping(header: &[String]) -> ColumnMapping {
+    let find = |name: &str| {
+        header
+            .iter()
+            .position(|cell| cell.trim().eq_ignore_ascii_case(name))
+            .map(|index| index + 1)
+    };
+    let class = find("class").or_else(|| find("fortune")).unwrap_or(1);
+    let description = find("description");
+    let first = description.unwrap_or(class) + 1;
+    let sections = (first..header.len())
+        .step_by(2)
+        .map(|name| (name, name + 1))
+        .collect();
+    ColumnMapping {
+        class,
+        description,
+        sections,
+    }
+}
+
+// Links to a Google Sheet are turned into its CSV export, other links are fetched as they are
+pub fn csv_url(link: &str) -> Option<Url> {
+    let mut url = Url::parse(link.trim()).ok()?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+    if url.host_str() == Some("docs.google.com") && url.path().starts_with("/spreadsheets/d/") {
+        let id = url.path_segments()?.nth(2)?.to_string();
+        // The sheet is picked by its gid, which the browser keeps in the fragment
+        let gid = url
+            .fragment()
+            .and_then(|fragment| fragment.strip_prefix("gid="))
+            .map(String::from);
+        url.set_path(format!("/spreadsheets/d/{}/export", id).as_str());
+        url.set_fragment(None);
+        match gid {
+            Some(gid) => url.set_query(Some(format!("format=csv&gid={}", gid).as_str())),
+            None => url.set_query(Some("format=csv")),
+        }
+    }
+    Some(url)
+}
+
+// Download the CSV behind a link, errors being shown to the admin who sent it
+pub async fn fetch_csv(url: &Url) -> Result<String, BotError> {
+    let failed =
+        |e: reqwest::Error| BotError::Validation(format!("Cannot download the CSV: {}", e));
+    let response = reqwest::get(url.clone())
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(failed)?;
+    let bytes = response.bytes().await.map_err(failed)?;
+    if bytes.len() > MAX_CSV_BYTES {
+        return Err(BotError::Validation(format!(
+            "The CSV is larger than {} KB.",
+            MAX_CSV_BYTES / 1024
+        )));
+    }
+    String::from_utf8(bytes.to_vec())
+        .map_err(|_| BotError::Validation(String::from("The CSV is not encoded in UTF-8.")))
+}
+
+// Rows of cells, as in RFC 4180: fields may be quoted, with "" standing for a quote inside
+// Quoted fields may span several lines, and blank lines are skipped
+pub fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = text.trim_start_matches('\u{FEFF}').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if cell.is_empty() => quoted = true,
+            ',' if !quoted => row.push(std::mem::take(&mut cell)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut cell));
+                if row.iter().any(|cell| !cell.is_empty()) {
+                    rows.push(std::mem::take(&mut row));
+                } else {
+                    row.clear();
+                }
+            }
+            c => cell.push(c),
+        }
+    }
+    row.push(cell);
+    if row.iter().any(|cell| !cell.is_empty()) {
+        rows.push(row);
+    }
+    rows
+}
+
+// Why a row cannot be imported
+#[derive(Debug, Clone, PartialEq)]
+pub enum RowIssue {
+    UnknownClass(String),
+    UnknownSection(String),
+    Strip(Issue),
+}
+
+impl fmt::Display for RowIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RowIssue::UnknownClass(class) => write!(f, "\"{}\" is not a fortune.", class),
+            RowIssue::UnknownSection(section) => write!(f, "\"{}\" is not a section.", section),
+            RowIssue::Strip(issue) => write!(f, "{}", issue),
+        }
+    }
+}
+
+// The strip in a row, checked like the ones saved by the creation wizard
+// Sections with an empty text are left out, so that rows may have different sections
+pub fn strip_from_row(
+    row: &[String],
+    mapping: &ColumnMapping,
+) -> Result<OmikujiMessage, Vec<RowIssue>> {
+    let cell = |column: usize| {
+        row.get(column - 1)
+            .and_then(|cell| sanitize(cell))
+            .unwrap_or_default()
+    };
+    let mut issues = Vec::new();
+    let class_name = cell(mapping.class);
+    let class = OmikujiClass::parse(class_name.as_str());
+    if class.is_none() && !class_name.is_empty() {
+        issues.push(RowIssue::UnknownClass(class_name.clone()));
+    }
+    let mut sections = Vec::new();
+    for (name, text) in &mapping.sections {
+        let (name, text) = (cell(*name), cell(*text));
+        if text.is_empty() {
+            continue;
+        }
+        match OmikujiSection::parse(name.as_str()) {
+            Some(section) => sections.push((section, text)),
+            None => issues.push(RowIssue::UnknownSection(name)),
+        }
+    }
+    let omikuji_message = OmikujiMessage {
+        photo: None,
+        class,
+        description: mapping
+            .description
+            .map(cell)
+            .filter(|description| !description.is_empty()),
+        poem: None,
+        sections,
+        community_id: None,
+        touched_at: None,
+        reminded: false,
+        anonymous: false,
+        expires_in: None,
+        editing: None,
+    };
+    // A class or sections which are there but unknown are reported once, not as missing as well
+    let unknown_sections = issues
+        .iter()
+        .any(|issue| matches!(issue, RowIssue::UnknownSection(_)));
+    issues.extend(
+        check_strip(&omikuji_message)
+            .into_iter()
+            .filter(|issue| class_name.is_empty() || *issue != Issue::MissingClass)
+            .filter(|issue| !unknown_sections || *issue != Issue::NoSections)
+            .map(RowIssue::Strip),
+    );
+    if issues.is_empty() {
+        Ok(omikuji_message)
+    } else {
+        Err(issues)
+    }
+}
@@ -0,0 +1,124 @@
+use crate::models::Language;
+use crate::models::OmikujiClass;
+use crate::models::OmikujiSection;
+use crate::models::OmikujiTemplate;
+use crate::signing::sign;
+use std::fmt;
+use strum::IntoEnumIterator;
+use teloxide_core::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+// Lays out buttons into an inline keyboard
+pub(crate) struct KeyboardBuilder {
+    buttons: Vec<InlineKeyboardButton>,
+    columns: usize,
+    // Callback command for the navigation buttons, page size and current page
+    pagination: Option<(String, usize, usize)>,
+    extra_rows: Vec<Vec<InlineKeyboardButton>>,
+}
+
+impl KeyboardBuilder {
+    pub fn new() -> Self {
+        KeyboardBuilder {
+            buttons: Vec::new(),
+            columns: 1,
+            pagination: None,
+            extra_rows: Vec::new(),
+        }
+    }
+
+    pub fn columns(mut self, columns: usize) -> Self {
+        self.columns = columns.max(1);
+        self
+    }
+
+    pub fn button<S: Into<String>, C: Into<String>>(mut self, label: S, callback: C) -> Self {
+        self.buttons
+            .push(InlineKeyboardButton::callback(label, sign(callback.into())));
+        self
+    }
+
+    // Only show `page_size` buttons at a time
+    // Navigation buttons send "<callback_command>/page:<n>"
+    pub fn paginate(mut self, callback_command: &str, page_size: usize, page: usize) -> Self {
+        self.pagination = Some((callback_command.to_string(), page_size.max(1), page));
+        self
+    }
+
+    // Rows added below the buttons, e.g. "Save"
+    pub fn extra_row(mut self, buttons: Vec<InlineKeyboardButton>) -> Self {
+        self.extra_rows.push(buttons);
+        self
+    }
+
+    pub fn build(self) -> InlineKeyboardMarkup {
+        let mut keyboard = InlineKeyboardMarkup::default();
+        let mut buttons = self.buttons;
+        let mut navigation = Vec::new();
+        if let Some((callback_command, page_size, page)) = self.pagination {
+            let pages = buttons.len().div_ceil(page_size);
+            // Fall back to the last page if the requested page does not exist (any more)
+            let page = page.min(pages.max(1) - 1);
+            buttons = buttons
+                .into_iter()
+                .skip(page * page_size)
+                .take(page_size)
+                .collect();
+            if page > 0 {
+                navigation.push(InlineKeyboardButton::callback(
+                    "« Previous",
+                    sign(format!("{}/page:{}", callback_command, page - 1)),
+                ));
+            }
+            if page + 1 < pages {
+                navigation.push(InlineKeyboardButton::callback(
+                    "Next »",
+                    sign(format!("{}/page:{}", callback_command, page + 1)),
+                ));
+            }
+        }
+        for row in buttons.chunks(self.columns) {
+            keyboard = keyboard.append_row(row.to_vec());
+        }
+        if !navigation.is_empty() {
+            keyboard = keyboard.append_row(navigation);
+        }
+        for row in self.extra_rows {
+            keyboard = keyboard.append_row(row);
+        }
+        keyboard
+    }
+}
+
+pub(crate) trait EnumExtension: IntoEnumIterator + fmt::Debug {
+    fn label(&self, language: Language) -> &'static str;
+
+    // One button per variant, sending "<callback_command>/<variant>"
+    fn to_keyboard(callback_command: &str, language: Language) -> KeyboardBuilder {
+        let mut builder = KeyboardBuilder::new();
+        for variant in Self::iter() {
+            builder = builder.button(
+                variant.label(language),
+                format!("{}/{:?}", callback_command, variant),
+            );
+        }
+        builder
+    }
+}
+
+impl EnumExtension for OmikujiClass {
+    fn label(&self, language: Language) -> &'static str {
+        self.name(language)
+    }
+}
+
+impl EnumExtension for OmikujiSection {
+    fn label(&self, language: Language) -> &'static str {
+        self.name(language)
+    }
+}
+
+impl EnumExtension for OmikujiTemplate {
+    fn label(&self, language: Language) -> &'static str {
+        self.name(language)
+    }
+}
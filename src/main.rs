@@ -1,49 +1,384 @@
-use anyhow::Error;
-use dotenv::dotenv;
-use futures::StreamExt;
+use anyhow::{anyhow, Error};
+use bot_api::{BotApi, DryRunApi};
+use clap::{Parser, Subcommand};
+use dotenvy::dotenv;
+use frontends::telegram;
+use middleware::Pipeline;
 use models::OmikujiMessage;
 use omikuji_bot::*;
+use repository::{CachedRepository, DieselRepository, LeaseRepository, Repository};
 use std::collections::HashMap;
-use std::env;
-use telegram_bot::*;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use teloxide_core::payloads::setters::*;
+use teloxide_core::payloads::{GetMe, GetUpdates};
+use teloxide_core::requests::{JsonRequest, Request};
+use teloxide_core::{Bot, RequestError};
+use tokio::{runtime, time};
 
-#[tokio::main]
-async fn main() -> Result<(), Error> {
+/// NUSCAS Omikuji Bot
+#[derive(Parser)]
+struct Cli {
+    /// Tenant whose library the maintenance commands work on
+    #[arg(long, global = true, default_value = config::DEFAULT_TENANT)]
+    tenant: String,
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Run pending database migrations
+    Migrate {
+        /// Revert the latest migration instead
+        #[arg(long)]
+        revert: bool,
+    },
+    /// List all omikuji strips
+    List,
+    /// Show an omikuji strip
+    Show { id: u32 },
+    /// Delete an omikuji strip
+    Delete { id: u32 },
+    /// Add the omikuji strips of a JSON file (as written by export) to the library
+    Import { file: String },
+    /// Write all omikuji strips to a JSON file
+    Export { file: String },
+    /// Feed recorded updates through the handlers again, logging the replies instead of sending them
+    Replay { file: String },
+}
+
+fn main() -> Result<(), Error> {
     dotenv().ok();
 
-    let token = env::var("TELEGRAM_BOT_TOKEN").expect("TELEGRAM_BOT_TOKEN not set");
-    let api = Api::new(token);
+    // Without a subcommand, the bots are started
+    let args = Cli::parse();
+    let command = match args.command {
+        Some(CliCommand::Migrate { revert }) => return migrate(revert),
+        Some(command) => command,
+        None => return run(),
+    };
+    let runtime = runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(maintain(command, args.tenant.as_str()))
+}
+
+async fn maintain(command: CliCommand, tenant: &str) -> Result<(), Error> {
+    let database = establish_connection().await;
+    let repository = DieselRepository::new(&database, tenant);
+    match command {
+        CliCommand::List => cli::list(&repository).await,
+        CliCommand::Show { id } => cli::show(&repository, id).await,
+        CliCommand::Delete { id } => cli::delete(&repository, id).await,
+        CliCommand::Import { file } => cli::import(&repository, file.as_str()).await,
+        CliCommand::Export { file } => cli::export(&repository, file.as_str()).await,
+        CliCommand::Replay { file } => replay(&repository, file.as_str()).await,
+        // Handled in main, without running migrations on connect
+        CliCommand::Migrate { .. } => unreachable!(),
+    }
+}
+
+fn migrate(revert: bool) -> Result<(), Error> {
+    let mut connection = db::connect();
+    if !revert {
+        return db::run_migrations(&mut connection);
+    }
+    match db::revert_latest_migration(&mut connection)? {
+        Some(name) => println!("Reverted migration {}", name),
+        None => println!("There is no migration to revert"),
+    }
+    Ok(())
+}
+
+fn run() -> Result<(), Error> {
+    self_check()?;
+    let tenants = config::get_tenants();
+
+    // The HTTP API runs on its own thread, with its own database connection
+    // It serves the library of the first tenant
+    if let Some((addr, token)) = config::get_api_config() {
+        let tenant = tenants[0].0.clone();
+        thread::spawn(move || {
+            let runtime = runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to start the HTTP API runtime");
+            if let Err(e) = runtime.block_on(web::serve(addr, token, tenant)) {
+                println!("HTTP API stopped: {}", e);
+            }
+        });
+    }
+
+    // The Matrix frontend, like the HTTP API, draws from the library of the first tenant
+    #[cfg(feature = "matrix")]
+    if let Some((homeserver, token)) = config::get_matrix_config() {
+        let tenant = tenants[0].0.clone();
+        thread::spawn(move || {
+            let runtime = runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to start the Matrix runtime");
+            if let Err(e) = runtime.block_on(frontends::matrix::serve(homeserver, token, tenant)) {
+                println!("Matrix frontend stopped: {}", e);
+            }
+        });
+    }
+
+    // So does the Discord bridge
+    #[cfg(feature = "discord")]
+    if let Some(token) = config::get_discord_token() {
+        let tenant = tenants[0].0.clone();
+        thread::spawn(move || {
+            let runtime = runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to start the Discord runtime");
+            if let Err(e) = runtime.block_on(frontends::discord::serve(token, tenant)) {
+                println!("Discord bridge stopped: {}", e);
+            }
+        });
+    }
+
+    // Each bot polls its own updates on its own thread, so a slow bot does not hold up the others
+    // The process stops as soon as any of the bots stops
+    let (stopped, stop) = mpsc::channel();
+    for (tenant, token) in tenants {
+        let stopped = stopped.clone();
+        thread::spawn(move || {
+            let runtime = runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to start the bot runtime");
+            let result = runtime.block_on(run_bot(&tenant, token));
+            if let Err(e) = &result {
+                println!("Bot {} stopped: {}", tenant, e);
+            }
+            stopped.send(result).ok();
+        });
+    }
+    stop.recv()?
+}
+
+// Startup self-check, so that a broken setup stops the process with a helpful message
+// instead of a panic in the middle of handling an update
+fn self_check() -> Result<(), Error> {
+    let problems = config::validate();
+    if !problems.is_empty() {
+        return Err(anyhow!(
+            "Invalid configuration, please check .env:\n{}",
+            problems.join("\n")
+        ));
+    }
+    db::connect_with_retry()?;
+
+    let runtime = runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    for (tenant, token) in config::get_tenants() {
+        let me = runtime
+            .block_on(JsonRequest::new(Bot::new(token), GetMe::new()).send())
+            .map_err(|e| {
+                anyhow!(
+                    "Telegram did not accept the bot token of {}: {}\nCheck the token with @BotFather",
+                    tenant,
+                    e
+                )
+            })?;
+        println!("Bot {} is @{}", tenant, me.username());
+    }
+    Ok(())
+}
+
+async fn run_bot(tenant: &str, token: String) -> Result<(), Error> {
+    let bot = Bot::new(token);
+
+    // Updates are always fetched from Telegram, but in a dry run the replies are only logged
+    let dry_run = DryRunApi::default();
+    let api: &dyn BotApi = if config::dry_run() {
+        println!("Bot {} is in a dry run, replies are not sent", tenant);
+        &dry_run
+    } else {
+        &bot
+    };
 
     let mut store = HashMap::<i64, OmikujiMessage>::new();
 
-    // Establish a connection to database server
-    let connection = establish_connection();
+    // Logging, rate limiting etc. applied to every update
+    let mut pipeline = Pipeline::default_chain();
+
+    // Set up a pool of connections to database server
+    let database = establish_connection().await;
+    let repository = CachedRepository::new(DieselRepository::new(&database, tenant));
+
+    // Drafts are only kept in `store` unless they are shared with other instances of the bot
+    let draft_store = drafts::open(config::get_draft_backend(), &database, tenant)?;
+    let draft_store = draft_store.as_deref();
+
+    // Show a command menu in Telegram clients
+    register_commands(api, &repository).await?;
+
+    // Periodic jobs (e.g. daily omikuji, reminders) are checked every minute
+    let mut ticker = time::interval(Duration::from_secs(60));
+    let mut last_usage = None;
+
+    // With several instances of the bot, the jobs are only run by the holder of the lease
+    let instance = config::get_instance_id();
+    let mut leading = false;
+
+    let update_log = config::get_update_log();
 
     // Fetch new updates via long poll method
-    let mut stream = api.stream();
-    while let Some(update) = stream.next().await {
-        let update = update?;
-        match update.kind {
-            UpdateKind::Message(message) => {
-                // Print received text message to stdout.
-
-                message_entry(&message, &api, &mut store, &connection).await?;
-                // TODO: Remove debug codes
-                // Extract text if the message is of kind Text
-                if let MessageKind::Text { ref data, .. } = message.kind {
-                    println!("<{}>: {}", &message.from.first_name, data);
-                } else {
-                    println!("<{}>: Non-text message", &message.from.first_name);
+    // Updates up to `offset` are confirmed to Telegram by the next request
+    let mut offset = 0;
+    // Failed polls are retried with increasing delays, counted over the lifetime of the bot
+    let mut poll_errors = 0u64;
+    let mut retry_delay = POLL_RETRY_MIN;
+    loop {
+        let request = GetUpdates::new().offset(offset).timeout(POLL_TIMEOUT);
+        let result = tokio::select! {
+            updates = JsonRequest::new(bot.clone(), request).send() => updates,
+            _ = ticker.tick() => {
+                // A lease which cannot be renewed is taken as lost, until the next tick
+                let lease = repository
+                    .acquire_lease(SCHEDULER_LEASE, &instance, SCHEDULER_LEASE_DURATION)
+                    .await
+                    .unwrap_or_else(|e| {
+                        println!("Bot {} failed to renew the lease of the scheduled jobs: {}", tenant, e);
+                        false
+                    });
+                if lease != leading {
+                    leading = lease;
+                    if leading {
+                        println!("Bot {} runs the scheduled jobs on {}", tenant, instance);
+                    } else {
+                        println!("Bot {} left the scheduled jobs to another instance", tenant);
+                    }
+                }
+                if !leading {
+                    continue;
+                }
+                log_job(tenant, "daily", daily_entry(api, &repository).await);
+                log_job(tenant, "weekly", weekly_entry(api, &repository).await);
+                log_job(tenant, "shrine", shrine_entry(api, &repository).await);
+                log_job(tenant, "poll", poll_entry(api, &repository).await);
+                log_job(tenant, "quiet", quiet_entry(api, &repository).await);
+                match draft_store {
+                    Some(draft_store) => {
+                        let reminders = async {
+                            let (mut stored, revisions) = drafts::load_drafts(draft_store).await?;
+                            let reminded = reminder_entry(api, &mut stored, &repository).await?;
+                            drafts::save_changed_drafts(draft_store, &stored, &revisions, &reminded).await
+                        };
+                        log_job(tenant, "reminder", reminders.await);
+                    }
+                    None => log_job(
+                        tenant,
+                        "reminder",
+                        reminder_entry(api, &mut store, &repository).await.map(|_| ()),
+                    ),
                 }
+                log_job(tenant, "archive", archive_entry(&repository).await);
+                log_job(tenant, "trash", trash_entry(&repository).await);
+                log_job(tenant, "usage", usage_entry(&repository, &mut last_usage).await);
+                continue;
             }
-            UpdateKind::CallbackQuery(callback) => {
-                callback_entry(&callback, &api, &mut store, &connection).await?;
+        };
+        let updates = match result {
+            Ok(updates) => {
+                retry_delay = POLL_RETRY_MIN;
+                updates
             }
-            _ => {
-                // Unsupported message kind
-                println!("Unsupported update kind received!");
+            Err(RequestError::RetryAfter(delay)) => {
+                time::sleep(delay).await;
+                continue;
+            }
+            // Anything else, e.g. a network error, a 5xx of Telegram or a garbled response
+            Err(e) => {
+                poll_errors += 1;
+                println!(
+                    "Bot {} failed to poll updates ({} errors so far), retrying in {}s: {}",
+                    tenant,
+                    poll_errors,
+                    retry_delay.as_secs(),
+                    e
+                );
+                time::sleep(retry_delay).await;
+                retry_delay = (retry_delay * 2).min(POLL_RETRY_MAX);
+                continue;
+            }
+        };
+        for update in updates {
+            offset = update.id + 1;
+            // An update which cannot be recorded (e.g. the disk is full) is still handled
+            if let Some(dir) = &update_log {
+                if let Err(e) = update_log::record(dir, tenant, &update) {
+                    println!(
+                        "Bot {} failed to record update {}: {}",
+                        tenant, update.id, e
+                    );
+                }
+            }
+            // Failures of single updates are logged, so that they do not stop the bot
+            if let Err(e) = telegram::handle_update(
+                update.kind,
+                api,
+                &mut pipeline,
+                &mut store,
+                &repository,
+                draft_store,
+            )
+            .await
+            {
+                println!(
+                    "Bot {} failed to handle update {}: {}",
+                    tenant, update.id, e
+                );
             }
         }
     }
+}
+
+// Failures of scheduled jobs are logged, so that they stop neither the bot nor the jobs after them,
+// which are tried again on the next tick
+fn log_job(tenant: &str, job: &str, result: Result<(), error::BotError>) {
+    if let Err(e) = result {
+        println!("Bot {} failed to run the {} job: {}", tenant, job, e);
+    }
+}
+
+// Replays run against the database of DATABASE_URL, which should be a test database
+async fn replay(repository: &dyn Repository, file: &str) -> Result<(), Error> {
+    let updates = update_log::read(file)?;
+    let api = DryRunApi::default();
+    let mut store = HashMap::<i64, OmikujiMessage>::new();
+    let mut pipeline = Pipeline::default_chain();
+    let count = updates.len();
+    for update in updates {
+        println!("Replaying update {}", update.id);
+        telegram::handle_update(
+            update.kind,
+            &api,
+            &mut pipeline,
+            &mut store,
+            repository,
+            None,
+        )
+        .await?;
+    }
+    println!("Replayed {} updates, {} requests logged", count, api.sent());
     Ok(())
 }
+
+// Lease for running the scheduled jobs, renewed on every tick of the ticker
+// Another instance takes over once a few ticks were missed, e.g. because this one stopped
+const SCHEDULER_LEASE: &str = "scheduler";
+const SCHEDULER_LEASE_DURATION: Duration = Duration::from_secs(180);
+
+// Long poll timeout in seconds
+const POLL_TIMEOUT: u32 = 30;
+
+// Delays between attempts to poll again after a failed poll
+const POLL_RETRY_MIN: Duration = Duration::from_secs(1);
+const POLL_RETRY_MAX: Duration = Duration::from_secs(60);
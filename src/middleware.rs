@@ -0,0 +1,426 @@
+use crate::bot_api::BotApi;
+use crate::commands::Command;
+use crate::config::{in_maintenance, is_admin};
+use crate::error::BotError;
+use crate::frontends::{EventKind, IncomingEvent};
+use crate::handlers;
+use crate::handlers::note_failure;
+use crate::models;
+use crate::models::OmikujiMessage;
+use crate::repository::Repository;
+use crate::signing::TAG_SEPARATOR;
+use crate::telegram_ext::ApiExtension;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use teloxide_core::payloads::SendMessage;
+use teloxide_core::types::ChatId;
+
+// Cross-cutting concerns which are applied to every update before and after handling
+// Middlewares are not required to be Send since the repository is shared by reference
+#[async_trait(?Send)]
+pub trait Middleware {
+    // Return Ok(false) to stop the update from reaching the handlers (and later middlewares)
+    async fn before(
+        &mut self,
+        event: &IncomingEvent,
+        api: &dyn BotApi,
+        repository: &dyn Repository,
+    ) -> Result<bool, BotError>;
+
+    // Called in reverse order for middlewares whose `before` passed, with the result
+    // of the handlers, or None if the update was stopped (or failed) in a later middleware
+    async fn after(
+        &mut self,
+        _event: &IncomingEvent,
+        _repository: &dyn Repository,
+        _result: Option<&Result<(), BotError>>,
+    ) {
+    }
+}
+
+#[derive(Default)]
+pub struct Pipeline {
+    middlewares: Vec<Box<dyn Middleware>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Pipeline {
+            middlewares: Vec::new(),
+        }
+    }
+
+    // The chain used by the bot: logging, metrics, usage analytics, user upsert, ban check,
+    // maintenance, rate limit
+    pub fn default_chain() -> Self {
+        Pipeline::new()
+            .with(Logger { started: None })
+            .with(Metrics::default())
+            .with(UsageTracker::default())
+            .with(UserUpsert)
+            .with(BanCheck)
+            .with(MaintenanceCheck)
+            .with(RateLimit::new(20, Duration::from_secs(60)))
+    }
+
+    pub fn with<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+
+    pub async fn handle(
+        &mut self,
+        event: &IncomingEvent,
+        api: &dyn BotApi,
+        store: &mut HashMap<i64, OmikujiMessage>,
+        repository: &dyn Repository,
+    ) -> Result<(), BotError> {
+        let mut passed = 0;
+        let mut failure = None;
+        for middleware in self.middlewares.iter_mut() {
+            match middleware.before(event, api, repository).await {
+                Ok(true) => passed += 1,
+                Ok(false) => break,
+                Err(e) => {
+                    failure = Some(e);
+                    break;
+                }
+            }
+        }
+        // A middleware which failed stops the update as well, the ones before it still get to
+        // see the end of it (e.g. the logger and the metrics)
+        if passed < self.middlewares.len() {
+            for middleware in self.middlewares[..passed].iter_mut().rev() {
+                middleware.after(event, repository, None).await;
+            }
+            return failure.map_or(Ok(()), Err);
+        }
+        let result = handlers::handle(event, api, store, repository).await;
+        for middleware in self.middlewares.iter_mut().rev() {
+            middleware.after(event, repository, Some(&result)).await;
+        }
+        // Errors about what the user asked for are only told to them
+        // Any other failure (e.g. the user blocking the bot while it replies) is logged and noted,
+        // so that a single update cannot stop the bot
+        let failure = match result {
+            Ok(()) => return Ok(()),
+            Err(BotError::Validation(message)) => {
+                api.send_text(&event.from, message.as_str()).await
+            }
+            Err(e @ BotError::NotFound(_)) => {
+                api.send_text(&event.from, format!("{}.", e).as_str()).await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = failure {
+            let tg_id = event.from.id;
+            println!("Failed to handle an update from {}: {}", tg_id, e);
+            note_failure(repository, tg_id, &e).await?;
+        }
+        Ok(())
+    }
+}
+
+//
+// Middlewares
+//
+
+// Print every update received and how long it took to be handled
+pub struct Logger {
+    started: Option<Instant>,
+}
+
+#[async_trait(?Send)]
+impl Middleware for Logger {
+    async fn before(
+        &mut self,
+        event: &IncomingEvent,
+        _api: &dyn BotApi,
+        _repository: &dyn Repository,
+    ) -> Result<bool, BotError> {
+        println!("<{}>: {}", event.from.name, event.kind);
+        self.started = Some(Instant::now());
+        Ok(true)
+    }
+
+    async fn after(
+        &mut self,
+        event: &IncomingEvent,
+        _repository: &dyn Repository,
+        result: Option<&Result<(), BotError>>,
+    ) {
+        let name = &event.from.name;
+        let elapsed = self.started.take().unwrap_or_else(Instant::now).elapsed();
+        match result {
+            Some(Ok(())) => println!("<{}>: handled in {:?}", name, elapsed),
+            Some(Err(e)) => println!("<{}>: failed in {:?} ({}): {}", name, elapsed, e.kind(), e),
+            None => println!("<{}>: rejected", name),
+        }
+    }
+}
+
+// Count updates and errors, printing a summary every REPORT_EVERY updates
+#[derive(Default)]
+pub struct Metrics {
+    pub messages: u64,
+    pub callbacks: u64,
+    pub rejected: u64,
+    pub errors: u64,
+    // Errors by BotError::kind, e.g. "database"
+    pub errors_by_kind: HashMap<&'static str, u64>,
+}
+
+impl Metrics {
+    const REPORT_EVERY: u64 = 100;
+}
+
+#[async_trait(?Send)]
+impl Middleware for Metrics {
+    async fn before(
+        &mut self,
+        event: &IncomingEvent,
+        _api: &dyn BotApi,
+        _repository: &dyn Repository,
+    ) -> Result<bool, BotError> {
+        match event.kind {
+            EventKind::Button { .. } => self.callbacks += 1,
+            _ => self.messages += 1,
+        }
+        let total = self.messages + self.callbacks;
+        if total.is_multiple_of(Metrics::REPORT_EVERY) {
+            let mut kinds: Vec<String> = self
+                .errors_by_kind
+                .iter()
+                .map(|(kind, count)| format!("{} {}", count, kind))
+                .collect();
+            kinds.sort();
+            println!(
+                "Metrics: {} messages, {} callbacks, {} rejected, {} errors ({})",
+                self.messages,
+                self.callbacks,
+                self.rejected,
+                self.errors,
+                kinds.join(", ")
+            );
+        }
+        Ok(true)
+    }
+
+    async fn after(
+        &mut self,
+        _event: &IncomingEvent,
+        _repository: &dyn Repository,
+        result: Option<&Result<(), BotError>>,
+    ) {
+        match result {
+            Some(Ok(())) => {}
+            Some(Err(e)) => {
+                self.errors += 1;
+                *self.errors_by_kind.entry(e.kind()).or_insert(0) += 1;
+            }
+            None => self.rejected += 1,
+        }
+    }
+}
+
+// Record every command and button handled, with its latency and whether it failed
+// Updates stopped by a later middleware (e.g. rate limited) are not recorded
+#[derive(Default)]
+pub struct UsageTracker {
+    started: Option<Instant>,
+}
+
+// Longest command name stored, anything longer is made up by a modified client anyway
+const COMMAND_LENGTH: usize = 32;
+
+impl UsageTracker {
+    // What an update invoked, e.g. "/draw" for commands (and quick actions), "vote" for
+    // buttons, "message" for anything else
+    pub fn command(event: &IncomingEvent) -> String {
+        let command = match &event.kind {
+            EventKind::Command { name, .. } => match Command::from_str(name) {
+                Ok(command) => format!("/{}", command.name()),
+                Err(_) => String::from("/unknown"),
+            },
+            EventKind::Button { data, .. } => data
+                .split(['/', TAG_SEPARATOR])
+                .next()
+                .unwrap_or("")
+                .to_string(),
+            _ => String::from("message"),
+        };
+        command.chars().take(COMMAND_LENGTH).collect()
+    }
+}
+
+#[async_trait(?Send)]
+impl Middleware for UsageTracker {
+    async fn before(
+        &mut self,
+        _event: &IncomingEvent,
+        _api: &dyn BotApi,
+        _repository: &dyn Repository,
+    ) -> Result<bool, BotError> {
+        self.started = Some(Instant::now());
+        Ok(true)
+    }
+
+    async fn after(
+        &mut self,
+        event: &IncomingEvent,
+        repository: &dyn Repository,
+        result: Option<&Result<(), BotError>>,
+    ) {
+        let started = self.started.take();
+        let (result, started) = match (result, started) {
+            (Some(result), Some(started)) => (result, started),
+            _ => return,
+        };
+        let command = UsageTracker::command(event);
+        let event = models::NewUsageEvent {
+            command: command.as_str(),
+            tg_id: event.from.id,
+            latency_ms: started.elapsed().as_millis().try_into().unwrap_or(u32::MAX),
+            success: result.is_ok(),
+        };
+        // Analytics are not worth failing the update for
+        if let Err(e) = repository.record_usage(&event).await {
+            println!("Failed to record usage of {}: {}", command, e);
+        }
+    }
+}
+
+// Keep the users table in sync with the profile Telegram sends along with every update
+pub struct UserUpsert;
+
+#[async_trait(?Send)]
+impl Middleware for UserUpsert {
+    async fn before(
+        &mut self,
+        event: &IncomingEvent,
+        _api: &dyn BotApi,
+        repository: &dyn Repository,
+    ) -> Result<bool, BotError> {
+        let from = &event.from;
+        repository
+            .upsert_user(&models::NewUser {
+                tg_id: from.id,
+                tg_name: from.name.as_str(),
+                tg_username: from.username.as_deref(),
+                language_code: from.language_code.as_deref(),
+            })
+            .await?;
+        Ok(true)
+    }
+}
+
+// Silently ignore banned users
+pub struct BanCheck;
+
+#[async_trait(?Send)]
+impl Middleware for BanCheck {
+    async fn before(
+        &mut self,
+        event: &IncomingEvent,
+        _api: &dyn BotApi,
+        repository: &dyn Repository,
+    ) -> Result<bool, BotError> {
+        Ok(!repository.is_banned(event.from.id).await?)
+    }
+}
+
+// Turn away everyone but admins while the bot is under maintenance
+pub struct MaintenanceCheck;
+
+#[async_trait(?Send)]
+impl Middleware for MaintenanceCheck {
+    async fn before(
+        &mut self,
+        event: &IncomingEvent,
+        api: &dyn BotApi,
+        _repository: &dyn Repository,
+    ) -> Result<bool, BotError> {
+        let from = &event.from;
+        if !in_maintenance() || is_admin(from) {
+            return Ok(true);
+        }
+        // The user may never have started the bot (e.g. in a group), which must not stop it
+        #[allow(unused_must_use)]
+        {
+            api.send_message(SendMessage::new(
+                ChatId(from.id),
+                "The bot is under maintenance. Please try again later.",
+            ))
+            .await;
+        }
+        Ok(false)
+    }
+}
+
+// Allow at most `limit` updates per user within `window`
+pub struct RateLimit {
+    limit: usize,
+    window: Duration,
+    history: HashMap<i64, Vec<Instant>>,
+    // Users who have not written within a window are forgotten once per window
+    swept: Instant,
+}
+
+impl RateLimit {
+    pub fn new(limit: usize, window: Duration) -> Self {
+        RateLimit {
+            limit,
+            window,
+            history: HashMap::new(),
+            swept: Instant::now(),
+        }
+    }
+
+    fn sweep(&mut self, now: Instant) {
+        let window = self.window;
+        self.history.retain(|_, history| {
+            history.retain(|time| now.duration_since(*time) < window);
+            !history.is_empty()
+        });
+        self.swept = now;
+    }
+}
+
+#[async_trait(?Send)]
+impl Middleware for RateLimit {
+    async fn before(
+        &mut self,
+        event: &IncomingEvent,
+        api: &dyn BotApi,
+        _repository: &dyn Repository,
+    ) -> Result<bool, BotError> {
+        let from = &event.from;
+        let now = Instant::now();
+        if now.duration_since(self.swept) >= self.window {
+            self.sweep(now);
+        }
+        let window = self.window;
+        let history = self.history.entry(from.id).or_default();
+        history.retain(|time| now.duration_since(*time) < window);
+        if history.len() >= self.limit {
+            // Only warn once per window, so that the warnings won't be spammed as well
+            if history.len() == self.limit {
+                history.push(now);
+                #[allow(unused_must_use)]
+                {
+                    api.send_message(SendMessage::new(
+                        ChatId(from.id),
+                        "You are sending too fast. Please take a break and try again later.",
+                    ))
+                    .await;
+                }
+            }
+            return Ok(false);
+        }
+        history.push(now);
+        Ok(true)
+    }
+}
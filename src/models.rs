@@ -1,25 +1,743 @@
+use super::schema::achievements;
+use super::schema::audit_log;
+use super::schema::booth_draws;
+use super::schema::comments;
+use super::schema::deferred_messages;
+use super::schema::draws;
+use super::schema::favorites;
+use super::schema::import_batches;
+use super::schema::interpretations;
+use super::schema::job_runs;
 use super::schema::omikujis;
+use super::schema::reactions;
+use super::schema::trash;
+use super::schema::usage_events;
+use super::schema::user_settings;
+use super::schema::users;
+use super::schema::weekly_polls;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 use strum_macros::EnumString;
 
-#[derive(Queryable, Identifiable, Debug)]
+#[derive(Queryable, Identifiable, Debug, Clone)]
 pub struct Omikuji {
     pub id: u32,
     pub message: String,
     pub vote_count: i32,
     pub tg_id: i64,
     pub tg_name: String,
-    pub updated_at: chrono::NaiveDateTime,
     pub created_at: chrono::NaiveDateTime,
+    // Touched by votes and draws as well, see edited_at for changes of the strip itself
+    pub updated_at: chrono::NaiveDateTime,
+    // Set once a moderator has approved or rejected the strip after it was hidden
+    pub reviewed_at: Option<chrono::NaiveDateTime>,
+    pub tenant_id: String,
+    // Group chat the strip was created from, None if created in a private chat
+    pub community_id: Option<i64>,
+    pub anonymous: bool,
+    // Strips with an expiry date (e.g. event-specific fortunes) are archived once it has passed
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub archived: bool,
+    // Hidden from draws (after downvotes, or as suspected spam) until an admin restores it
+    pub quarantined: bool,
+    pub draw_count: u32,
+    pub edited_at: Option<chrono::NaiveDateTime>,
+    // Given once the strip is first drawn from the library, see slip_label
+    pub slip_number: Option<u32>,
+    // Strips imported together with /importurl share a batch
+    pub import_batch: Option<u32>,
+}
+
+// Numbers in kanji, e.g. 12 as 十二 and 10,500 as 一万五百
+pub fn kanji_numeral(number: u32) -> String {
+    const DIGITS: [&str; 10] = ["〇", "一", "二", "三", "四", "五", "六", "七", "八", "九"];
+    // Below 10,000, where a leading one is left out (十二, not 一十二)
+    fn group(number: u32) -> String {
+        let mut text = String::new();
+        for (unit, value) in [("千", 1000), ("百", 100), ("十", 10)] {
+            let digit = (number / value % 10) as usize;
+            if digit > 1 {
+                text += DIGITS[digit];
+            }
+            if digit > 0 {
+                text += unit;
+            }
+        }
+        if !number.is_multiple_of(10) {
+            text += DIGITS[(number % 10) as usize];
+        }
+        text
+    }
+    if number == 0 {
+        return String::from(DIGITS[0]);
+    }
+    let mut text = String::new();
+    for (unit, value) in [("億", 100_000_000), ("万", 10_000)] {
+        let count = number / value % 10_000;
+        if count > 0 {
+            text += format!("{}{}", group(count), unit).as_str();
+        }
+    }
+    text + group(number % 10_000).as_str()
+}
+
+impl Omikuji {
+    // Where the strip came from, e.g. "#12 · Submitted 2021-03-02", shown under drawn strips
+    // if SHOW_STRIP_INFO is set
+    pub fn info(&self, language: Language) -> String {
+        let (submitted, edited) = match language {
+            Language::English => ("Submitted", "edited"),
+            Language::Japanese => ("投稿", "編集"),
+        };
+        let mut info = format!(
+            "#{} · {} {}",
+            self.id,
+            submitted,
+            self.created_at.format("%Y-%m-%d")
+        );
+        if let Some(edited_at) = self.edited_at {
+            info += format!(" · {} {}", edited, edited_at.format("%Y-%m-%d")).as_str();
+        }
+        info
+    }
+
+    // Number of the slip the way shrines write it, e.g. "第十二番 (No. 12)", at the top of drawn
+    // strips
+    pub fn slip_label(&self, language: Language) -> Option<String> {
+        let number = self.slip_number?;
+        Some(match language {
+            Language::English => format!("第{}番 (No. {})", kanji_numeral(number), number),
+            Language::Japanese => format!("第{}番", kanji_numeral(number)),
+        })
+    }
+
+    // Expired strips are not drawn, even before the archival job has flagged them
+    pub fn is_expired(&self, now: chrono::NaiveDateTime) -> bool {
+        self.archived || self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    // Whether the strip is drawn, and if not why, for its author and the admins
+    pub fn status(&self, now: chrono::NaiveDateTime) -> &'static str {
+        if self.archived {
+            "archived"
+        } else if self.is_expired(now) {
+            "expired"
+        } else if self.quarantined && self.reviewed_at.is_some() {
+            "quarantined, rejected before"
+        } else if self.quarantined {
+            "quarantined, pending"
+        } else {
+            "visible"
+        }
+    }
+}
+
+// Strips imported together from a CSV, see import
+#[derive(Insertable)]
+#[diesel(table_name = import_batches)]
+pub struct NewImportBatch<'a> {
+    pub tg_id: i64,
+    pub source: &'a str,
 }
 
 #[derive(Insertable)]
-#[table_name = "omikujis"]
+#[diesel(table_name = omikujis)]
 pub struct NewOmikuji<'a> {
     pub message: &'a str,
     pub tg_id: i64,
     pub tg_name: &'a str,
+    pub community_id: Option<i64>,
+    pub vote_count: i32,
+    pub anonymous: bool,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    // Suspected spam starts quarantined, waiting for a moderator
+    pub quarantined: bool,
+}
+
+// A strip drawn by a user, kept for statistics
+#[derive(Queryable, Identifiable, Debug, Clone)]
+pub struct Draw {
+    pub id: u32,
+    pub tg_id: i64,
+    pub omikuji_id: u32,
+    pub created_at: chrono::NaiveDateTime,
+    pub tenant_id: String,
+    // Draw strategy of the experiment the user took part in, if any
+    pub variant: Option<String>,
+    // Vote of the user on the drawn strip (+1 or -1), if any
+    pub vote: Option<i8>,
+    // Bad fortunes tied at the shrine are left out of the history of the user
+    pub tied_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = draws)]
+pub struct NewDraw {
+    pub tg_id: i64,
+    pub omikuji_id: u32,
+    pub variant: Option<String>,
+}
+
+// A strip drawn at a booth for a visitor, by the user running the booth
+#[derive(Queryable, Debug, Clone)]
+pub struct BoothDraw {
+    pub id: u32,
+    pub chat_id: i64,
+    pub tg_id: i64,
+    pub visitor: String,
+    pub omikuji_id: u32,
+    pub created_at: chrono::NaiveDateTime,
+    pub tenant_id: String,
+}
+
+// A strip drawn at a booth for a visitor, who is only known by the name typed in
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = booth_draws)]
+pub struct NewBoothDraw {
+    pub chat_id: i64,
+    pub tg_id: i64,
+    pub visitor: String,
+    pub omikuji_id: u32,
+}
+
+// When a scheduled job last ran, so that an instance taking over the lease does not run it again
+#[derive(Queryable, Insertable, Debug, Clone, PartialEq)]
+#[diesel(table_name = job_runs)]
+pub struct JobRun {
+    pub job: String,
+    // The user the job ran for, 0 for jobs which do not run per user
+    pub tg_id: i64,
+    pub ran_on: chrono::NaiveDate,
+    // The message the job keeps up to date (e.g. the shrine board), and the count shown in it
+    pub message_id: Option<i32>,
+    pub count: Option<i64>,
+}
+
+impl JobRun {
+    pub fn new(job: &str, tg_id: i64, ran_on: chrono::NaiveDate) -> Self {
+        JobRun {
+            job: job.to_string(),
+            tg_id,
+            ran_on,
+            message_id: None,
+            count: None,
+        }
+    }
+}
+
+// A notification held back during the quiet hours of its recipient
+#[derive(Queryable, Debug, Clone)]
+pub struct DeferredMessage {
+    pub id: u32,
+    pub tg_id: i64,
+    // The sendMessage request, as JSON
+    pub message: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub tenant_id: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = deferred_messages)]
+pub struct NewDeferredMessage<'a> {
+    pub tg_id: i64,
+    pub message: &'a str,
+}
+
+// Engagement with the strips drawn by one variant of the draw experiment
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariantResult {
+    pub variant: String,
+    pub users: i64,
+    pub draws: i64,
+    pub upvotes: i64,
+    pub downvotes: i64,
+}
+
+// What happened to the strips of an author over a period, for the weekly digest
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuthorDigest {
+    // Draws by other users
+    pub draws: i64,
+    pub upvotes: i64,
+    pub downvotes: i64,
+    // Comments on the strips themselves, not replies
+    pub comments: i64,
+}
+
+// An admin or moderation action, kept for accountability
+#[derive(Queryable, Identifiable, Debug, Clone)]
+#[diesel(table_name = audit_log)]
+pub struct AuditEntry {
+    pub id: u32,
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub tenant_id: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = audit_log)]
+pub struct NewAuditEntry<'a> {
+    pub actor: &'a str,
+    // As serialized from AuditAction, e.g. "Approve"
+    pub action: String,
+    pub target: String,
+}
+
+impl<'a> NewAuditEntry<'a> {
+    pub fn new(actor: &'a str, action: AuditAction, target: String) -> Self {
+        NewAuditEntry {
+            actor,
+            action: format!("{:?}", action),
+            target,
+        }
+    }
+}
+
+// What can be restored from the trash
+#[derive(EnumString, Debug, Clone, Copy, PartialEq)]
+pub enum TrashKind {
+    Strip,
+    Draft,
+}
+
+// A strip or draft deleted by a user, who can restore it until the trash is emptied
+#[derive(Queryable, Identifiable, Debug, Clone)]
+#[diesel(table_name = trash)]
+pub struct TrashItem {
+    pub id: u32,
+    pub tg_id: i64,
+    // As serialized from TrashKind, e.g. "Strip"
+    pub kind: String,
+    // JSON, whose format depends on the kind
+    pub content: String,
+    pub deleted_at: chrono::NaiveDateTime,
+    pub tenant_id: String,
+}
+
+impl TrashItem {
+    pub fn kind(&self) -> Option<TrashKind> {
+        TrashKind::from_str(self.kind.as_str()).ok()
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = trash)]
+pub struct NewTrashItem<'a> {
+    pub tg_id: i64,
+    pub kind: String,
+    pub content: &'a str,
+}
+
+impl<'a> NewTrashItem<'a> {
+    pub fn new(tg_id: i64, kind: TrashKind, content: &'a str) -> Self {
+        NewTrashItem {
+            tg_id,
+            kind: format!("{:?}", kind),
+            content,
+        }
+    }
+}
+
+// Badge awarded to a user, at most once per badge
+#[derive(Insertable)]
+#[diesel(table_name = achievements)]
+pub struct NewAchievement {
+    pub tg_id: i64,
+    // As serialized from Badge, e.g. "FirstStrip"
+    pub badge: String,
+}
+
+// Strip saved by a user to be read again later, at most once per strip
+#[derive(Insertable)]
+#[diesel(table_name = favorites)]
+pub struct NewFavorite {
+    pub tg_id: i64,
+    pub omikuji_id: u32,
+}
+
+// Short comment left on a strip after drawing it, shown to its author
+// Replies between the author and the commenter are comments too, threaded by parent_id
+#[derive(Queryable, Identifiable, Debug, Clone)]
+pub struct Comment {
+    pub id: u32,
+    pub omikuji_id: u32,
+    pub tg_id: i64,
+    pub text: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub tenant_id: String,
+    // Comment this one replies to, None for comments on the strip itself
+    pub parent_id: Option<u32>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = comments)]
+pub struct NewComment<'a> {
+    pub omikuji_id: u32,
+    pub tg_id: i64,
+    pub text: &'a str,
+    pub parent_id: Option<u32>,
+}
+
+// Weekly poll for the omikuji of the week, among the top strips of the week
+#[derive(Queryable, Identifiable, Debug, Clone)]
+pub struct WeeklyPoll {
+    pub id: u32,
+    // Chat id or @username of the channel, as in WEEKLY_POLL_ID
+    pub chat: String,
+    pub message_id: i32,
+    // IDs of the strips in the order of the options, separated by commas
+    pub candidates: String,
+    // None until the poll is closed, or if nobody voted
+    pub winner_id: Option<u32>,
+    pub created_at: chrono::NaiveDateTime,
+    pub closed_at: Option<chrono::NaiveDateTime>,
+    pub tenant_id: String,
+}
+
+impl WeeklyPoll {
+    pub fn candidate_ids(&self) -> Vec<u32> {
+        self.candidates
+            .split(',')
+            .filter_map(|id| id.parse().ok())
+            .collect()
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = weekly_polls)]
+pub struct NewWeeklyPoll<'a> {
+    pub chat: &'a str,
+    pub message_id: i32,
+    pub candidates: &'a str,
+}
+
+// A command or button handled by the bot, with how long it took and whether it failed
+#[derive(Queryable, Debug, Clone)]
+pub struct UsageEvent {
+    // e.g. "/draw" for commands, "vote" for buttons, "message" for anything else
+    pub command: String,
+    pub tg_id: i64,
+    pub latency_ms: u32,
+    pub success: bool,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = usage_events)]
+pub struct NewUsageEvent<'a> {
+    pub command: &'a str,
+    pub tg_id: i64,
+    pub latency_ms: u32,
+    pub success: bool,
+}
+
+// Usage events of a command on one day, added up
+#[derive(Queryable, Debug, Clone, PartialEq)]
+pub struct UsageDay {
+    pub day: chrono::NaiveDate,
+    pub command: String,
+    pub invocations: i64,
+    pub failures: i64,
+    pub total_latency_ms: i64,
+}
+
+impl UsageDay {
+    // Totals per day and command, in that order
+    pub fn tally<'a, I: IntoIterator<Item = &'a UsageEvent>>(events: I) -> Vec<UsageDay> {
+        let mut days: Vec<UsageDay> = Vec::new();
+        for event in events {
+            let day = event.created_at.date();
+            let index = match days
+                .iter()
+                .position(|total| total.day == day && total.command == event.command)
+            {
+                Some(index) => index,
+                None => {
+                    days.push(UsageDay {
+                        day,
+                        command: event.command.clone(),
+                        invocations: 0,
+                        failures: 0,
+                        total_latency_ms: 0,
+                    });
+                    days.len() - 1
+                }
+            };
+            let total = &mut days[index];
+            total.invocations += 1;
+            total.failures += i64::from(!event.success);
+            total.total_latency_ms += i64::from(event.latency_ms);
+        }
+        days.sort_by(|a, b| (a.day, &a.command).cmp(&(b.day, &b.command)));
+        days
+    }
+
+    pub fn add(&mut self, other: &UsageDay) {
+        self.invocations += other.invocations;
+        self.failures += other.failures;
+        self.total_latency_ms += other.total_latency_ms;
+    }
+}
+
+// Reaction of a user to a strip, at most one per user and strip (a new one replaces it)
+#[derive(Insertable)]
+#[diesel(table_name = reactions)]
+pub struct NewReaction {
+    pub tg_id: i64,
+    pub omikuji_id: u32,
+    // As serialized from Reaction, e.g. "Pray"
+    pub reaction: String,
+}
+
+// Interpretation of a class of strips, set by admins and appended to drawn strips
+#[derive(Insertable)]
+#[diesel(table_name = interpretations)]
+pub struct NewInterpretation<'a> {
+    // As serialized from OmikujiClass, e.g. "FutureBlessing"
+    pub class: &'a str,
+    pub text: &'a str,
+}
+
+#[derive(Queryable, Identifiable, Debug, Clone)]
+#[diesel(table_name = user_settings)]
+#[diesel(primary_key(tenant_id, tg_id))]
+pub struct UserSettings {
+    pub tg_id: i64,
+    pub notifications: bool,
+    pub language: String,
+    pub daily_subscription: bool,
+    pub reply_keyboard: bool,
+    pub voice: bool,
+    pub pool: String,
+    // Opt-in "by <name>" line on drawn strips, never shown for anonymous strips
+    pub credit: bool,
+    // Opt-in weekly summary of what happened to the strips of the user
+    pub weekly_digest: bool,
+    // Quiet hours, in which notifications and the daily omikuji are held back (see /quiet)
+    pub quiet_start: Option<chrono::NaiveTime>,
+    pub quiet_end: Option<chrono::NaiveTime>,
+    // IANA time zone the scheduled messages of the user follow (see /timezone)
+    pub time_zone: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+    // The bot the user talked to, users of different bots are kept apart
+    pub tenant_id: String,
+}
+
+impl UserSettings {
+    // Falls back to English if the stored value is no longer a known language
+    pub fn language(&self) -> Language {
+        Language::from_str(self.language.as_str()).unwrap_or(Language::English)
+    }
+
+    pub fn pool(&self) -> DrawPool {
+        DrawPool::from_str(self.pool.as_str()).unwrap_or(DrawPool::Both)
+    }
+
+    // Quiet hours may go over midnight, e.g. from 23:00 to 08:00
+    pub fn is_quiet(&self, time: chrono::NaiveTime) -> bool {
+        match (self.quiet_start, self.quiet_end) {
+            (Some(start), Some(end)) if start <= end => start <= time && time < end,
+            (Some(start), Some(end)) => time >= start || time < end,
+            _ => false,
+        }
+    }
+
+    // The time of the user, in the time of the server if they have not set their time zone
+    pub fn local_now(&self) -> chrono::NaiveDateTime {
+        match self.time_zone() {
+            Some(time_zone) => chrono::Utc::now().with_timezone(&time_zone).naive_local(),
+            None => chrono::Local::now().naive_local(),
+        }
+    }
+
+    // Falls back to the time of the server if the stored value is no longer a known time zone
+    pub fn time_zone(&self) -> Option<chrono_tz::Tz> {
+        self.time_zone
+            .as_deref()
+            .and_then(|time_zone| chrono_tz::Tz::from_str(time_zone).ok())
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = user_settings)]
+pub struct NewUserSettings {
+    pub tg_id: i64,
+    // Detected from the Telegram client on first contact
+    pub language: String,
+}
+
+#[derive(Queryable, Identifiable, Debug, Clone)]
+#[diesel(primary_key(tenant_id, tg_id))]
+pub struct User {
+    pub tg_id: i64,
+    pub tg_name: String,
+    pub tg_username: Option<String>,
+    pub language_code: Option<String>,
+    pub banned: bool,
+    // Set when the user blocked the bot, cleared when they write to it again
+    pub inactive: bool,
+    // Set when the user joined through someone's invite link
+    pub referred_by: Option<i64>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+    pub tenant_id: String,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = users)]
+#[diesel(treat_none_as_null = true)]
+pub struct NewUser<'a> {
+    pub tg_id: i64,
+    pub tg_name: &'a str,
+    pub tg_username: Option<&'a str>,
+    pub language_code: Option<&'a str>,
+}
+
+// Names as compared when typed by users
+fn fold_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-' && *c != '_')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+// Languages that omikuji strips can be rendered in
+#[derive(EnumIter, EnumString, Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    English,
+    Japanese,
+}
+
+impl Language {
+    // IETF language tag, as used by Telegram
+    pub fn code(&self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::Japanese => "ja",
+        }
+    }
+
+    // Language matching a tag sent by Telegram, e.g. "ja" or "en-GB"
+    pub fn from_code(code: &str) -> Option<Language> {
+        let primary = code.split('-').next().unwrap_or_default();
+        Language::iter().find(|language| language.code().eq_ignore_ascii_case(primary))
+    }
+
+    // Language used for a user who has not picked one: that of the Telegram client if supported,
+    // English otherwise
+    pub fn detect(code: Option<&str>) -> Language {
+        code.and_then(Language::from_code)
+            .unwrap_or(Language::English)
+    }
+}
+
+// Strips that can be drawn, relative to the community (group chat) the draw happens in
+// In private chats, the local strips are those not created in any group
+#[derive(EnumIter, EnumString, Debug, Clone, Copy, PartialEq)]
+pub enum DrawPool {
+    // Only strips from the same community
+    Local,
+    // Strips from all communities
+    Global,
+    // Strips from the same community, and those not created in any group
+    Both,
+}
+
+impl DrawPool {
+    pub fn includes(&self, strip_community: Option<i64>, community: Option<i64>) -> bool {
+        match self {
+            DrawPool::Local => strip_community == community,
+            DrawPool::Global => true,
+            DrawPool::Both => strip_community.is_none() || strip_community == community,
+        }
+    }
+}
+
+// How a strip is picked when drawing, configured in DRAW_STRATEGY
+#[derive(EnumIter, EnumString, Debug, Clone, Copy, PartialEq)]
+pub enum DrawStrategy {
+    // Every visible strip is equally likely
+    Uniform,
+    // Now and then, one of the strips drawn least often so far, so that new strips get shown
+    FairExposure,
+    // Strips with more (decayed) votes are more likely
+    Weighted,
+    // Classes are drawn by their target shares, whatever the number of strips per class
+    Balanced,
+}
+
+// Actions recorded in the audit log
+#[derive(EnumIter, EnumString, Debug, Clone, Copy, PartialEq)]
+pub enum AuditAction {
+    Ban,
+    Approve,
+    Reject,
+    Delete,
+    // Change of the vote count a strip is hidden at
+    Threshold,
+    Broadcast,
+    Maintenance,
+    Interpretation,
+    Import,
+}
+
+// Badges awarded for achievements, shown in /stats
+#[derive(EnumIter, EnumString, Debug, Clone, Copy, PartialEq)]
+pub enum Badge {
+    // Authored a first strip
+    FirstStrip,
+    // Drew 100 strips
+    HundredDraws,
+    // Authored a strip which reached +10 votes
+    PopularStrip,
+    // Authored the winner of a weekly poll
+    OmikujiOfTheWeek,
+}
+
+impl Badge {
+    pub fn name(&self, language: Language) -> &'static str {
+        use Badge::*;
+        match language {
+            Language::English => match self {
+                FirstStrip => "✍️ First Strip",
+                HundredDraws => "🎴 Hundred Draws",
+                PopularStrip => "⭐ Popular Strip",
+                OmikujiOfTheWeek => "🏆 Omikuji of the Week",
+            },
+            Language::Japanese => match self {
+                FirstStrip => "✍️ 初めてのおみくじ",
+                HundredDraws => "🎴 百回引いた",
+                PopularStrip => "⭐ 人気のおみくじ",
+                OmikujiOfTheWeek => "🏆 今週のおみくじ",
+            },
+        }
+    }
+}
+
+// Emoji reactions to drawn strips, in the order of their buttons
+#[derive(EnumIter, EnumString, Debug, Clone, Copy, PartialEq)]
+pub enum Reaction {
+    Pray,
+    Laugh,
+    Scream,
+    Heart,
+}
+
+impl Reaction {
+    pub fn emoji(&self) -> &'static str {
+        match self {
+            Reaction::Pray => "🙏",
+            Reaction::Laugh => "😂",
+            Reaction::Scream => "😱",
+            Reaction::Heart => "❤️",
+        }
+    }
 }
 
 // Ref: https://en.wikipedia.org/wiki/O-mikuji (ordered by the extent of fortune)
@@ -52,6 +770,146 @@ pub enum OmikujiClass {
     Other,
 }
 
+impl OmikujiClass {
+    pub fn name(&self, language: Language) -> &'static str {
+        use OmikujiClass::*;
+        match language {
+            Language::English => match self {
+                GreatBlessing => "Great Blessing",
+                MiddleBlessing => "Middle Blessing",
+                SmallBlessing => "Small Blessing",
+                Blessing => "Blessing",
+                HalfBlessing => "Half-blessing",
+                FutureBlessing => "Future Blessing",
+                FutureSmallBlessing => "Future Small Blessing",
+                Curse => "Curse",
+                SmallCurse => "Small Curse",
+                HalfCurse => "Half-curse",
+                FutureCurse => "Future Curse",
+                GreatCurse => "Great Curse",
+                Other => "Other",
+            },
+            Language::Japanese => match self {
+                GreatBlessing => "大吉",
+                MiddleBlessing => "中吉",
+                SmallBlessing => "小吉",
+                Blessing => "吉",
+                HalfBlessing => "半吉",
+                FutureBlessing => "末吉",
+                FutureSmallBlessing => "末小吉",
+                Curse => "凶",
+                SmallCurse => "小凶",
+                HalfCurse => "半凶",
+                FutureCurse => "末凶",
+                GreatCurse => "大凶",
+                Other => "その他",
+            },
+        }
+    }
+
+    // Other names users may type for the class, besides the names above: romaji, and Chinese
+    // where it differs from Japanese
+    pub fn aliases(&self) -> &'static [&'static str] {
+        use OmikujiClass::*;
+        match self {
+            GreatBlessing => &["daikichi"],
+            MiddleBlessing => &["chūkichi", "chukichi"],
+            SmallBlessing => &["shōkichi", "shokichi"],
+            Blessing => &["kichi"],
+            HalfBlessing => &["hankichi"],
+            FutureBlessing => &["suekichi"],
+            FutureSmallBlessing => &["sueshōkichi", "sueshokichi"],
+            Curse => &["kyō", "kyo"],
+            SmallCurse => &["shōkyō", "shokyo"],
+            HalfCurse => &["hankyō", "hankyo"],
+            FutureCurse => &["suekyō", "suekyo"],
+            GreatCurse => &["daikyō", "daikyo"],
+            Other => &["其他"],
+        }
+    }
+
+    // The class typed by a user, by any of its names in any language, its aliases or as
+    // serialized, ignoring case, spaces and hyphens (e.g. "大吉", "great blessing", "Daikichi")
+    pub fn parse(input: &str) -> Option<OmikujiClass> {
+        let input = fold_name(input);
+        if input.is_empty() {
+            return None;
+        }
+        OmikujiClass::iter().find(|class| {
+            let serialized = format!("{:?}", class);
+            Language::iter()
+                .map(|language| class.name(language))
+                .chain(class.aliases().iter().copied())
+                .chain(std::iter::once(serialized.as_str()))
+                .any(|name| fold_name(name) == input)
+        })
+    }
+
+    // Position in the canonical ordering above, 0 being the best fortune, None for Other
+    pub fn rank(&self) -> Option<usize> {
+        use OmikujiClass::*;
+        match self {
+            GreatBlessing => Some(0),
+            MiddleBlessing => Some(1),
+            SmallBlessing => Some(2),
+            Blessing => Some(3),
+            HalfBlessing => Some(4),
+            FutureBlessing => Some(5),
+            FutureSmallBlessing => Some(6),
+            Curse => Some(7),
+            SmallCurse => Some(8),
+            HalfCurse => Some(9),
+            FutureCurse => Some(10),
+            GreatCurse => Some(11),
+            Other => None,
+        }
+    }
+
+    // Bad fortunes, whose strips are traditionally tied at the shrine instead of being kept
+    pub fn is_curse(&self) -> bool {
+        use OmikujiClass::*;
+        matches!(
+            self,
+            Curse | SmallCurse | HalfCurse | FutureCurse | GreatCurse
+        )
+    }
+
+    // Share of all strips (in percent) this class should have, loosely after the odds at shrines,
+    // where blessings are far more common than curses; None for Other
+    pub fn target_share(&self) -> Option<u32> {
+        use OmikujiClass::*;
+        match self {
+            GreatBlessing => Some(16),
+            MiddleBlessing => Some(12),
+            SmallBlessing => Some(10),
+            Blessing => Some(20),
+            HalfBlessing => Some(5),
+            FutureBlessing => Some(8),
+            FutureSmallBlessing => Some(4),
+            Curse => Some(10),
+            SmallCurse => Some(5),
+            HalfCurse => Some(3),
+            FutureCurse => Some(3),
+            GreatCurse => Some(4),
+            Other => None,
+        }
+    }
+
+    // Share of the other ranked classes (in percent) which are worse than this one
+    pub fn better_than(&self) -> Option<usize> {
+        let ranked = OmikujiClass::iter()
+            .filter_map(|class| class.rank())
+            .count();
+        self.rank()
+            .map(|rank| (ranked - 1 - rank) * 100 / (ranked - 1))
+    }
+
+    // The ranked class closest to an average rank
+    pub fn from_rank(rank: f64) -> Option<OmikujiClass> {
+        OmikujiClass::iter().find(|class| class.rank() == Some(rank.round() as usize))
+    }
+}
+
 // Ref: https://en.wikipedia.org/wiki/O-mikuji (only selected part of the more relevant ones)
 // hōgaku (方角) - auspicious/inauspicious directions (see feng shui)
 // negaigoto (願事) – one's wish or desire
@@ -68,7 +926,7 @@ pub enum OmikujiClass {
 // <IGNORED> tenkyo (転居) – moving or changing residence
 // <IGNORED> shussan (出産) – childbirth, delivery
 // <IGNORED> endan (縁談) – marriage proposal or engagement
-#[derive(Serialize, Deserialize, EnumIter, EnumString, Debug)]
+#[derive(Serialize, Deserialize, EnumIter, EnumString, Debug, PartialEq)]
 pub enum OmikujiSection {
     // predefined titles, with the String being explanation
     FortuneDirection,
@@ -84,10 +942,199 @@ pub enum OmikujiSection {
     Other,
 }
 
+impl OmikujiSection {
+    pub fn name(&self, language: Language) -> &'static str {
+        use OmikujiSection::*;
+        match language {
+            Language::English => match self {
+                FortuneDirection => "Fortune Direction",
+                Desire => "Desire",
+                PersonWaitedFor => "Person Waited For",
+                LostArticle => "Lost Article",
+                Travel => "Travel",
+                Business => "Business",
+                Study => "Study",
+                Dispute => "Dispute",
+                Love => "Love",
+                Illness => "Illness",
+                Other => "Other",
+            },
+            Language::Japanese => match self {
+                FortuneDirection => "方角",
+                Desire => "願事",
+                PersonWaitedFor => "待人",
+                LostArticle => "失せ物",
+                Travel => "旅立ち",
+                Business => "商い",
+                Study => "学問",
+                Dispute => "争事",
+                Love => "恋愛",
+                Illness => "病気",
+                Other => "その他",
+            },
+        }
+    }
+
+    // The section named in a cell of an import, by its name in any language or as serialized,
+    // ignoring case, spaces and hyphens like OmikujiClass::parse
+    pub fn parse(input: &str) -> Option<OmikujiSection> {
+        let input = fold_name(input);
+        if input.is_empty() {
+            return None;
+        }
+        OmikujiSection::iter().find(|section| {
+            let serialized = format!("{:?}", section);
+            Language::iter()
+                .map(|language| section.name(language))
+                .chain(std::iter::once(serialized.as_str()))
+                .any(|name| fold_name(name) == input)
+        })
+    }
+}
+
+// Predefined sets of sections, so that the user only has to type the descriptions
+#[derive(EnumIter, EnumString, Debug)]
+pub enum OmikujiTemplate {
+    // All predefined sections, as found on a traditional strip
+    Classic,
+    Short,
+}
+
+impl OmikujiTemplate {
+    pub fn name(&self, language: Language) -> &'static str {
+        use OmikujiTemplate::*;
+        match language {
+            Language::English => match self {
+                Classic => "Classic (10 sections)",
+                Short => "Short (3 sections)",
+            },
+            Language::Japanese => match self {
+                Classic => "定番（10項目）",
+                Short => "簡易（3項目）",
+            },
+        }
+    }
+
+    pub fn sections(&self) -> Vec<OmikujiSection> {
+        use OmikujiSection::*;
+        match self {
+            OmikujiTemplate::Classic => vec![
+                FortuneDirection,
+                Desire,
+                PersonWaitedFor,
+                LostArticle,
+                Travel,
+                Business,
+                Study,
+                Dispute,
+                Love,
+                Illness,
+            ],
+            OmikujiTemplate::Short => vec![Desire, Study, Love],
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct OmikujiMessage {
     pub photo: Option<String>,
     pub class: Option<OmikujiClass>,
     pub description: Option<String>,
+    // Short poem (waka) above the description, one verse per line
+    // Empty while the author is being asked for it, and left out of strips without one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poem: Option<String>,
     pub sections: Vec<(OmikujiSection, String)>,
+    // Group chat the draft was started from, stored in its own column rather than the message
+    #[serde(skip)]
+    pub community_id: Option<i64>,
+    // When the draft was last worked on, and whether the user has been reminded since
+    #[serde(skip)]
+    pub touched_at: Option<chrono::NaiveDateTime>,
+    #[serde(skip)]
+    pub reminded: bool,
+    // Whether the author's name is hidden, stored in its own column
+    #[serde(skip)]
+    pub anonymous: bool,
+    // Number of days after saving the strip expires, None if it never does
+    #[serde(skip)]
+    pub expires_in: Option<i64>,
+    // Strip being edited by its author, which is replaced on saving instead of adding a new one
+    #[serde(skip)]
+    pub editing: Option<u32>,
+}
+
+// Steps of the creation wizard, in order
+// Apart from the photo, the step is derived from what has been filled in so far
+#[derive(EnumIter, EnumString, Debug, Clone, Copy, PartialEq)]
+pub enum DraftStep {
+    Class,
+    Description,
+    Sections,
+    Photo,
+}
+
+impl DraftStep {
+    pub fn title(&self) -> &'static str {
+        match self {
+            DraftStep::Class => "choose a class",
+            DraftStep::Description => "write a description",
+            DraftStep::Sections => "add sections",
+            DraftStep::Photo => "add a photo",
+        }
+    }
+
+    // e.g. "Step 3/4: add sections"
+    pub fn progress(&self) -> String {
+        let number = DraftStep::iter()
+            .position(|step| step == *self)
+            .unwrap_or(0)
+            + 1;
+        format!(
+            "Step {}/{}: {}",
+            number,
+            DraftStep::iter().count(),
+            self.title()
+        )
+    }
+}
+
+impl OmikujiMessage {
+    // The photo is only asked for on request, so a draft never reaches that step by itself
+    pub fn step(&self) -> DraftStep {
+        if self.class.is_none() {
+            DraftStep::Class
+        } else if self.description.is_none() {
+            DraftStep::Description
+        } else {
+            DraftStep::Sections
+        }
+    }
+
+    pub fn render(&self, language: Language) -> String {
+        let mut text = String::new();
+        if let Some(class) = &self.class {
+            text += format!("*{}*\n", class.name(language)).as_str();
+        }
+        // Verses are set in italics behind a bar, since Telegram's Markdown has no block quotes
+        if let Some(poem) = &self.poem {
+            for verse in poem.lines().filter(|verse| !verse.trim().is_empty()) {
+                text += format!("▎_{}_\n", verse.trim()).as_str();
+            }
+        }
+        // A skipped description is left empty
+        if let Some(description) = self.description.as_ref().filter(|d| !d.is_empty()) {
+            text += format!("{}\n", description).as_str();
+        }
+        for (section_name, description) in &self.sections {
+            text += format!("\n*{}*: {}", section_name.name(language), description).as_str();
+        }
+        text
+    }
+}
+
+impl fmt::Display for OmikujiMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.render(Language::English))
+    }
 }
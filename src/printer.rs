@@ -0,0 +1,118 @@
+use crate::error::BotError;
+use crate::models::{Language, Omikuji, OmikujiMessage};
+use crate::tts::speech_text;
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+//
+// Receipt printers at booths, where strips drawn in the booth chat are printed as they are drawn
+// Printers are reached over TCP (usually port 9100) and spoken to in ESC/POS
+//
+
+const ESC: u8 = 0x1B;
+const GS: u8 = 0x1D;
+const FS: u8 = 0x1C;
+
+// How often a print job is tried before it is given up, e.g. while the printer is switched off
+const ATTEMPTS: u32 = 3;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// A strip as printed on a receipt, which the printer wraps to its width by itself
+pub fn receipt(omikuji: &Omikuji, language: Language) -> Result<Vec<u8>, BotError> {
+    let message: OmikujiMessage = serde_json::from_str(omikuji.message.as_str())?;
+    let mut receipt = Vec::new();
+    // Initialize, then take text as UTF-8 (FS ( C, supported by recent Epson printers)
+    receipt.extend_from_slice(&[ESC, b'@', FS, b'(', b'C', 2, 0, 48, 2]);
+    receipt.extend_from_slice(&[ESC, b'a', 1]);
+    if let Some(label) = omikuji.slip_label(language) {
+        receipt.extend_from_slice(format!("{}\n", label).as_bytes());
+    }
+    if let Some(class) = &message.class {
+        // Double width and height for the fortune
+        receipt.extend_from_slice(&[GS, b'!', 0x11]);
+        receipt.extend_from_slice(format!("{}\n", class.name(language)).as_bytes());
+        receipt.extend_from_slice(&[GS, b'!', 0]);
+    }
+    if let Some(poem) = &message.poem {
+        receipt.extend_from_slice(format!("\n{}\n", poem.trim()).as_bytes());
+    }
+    receipt.extend_from_slice(&[ESC, b'a', 0]);
+    if let Some(description) = &message.description {
+        receipt.extend_from_slice(format!("\n{}\n", speech_text(description)).as_bytes());
+    }
+    if !message.sections.is_empty() {
+        receipt.push(b'\n');
+    }
+    for (section, text) in &message.sections {
+        receipt.extend_from_slice(
+            format!("【{}】{}\n", section.name(language), speech_text(text)).as_bytes(),
+        );
+    }
+    // Feed the strip past the cutter, then cut it
+    receipt.extend_from_slice(&[ESC, b'd', 4, GS, b'V', 66, 0]);
+    Ok(receipt)
+}
+
+struct PrintJob {
+    printer: String,
+    receipt: Vec<u8>,
+}
+
+// Print jobs of all booths go through one queue, so that drawing never waits for a printer and
+// strips drawn one after another are printed in that order
+static QUEUE: OnceLock<Sender<PrintJob>> = OnceLock::new();
+
+// Queue a receipt for the printer at an address like 192.168.1.50:9100
+pub fn enqueue(printer: &str, receipt: Vec<u8>) {
+    let queue = QUEUE.get_or_init(|| {
+        let (sender, receiver) = channel::<PrintJob>();
+        thread::spawn(move || {
+            for job in receiver {
+                if let Err(e) = print(&job) {
+                    println!("Failed to print at {}: {}", job.printer, e);
+                }
+            }
+        });
+        sender
+    });
+    let job = PrintJob {
+        printer: printer.to_string(),
+        receipt,
+    };
+    if queue.send(job).is_err() {
+        println!(
+            "The print queue has stopped, a strip for {} is lost",
+            printer
+        );
+    }
+}
+
+fn print(job: &PrintJob) -> std::io::Result<()> {
+    let mut result = Ok(());
+    for attempt in 1..=ATTEMPTS {
+        result = send(job);
+        if result.is_ok() {
+            break;
+        }
+        if attempt < ATTEMPTS {
+            thread::sleep(TIMEOUT);
+        }
+    }
+    result
+}
+
+fn send(job: &PrintJob) -> std::io::Result<()> {
+    let addr = job
+        .printer
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no address"))?;
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    stream.write_all(job.receipt.as_slice())?;
+    stream.flush()
+}
@@ -0,0 +1,224 @@
+use crate::config::get_strip_font;
+use crate::error::BotError;
+use image::{ImageOutputFormat, Rgb, RgbImage};
+use rusttype::{point, Font, Scale};
+use std::io::Cursor;
+use std::sync::OnceLock;
+
+//
+// Drawn strips rendered as a sheet of omikuji paper, built with `--features image-render`
+// The font is read from STRIP_FONT, which should have CJK glyphs (e.g. Noto Sans CJK); strips it
+// cannot print are sent as Markdown text, like without the feature
+//
+
+const WIDTH: u32 = 720;
+const MARGIN: u32 = 72;
+// The double frame in vermilion, inset from the edge of the paper
+const FRAME_INSET: u32 = 20;
+const FRAME_WIDTH: u32 = 4;
+const FRAME_GAP: u32 = 8;
+const TEXT_SIZE: f32 = 30.0;
+// Lines which are bold as a whole, like the class of the strip
+const TITLE_SIZE: f32 = 44.0;
+const LINE_HEIGHT: f32 = 1.5;
+// Telegram refuses photos more than 20 times as high as they are wide
+const MAX_HEIGHT: u32 = WIDTH * 20;
+
+const PAPER: Rgb<u8> = Rgb([250, 244, 228]);
+const INK: Rgb<u8> = Rgb([40, 32, 28]);
+const VERMILION: Rgb<u8> = Rgb([196, 48, 36]);
+
+static FONT: OnceLock<Option<Font<'static>>> = OnceLock::new();
+
+// Read a TrueType or OpenType font (the first one, of a collection), None if it cannot be read
+pub fn load_font(path: &str) -> Option<Font<'static>> {
+    let font = std::fs::read(path).ok().and_then(Font::try_from_vec);
+    if font.is_none() {
+        println!("Cannot read the font {}, strips are sent as text", path);
+    }
+    font
+}
+
+// Render a strip as sent in Markdown into a PNG, with the font of STRIP_FONT
+// None if there is no font, or the strip has to be sent as text after all
+pub fn render_strip(markdown: &str) -> Result<Option<Vec<u8>>, BotError> {
+    let font = FONT.get_or_init(|| load_font(get_strip_font()?.as_str()));
+    match font {
+        Some(font) => render_with(font, markdown),
+        None => Ok(None),
+    }
+}
+
+// None if the font lacks letters of the strip (e.g. a Latin font for Japanese), or the strip is
+// too long to be sent as a photo
+// Emojis and other symbols the font lacks are left out, they are decoration only
+pub fn render_with(font: &Font<'_>, markdown: &str) -> Result<Option<Vec<u8>>, BotError> {
+    let missing = |c: char| font.glyph(c).id().0 == 0;
+    if markdown.chars().any(|c| c.is_alphanumeric() && missing(c)) {
+        return Ok(None);
+    }
+    let markdown: String = markdown
+        .chars()
+        .filter(|&c| c.is_whitespace() || !missing(c))
+        .collect();
+
+    let text_width = (WIDTH - 2 * MARGIN) as f32;
+    let mut lines = Vec::new();
+    for line in markdown.lines() {
+        let line = line.trim();
+        let inner = line
+            .strip_prefix('*')
+            .and_then(|line| line.strip_suffix('*'));
+        let (text, scale, title) = match inner {
+            Some(inner) if !inner.is_empty() && !inner.contains('*') => {
+                (plain_text(inner), Scale::uniform(TITLE_SIZE), true)
+            }
+            _ => (plain_text(line), Scale::uniform(TEXT_SIZE), false),
+        };
+        if text.is_empty() {
+            // Paragraphs are set apart by half a line
+            lines.push((String::new(), Scale::uniform(TEXT_SIZE / 2.0), false));
+            continue;
+        }
+        for wrapped in wrap(font, scale, text.as_str(), text_width) {
+            lines.push((wrapped, scale, title));
+        }
+    }
+    let height = lines
+        .iter()
+        .map(|(_, scale, _)| scale.y * LINE_HEIGHT)
+        .sum::<f32>()
+        .ceil() as u32
+        + 2 * MARGIN;
+    if height > MAX_HEIGHT {
+        return Ok(None);
+    }
+
+    let mut image = RgbImage::from_pixel(WIDTH, height, PAPER);
+    frame(&mut image, FRAME_INSET, FRAME_WIDTH);
+    frame(&mut image, FRAME_INSET + FRAME_WIDTH + FRAME_GAP, 1);
+    let mut top = MARGIN as f32;
+    for (line, scale, title) in &lines {
+        let ascent = font.v_metrics(*scale).ascent;
+        let baseline = top + (scale.y * LINE_HEIGHT - scale.y) / 2.0 + ascent;
+        // Titles are centred, everything else starts at the margin
+        let left = if *title {
+            (WIDTH as f32 - width(font, *scale, line)) / 2.0
+        } else {
+            MARGIN as f32
+        };
+        let color = if *title { VERMILION } else { INK };
+        draw_text(&mut image, font, *scale, line, left, baseline, color);
+        top += scale.y * LINE_HEIGHT;
+    }
+
+    let mut png = Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, ImageOutputFormat::Png)
+        .map_err(|e| BotError::State(format!("Cannot encode the strip as PNG: {}", e)))?;
+    Ok(Some(png.into_inner()))
+}
+
+// Text as printed, without the marks of Telegram's Markdown but with the characters escaped
+// from it (e.g. in names)
+fn plain_text(markdown: &str) -> String {
+    let mut text = String::with_capacity(markdown.len());
+    let mut chars = markdown.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            '*' | '_' | '`' | '[' | ']' => {}
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+// Width of a line as set in the font
+fn width(font: &Font<'_>, scale: Scale, text: &str) -> f32 {
+    font.layout(text, scale, point(0.0, 0.0))
+        .last()
+        .map(|glyph| glyph.position().x + glyph.unpositioned().h_metrics().advance_width)
+        .unwrap_or(0.0)
+}
+
+// Break a line into lines fitting the width, between words where there are spaces and between
+// any two characters otherwise (as Japanese has no spaces)
+// Widths are summed up from the advances of the characters, kerning hardly matters here
+fn wrap(font: &Font<'_>, scale: Scale, text: &str, max_width: f32) -> Vec<String> {
+    let advance = |c: char| font.glyph(c).scaled(scale).h_metrics().advance_width;
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut line_width = 0.0;
+    for c in text.chars() {
+        if line_width + advance(c) > max_width && !line.is_empty() {
+            let rest = match line.rfind(' ') {
+                Some(space) if c != ' ' && space > 0 => {
+                    let rest = line[space + 1..].to_string();
+                    line.truncate(space);
+                    rest
+                }
+                _ => String::new(),
+            };
+            lines.push(line.trim_end().to_string());
+            line = rest;
+            line_width = line.chars().map(advance).sum();
+            if c == ' ' {
+                continue;
+            }
+        }
+        line.push(c);
+        line_width += advance(c);
+    }
+    if !line.trim().is_empty() {
+        lines.push(line.trim_end().to_string());
+    }
+    lines
+}
+
+// A rectangle of the given line width, inset from the edges of the image
+fn frame(image: &mut RgbImage, inset: u32, line_width: u32) {
+    let (width, height) = image.dimensions();
+    for y in inset..height - inset {
+        for x in inset..width - inset {
+            let from_edge = (x - inset)
+                .min(y - inset)
+                .min(width - inset - 1 - x)
+                .min(height - inset - 1 - y);
+            if from_edge < line_width {
+                image.put_pixel(x, y, VERMILION);
+            }
+        }
+    }
+}
+
+fn draw_text(
+    image: &mut RgbImage,
+    font: &Font<'_>,
+    scale: Scale,
+    text: &str,
+    left: f32,
+    baseline: f32,
+    color: Rgb<u8>,
+) {
+    let (width, height) = image.dimensions();
+    for glyph in font.layout(text, scale, point(left, baseline)) {
+        let bounds = match glyph.pixel_bounding_box() {
+            Some(bounds) => bounds,
+            None => continue,
+        };
+        glyph.draw(|x, y, coverage| {
+            let x = bounds.min.x + x as i32;
+            let y = bounds.min.y + y as i32;
+            if x < 0 || y < 0 || x as u32 >= width || y as u32 >= height {
+                return;
+            }
+            // Glyphs are blended into the paper by how much of a pixel they cover
+            let pixel = image.get_pixel_mut(x as u32, y as u32);
+            for (channel, ink) in pixel.0.iter_mut().zip(color.0.iter()) {
+                *channel = (f32::from(*channel) * (1.0 - coverage) + f32::from(*ink) * coverage)
+                    .round() as u8;
+            }
+        });
+    }
+}
@@ -0,0 +1,4440 @@
+use crate::config::{vote_half_life, DEFAULT_TENANT};
+use crate::db::Database;
+use crate::error::BotError;
+use crate::models::{
+    AuditEntry, AuthorDigest, BoothDraw, Comment, DeferredMessage, Draw, DrawPool, JobRun,
+    Language, NewAchievement, NewAuditEntry, NewBoothDraw, NewComment, NewDeferredMessage, NewDraw,
+    NewFavorite, NewImportBatch, NewInterpretation, NewOmikuji, NewReaction, NewTrashItem,
+    NewUsageEvent, NewUser, NewUserSettings, NewWeeklyPoll, Omikuji, OmikujiMessage, TrashItem,
+    UsageDay, UsageEvent, User, UserSettings, VariantResult, WeeklyPoll,
+};
+use crate::schema;
+use crate::scoring;
+use async_trait::async_trait;
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::mysql::Mysql;
+use diesel::prelude::*;
+use diesel::sql_types::Bigint;
+use diesel_async::pooled_connection::bb8::PooledConnection;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncMysqlConnection, RunQueryDsl};
+use rand::{thread_rng, Rng};
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// Omikuji strips are quarantined (no longer drawn) once their vote_count drops to this
+pub const HIDE_THRESHOLD: i32 = -3;
+
+// Author of the strips left behind by users who asked to be forgotten
+pub const ANONYMOUS_ID: i64 = 0;
+pub const ANONYMOUS_NAME: &str = "anonymous";
+
+// Persistence of omikuji strips, so that handlers don't depend on a particular database
+#[async_trait(?Send)]
+pub trait OmikujiRepository {
+    // Returns the ID of the new strip
+    async fn insert_omikuji(&self, omikuji: &NewOmikuji) -> Result<u32, BotError>;
+    async fn find_omikuji(&self, omikuji_id: u32) -> Result<Option<Omikuji>, BotError>;
+    // The strip given this slip number, hidden or not
+    async fn find_omikuji_by_slip(&self, slip_number: u32) -> Result<Option<Omikuji>, BotError>;
+    async fn find_omikujis_by_author(&self, tg_id: i64) -> Result<Vec<Omikuji>, BotError>;
+    // All strips, hidden or not, in the order they were saved
+    async fn all_omikujis(&self) -> Result<Vec<Omikuji>, BotError>;
+    // Pick a random strip among those that are not hidden, from the pool seen by `community`
+    async fn random_omikuji(
+        &self,
+        pool: DrawPool,
+        community: Option<i64>,
+    ) -> Result<Option<Omikuji>, BotError>;
+    // Pick a random strip among those drawn least often so far, from the same pool as above
+    async fn least_drawn_omikuji(
+        &self,
+        pool: DrawPool,
+        community: Option<i64>,
+    ) -> Result<Option<Omikuji>, BotError>;
+    // Pick a random strip, those with a higher (decayed) score being more likely
+    async fn weighted_omikuji(
+        &self,
+        pool: DrawPool,
+        community: Option<i64>,
+    ) -> Result<Option<Omikuji>, BotError>;
+    // Pick a random strip by the target shares of the classes, see scoring::balanced_index
+    async fn balanced_omikuji(
+        &self,
+        pool: DrawPool,
+        community: Option<i64>,
+    ) -> Result<Option<Omikuji>, BotError>;
+    // IDs (and communities) of all strips that are drawn
+    async fn visible_omikuji_ids(&self) -> Result<Vec<(u32, Option<i64>)>, BotError>;
+    // The strip with the highest (decayed) score among those written since the given time
+    async fn top_omikuji(&self, since: NaiveDateTime) -> Result<Option<Omikuji>, BotError>;
+    // Like top_omikuji, the best `limit` strips, best first
+    async fn top_omikujis(
+        &self,
+        since: NaiveDateTime,
+        limit: usize,
+    ) -> Result<Vec<Omikuji>, BotError>;
+    // Add `delta` to the vote count in a single step, so that concurrent votes are not lost
+    async fn add_vote(&self, omikuji_id: u32, delta: i32) -> Result<(), BotError>;
+    // Flag strips which expired by the given time as archived, returns how many were archived
+    async fn archive_expired(&self, now: NaiveDateTime) -> Result<usize, BotError>;
+    // Replace the message of a strip edited by its author, quarantining it (for review) if
+    // `quarantine`, otherwise leaving it as it was
+    async fn update_omikuji(
+        &self,
+        omikuji_id: u32,
+        message: &str,
+        quarantine: bool,
+    ) -> Result<(), BotError>;
+    async fn set_anonymous(&self, omikuji_id: u32, anonymous: bool) -> Result<(), BotError>;
+    // Move the strips of a group which got a new id when it was upgraded to a supergroup,
+    // returns how many were moved
+    async fn migrate_community(&self, from: i64, to: i64) -> Result<usize, BotError>;
+}
+
+// Persistence of users and their settings
+#[async_trait(?Send)]
+pub trait UserRepository {
+    // Insert the user, or refresh the profile if the user is already known
+    async fn upsert_user(&self, user: &NewUser) -> Result<(), BotError>;
+    async fn is_banned(&self, tg_id: i64) -> Result<bool, BotError>;
+    async fn find_user(&self, tg_id: i64) -> Result<Option<User>, BotError>;
+    // Users who never touched their settings get default values
+    async fn get_user_settings(&self, tg_id: i64) -> Result<UserSettings, BotError>;
+    async fn update_user_settings(&self, settings: &UserSettings) -> Result<(), BotError>;
+    // Settings of the subscribers, in one query for the scheduled jobs
+    // Subscribers leave out inactive users, who blocked the bot
+    async fn get_daily_subscribers(&self) -> Result<Vec<UserSettings>, BotError>;
+    async fn get_digest_subscribers(&self) -> Result<Vec<UserSettings>, BotError>;
+    // Users who blocked the bot are inactive until they write to it again (see upsert_user)
+    async fn set_inactive(&self, tg_id: i64, inactive: bool) -> Result<(), BotError>;
+    // Current name of an author who opted in to be credited on drawn strips
+    async fn credit_name(&self, tg_id: i64) -> Result<Option<String>, BotError>;
+    // Delete everything known about a user, their strips are kept but no longer linked to them
+    // Their votes stay in the vote counts of the strips, while the draws they were cast on go
+    async fn forget_user(&self, tg_id: i64) -> Result<(), BotError>;
+    // Credit the referrer of a user, returns false if the user had been referred already
+    async fn set_referrer(&self, tg_id: i64, referrer: i64) -> Result<bool, BotError>;
+}
+
+// Draw history and aggregates shown to admins
+#[async_trait(?Send)]
+pub trait StatsRepository {
+    // Also counts the draw on the strip
+    async fn record_draw(&self, draw: &NewDraw) -> Result<(), BotError>;
+    async fn find_draws_by_user(&self, tg_id: i64) -> Result<Vec<Draw>, BotError>;
+    // Draws of the user since the given time, e.g. for the daily limit
+    async fn count_draws_by_user(&self, tg_id: i64, since: NaiveDateTime) -> Result<i64, BotError>;
+    // Latest draw of the user, leaving out tied bad fortunes
+    async fn find_last_draw(&self, tg_id: i64) -> Result<Option<Draw>, BotError>;
+    // Number of strips per class (as serialized, e.g. "GreatBlessing"), None for unknown classes
+    async fn count_omikujis_by_class(&self) -> Result<Vec<(Option<String>, i64)>, BotError>;
+    async fn count_hidden_omikujis(&self) -> Result<i64, BotError>;
+    // Strips which are drawn, but have not been drawn yet
+    async fn count_undrawn_omikujis(&self) -> Result<i64, BotError>;
+    // IDs of the strips drawn most often, with their number of draws
+    async fn most_drawn_omikujis(&self, limit: i64) -> Result<Vec<(u32, u32)>, BotError>;
+    // Users who sent anything to the bot since the given time
+    async fn count_active_users(&self, since: NaiveDateTime) -> Result<i64, BotError>;
+    async fn count_draws_by_day(
+        &self,
+        since: NaiveDateTime,
+    ) -> Result<Vec<(NaiveDate, i64)>, BotError>;
+    // Names of the authors who wrote the most strips, with their number of strips
+    async fn top_authors(&self, limit: i64) -> Result<Vec<(String, i64)>, BotError>;
+    async fn count_referrals(&self, tg_id: i64) -> Result<i64, BotError>;
+    // Names of the users who invited the most users, with their number of invites
+    async fn top_referrers(&self, limit: i64) -> Result<Vec<(String, i64)>, BotError>;
+    // Note the vote on the latest draw of the strip by the user, unless it has a vote already
+    async fn record_vote(&self, tg_id: i64, omikuji_id: u32, vote: i8) -> Result<(), BotError>;
+    // Tie the latest draw of the strip by the user at the shrine, return false if there is no
+    // such draw or it is tied already
+    async fn tie_draw(&self, tg_id: i64, omikuji_id: u32) -> Result<bool, BotError>;
+    // Bad fortunes tied by any user since the given time
+    async fn count_tied_draws(&self, since: NaiveDateTime) -> Result<i64, BotError>;
+    // Engagement per variant of the draw experiment, ordered by variant
+    async fn experiment_results(&self) -> Result<Vec<VariantResult>, BotError>;
+    // Draws, votes and comments on the strips of an author since the given time
+    async fn author_digest(
+        &self,
+        tg_id: i64,
+        since: NaiveDateTime,
+    ) -> Result<AuthorDigest, BotError>;
+    // Also counts the draw on the strip, but not as a draw of the user at the booth
+    async fn record_booth_draw(&self, draw: &NewBoothDraw) -> Result<(), BotError>;
+    async fn count_booth_draws(&self, since: NaiveDateTime) -> Result<i64, BotError>;
+    // Booth draws run by the user, oldest first
+    async fn find_booth_draws_by_user(&self, tg_id: i64) -> Result<Vec<BoothDraw>, BotError>;
+}
+
+// Review of quarantined strips, used by the web admin panel and /review
+#[async_trait(?Send)]
+pub trait ModerationRepository {
+    // Quarantined strips which have not been reviewed yet
+    async fn find_pending_omikujis(&self) -> Result<Vec<Omikuji>, BotError>;
+    // All quarantined strips, including those rejected before
+    async fn find_quarantined_omikujis(&self) -> Result<Vec<Omikuji>, BotError>;
+    // Strips (quarantined or not) whose text contains the query
+    async fn search_omikujis(&self, query: &str, limit: i64) -> Result<Vec<Omikuji>, BotError>;
+    // Approved strips are restored, rejected strips stay quarantined
+    async fn review_omikuji(&self, omikuji_id: u32, approve: bool) -> Result<(), BotError>;
+    async fn delete_omikuji(&self, omikuji_id: u32) -> Result<(), BotError>;
+    // Batches of the above, all or nothing, returning how many of the strips were found
+    async fn review_omikujis(&self, omikuji_ids: &[u32], approve: bool) -> Result<usize, BotError>;
+    async fn delete_omikujis(&self, omikuji_ids: &[u32]) -> Result<usize, BotError>;
+}
+
+// Admin and moderation actions, for accountability
+#[async_trait(?Send)]
+pub trait AuditRepository {
+    async fn record_audit(&self, entry: &NewAuditEntry) -> Result<(), BotError>;
+    // Most recent entries first
+    async fn find_audit_entries(
+        &self,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>, BotError>;
+}
+
+// Interpretations of the classes of strips, keyed by the class as serialized
+#[async_trait(?Send)]
+pub trait InterpretationRepository {
+    async fn find_interpretation(&self, class: &str) -> Result<Option<String>, BotError>;
+    // An empty text removes the interpretation
+    async fn set_interpretation(&self, interpretation: &NewInterpretation) -> Result<(), BotError>;
+}
+
+// Badges awarded to users, keyed by the badge as serialized
+#[async_trait(?Send)]
+pub trait AchievementRepository {
+    // Returns false if the user already had the badge
+    async fn award_badge(&self, achievement: &NewAchievement) -> Result<bool, BotError>;
+    // In the order they were awarded
+    async fn find_badges(&self, tg_id: i64) -> Result<Vec<String>, BotError>;
+}
+
+// Strips saved by users to be read again later
+#[async_trait(?Send)]
+pub trait FavoriteRepository {
+    // Returns false if the strip was saved already
+    async fn add_favorite(&self, favorite: &NewFavorite) -> Result<bool, BotError>;
+    // Most recently saved first
+    async fn find_favorites(
+        &self,
+        tg_id: i64,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<Omikuji>, BotError>;
+    async fn count_favorites(&self, tg_id: i64) -> Result<i64, BotError>;
+}
+
+// Emoji reactions to strips, keyed by the reaction as serialized
+#[async_trait(?Send)]
+pub trait ReactionRepository {
+    // Replaces the previous reaction of the user to the strip, if any
+    async fn set_reaction(&self, reaction: &NewReaction) -> Result<(), BotError>;
+    // Number of users per reaction, reactions nobody chose are left out
+    async fn count_reactions(&self, omikuji_id: u32) -> Result<Vec<(String, i64)>, BotError>;
+    // The reactions of the user, as IDs of the strips with the reaction to each of them
+    async fn find_reactions_by_user(&self, tg_id: i64) -> Result<Vec<(u32, String)>, BotError>;
+}
+
+// Comments left on strips by the users who drew them
+#[async_trait(?Send)]
+pub trait CommentRepository {
+    // Returns the id of the new comment
+    async fn insert_comment(&self, comment: &NewComment) -> Result<u32, BotError>;
+    async fn find_comment(&self, comment_id: u32) -> Result<Option<Comment>, BotError>;
+    // Comments on the strips of an author, most recent first
+    async fn find_comments_for_author(
+        &self,
+        tg_id: i64,
+        limit: i64,
+    ) -> Result<Vec<Comment>, BotError>;
+    // Comments on all strips, most recent first, for moderation
+    async fn find_recent_comments(&self, limit: i64) -> Result<Vec<Comment>, BotError>;
+    // Comments written by the user, oldest first
+    async fn find_comments_by_user(&self, tg_id: i64) -> Result<Vec<Comment>, BotError>;
+    // Returns false if the comment does not exist (any more)
+    async fn delete_comment(&self, comment_id: u32) -> Result<bool, BotError>;
+}
+
+// Weekly polls for the omikuji of the week
+#[async_trait(?Send)]
+pub trait PollRepository {
+    async fn insert_weekly_poll(&self, poll: &NewWeeklyPoll) -> Result<(), BotError>;
+    // The latest poll, unless it has been closed already
+    async fn find_open_weekly_poll(&self) -> Result<Option<WeeklyPoll>, BotError>;
+    async fn close_weekly_poll(&self, poll_id: u32, winner_id: Option<u32>)
+        -> Result<(), BotError>;
+    // Strips which won a poll, most recent first
+    async fn find_weekly_winners(&self) -> Result<Vec<u32>, BotError>;
+    // Like migrate_community, for polls sent to a group which was upgraded to a supergroup
+    async fn migrate_poll_chat(&self, from: i64, to: i64) -> Result<(), BotError>;
+}
+
+// Usage of commands and buttons, for trends on /adminstats and capacity planning
+#[async_trait(?Send)]
+pub trait UsageRepository {
+    async fn record_usage(&self, event: &NewUsageEvent) -> Result<(), BotError>;
+    // Roll the events before the given time up into daily totals, which keeps the events table
+    // small, returns how many events were rolled up
+    async fn aggregate_usage(&self, before: NaiveDateTime) -> Result<usize, BotError>;
+    // Daily totals since the given day, including the events not rolled up yet
+    async fn usage_by_day(&self, since: NaiveDate) -> Result<Vec<UsageDay>, BotError>;
+}
+
+// Leases making sure that only one of several instances of the bot runs a job (e.g. the
+// scheduled jobs), with the others taking over once the lease of a stopped instance expires
+#[async_trait(?Send)]
+pub trait LeaseRepository {
+    // Take the lease if it is free or has expired, or extend it if `holder` has it already,
+    // returns whether `holder` has the lease for the given time from now
+    async fn acquire_lease(
+        &self,
+        name: &str,
+        holder: &str,
+        duration: Duration,
+    ) -> Result<bool, BotError>;
+    // The last runs of a job kept by the holder of the lease, one per user it runs for
+    async fn find_job_runs(&self, job: &str) -> Result<Vec<JobRun>, BotError>;
+    // Inserted, or replacing the previous run of the job for the same user
+    async fn record_job_run(&self, run: &JobRun) -> Result<(), BotError>;
+}
+
+// Strips and drafts deleted by their users, kept for a while so that they can be restored
+#[async_trait(?Send)]
+pub trait TrashRepository {
+    async fn insert_trash(&self, item: &NewTrashItem) -> Result<u32, BotError>;
+    // Items the user deleted since the given time, most recent first
+    async fn find_trash(
+        &self,
+        tg_id: i64,
+        since: NaiveDateTime,
+    ) -> Result<Vec<TrashItem>, BotError>;
+    // Remove an item of the user from the trash to restore it, None if it is not there (any more)
+    async fn take_trash(&self, tg_id: i64, trash_id: u32) -> Result<Option<TrashItem>, BotError>;
+    // Delete the items deleted before the given time for good, returns how many there were
+    async fn purge_trash(&self, before: NaiveDateTime) -> Result<usize, BotError>;
+}
+
+// Strips imported by admins (see import), saved together under a batch
+#[async_trait(?Send)]
+pub trait ImportRepository {
+    // All strips are saved, or none of them, returns the ID of the batch
+    async fn import_omikujis(
+        &self,
+        batch: &NewImportBatch,
+        omikujis: &[NewOmikuji],
+    ) -> Result<u32, BotError>;
+}
+
+// Notifications held back during the quiet hours of their recipients (see handlers::quiet)
+#[async_trait(?Send)]
+pub trait DeferredRepository {
+    async fn defer_message(&self, message: &NewDeferredMessage) -> Result<(), BotError>;
+    // Oldest first
+    async fn find_deferred_messages(&self) -> Result<Vec<DeferredMessage>, BotError>;
+    async fn delete_deferred_message(&self, id: u32) -> Result<(), BotError>;
+}
+
+// Drafts being written, when they are shared by several instances of the bot (see drafts)
+// Not part of Repository, since the handlers only see the drafts of the store passed to them
+#[async_trait(?Send)]
+pub trait DraftStore {
+    async fn load_draft(&self, tg_id: i64) -> Result<Option<OmikujiMessage>, BotError>;
+    // Inserted, or replacing the previous draft of the user
+    async fn save_draft(&self, tg_id: i64, draft: &OmikujiMessage) -> Result<(), BotError>;
+    async fn delete_draft(&self, tg_id: i64) -> Result<(), BotError>;
+    // Every draft, with the revision it is at, which changes whenever the draft is saved
+    async fn load_drafts(&self) -> Result<Vec<(i64, NaiveDateTime, OmikujiMessage)>, BotError>;
+    // Replace the draft only if it is still at the given revision, so that a draft another
+    // instance saved (or deleted) in the meantime is kept; returns whether it was replaced
+    async fn save_draft_if(
+        &self,
+        tg_id: i64,
+        draft: &OmikujiMessage,
+        revision: NaiveDateTime,
+    ) -> Result<bool, BotError>;
+}
+
+// Everything the handlers need to persist
+pub trait Repository:
+    OmikujiRepository
+    + UserRepository
+    + StatsRepository
+    + ModerationRepository
+    + AuditRepository
+    + InterpretationRepository
+    + AchievementRepository
+    + FavoriteRepository
+    + CommentRepository
+    + ReactionRepository
+    + PollRepository
+    + UsageRepository
+    + LeaseRepository
+    + TrashRepository
+    + ImportRepository
+    + DeferredRepository
+{
+}
+
+impl<T> Repository for T where
+    T: OmikujiRepository
+        + UserRepository
+        + StatsRepository
+        + ModerationRepository
+        + AuditRepository
+        + InterpretationRepository
+        + AchievementRepository
+        + FavoriteRepository
+        + CommentRepository
+        + ReactionRepository
+        + PollRepository
+        + UsageRepository
+        + LeaseRepository
+        + TrashRepository
+        + ImportRepository
+        + DeferredRepository
+{
+}
+
+//
+// Diesel (MySQL) implementation
+//
+
+// Queries are awaited on a connection from the pool of Database, taken for one call and put
+// back once it is done, so that the other updates need not wait for it
+// Every query is scoped to one tenant, so several bots can share the same database
+pub struct DieselRepository<'a> {
+    database: &'a Database,
+    tenant: String,
+}
+
+impl<'a> DieselRepository<'a> {
+    pub fn new(database: &'a Database, tenant: &str) -> Self {
+        DieselRepository {
+            database,
+            tenant: tenant.to_string(),
+        }
+    }
+
+    async fn connection(&self) -> Result<PooledConnection<'_, AsyncMysqlConnection>, BotError> {
+        self.database.connection().await
+    }
+
+    // IDs of the users who blocked the bot, to be left out of broadcasts
+    fn inactive_users(&self) -> schema::users::BoxedQuery<'_, Mysql, Bigint> {
+        use schema::users::dsl::{inactive, tenant_id, tg_id, users};
+        users
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(inactive.eq(true))
+            .select(tg_id)
+            .into_boxed()
+    }
+
+    // Strips of the pool seen by `community`, hidden or not
+    fn library(
+        &self,
+        pool: DrawPool,
+        community: Option<i64>,
+    ) -> schema::omikujis::BoxedQuery<'_, Mysql> {
+        use schema::omikujis::dsl::{community_id, omikujis, tenant_id};
+        let query = omikujis.filter(tenant_id.eq(&self.tenant)).into_boxed();
+        match (pool, community) {
+            (DrawPool::Global, _) => query,
+            (DrawPool::Local, Some(community)) => query.filter(community_id.eq(community)),
+            (DrawPool::Both, Some(community)) => {
+                query.filter(community_id.is_null().or(community_id.eq(community)))
+            }
+            (_, None) => query.filter(community_id.is_null()),
+        }
+    }
+
+    // Give the next slip numbers to drawable strips which have none yet, in the order they were
+    // saved, so that numbers are never reused even once strips are hidden again
+    // Run on the connection of the transaction the strips were changed in
+    async fn number_slips(&self, connection: &mut AsyncMysqlConnection) -> Result<(), BotError> {
+        use schema::omikujis::dsl::{id, omikujis, quarantined, slip_number, tenant_id};
+        let last: Option<Option<u32>> = omikujis
+            .filter(tenant_id.eq(&self.tenant))
+            .order(slip_number.desc())
+            .select(slip_number)
+            .first(&mut *connection)
+            .await
+            .optional()?;
+        let unnumbered: Vec<u32> = omikujis
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(quarantined.eq(false))
+            .filter(slip_number.is_null())
+            .order(id)
+            .select(id)
+            .load(&mut *connection)
+            .await?;
+        for (number, omikuji_id) in (last.flatten().unwrap_or(0) + 1..).zip(unnumbered) {
+            diesel::update(omikujis.find(omikuji_id))
+                .set(slip_number.eq(number))
+                .execute(&mut *connection)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a> OmikujiRepository for DieselRepository<'a> {
+    async fn insert_omikuji(&self, omikuji: &NewOmikuji) -> Result<u32, BotError> {
+        use diesel::dsl::sql;
+        use diesel::sql_types::{BigInt, Unsigned};
+        use schema::omikujis::dsl::tenant_id;
+        let mut connection = self.connection().await?;
+        // Rows belonging to the strip are inserted together, or not at all
+        connection
+            .transaction::<_, BotError, _>(|connection| {
+                async move {
+                    diesel::insert_into(schema::omikujis::table)
+                        .values((omikuji, tenant_id.eq(&self.tenant)))
+                        .execute(connection)
+                        .await?;
+                    let id: u64 = diesel::select(sql::<Unsigned<BigInt>>("LAST_INSERT_ID()"))
+                        .get_result(connection)
+                        .await?;
+                    self.number_slips(connection).await?;
+                    Ok(id as u32)
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+
+    async fn find_omikuji(&self, omikuji_id: u32) -> Result<Option<Omikuji>, BotError> {
+        use schema::omikujis::dsl::{omikujis, tenant_id};
+        let mut connection = self.connection().await?;
+        Ok(omikujis
+            .find(omikuji_id)
+            .filter(tenant_id.eq(&self.tenant))
+            .get_result(&mut connection)
+            .await
+            .optional()?)
+    }
+
+    async fn find_omikuji_by_slip(&self, number: u32) -> Result<Option<Omikuji>, BotError> {
+        use schema::omikujis::dsl::{omikujis, slip_number, tenant_id};
+        let mut connection = self.connection().await?;
+        Ok(omikujis
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(slip_number.eq(number))
+            .get_result(&mut connection)
+            .await
+            .optional()?)
+    }
+
+    async fn find_omikujis_by_author(&self, author_id: i64) -> Result<Vec<Omikuji>, BotError> {
+        use schema::omikujis::dsl::{id, omikujis, tenant_id, tg_id};
+        let mut connection = self.connection().await?;
+        Ok(omikujis
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(tg_id.eq(author_id))
+            .order(id)
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn all_omikujis(&self) -> Result<Vec<Omikuji>, BotError> {
+        use schema::omikujis::dsl::{id, omikujis, tenant_id};
+        let mut connection = self.connection().await?;
+        Ok(omikujis
+            .filter(tenant_id.eq(&self.tenant))
+            .order(id)
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn random_omikuji(
+        &self,
+        pool: DrawPool,
+        community: Option<i64>,
+    ) -> Result<Option<Omikuji>, BotError> {
+        use schema::omikujis::dsl::{archived, expires_at, id, quarantined};
+        let mut connection = self.connection().await?;
+        // MIN/MAX of the primary key are read from the index, unlike COUNT + OFFSET which
+        // scans the table, so draws stay fast on large libraries
+        let library = || self.library(pool, community);
+        let low: Option<u32> = library()
+            .select(diesel::dsl::min(id))
+            .get_result(&mut connection)
+            .await?;
+        let high: Option<u32> = library()
+            .select(diesel::dsl::max(id))
+            .get_result(&mut connection)
+            .await?;
+        let (low, high) = match (low, high) {
+            (Some(low), Some(high)) => (low, high),
+            _ => return Ok(None),
+        };
+        // gen_range excludes its upper bound, so high + 1 lets the last id be picked as well
+        let x: u32 = thread_rng().gen_range(low, high + 1);
+        // Take the first visible strip from a random id onwards, wrapping around to the start
+        // Strips after a gap in the ids (or after hidden strips) are slightly more likely
+        let now = chrono::Local::now().naive_local();
+        let visible = || {
+            library()
+                .filter(quarantined.eq(false))
+                .filter(archived.eq(false))
+                .filter(expires_at.is_null().or(expires_at.gt(now)))
+                .order(id)
+        };
+        let omikuji = visible()
+            .filter(id.ge(x))
+            .first(&mut connection)
+            .await
+            .optional()?;
+        match omikuji {
+            Some(omikuji) => Ok(Some(omikuji)),
+            None => Ok(visible().first(&mut connection).await.optional()?),
+        }
+    }
+
+    async fn least_drawn_omikuji(
+        &self,
+        pool: DrawPool,
+        community: Option<i64>,
+    ) -> Result<Option<Omikuji>, BotError> {
+        use schema::omikujis::dsl::{archived, draw_count, expires_at, id, quarantined};
+        let mut connection = self.connection().await?;
+        let now = chrono::Local::now().naive_local();
+        let visible = || {
+            self.library(pool, community)
+                .filter(quarantined.eq(false))
+                .filter(archived.eq(false))
+                .filter(expires_at.is_null().or(expires_at.gt(now)))
+        };
+        let fewest: Option<u32> = visible()
+            .select(diesel::dsl::min(draw_count))
+            .get_result(&mut connection)
+            .await?;
+        let fewest = match fewest {
+            Some(fewest) => fewest,
+            None => return Ok(None),
+        };
+        let ids: Vec<u32> = visible()
+            .filter(draw_count.eq(fewest))
+            .select(id)
+            .load(&mut connection)
+            .await?;
+        if ids.is_empty() {
+            return Ok(None);
+        }
+        let x = thread_rng().gen_range(0, ids.len());
+        self.find_omikuji(ids[x]).await
+    }
+
+    async fn weighted_omikuji(
+        &self,
+        pool: DrawPool,
+        community: Option<i64>,
+    ) -> Result<Option<Omikuji>, BotError> {
+        use schema::omikujis::dsl::{
+            archived, created_at, expires_at, id, quarantined, vote_count,
+        };
+        let mut connection = self.connection().await?;
+        let now = chrono::Local::now().naive_local();
+        // Only the columns needed for the weights are loaded, the strip itself by its ID
+        let candidates: Vec<(u32, i32, NaiveDateTime)> = self
+            .library(pool, community)
+            .filter(quarantined.eq(false))
+            .filter(archived.eq(false))
+            .filter(expires_at.is_null().or(expires_at.gt(now)))
+            .select((id, vote_count, created_at))
+            .load(&mut connection)
+            .await?;
+        let weights: Vec<f64> = candidates
+            .iter()
+            .map(|(_, votes, created)| {
+                scoring::weight(scoring::decayed_votes(
+                    *votes,
+                    *created,
+                    now,
+                    vote_half_life(),
+                ))
+            })
+            .collect();
+        match scoring::weighted_index(&weights, &mut thread_rng()) {
+            Some(index) => self.find_omikuji(candidates[index].0).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn balanced_omikuji(
+        &self,
+        pool: DrawPool,
+        community: Option<i64>,
+    ) -> Result<Option<Omikuji>, BotError> {
+        use diesel::dsl::sql;
+        use diesel::sql_types::{Nullable, Text};
+        use schema::omikujis::dsl::{archived, expires_at, id, quarantined};
+        let mut connection = self.connection().await?;
+        let now = chrono::Local::now().naive_local();
+        // The class is only stored inside the serialized message
+        let class = "JSON_UNQUOTE(JSON_EXTRACT(message, '$.class'))";
+        let candidates: Vec<(u32, Option<String>)> = self
+            .library(pool, community)
+            .filter(quarantined.eq(false))
+            .filter(archived.eq(false))
+            .filter(expires_at.is_null().or(expires_at.gt(now)))
+            .select((id, sql::<Nullable<Text>>(class)))
+            .load(&mut connection)
+            .await?;
+        let classes: Vec<Option<String>> =
+            candidates.iter().map(|(_, class)| class.clone()).collect();
+        match scoring::balanced_index(&classes, &mut thread_rng()) {
+            Some(index) => self.find_omikuji(candidates[index].0).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn visible_omikuji_ids(&self) -> Result<Vec<(u32, Option<i64>)>, BotError> {
+        use schema::omikujis::dsl::{
+            archived, community_id, expires_at, id, omikujis, quarantined, tenant_id,
+        };
+        let mut connection = self.connection().await?;
+        let now = chrono::Local::now().naive_local();
+        Ok(omikujis
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(quarantined.eq(false))
+            .filter(archived.eq(false))
+            .filter(expires_at.is_null().or(expires_at.gt(now)))
+            .select((id, community_id))
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn top_omikuji(&self, since: NaiveDateTime) -> Result<Option<Omikuji>, BotError> {
+        use schema::omikujis::dsl::{
+            archived, created_at, expires_at, omikujis, quarantined, tenant_id,
+        };
+        let mut connection = self.connection().await?;
+        let now = chrono::Local::now().naive_local();
+        // Decayed scores are computed here, the candidates are limited by `since` anyway
+        let candidates: Vec<Omikuji> = omikujis
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(created_at.ge(since))
+            .filter(quarantined.eq(false))
+            .filter(archived.eq(false))
+            .filter(expires_at.is_null().or(expires_at.gt(now)))
+            .load(&mut connection)
+            .await?;
+        Ok(scoring::top(&candidates, now, vote_half_life()).cloned())
+    }
+
+    async fn top_omikujis(
+        &self,
+        since: NaiveDateTime,
+        limit: usize,
+    ) -> Result<Vec<Omikuji>, BotError> {
+        use schema::omikujis::dsl::{
+            archived, created_at, expires_at, omikujis, quarantined, tenant_id,
+        };
+        let mut connection = self.connection().await?;
+        let now = chrono::Local::now().naive_local();
+        let candidates: Vec<Omikuji> = omikujis
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(created_at.ge(since))
+            .filter(quarantined.eq(false))
+            .filter(archived.eq(false))
+            .filter(expires_at.is_null().or(expires_at.gt(now)))
+            .load(&mut connection)
+            .await?;
+        Ok(scoring::top_n(&candidates, now, vote_half_life(), limit)
+            .into_iter()
+            .cloned()
+            .collect())
+    }
+
+    async fn add_vote(&self, omikuji_id: u32, delta: i32) -> Result<(), BotError> {
+        use schema::omikujis::dsl::{omikujis, quarantined, tenant_id, vote_count};
+        let mut connection = self.connection().await?;
+        let omikuji = omikujis.find(omikuji_id).filter(tenant_id.eq(&self.tenant));
+        // UPDATE ... SET vote_count = vote_count + delta, evaluated by the database
+        diesel::update(omikuji)
+            .set(vote_count.eq(vote_count + delta))
+            .execute(&mut connection)
+            .await?;
+        diesel::update(omikuji.filter(vote_count.le(HIDE_THRESHOLD)))
+            .set(quarantined.eq(true))
+            .execute(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn archive_expired(&self, now: NaiveDateTime) -> Result<usize, BotError> {
+        use schema::omikujis::dsl::{archived, expires_at, omikujis, tenant_id};
+        let mut connection = self.connection().await?;
+        Ok(diesel::update(
+            omikujis
+                .filter(tenant_id.eq(&self.tenant))
+                .filter(archived.eq(false))
+                .filter(expires_at.le(now)),
+        )
+        .set(archived.eq(true))
+        .execute(&mut connection)
+        .await?)
+    }
+
+    async fn update_omikuji(
+        &self,
+        omikuji_id: u32,
+        text: &str,
+        quarantine: bool,
+    ) -> Result<(), BotError> {
+        use diesel::dsl::now;
+        use schema::omikujis::dsl::{
+            edited_at, message, omikujis, quarantined, reviewed_at, tenant_id,
+        };
+        let mut connection = self.connection().await?;
+        let omikuji = omikujis.find(omikuji_id).filter(tenant_id.eq(&self.tenant));
+        connection
+            .transaction::<_, BotError, _>(|connection| {
+                async move {
+                    diesel::update(omikuji)
+                        .set((message.eq(text), edited_at.eq(now.nullable())))
+                        .execute(connection)
+                        .await?;
+                    if quarantine {
+                        diesel::update(omikuji)
+                            .set((quarantined.eq(true), reviewed_at.eq(None::<NaiveDateTime>)))
+                            .execute(connection)
+                            .await?;
+                    }
+                    Ok(())
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+
+    async fn set_anonymous(&self, omikuji_id: u32, value: bool) -> Result<(), BotError> {
+        use schema::omikujis::dsl::{anonymous, omikujis, tenant_id};
+        let mut connection = self.connection().await?;
+        diesel::update(omikujis.find(omikuji_id).filter(tenant_id.eq(&self.tenant)))
+            .set(anonymous.eq(value))
+            .execute(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn migrate_community(&self, from: i64, to: i64) -> Result<usize, BotError> {
+        use schema::omikujis::dsl::{community_id, omikujis, tenant_id};
+        let mut connection = self.connection().await?;
+        Ok(diesel::update(
+            omikujis
+                .filter(tenant_id.eq(&self.tenant))
+                .filter(community_id.eq(from)),
+        )
+        .set(community_id.eq(to))
+        .execute(&mut connection)
+        .await?)
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a> UserRepository for DieselRepository<'a> {
+    async fn upsert_user(&self, user: &NewUser) -> Result<(), BotError> {
+        use schema::users::dsl::{inactive, tenant_id, updated_at, users};
+        let mut connection = self.connection().await?;
+        connection
+            .transaction::<_, BotError, _>(|connection| {
+                async move {
+                    diesel::insert_or_ignore_into(schema::users::table)
+                        .values((user, tenant_id.eq(&self.tenant)))
+                        .execute(connection)
+                        .await?;
+                    // updated_at is set explicitly, since MySQL leaves unchanged rows alone
+                    // Writing to the bot means it is no longer blocked
+                    diesel::update(users.find((&self.tenant, user.tg_id)))
+                        .set((user, inactive.eq(false), updated_at.eq(diesel::dsl::now)))
+                        .execute(connection)
+                        .await?;
+                    Ok(())
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+
+    async fn is_banned(&self, tg_id: i64) -> Result<bool, BotError> {
+        use schema::users::dsl::{banned, users};
+        let mut connection = self.connection().await?;
+        Ok(users
+            .find((&self.tenant, tg_id))
+            .select(banned)
+            .get_result(&mut connection)
+            .await
+            .optional()?
+            .unwrap_or(false))
+    }
+
+    async fn find_user(&self, tg_id: i64) -> Result<Option<User>, BotError> {
+        use schema::users::dsl::users;
+        let mut connection = self.connection().await?;
+        Ok(users
+            .find((&self.tenant, tg_id))
+            .get_result(&mut connection)
+            .await
+            .optional()?)
+    }
+
+    async fn get_user_settings(&self, tg_id: i64) -> Result<UserSettings, BotError> {
+        use schema::user_settings::dsl::{tenant_id, user_settings};
+        let mut connection = self.connection().await?;
+        let settings = user_settings
+            .find((&self.tenant, tg_id))
+            .get_result(&mut connection)
+            .await
+            .optional()?;
+        if let Some(settings) = settings {
+            return Ok(settings);
+        }
+        // A row with default values is inserted for new users, in the language of their client
+        let code = self
+            .find_user(tg_id)
+            .await?
+            .and_then(|user| user.language_code);
+        let language = Language::detect(code.as_deref());
+        diesel::insert_or_ignore_into(schema::user_settings::table)
+            .values((
+                &NewUserSettings {
+                    tg_id,
+                    language: format!("{:?}", language),
+                },
+                tenant_id.eq(&self.tenant),
+            ))
+            .execute(&mut connection)
+            .await?;
+        Ok(user_settings
+            .find((&self.tenant, tg_id))
+            .get_result(&mut connection)
+            .await?)
+    }
+
+    async fn update_user_settings(&self, settings: &UserSettings) -> Result<(), BotError> {
+        use schema::user_settings::dsl::{
+            credit, daily_subscription, language, notifications, pool, quiet_end, quiet_start,
+            reply_keyboard, time_zone, voice, weekly_digest,
+        };
+        let mut connection = self.connection().await?;
+        diesel::update(settings)
+            .set((
+                notifications.eq(settings.notifications),
+                language.eq(&settings.language),
+                daily_subscription.eq(settings.daily_subscription),
+                reply_keyboard.eq(settings.reply_keyboard),
+                voice.eq(settings.voice),
+                pool.eq(&settings.pool),
+                credit.eq(settings.credit),
+                weekly_digest.eq(settings.weekly_digest),
+                quiet_start.eq(settings.quiet_start),
+                quiet_end.eq(settings.quiet_end),
+                time_zone.eq(&settings.time_zone),
+            ))
+            .execute(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_daily_subscribers(&self) -> Result<Vec<UserSettings>, BotError> {
+        use schema::user_settings::dsl::{daily_subscription, tenant_id, tg_id, user_settings};
+        let mut connection = self.connection().await?;
+        let inactive_users = self.inactive_users();
+        Ok(user_settings
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(daily_subscription.eq(true))
+            .filter(tg_id.ne_all(inactive_users))
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn get_digest_subscribers(&self) -> Result<Vec<UserSettings>, BotError> {
+        use schema::user_settings::dsl::{tenant_id, tg_id, user_settings, weekly_digest};
+        let mut connection = self.connection().await?;
+        let inactive_users = self.inactive_users();
+        Ok(user_settings
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(weekly_digest.eq(true))
+            .filter(tg_id.ne_all(inactive_users))
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn set_inactive(&self, user_id: i64, value: bool) -> Result<(), BotError> {
+        use schema::users::dsl::{inactive, users};
+        let mut connection = self.connection().await?;
+        diesel::update(users.find((&self.tenant, user_id)))
+            .set(inactive.eq(value))
+            .execute(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn credit_name(&self, tg_id: i64) -> Result<Option<String>, BotError> {
+        use schema::{user_settings, users};
+        let mut connection = self.connection().await?;
+        // The name comes from the users table, so that it follows the author's profile
+        let credited = user_settings::table
+            .filter(user_settings::tenant_id.eq(&self.tenant))
+            .filter(user_settings::credit.eq(true))
+            .select(user_settings::tg_id);
+        Ok(users::table
+            .find((&self.tenant, tg_id))
+            .filter(users::tg_id.eq_any(credited))
+            .select(users::tg_name)
+            .get_result(&mut connection)
+            .await
+            .optional()?)
+    }
+
+    async fn forget_user(&self, author_id: i64) -> Result<(), BotError> {
+        use schema::{
+            achievements, audit_log, booth_draws, comments, deferred_messages, drafts, draws,
+            favorites, job_runs, omikujis, reactions, trash, usage_events, user_settings, users,
+        };
+        let mut connection = self.connection().await?;
+        connection
+            .transaction::<_, BotError, _>(|connection| {
+                async move {
+                    diesel::update(
+                        omikujis::table
+                            .filter(omikujis::tenant_id.eq(&self.tenant))
+                            .filter(omikujis::tg_id.eq(author_id)),
+                    )
+                    .set((
+                        omikujis::tg_id.eq(ANONYMOUS_ID),
+                        omikujis::tg_name.eq(ANONYMOUS_NAME),
+                        omikujis::anonymous.eq(true),
+                    ))
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(
+                        draws::table
+                            .filter(draws::tenant_id.eq(&self.tenant))
+                            .filter(draws::tg_id.eq(author_id)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(
+                        achievements::table
+                            .filter(achievements::tenant_id.eq(&self.tenant))
+                            .filter(achievements::tg_id.eq(author_id)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(
+                        favorites::table
+                            .filter(favorites::tenant_id.eq(&self.tenant))
+                            .filter(favorites::tg_id.eq(author_id)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(
+                        comments::table
+                            .filter(comments::tenant_id.eq(&self.tenant))
+                            .filter(comments::tg_id.eq(author_id)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(
+                        reactions::table
+                            .filter(reactions::tenant_id.eq(&self.tenant))
+                            .filter(reactions::tg_id.eq(author_id)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(
+                        trash::table
+                            .filter(trash::tenant_id.eq(&self.tenant))
+                            .filter(trash::tg_id.eq(author_id)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(
+                        deferred_messages::table
+                            .filter(deferred_messages::tenant_id.eq(&self.tenant))
+                            .filter(deferred_messages::tg_id.eq(author_id)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(
+                        booth_draws::table
+                            .filter(booth_draws::tenant_id.eq(&self.tenant))
+                            .filter(booth_draws::tg_id.eq(author_id)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    // The daily totals of usage_daily do not tell who sent the commands
+                    diesel::delete(
+                        usage_events::table
+                            .filter(usage_events::tenant_id.eq(&self.tenant))
+                            .filter(usage_events::tg_id.eq(author_id)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(
+                        job_runs::table
+                            .filter(job_runs::tenant_id.eq(&self.tenant))
+                            .filter(job_runs::tg_id.eq(author_id)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(drafts::table.find((&self.tenant, author_id)))
+                        .execute(connection)
+                        .await?;
+                    // Actions of admins are kept for accountability, but no longer tell who took them
+                    diesel::update(
+                        audit_log::table
+                            .filter(audit_log::tenant_id.eq(&self.tenant))
+                            .filter(audit_log::actor.eq(format!("tg:{}", author_id))),
+                    )
+                    .set(audit_log::actor.eq(ANONYMOUS_NAME))
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(user_settings::table.find((&self.tenant, author_id)))
+                        .execute(connection)
+                        .await?;
+                    diesel::delete(users::table.find((&self.tenant, author_id)))
+                        .execute(connection)
+                        .await?;
+                    diesel::update(
+                        users::table
+                            .filter(users::tenant_id.eq(&self.tenant))
+                            .filter(users::referred_by.eq(author_id)),
+                    )
+                    .set(users::referred_by.eq(None::<i64>))
+                    .execute(connection)
+                    .await?;
+                    Ok(())
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+
+    async fn set_referrer(&self, user_id: i64, referrer: i64) -> Result<bool, BotError> {
+        use schema::users::dsl::{referred_by, users};
+        let mut connection = self.connection().await?;
+        let updated = diesel::update(
+            users
+                .find((&self.tenant, user_id))
+                .filter(referred_by.is_null()),
+        )
+        .set(referred_by.eq(referrer))
+        .execute(&mut connection)
+        .await?;
+        Ok(updated > 0)
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a> StatsRepository for DieselRepository<'a> {
+    async fn record_draw(&self, draw: &NewDraw) -> Result<(), BotError> {
+        use diesel::dsl::sql;
+        use diesel::sql_types::{Integer, Unsigned};
+        use schema::draws::dsl::tenant_id;
+        use schema::omikujis::dsl::{draw_count, id, omikujis};
+        let mut connection = self.connection().await?;
+        connection
+            .transaction::<_, BotError, _>(|connection| {
+                async move {
+                    diesel::insert_into(schema::draws::table)
+                        .values((draw, tenant_id.eq(&self.tenant)))
+                        .execute(connection)
+                        .await?;
+                    diesel::update(omikujis.filter(id.eq(draw.omikuji_id)))
+                        // Diesel has no arithmetic on unsigned columns
+                        .set(draw_count.eq(sql::<Unsigned<Integer>>("draw_count + 1")))
+                        .execute(connection)
+                        .await?;
+                    Ok(())
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+
+    async fn find_draws_by_user(&self, user_id: i64) -> Result<Vec<Draw>, BotError> {
+        use schema::draws::dsl::{draws, id, tenant_id, tg_id};
+        let mut connection = self.connection().await?;
+        Ok(draws
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(tg_id.eq(user_id))
+            .order(id)
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn count_draws_by_user(
+        &self,
+        user_id: i64,
+        since: NaiveDateTime,
+    ) -> Result<i64, BotError> {
+        use schema::draws::dsl::{created_at, draws, tenant_id, tg_id};
+        let mut connection = self.connection().await?;
+        Ok(draws
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(tg_id.eq(user_id))
+            .filter(created_at.ge(since))
+            .count()
+            .get_result(&mut connection)
+            .await?)
+    }
+
+    async fn find_last_draw(&self, user_id: i64) -> Result<Option<Draw>, BotError> {
+        use schema::draws::dsl::{draws, id, tenant_id, tg_id, tied_at};
+        let mut connection = self.connection().await?;
+        Ok(draws
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(tg_id.eq(user_id))
+            .filter(tied_at.is_null())
+            .order(id.desc())
+            .first(&mut connection)
+            .await
+            .optional()?)
+    }
+
+    async fn count_omikujis_by_class(&self) -> Result<Vec<(Option<String>, i64)>, BotError> {
+        use diesel::dsl::sql;
+        use diesel::sql_types::{BigInt, Nullable, Text};
+        use schema::omikujis::dsl::{omikujis, tenant_id};
+        let mut connection = self.connection().await?;
+        // The class is only stored inside the serialized message
+        let class = "JSON_UNQUOTE(JSON_EXTRACT(message, '$.class'))";
+        Ok(omikujis
+            .filter(tenant_id.eq(&self.tenant))
+            .group_by(sql::<Nullable<Text>>(class))
+            .select((sql::<Nullable<Text>>(class), sql::<BigInt>("COUNT(*)")))
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn count_hidden_omikujis(&self) -> Result<i64, BotError> {
+        use schema::omikujis::dsl::{omikujis, quarantined, tenant_id};
+        let mut connection = self.connection().await?;
+        Ok(omikujis
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(quarantined.eq(true))
+            .count()
+            .get_result(&mut connection)
+            .await?)
+    }
+
+    async fn count_undrawn_omikujis(&self) -> Result<i64, BotError> {
+        use schema::omikujis::dsl::{archived, draw_count, omikujis, quarantined, tenant_id};
+        let mut connection = self.connection().await?;
+        Ok(omikujis
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(quarantined.eq(false))
+            .filter(archived.eq(false))
+            .filter(draw_count.eq(0))
+            .count()
+            .get_result(&mut connection)
+            .await?)
+    }
+
+    async fn most_drawn_omikujis(&self, limit: i64) -> Result<Vec<(u32, u32)>, BotError> {
+        use schema::omikujis::dsl::{draw_count, id, omikujis, tenant_id};
+        let mut connection = self.connection().await?;
+        Ok(omikujis
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(draw_count.gt(0))
+            .select((id, draw_count))
+            .order((draw_count.desc(), id.asc()))
+            .limit(limit)
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn count_active_users(&self, since: NaiveDateTime) -> Result<i64, BotError> {
+        use schema::users::dsl::{tenant_id, updated_at, users};
+        let mut connection = self.connection().await?;
+        Ok(users
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(updated_at.ge(since))
+            .count()
+            .get_result(&mut connection)
+            .await?)
+    }
+
+    async fn count_draws_by_day(
+        &self,
+        since: NaiveDateTime,
+    ) -> Result<Vec<(NaiveDate, i64)>, BotError> {
+        use diesel::dsl::sql;
+        use diesel::sql_types::{BigInt, Date};
+        use schema::draws::dsl::{created_at, draws, tenant_id};
+        let mut connection = self.connection().await?;
+        Ok(draws
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(created_at.ge(since))
+            .group_by(sql::<Date>("DATE(created_at)"))
+            .select((sql::<Date>("DATE(created_at)"), sql::<BigInt>("COUNT(*)")))
+            .order(sql::<Date>("DATE(created_at)"))
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn top_authors(&self, limit: i64) -> Result<Vec<(String, i64)>, BotError> {
+        use diesel::dsl::sql;
+        use diesel::sql_types::{BigInt, Text};
+        use schema::omikujis::dsl::{anonymous, omikujis, tenant_id, tg_id};
+        let mut connection = self.connection().await?;
+        // Strips submitted anonymously don't count towards anyone
+        Ok(omikujis
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(anonymous.eq(false))
+            .group_by(tg_id)
+            .select((sql::<Text>("MAX(tg_name)"), sql::<BigInt>("COUNT(*)")))
+            .order(sql::<BigInt>("COUNT(*) DESC"))
+            .limit(limit)
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn record_vote(&self, user_id: i64, voted_id: u32, value: i8) -> Result<(), BotError> {
+        use schema::draws::dsl::{draws, id, omikuji_id, tenant_id, tg_id, vote};
+        let mut connection = self.connection().await?;
+        let latest: Option<u32> = draws
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(tg_id.eq(user_id))
+            .filter(omikuji_id.eq(voted_id))
+            .select(id)
+            .order(id.desc())
+            .first(&mut connection)
+            .await
+            .optional()?;
+        if let Some(latest) = latest {
+            diesel::update(draws.find(latest).filter(vote.is_null()))
+                .set(vote.eq(value))
+                .execute(&mut connection)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn tie_draw(&self, user_id: i64, tied_id: u32) -> Result<bool, BotError> {
+        use diesel::dsl::now;
+        use schema::draws::dsl::{draws, id, omikuji_id, tenant_id, tg_id, tied_at};
+        let mut connection = self.connection().await?;
+        let latest: Option<u32> = draws
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(tg_id.eq(user_id))
+            .filter(omikuji_id.eq(tied_id))
+            .select(id)
+            .order(id.desc())
+            .first(&mut connection)
+            .await
+            .optional()?;
+        let tied = match latest {
+            Some(latest) => {
+                diesel::update(draws.find(latest).filter(tied_at.is_null()))
+                    .set(tied_at.eq(now.nullable()))
+                    .execute(&mut connection)
+                    .await?
+            }
+            None => 0,
+        };
+        Ok(tied > 0)
+    }
+
+    async fn count_tied_draws(&self, since: NaiveDateTime) -> Result<i64, BotError> {
+        use schema::draws::dsl::{draws, tenant_id, tied_at};
+        let mut connection = self.connection().await?;
+        Ok(draws
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(tied_at.ge(since))
+            .count()
+            .get_result(&mut connection)
+            .await?)
+    }
+
+    async fn experiment_results(&self) -> Result<Vec<VariantResult>, BotError> {
+        use diesel::dsl::sql;
+        use diesel::sql_types::{BigInt, Text};
+        use schema::draws::dsl::{draws, tenant_id, variant};
+        let mut connection = self.connection().await?;
+        let rows: Vec<(String, i64, i64, i64, i64)> = draws
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(variant.is_not_null())
+            .group_by(variant)
+            .select((
+                sql::<Text>("MAX(variant)"),
+                sql::<BigInt>("COUNT(DISTINCT tg_id)"),
+                sql::<BigInt>("COUNT(*)"),
+                sql::<BigInt>("COUNT(CASE WHEN vote > 0 THEN 1 END)"),
+                sql::<BigInt>("COUNT(CASE WHEN vote < 0 THEN 1 END)"),
+            ))
+            .order(variant)
+            .load(&mut connection)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(name, users, count, upvotes, downvotes)| VariantResult {
+                variant: name,
+                users,
+                draws: count,
+                upvotes,
+                downvotes,
+            })
+            .collect())
+    }
+
+    async fn author_digest(
+        &self,
+        author_id: i64,
+        since: NaiveDateTime,
+    ) -> Result<AuthorDigest, BotError> {
+        use diesel::dsl::sql;
+        use diesel::sql_types::BigInt;
+        use schema::{comments, draws, omikujis};
+        let mut connection = self.connection().await?;
+        let strips = || {
+            omikujis::table
+                .filter(omikujis::tenant_id.eq(&self.tenant))
+                .filter(omikujis::tg_id.eq(author_id))
+                .select(omikujis::id)
+        };
+        // Drawing (or commenting on) one's own strips is not news to the author
+        let (count, upvotes, downvotes): (i64, i64, i64) = draws::table
+            .filter(draws::tenant_id.eq(&self.tenant))
+            .filter(draws::omikuji_id.eq_any(strips()))
+            .filter(draws::tg_id.ne(author_id))
+            .filter(draws::created_at.ge(since))
+            .select((
+                sql::<BigInt>("COUNT(*)"),
+                sql::<BigInt>("COUNT(CASE WHEN vote > 0 THEN 1 END)"),
+                sql::<BigInt>("COUNT(CASE WHEN vote < 0 THEN 1 END)"),
+            ))
+            .get_result(&mut connection)
+            .await?;
+        let comment_count = comments::table
+            .filter(comments::tenant_id.eq(&self.tenant))
+            .filter(comments::omikuji_id.eq_any(strips()))
+            .filter(comments::tg_id.ne(author_id))
+            .filter(comments::parent_id.is_null())
+            .filter(comments::created_at.ge(since))
+            .count()
+            .get_result(&mut connection)
+            .await?;
+        Ok(AuthorDigest {
+            draws: count,
+            upvotes,
+            downvotes,
+            comments: comment_count,
+        })
+    }
+
+    async fn count_referrals(&self, user_id: i64) -> Result<i64, BotError> {
+        use schema::users::dsl::{referred_by, tenant_id, users};
+        let mut connection = self.connection().await?;
+        Ok(users
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(referred_by.eq(user_id))
+            .count()
+            .get_result(&mut connection)
+            .await?)
+    }
+
+    async fn top_referrers(&self, limit: i64) -> Result<Vec<(String, i64)>, BotError> {
+        use diesel::dsl::sql;
+        use diesel::sql_types::BigInt;
+        use schema::users::dsl::{referred_by, tenant_id, tg_id, tg_name, users};
+        let mut connection = self.connection().await?;
+        let counts: Vec<(Option<i64>, i64)> = users
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(referred_by.is_not_null())
+            .group_by(referred_by)
+            .select((referred_by, sql::<BigInt>("COUNT(*)")))
+            .order(sql::<BigInt>("COUNT(*) DESC"))
+            .limit(limit)
+            .load(&mut connection)
+            .await?;
+        // Names are looked up separately, as Diesel can't join a table with itself
+        let referrers: Vec<i64> = counts
+            .iter()
+            .filter_map(|(referrer, _)| *referrer)
+            .collect();
+        let names: HashMap<i64, String> = users
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(tg_id.eq_any(referrers))
+            .select((tg_id, tg_name))
+            .load::<(i64, String)>(&mut connection)
+            .await?
+            .into_iter()
+            .collect();
+        Ok(counts
+            .into_iter()
+            .filter_map(|(referrer, count)| Some((names.get(&referrer?)?.clone(), count)))
+            .collect())
+    }
+
+    async fn record_booth_draw(&self, draw: &NewBoothDraw) -> Result<(), BotError> {
+        use diesel::dsl::sql;
+        use diesel::sql_types::{Integer, Unsigned};
+        use schema::booth_draws::dsl::tenant_id;
+        use schema::omikujis::dsl::{draw_count, id, omikujis};
+        let mut connection = self.connection().await?;
+        connection
+            .transaction::<_, BotError, _>(|connection| {
+                async move {
+                    diesel::insert_into(schema::booth_draws::table)
+                        .values((draw, tenant_id.eq(&self.tenant)))
+                        .execute(connection)
+                        .await?;
+                    diesel::update(omikujis.filter(id.eq(draw.omikuji_id)))
+                        .set(draw_count.eq(sql::<Unsigned<Integer>>("draw_count + 1")))
+                        .execute(connection)
+                        .await?;
+                    Ok(())
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+
+    async fn count_booth_draws(&self, since: NaiveDateTime) -> Result<i64, BotError> {
+        use schema::booth_draws::dsl::{booth_draws, created_at, tenant_id};
+        let mut connection = self.connection().await?;
+        Ok(booth_draws
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(created_at.ge(since))
+            .count()
+            .get_result(&mut connection)
+            .await?)
+    }
+
+    async fn find_booth_draws_by_user(&self, user_id: i64) -> Result<Vec<BoothDraw>, BotError> {
+        use schema::booth_draws::dsl::{booth_draws, id, tenant_id, tg_id};
+        let mut connection = self.connection().await?;
+        Ok(booth_draws
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(tg_id.eq(user_id))
+            .order(id)
+            .load(&mut connection)
+            .await?)
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a> ModerationRepository for DieselRepository<'a> {
+    async fn find_pending_omikujis(&self) -> Result<Vec<Omikuji>, BotError> {
+        use schema::omikujis::dsl::{id, omikujis, quarantined, reviewed_at, tenant_id};
+        let mut connection = self.connection().await?;
+        Ok(omikujis
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(quarantined.eq(true))
+            .filter(reviewed_at.is_null())
+            .order(id)
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn find_quarantined_omikujis(&self) -> Result<Vec<Omikuji>, BotError> {
+        use schema::omikujis::dsl::{id, omikujis, quarantined, tenant_id};
+        let mut connection = self.connection().await?;
+        Ok(omikujis
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(quarantined.eq(true))
+            .order(id)
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn search_omikujis(&self, query: &str, limit: i64) -> Result<Vec<Omikuji>, BotError> {
+        use schema::omikujis::dsl::{id, message, omikujis, tenant_id};
+        let mut connection = self.connection().await?;
+        // Wildcards typed by the moderator are matched literally
+        let pattern = query
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        Ok(omikujis
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(message.like(format!("%{}%", pattern)))
+            .order(id.desc())
+            .limit(limit)
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn review_omikuji(&self, omikuji_id: u32, approve: bool) -> Result<(), BotError> {
+        use diesel::dsl::now;
+        use schema::omikujis::dsl::{omikujis, quarantined, reviewed_at, tenant_id, vote_count};
+        let mut connection = self.connection().await?;
+        let omikuji = omikujis.find(omikuji_id).filter(tenant_id.eq(&self.tenant));
+        if approve {
+            connection
+                .transaction::<_, BotError, _>(|connection| {
+                    async move {
+                        diesel::update(omikuji)
+                            .set((
+                                vote_count.eq(0),
+                                quarantined.eq(false),
+                                reviewed_at.eq(now.nullable()),
+                            ))
+                            .execute(connection)
+                            .await?;
+                        self.number_slips(connection).await
+                    }
+                    .scope_boxed()
+                })
+                .await?;
+        } else {
+            diesel::update(omikuji)
+                .set(reviewed_at.eq(now.nullable()))
+                .execute(&mut connection)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn delete_omikuji(&self, omikuji_id: u32) -> Result<(), BotError> {
+        use schema::{comments, favorites, omikujis, reactions};
+        let mut connection = self.connection().await?;
+        connection
+            .transaction::<_, BotError, _>(|connection| {
+                async move {
+                    diesel::delete(
+                        omikujis::table
+                            .find(omikuji_id)
+                            .filter(omikujis::tenant_id.eq(&self.tenant)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(
+                        favorites::table
+                            .filter(favorites::tenant_id.eq(&self.tenant))
+                            .filter(favorites::omikuji_id.eq(omikuji_id)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(
+                        comments::table
+                            .filter(comments::tenant_id.eq(&self.tenant))
+                            .filter(comments::omikuji_id.eq(omikuji_id)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(
+                        reactions::table
+                            .filter(reactions::tenant_id.eq(&self.tenant))
+                            .filter(reactions::omikuji_id.eq(omikuji_id)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    Ok(())
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+
+    async fn review_omikujis(&self, omikuji_ids: &[u32], approve: bool) -> Result<usize, BotError> {
+        use diesel::dsl::now;
+        use schema::omikujis::dsl::{
+            id, omikujis, quarantined, reviewed_at, tenant_id, vote_count,
+        };
+        let mut connection = self.connection().await?;
+        let batch = omikujis
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(id.eq_any(omikuji_ids));
+        connection
+            .transaction::<_, BotError, _>(|connection| {
+                async move {
+                    if !approve {
+                        return Ok(diesel::update(batch)
+                            .set(reviewed_at.eq(now.nullable()))
+                            .execute(connection)
+                            .await?);
+                    }
+                    let reviewed = diesel::update(batch)
+                        .set((
+                            vote_count.eq(0),
+                            quarantined.eq(false),
+                            reviewed_at.eq(now.nullable()),
+                        ))
+                        .execute(connection)
+                        .await?;
+                    self.number_slips(connection).await?;
+                    Ok(reviewed)
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+
+    async fn delete_omikujis(&self, omikuji_ids: &[u32]) -> Result<usize, BotError> {
+        use schema::{comments, favorites, omikujis, reactions};
+        let mut connection = self.connection().await?;
+        connection
+            .transaction::<_, BotError, _>(|connection| {
+                async move {
+                    let deleted = diesel::delete(
+                        omikujis::table
+                            .filter(omikujis::tenant_id.eq(&self.tenant))
+                            .filter(omikujis::id.eq_any(omikuji_ids)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(
+                        favorites::table
+                            .filter(favorites::tenant_id.eq(&self.tenant))
+                            .filter(favorites::omikuji_id.eq_any(omikuji_ids)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(
+                        comments::table
+                            .filter(comments::tenant_id.eq(&self.tenant))
+                            .filter(comments::omikuji_id.eq_any(omikuji_ids)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    diesel::delete(
+                        reactions::table
+                            .filter(reactions::tenant_id.eq(&self.tenant))
+                            .filter(reactions::omikuji_id.eq_any(omikuji_ids)),
+                    )
+                    .execute(connection)
+                    .await?;
+                    Ok(deleted)
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a> InterpretationRepository for DieselRepository<'a> {
+    async fn find_interpretation(&self, name: &str) -> Result<Option<String>, BotError> {
+        use schema::interpretations::dsl::{interpretations, text};
+        let mut connection = self.connection().await?;
+        Ok(interpretations
+            .find((&self.tenant, name))
+            .select(text)
+            .get_result(&mut connection)
+            .await
+            .optional()?)
+    }
+
+    async fn set_interpretation(&self, interpretation: &NewInterpretation) -> Result<(), BotError> {
+        use schema::interpretations::dsl::{interpretations, tenant_id};
+        let mut connection = self.connection().await?;
+        if interpretation.text.is_empty() {
+            diesel::delete(interpretations.find((&self.tenant, interpretation.class)))
+                .execute(&mut connection)
+                .await?;
+        } else {
+            diesel::replace_into(interpretations)
+                .values((interpretation, tenant_id.eq(&self.tenant)))
+                .execute(&mut connection)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a> AchievementRepository for DieselRepository<'a> {
+    async fn award_badge(&self, achievement: &NewAchievement) -> Result<bool, BotError> {
+        use schema::achievements::dsl::tenant_id;
+        let mut connection = self.connection().await?;
+        // Nothing is inserted if the user already has the badge
+        let inserted = diesel::insert_or_ignore_into(schema::achievements::table)
+            .values((achievement, tenant_id.eq(&self.tenant)))
+            .execute(&mut connection)
+            .await?;
+        Ok(inserted > 0)
+    }
+
+    async fn find_badges(&self, user_id: i64) -> Result<Vec<String>, BotError> {
+        use schema::achievements::dsl::{achievements, badge, created_at, tenant_id, tg_id};
+        let mut connection = self.connection().await?;
+        Ok(achievements
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(tg_id.eq(user_id))
+            .order(created_at.asc())
+            .select(badge)
+            .load(&mut connection)
+            .await?)
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a> FavoriteRepository for DieselRepository<'a> {
+    async fn add_favorite(&self, favorite: &NewFavorite) -> Result<bool, BotError> {
+        use schema::favorites::dsl::tenant_id;
+        let mut connection = self.connection().await?;
+        // Nothing is inserted if the user saved the strip already
+        let inserted = diesel::insert_or_ignore_into(schema::favorites::table)
+            .values((favorite, tenant_id.eq(&self.tenant)))
+            .execute(&mut connection)
+            .await?;
+        Ok(inserted > 0)
+    }
+
+    async fn find_favorites(
+        &self,
+        user_id: i64,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<Omikuji>, BotError> {
+        use schema::{favorites, omikujis};
+        let mut connection = self.connection().await?;
+        let ids: Vec<u32> = favorites::table
+            .filter(favorites::tenant_id.eq(&self.tenant))
+            .filter(favorites::tg_id.eq(user_id))
+            .order((favorites::created_at.desc(), favorites::omikuji_id.desc()))
+            .offset(offset)
+            .limit(limit)
+            .select(favorites::omikuji_id)
+            .load(&mut connection)
+            .await?;
+        let found: Vec<Omikuji> = omikujis::table
+            .filter(omikujis::tenant_id.eq(&self.tenant))
+            .filter(omikujis::id.eq_any(&ids))
+            .load(&mut connection)
+            .await?;
+        // Keep the order of the favorites rather than that of the strips
+        Ok(ids
+            .iter()
+            .filter_map(|id| found.iter().find(|omikuji| omikuji.id == *id).cloned())
+            .collect())
+    }
+
+    async fn count_favorites(&self, user_id: i64) -> Result<i64, BotError> {
+        use schema::favorites::dsl::{favorites, tenant_id, tg_id};
+        let mut connection = self.connection().await?;
+        Ok(favorites
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(tg_id.eq(user_id))
+            .count()
+            .get_result(&mut connection)
+            .await?)
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a> ReactionRepository for DieselRepository<'a> {
+    async fn set_reaction(&self, reaction: &NewReaction) -> Result<(), BotError> {
+        use schema::reactions::dsl::tenant_id;
+        let mut connection = self.connection().await?;
+        diesel::replace_into(schema::reactions::table)
+            .values((reaction, tenant_id.eq(&self.tenant)))
+            .execute(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn count_reactions(&self, strip_id: u32) -> Result<Vec<(String, i64)>, BotError> {
+        use diesel::dsl::sql;
+        use diesel::sql_types::{BigInt, Text};
+        use schema::reactions::dsl::{omikuji_id, reaction, reactions, tenant_id};
+        let mut connection = self.connection().await?;
+        Ok(reactions
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(omikuji_id.eq(strip_id))
+            .group_by(reaction)
+            .select((sql::<Text>("MAX(reaction)"), sql::<BigInt>("COUNT(*)")))
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn find_reactions_by_user(&self, user_id: i64) -> Result<Vec<(u32, String)>, BotError> {
+        use schema::reactions::dsl::{omikuji_id, reaction, reactions, tenant_id, tg_id};
+        let mut connection = self.connection().await?;
+        Ok(reactions
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(tg_id.eq(user_id))
+            .order(omikuji_id)
+            .select((omikuji_id, reaction))
+            .load(&mut connection)
+            .await?)
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a> PollRepository for DieselRepository<'a> {
+    async fn insert_weekly_poll(&self, poll: &NewWeeklyPoll) -> Result<(), BotError> {
+        use schema::weekly_polls::dsl::tenant_id;
+        let mut connection = self.connection().await?;
+        diesel::insert_into(schema::weekly_polls::table)
+            .values((poll, tenant_id.eq(&self.tenant)))
+            .execute(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn find_open_weekly_poll(&self) -> Result<Option<WeeklyPoll>, BotError> {
+        use schema::weekly_polls::dsl::{id, tenant_id, weekly_polls};
+        let mut connection = self.connection().await?;
+        let latest: Option<WeeklyPoll> = weekly_polls
+            .filter(tenant_id.eq(&self.tenant))
+            .order(id.desc())
+            .first(&mut connection)
+            .await
+            .optional()?;
+        Ok(latest.filter(|poll| poll.closed_at.is_none()))
+    }
+
+    async fn close_weekly_poll(&self, poll_id: u32, winner: Option<u32>) -> Result<(), BotError> {
+        use diesel::dsl::now;
+        use schema::weekly_polls::dsl::{closed_at, tenant_id, weekly_polls, winner_id};
+        let mut connection = self.connection().await?;
+        diesel::update(
+            weekly_polls
+                .find(poll_id)
+                .filter(tenant_id.eq(&self.tenant)),
+        )
+        .set((winner_id.eq(winner), closed_at.eq(now.nullable())))
+        .execute(&mut connection)
+        .await?;
+        Ok(())
+    }
+
+    async fn find_weekly_winners(&self) -> Result<Vec<u32>, BotError> {
+        use schema::weekly_polls::dsl::{id, tenant_id, weekly_polls, winner_id};
+        let mut connection = self.connection().await?;
+        let winners: Vec<Option<u32>> = weekly_polls
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(winner_id.is_not_null())
+            .order(id.desc())
+            .select(winner_id)
+            .load(&mut connection)
+            .await?;
+        Ok(winners.into_iter().flatten().collect())
+    }
+
+    async fn migrate_poll_chat(&self, from: i64, to: i64) -> Result<(), BotError> {
+        use schema::weekly_polls::dsl::{chat, tenant_id, weekly_polls};
+        let mut connection = self.connection().await?;
+        diesel::update(
+            weekly_polls
+                .filter(tenant_id.eq(&self.tenant))
+                .filter(chat.eq(from.to_string())),
+        )
+        .set(chat.eq(to.to_string()))
+        .execute(&mut connection)
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a> UsageRepository for DieselRepository<'a> {
+    async fn record_usage(&self, event: &NewUsageEvent) -> Result<(), BotError> {
+        use schema::usage_events::dsl::tenant_id;
+        let mut connection = self.connection().await?;
+        diesel::insert_into(schema::usage_events::table)
+            .values((event, tenant_id.eq(&self.tenant)))
+            .execute(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn aggregate_usage(&self, before: NaiveDateTime) -> Result<usize, BotError> {
+        use schema::{usage_daily, usage_events};
+        let mut connection = self.connection().await?;
+        connection
+            .transaction::<_, BotError, _>(|connection| {
+                async move {
+                    let events = usage_events::table
+                        .filter(usage_events::tenant_id.eq(&self.tenant))
+                        .filter(usage_events::created_at.lt(before));
+                    let loaded: Vec<UsageEvent> =
+                        events.select(USAGE_EVENT_COLUMNS).load(connection).await?;
+                    for total in UsageDay::tally(&loaded) {
+                        // Totals of the day may exist already, e.g. if the clock was turned back
+                        let key = (&self.tenant, total.day, &total.command);
+                        let stored: Option<UsageDay> = usage_daily::table
+                            .find(key)
+                            .select(USAGE_DAY_COLUMNS)
+                            .get_result(connection)
+                            .await
+                            .optional()?;
+                        match stored {
+                            Some(mut stored) => {
+                                stored.add(&total);
+                                diesel::update(usage_daily::table.find(key))
+                                    .set((
+                                        usage_daily::invocations.eq(stored.invocations),
+                                        usage_daily::failures.eq(stored.failures),
+                                        usage_daily::total_latency_ms.eq(stored.total_latency_ms),
+                                    ))
+                                    .execute(connection)
+                                    .await?;
+                            }
+                            None => {
+                                diesel::insert_into(usage_daily::table)
+                                    .values((
+                                        usage_daily::tenant_id.eq(&self.tenant),
+                                        usage_daily::day.eq(total.day),
+                                        usage_daily::command.eq(&total.command),
+                                        usage_daily::invocations.eq(total.invocations),
+                                        usage_daily::failures.eq(total.failures),
+                                        usage_daily::total_latency_ms.eq(total.total_latency_ms),
+                                    ))
+                                    .execute(connection)
+                                    .await?;
+                            }
+                        }
+                    }
+                    diesel::delete(events).execute(connection).await?;
+                    Ok(loaded.len())
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+
+    async fn usage_by_day(&self, since: NaiveDate) -> Result<Vec<UsageDay>, BotError> {
+        use schema::{usage_daily, usage_events};
+        let mut connection = self.connection().await?;
+        let midnight = since
+            .and_hms_opt(0, 0, 0)
+            .expect("midnight is a valid time");
+        let totals: Vec<UsageDay> = usage_daily::table
+            .filter(usage_daily::tenant_id.eq(&self.tenant))
+            .filter(usage_daily::day.ge(since))
+            .select(USAGE_DAY_COLUMNS)
+            .load(&mut connection)
+            .await?;
+        let events: Vec<UsageEvent> = usage_events::table
+            .filter(usage_events::tenant_id.eq(&self.tenant))
+            .filter(usage_events::created_at.ge(midnight))
+            .select(USAGE_EVENT_COLUMNS)
+            .load(&mut connection)
+            .await?;
+        Ok(merge_usage(totals, UsageDay::tally(&events)))
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a> LeaseRepository for DieselRepository<'a> {
+    async fn acquire_lease(
+        &self,
+        name: &str,
+        holder: &str,
+        duration: Duration,
+    ) -> Result<bool, BotError> {
+        use schema::leases;
+        let mut connection = self.connection().await?;
+        let now = chrono::Local::now().naive_local();
+        let expires_at = now
+            + chrono::Duration::from_std(duration)
+                .map_err(|e| BotError::Validation(format!("Lease of {}: {}", name, e)))?;
+        connection
+            .transaction::<_, BotError, _>(|connection| {
+                async move {
+                    diesel::insert_or_ignore_into(leases::table)
+                        .values((
+                            leases::tenant_id.eq(&self.tenant),
+                            leases::name.eq(name),
+                            leases::holder.eq(holder),
+                            leases::expires_at.eq(expires_at),
+                        ))
+                        .execute(connection)
+                        .await?;
+                    // Rows are locked by the update, so only one instance can take an expired lease
+                    diesel::update(leases::table.find((&self.tenant, name)))
+                        .filter(leases::holder.eq(holder).or(leases::expires_at.lt(now)))
+                        .set((leases::holder.eq(holder), leases::expires_at.eq(expires_at)))
+                        .execute(connection)
+                        .await?;
+                    let current: String = leases::table
+                        .find((&self.tenant, name))
+                        .select(leases::holder)
+                        .get_result(connection)
+                        .await?;
+                    Ok(current == holder)
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+
+    async fn find_job_runs(&self, name: &str) -> Result<Vec<JobRun>, BotError> {
+        use schema::job_runs::dsl::{count, job, job_runs, message_id, ran_on, tenant_id, tg_id};
+        let mut connection = self.connection().await?;
+        Ok(job_runs
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(job.eq(name))
+            .select((job, tg_id, ran_on, message_id, count))
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn record_job_run(&self, run: &JobRun) -> Result<(), BotError> {
+        use schema::job_runs::dsl::{job_runs, tenant_id};
+        let mut connection = self.connection().await?;
+        diesel::replace_into(job_runs)
+            .values((run, tenant_id.eq(&self.tenant)))
+            .execute(&mut connection)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a> TrashRepository for DieselRepository<'a> {
+    async fn insert_trash(&self, item: &NewTrashItem) -> Result<u32, BotError> {
+        use diesel::dsl::sql;
+        use diesel::sql_types::{BigInt, Unsigned};
+        use schema::trash::dsl::tenant_id;
+        let mut connection = self.connection().await?;
+        diesel::insert_into(schema::trash::table)
+            .values((item, tenant_id.eq(&self.tenant)))
+            .execute(&mut connection)
+            .await?;
+        let id: u64 = diesel::select(sql::<Unsigned<BigInt>>("LAST_INSERT_ID()"))
+            .get_result(&mut connection)
+            .await?;
+        Ok(id as u32)
+    }
+
+    async fn find_trash(
+        &self,
+        user_id: i64,
+        since: NaiveDateTime,
+    ) -> Result<Vec<TrashItem>, BotError> {
+        use schema::trash::dsl::{deleted_at, id, tenant_id, tg_id, trash};
+        let mut connection = self.connection().await?;
+        Ok(trash
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(tg_id.eq(user_id))
+            .filter(deleted_at.ge(since))
+            .order((deleted_at.desc(), id.desc()))
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn take_trash(&self, user_id: i64, trash_id: u32) -> Result<Option<TrashItem>, BotError> {
+        use schema::trash::dsl::{tenant_id, tg_id, trash};
+        let mut connection = self.connection().await?;
+        let item = trash
+            .find(trash_id)
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(tg_id.eq(user_id));
+        connection
+            .transaction::<_, BotError, _>(|connection| {
+                async move {
+                    let taken: Option<TrashItem> = item.first(connection).await.optional()?;
+                    // Only one of several taps on the same button gets the item
+                    let deleted = diesel::delete(item).execute(connection).await?;
+                    Ok(taken.filter(|_| deleted > 0))
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+
+    async fn purge_trash(&self, before: NaiveDateTime) -> Result<usize, BotError> {
+        use schema::trash::dsl::{deleted_at, tenant_id, trash};
+        let mut connection = self.connection().await?;
+        Ok(diesel::delete(
+            trash
+                .filter(tenant_id.eq(&self.tenant))
+                .filter(deleted_at.lt(before)),
+        )
+        .execute(&mut connection)
+        .await?)
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a> ImportRepository for DieselRepository<'a> {
+    async fn import_omikujis(
+        &self,
+        batch: &NewImportBatch,
+        new_omikujis: &[NewOmikuji],
+    ) -> Result<u32, BotError> {
+        use diesel::dsl::sql;
+        use diesel::sql_types::{BigInt, Unsigned};
+        use schema::{import_batches, omikujis};
+        let mut connection = self.connection().await?;
+        connection
+            .transaction::<_, BotError, _>(|connection| {
+                async move {
+                    diesel::insert_into(import_batches::table)
+                        .values((batch, import_batches::tenant_id.eq(&self.tenant)))
+                        .execute(connection)
+                        .await?;
+                    let id: u64 = diesel::select(sql::<Unsigned<BigInt>>("LAST_INSERT_ID()"))
+                        .get_result(connection)
+                        .await?;
+                    let batch_id = id as u32;
+                    for omikuji in new_omikujis {
+                        diesel::insert_into(omikujis::table)
+                            .values((
+                                omikuji,
+                                omikujis::tenant_id.eq(&self.tenant),
+                                omikujis::import_batch.eq(batch_id),
+                            ))
+                            .execute(connection)
+                            .await?;
+                    }
+                    self.number_slips(connection).await?;
+                    Ok(batch_id)
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a> DeferredRepository for DieselRepository<'a> {
+    async fn defer_message(&self, message: &NewDeferredMessage) -> Result<(), BotError> {
+        use schema::deferred_messages::dsl::{deferred_messages, tenant_id};
+        let mut connection = self.connection().await?;
+        diesel::insert_into(deferred_messages)
+            .values((message, tenant_id.eq(&self.tenant)))
+            .execute(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn find_deferred_messages(&self) -> Result<Vec<DeferredMessage>, BotError> {
+        use schema::deferred_messages::dsl::{deferred_messages, id, tenant_id};
+        let mut connection = self.connection().await?;
+        Ok(deferred_messages
+            .filter(tenant_id.eq(&self.tenant))
+            .order(id.asc())
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn delete_deferred_message(&self, message_id: u32) -> Result<(), BotError> {
+        use schema::deferred_messages::dsl::{deferred_messages, id, tenant_id};
+        let mut connection = self.connection().await?;
+        diesel::delete(
+            deferred_messages
+                .filter(tenant_id.eq(&self.tenant))
+                .filter(id.eq(message_id)),
+        )
+        .execute(&mut connection)
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a> DraftStore for DieselRepository<'a> {
+    async fn load_draft(&self, tg_id: i64) -> Result<Option<OmikujiMessage>, BotError> {
+        use schema::drafts::dsl::{draft, drafts};
+        let mut connection = self.connection().await?;
+        let text: Option<String> = drafts
+            .find((&self.tenant, tg_id))
+            .select(draft)
+            .get_result(&mut connection)
+            .await
+            .optional()?;
+        text.map(|text| crate::drafts::decode(text.as_str()))
+            .transpose()
+    }
+
+    async fn save_draft(&self, user_id: i64, message: &OmikujiMessage) -> Result<(), BotError> {
+        use schema::drafts::dsl::{draft, drafts, tenant_id, tg_id};
+        let mut connection = self.connection().await?;
+        diesel::replace_into(drafts)
+            .values((
+                tenant_id.eq(&self.tenant),
+                tg_id.eq(user_id),
+                draft.eq(crate::drafts::encode(message)?),
+            ))
+            .execute(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_draft(&self, tg_id: i64) -> Result<(), BotError> {
+        use schema::drafts::dsl::drafts;
+        let mut connection = self.connection().await?;
+        diesel::delete(drafts.find((&self.tenant, tg_id)))
+            .execute(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn load_drafts(&self) -> Result<Vec<(i64, NaiveDateTime, OmikujiMessage)>, BotError> {
+        use schema::drafts::dsl::{draft, drafts, tenant_id, tg_id, updated_at};
+        let mut connection = self.connection().await?;
+        let texts: Vec<(i64, NaiveDateTime, String)> = drafts
+            .filter(tenant_id.eq(&self.tenant))
+            .select((tg_id, updated_at, draft))
+            .load(&mut connection)
+            .await?;
+        texts
+            .into_iter()
+            .map(|(user_id, revision, text)| {
+                Ok((user_id, revision, crate::drafts::decode(text.as_str())?))
+            })
+            .collect()
+    }
+
+    // updated_at is kept to the microsecond, and set by MySQL on every save
+    async fn save_draft_if(
+        &self,
+        user_id: i64,
+        message: &OmikujiMessage,
+        revision: NaiveDateTime,
+    ) -> Result<bool, BotError> {
+        use schema::drafts::dsl::{draft, drafts, tenant_id, tg_id, updated_at};
+        let mut connection = self.connection().await?;
+        let updated = diesel::update(
+            drafts
+                .filter(tenant_id.eq(&self.tenant))
+                .filter(tg_id.eq(user_id))
+                .filter(updated_at.eq(revision)),
+        )
+        .set(draft.eq(crate::drafts::encode(message)?))
+        .execute(&mut connection)
+        .await?;
+        Ok(updated > 0)
+    }
+}
+
+// Columns of UsageEvent and UsageDay, in the order of their fields
+const USAGE_EVENT_COLUMNS: (
+    schema::usage_events::command,
+    schema::usage_events::tg_id,
+    schema::usage_events::latency_ms,
+    schema::usage_events::success,
+    schema::usage_events::created_at,
+) = (
+    schema::usage_events::command,
+    schema::usage_events::tg_id,
+    schema::usage_events::latency_ms,
+    schema::usage_events::success,
+    schema::usage_events::created_at,
+);
+const USAGE_DAY_COLUMNS: (
+    schema::usage_daily::day,
+    schema::usage_daily::command,
+    schema::usage_daily::invocations,
+    schema::usage_daily::failures,
+    schema::usage_daily::total_latency_ms,
+) = (
+    schema::usage_daily::day,
+    schema::usage_daily::command,
+    schema::usage_daily::invocations,
+    schema::usage_daily::failures,
+    schema::usage_daily::total_latency_ms,
+);
+
+// Totals of the same day and command added up, ordered by day and command
+fn merge_usage(totals: Vec<UsageDay>, more: Vec<UsageDay>) -> Vec<UsageDay> {
+    let mut merged = totals;
+    for total in more {
+        match merged
+            .iter_mut()
+            .find(|merged| merged.day == total.day && merged.command == total.command)
+        {
+            Some(merged) => merged.add(&total),
+            None => merged.push(total),
+        }
+    }
+    merged.sort_by(|a, b| (a.day, &a.command).cmp(&(b.day, &b.command)));
+    merged
+}
+
+#[async_trait(?Send)]
+impl<'a> CommentRepository for DieselRepository<'a> {
+    async fn insert_comment(&self, comment: &NewComment) -> Result<u32, BotError> {
+        use diesel::dsl::sql;
+        use diesel::sql_types::{BigInt, Unsigned};
+        use schema::comments::dsl::tenant_id;
+        let mut connection = self.connection().await?;
+        diesel::insert_into(schema::comments::table)
+            .values((comment, tenant_id.eq(&self.tenant)))
+            .execute(&mut connection)
+            .await?;
+        // MySQL has no RETURNING, but keeps the last generated id per connection
+        let id: u64 = diesel::select(sql::<Unsigned<BigInt>>("LAST_INSERT_ID()"))
+            .get_result(&mut connection)
+            .await?;
+        Ok(id as u32)
+    }
+
+    async fn find_comment(&self, comment_id: u32) -> Result<Option<Comment>, BotError> {
+        use schema::comments::dsl::{comments, tenant_id};
+        let mut connection = self.connection().await?;
+        Ok(comments
+            .find(comment_id)
+            .filter(tenant_id.eq(&self.tenant))
+            .first(&mut connection)
+            .await
+            .optional()?)
+    }
+
+    async fn find_comments_for_author(
+        &self,
+        author_id: i64,
+        limit: i64,
+    ) -> Result<Vec<Comment>, BotError> {
+        use schema::{comments, omikujis};
+        let mut connection = self.connection().await?;
+        let strips = omikujis::table
+            .filter(omikujis::tenant_id.eq(&self.tenant))
+            .filter(omikujis::tg_id.eq(author_id))
+            .select(omikujis::id);
+        Ok(comments::table
+            .filter(comments::tenant_id.eq(&self.tenant))
+            .filter(comments::omikuji_id.eq_any(strips))
+            .order(comments::id.desc())
+            .limit(limit)
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn find_recent_comments(&self, limit: i64) -> Result<Vec<Comment>, BotError> {
+        use schema::comments::dsl::{comments, id, tenant_id};
+        let mut connection = self.connection().await?;
+        Ok(comments
+            .filter(tenant_id.eq(&self.tenant))
+            .order(id.desc())
+            .limit(limit)
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn find_comments_by_user(&self, user_id: i64) -> Result<Vec<Comment>, BotError> {
+        use schema::comments::dsl::{comments, id, tenant_id, tg_id};
+        let mut connection = self.connection().await?;
+        Ok(comments
+            .filter(tenant_id.eq(&self.tenant))
+            .filter(tg_id.eq(user_id))
+            .order(id)
+            .load(&mut connection)
+            .await?)
+    }
+
+    async fn delete_comment(&self, comment_id: u32) -> Result<bool, BotError> {
+        use schema::comments::dsl::{comments, tenant_id};
+        let mut connection = self.connection().await?;
+        let deleted = diesel::delete(comments.find(comment_id).filter(tenant_id.eq(&self.tenant)))
+            .execute(&mut connection)
+            .await?;
+        Ok(deleted > 0)
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a> AuditRepository for DieselRepository<'a> {
+    async fn record_audit(&self, entry: &NewAuditEntry) -> Result<(), BotError> {
+        use schema::audit_log::dsl::tenant_id;
+        let mut connection = self.connection().await?;
+        diesel::insert_into(schema::audit_log::table)
+            .values((entry, tenant_id.eq(&self.tenant)))
+            .execute(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn find_audit_entries(
+        &self,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>, BotError> {
+        use schema::audit_log::dsl::{audit_log, id, tenant_id};
+        let mut connection = self.connection().await?;
+        Ok(audit_log
+            .filter(tenant_id.eq(&self.tenant))
+            .order(id.desc())
+            .offset(offset)
+            .limit(limit)
+            .load(&mut connection)
+            .await?)
+    }
+}
+
+//
+// Caching decorator
+//
+
+// Keeps the IDs of drawable strips in memory, so that a draw only fetches one row by primary key
+// The IDs are reloaded after CACHE_TTL, or as soon as a strip is saved or downvoted
+pub struct CachedRepository<R> {
+    inner: R,
+    visible_ids: RefCell<Option<(Instant, VisibleIds)>>,
+}
+
+// IDs of drawable strips, with the community each belongs to
+type VisibleIds = Vec<(u32, Option<i64>)>;
+
+impl<R> CachedRepository<R> {
+    const CACHE_TTL: Duration = Duration::from_secs(600);
+
+    pub fn new(inner: R) -> Self {
+        CachedRepository {
+            inner,
+            visible_ids: RefCell::new(None),
+        }
+    }
+
+    fn invalidate(&self) {
+        self.visible_ids.replace(None);
+    }
+}
+
+#[async_trait(?Send)]
+impl<R: OmikujiRepository> OmikujiRepository for CachedRepository<R> {
+    async fn insert_omikuji(&self, omikuji: &NewOmikuji) -> Result<u32, BotError> {
+        let id = self.inner.insert_omikuji(omikuji).await?;
+        self.invalidate();
+        Ok(id)
+    }
+
+    async fn find_omikuji(&self, omikuji_id: u32) -> Result<Option<Omikuji>, BotError> {
+        self.inner.find_omikuji(omikuji_id).await
+    }
+
+    async fn find_omikuji_by_slip(&self, slip_number: u32) -> Result<Option<Omikuji>, BotError> {
+        self.inner.find_omikuji_by_slip(slip_number).await
+    }
+
+    async fn find_omikujis_by_author(&self, tg_id: i64) -> Result<Vec<Omikuji>, BotError> {
+        self.inner.find_omikujis_by_author(tg_id).await
+    }
+
+    async fn all_omikujis(&self) -> Result<Vec<Omikuji>, BotError> {
+        self.inner.all_omikujis().await
+    }
+
+    async fn random_omikuji(
+        &self,
+        pool: DrawPool,
+        community: Option<i64>,
+    ) -> Result<Option<Omikuji>, BotError> {
+        let ids: Vec<u32> = self
+            .visible_omikuji_ids()
+            .await?
+            .into_iter()
+            .filter(|(_, strip_community)| pool.includes(*strip_community, community))
+            .map(|(id, _)| id)
+            .collect();
+        if ids.is_empty() {
+            return Ok(None);
+        }
+        let x = thread_rng().gen_range(0, ids.len());
+        match self.inner.find_omikuji(ids[x]).await? {
+            Some(omikuji)
+                if !omikuji.quarantined
+                    && !omikuji.is_expired(chrono::Local::now().naive_local()) =>
+            {
+                Ok(Some(omikuji))
+            }
+            // The cache is out of date, e.g. the strip was hidden by someone else
+            _ => {
+                self.invalidate();
+                self.inner.random_omikuji(pool, community).await
+            }
+        }
+    }
+
+    async fn least_drawn_omikuji(
+        &self,
+        pool: DrawPool,
+        community: Option<i64>,
+    ) -> Result<Option<Omikuji>, BotError> {
+        self.inner.least_drawn_omikuji(pool, community).await
+    }
+
+    async fn weighted_omikuji(
+        &self,
+        pool: DrawPool,
+        community: Option<i64>,
+    ) -> Result<Option<Omikuji>, BotError> {
+        self.inner.weighted_omikuji(pool, community).await
+    }
+
+    async fn balanced_omikuji(
+        &self,
+        pool: DrawPool,
+        community: Option<i64>,
+    ) -> Result<Option<Omikuji>, BotError> {
+        self.inner.balanced_omikuji(pool, community).await
+    }
+
+    async fn visible_omikuji_ids(&self) -> Result<Vec<(u32, Option<i64>)>, BotError> {
+        if let Some((loaded_at, ids)) = &*self.visible_ids.borrow() {
+            if loaded_at.elapsed() < Self::CACHE_TTL {
+                return Ok(ids.clone());
+            }
+        }
+        let ids = self.inner.visible_omikuji_ids().await?;
+        self.visible_ids
+            .replace(Some((Instant::now(), ids.clone())));
+        Ok(ids)
+    }
+
+    async fn top_omikuji(&self, since: NaiveDateTime) -> Result<Option<Omikuji>, BotError> {
+        self.inner.top_omikuji(since).await
+    }
+
+    async fn top_omikujis(
+        &self,
+        since: NaiveDateTime,
+        limit: usize,
+    ) -> Result<Vec<Omikuji>, BotError> {
+        self.inner.top_omikujis(since, limit).await
+    }
+
+    async fn add_vote(&self, omikuji_id: u32, delta: i32) -> Result<(), BotError> {
+        self.inner.add_vote(omikuji_id, delta).await?;
+        // Only downvotes can hide a strip; strips shown again by upvotes wait for the TTL
+        if delta < 0 {
+            self.invalidate();
+        }
+        Ok(())
+    }
+
+    async fn archive_expired(&self, now: NaiveDateTime) -> Result<usize, BotError> {
+        let archived = self.inner.archive_expired(now).await?;
+        if archived > 0 {
+            self.invalidate();
+        }
+        Ok(archived)
+    }
+
+    async fn update_omikuji(
+        &self,
+        omikuji_id: u32,
+        message: &str,
+        quarantine: bool,
+    ) -> Result<(), BotError> {
+        self.inner
+            .update_omikuji(omikuji_id, message, quarantine)
+            .await?;
+        if quarantine {
+            self.invalidate();
+        }
+        Ok(())
+    }
+
+    async fn set_anonymous(&self, omikuji_id: u32, anonymous: bool) -> Result<(), BotError> {
+        self.inner.set_anonymous(omikuji_id, anonymous).await
+    }
+
+    // The cached IDs carry the community of each strip
+    async fn migrate_community(&self, from: i64, to: i64) -> Result<usize, BotError> {
+        let moved = self.inner.migrate_community(from, to).await?;
+        if moved > 0 {
+            self.invalidate();
+        }
+        Ok(moved)
+    }
+}
+
+#[async_trait(?Send)]
+impl<R: UserRepository> UserRepository for CachedRepository<R> {
+    async fn upsert_user(&self, user: &NewUser) -> Result<(), BotError> {
+        self.inner.upsert_user(user).await
+    }
+
+    async fn is_banned(&self, tg_id: i64) -> Result<bool, BotError> {
+        self.inner.is_banned(tg_id).await
+    }
+
+    async fn find_user(&self, tg_id: i64) -> Result<Option<User>, BotError> {
+        self.inner.find_user(tg_id).await
+    }
+
+    async fn get_user_settings(&self, tg_id: i64) -> Result<UserSettings, BotError> {
+        self.inner.get_user_settings(tg_id).await
+    }
+
+    async fn update_user_settings(&self, settings: &UserSettings) -> Result<(), BotError> {
+        self.inner.update_user_settings(settings).await
+    }
+
+    async fn get_daily_subscribers(&self) -> Result<Vec<UserSettings>, BotError> {
+        self.inner.get_daily_subscribers().await
+    }
+
+    async fn get_digest_subscribers(&self) -> Result<Vec<UserSettings>, BotError> {
+        self.inner.get_digest_subscribers().await
+    }
+
+    async fn set_inactive(&self, tg_id: i64, inactive: bool) -> Result<(), BotError> {
+        self.inner.set_inactive(tg_id, inactive).await
+    }
+
+    async fn credit_name(&self, tg_id: i64) -> Result<Option<String>, BotError> {
+        self.inner.credit_name(tg_id).await
+    }
+
+    // Strips are kept, so the cached IDs are still valid
+    async fn forget_user(&self, tg_id: i64) -> Result<(), BotError> {
+        self.inner.forget_user(tg_id).await
+    }
+
+    async fn set_referrer(&self, tg_id: i64, referrer: i64) -> Result<bool, BotError> {
+        self.inner.set_referrer(tg_id, referrer).await
+    }
+}
+
+#[async_trait(?Send)]
+impl<R: StatsRepository> StatsRepository for CachedRepository<R> {
+    async fn record_draw(&self, draw: &NewDraw) -> Result<(), BotError> {
+        self.inner.record_draw(draw).await
+    }
+
+    async fn find_draws_by_user(&self, tg_id: i64) -> Result<Vec<Draw>, BotError> {
+        self.inner.find_draws_by_user(tg_id).await
+    }
+
+    async fn count_draws_by_user(&self, tg_id: i64, since: NaiveDateTime) -> Result<i64, BotError> {
+        self.inner.count_draws_by_user(tg_id, since).await
+    }
+
+    async fn find_last_draw(&self, tg_id: i64) -> Result<Option<Draw>, BotError> {
+        self.inner.find_last_draw(tg_id).await
+    }
+
+    async fn count_omikujis_by_class(&self) -> Result<Vec<(Option<String>, i64)>, BotError> {
+        self.inner.count_omikujis_by_class().await
+    }
+
+    async fn count_hidden_omikujis(&self) -> Result<i64, BotError> {
+        self.inner.count_hidden_omikujis().await
+    }
+
+    async fn count_undrawn_omikujis(&self) -> Result<i64, BotError> {
+        self.inner.count_undrawn_omikujis().await
+    }
+
+    async fn most_drawn_omikujis(&self, limit: i64) -> Result<Vec<(u32, u32)>, BotError> {
+        self.inner.most_drawn_omikujis(limit).await
+    }
+
+    async fn count_active_users(&self, since: NaiveDateTime) -> Result<i64, BotError> {
+        self.inner.count_active_users(since).await
+    }
+
+    async fn count_draws_by_day(
+        &self,
+        since: NaiveDateTime,
+    ) -> Result<Vec<(NaiveDate, i64)>, BotError> {
+        self.inner.count_draws_by_day(since).await
+    }
+
+    async fn top_authors(&self, limit: i64) -> Result<Vec<(String, i64)>, BotError> {
+        self.inner.top_authors(limit).await
+    }
+
+    async fn count_referrals(&self, tg_id: i64) -> Result<i64, BotError> {
+        self.inner.count_referrals(tg_id).await
+    }
+
+    async fn top_referrers(&self, limit: i64) -> Result<Vec<(String, i64)>, BotError> {
+        self.inner.top_referrers(limit).await
+    }
+
+    async fn record_vote(&self, tg_id: i64, omikuji_id: u32, vote: i8) -> Result<(), BotError> {
+        self.inner.record_vote(tg_id, omikuji_id, vote).await
+    }
+
+    async fn tie_draw(&self, tg_id: i64, omikuji_id: u32) -> Result<bool, BotError> {
+        self.inner.tie_draw(tg_id, omikuji_id).await
+    }
+
+    async fn count_tied_draws(&self, since: NaiveDateTime) -> Result<i64, BotError> {
+        self.inner.count_tied_draws(since).await
+    }
+
+    async fn experiment_results(&self) -> Result<Vec<VariantResult>, BotError> {
+        self.inner.experiment_results().await
+    }
+
+    async fn author_digest(
+        &self,
+        tg_id: i64,
+        since: NaiveDateTime,
+    ) -> Result<AuthorDigest, BotError> {
+        self.inner.author_digest(tg_id, since).await
+    }
+
+    async fn record_booth_draw(&self, draw: &NewBoothDraw) -> Result<(), BotError> {
+        self.inner.record_booth_draw(draw).await
+    }
+
+    async fn count_booth_draws(&self, since: NaiveDateTime) -> Result<i64, BotError> {
+        self.inner.count_booth_draws(since).await
+    }
+
+    async fn find_booth_draws_by_user(&self, tg_id: i64) -> Result<Vec<BoothDraw>, BotError> {
+        self.inner.find_booth_draws_by_user(tg_id).await
+    }
+}
+
+#[async_trait(?Send)]
+impl<R: ModerationRepository> ModerationRepository for CachedRepository<R> {
+    async fn find_pending_omikujis(&self) -> Result<Vec<Omikuji>, BotError> {
+        self.inner.find_pending_omikujis().await
+    }
+
+    async fn find_quarantined_omikujis(&self) -> Result<Vec<Omikuji>, BotError> {
+        self.inner.find_quarantined_omikujis().await
+    }
+
+    async fn search_omikujis(&self, query: &str, limit: i64) -> Result<Vec<Omikuji>, BotError> {
+        self.inner.search_omikujis(query, limit).await
+    }
+
+    async fn review_omikuji(&self, omikuji_id: u32, approve: bool) -> Result<(), BotError> {
+        self.inner.review_omikuji(omikuji_id, approve).await?;
+        self.invalidate();
+        Ok(())
+    }
+
+    async fn delete_omikuji(&self, omikuji_id: u32) -> Result<(), BotError> {
+        self.inner.delete_omikuji(omikuji_id).await?;
+        self.invalidate();
+        Ok(())
+    }
+
+    async fn review_omikujis(&self, omikuji_ids: &[u32], approve: bool) -> Result<usize, BotError> {
+        let reviewed = self.inner.review_omikujis(omikuji_ids, approve).await?;
+        self.invalidate();
+        Ok(reviewed)
+    }
+
+    async fn delete_omikujis(&self, omikuji_ids: &[u32]) -> Result<usize, BotError> {
+        let deleted = self.inner.delete_omikujis(omikuji_ids).await?;
+        self.invalidate();
+        Ok(deleted)
+    }
+}
+
+#[async_trait(?Send)]
+impl<R: InterpretationRepository> InterpretationRepository for CachedRepository<R> {
+    async fn find_interpretation(&self, class: &str) -> Result<Option<String>, BotError> {
+        self.inner.find_interpretation(class).await
+    }
+
+    async fn set_interpretation(&self, interpretation: &NewInterpretation) -> Result<(), BotError> {
+        self.inner.set_interpretation(interpretation).await
+    }
+}
+
+#[async_trait(?Send)]
+impl<R: AchievementRepository> AchievementRepository for CachedRepository<R> {
+    async fn award_badge(&self, achievement: &NewAchievement) -> Result<bool, BotError> {
+        self.inner.award_badge(achievement).await
+    }
+
+    async fn find_badges(&self, tg_id: i64) -> Result<Vec<String>, BotError> {
+        self.inner.find_badges(tg_id).await
+    }
+}
+
+#[async_trait(?Send)]
+impl<R: FavoriteRepository> FavoriteRepository for CachedRepository<R> {
+    async fn add_favorite(&self, favorite: &NewFavorite) -> Result<bool, BotError> {
+        self.inner.add_favorite(favorite).await
+    }
+
+    async fn find_favorites(
+        &self,
+        tg_id: i64,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<Omikuji>, BotError> {
+        self.inner.find_favorites(tg_id, offset, limit).await
+    }
+
+    async fn count_favorites(&self, tg_id: i64) -> Result<i64, BotError> {
+        self.inner.count_favorites(tg_id).await
+    }
+}
+
+#[async_trait(?Send)]
+impl<R: ReactionRepository> ReactionRepository for CachedRepository<R> {
+    async fn set_reaction(&self, reaction: &NewReaction) -> Result<(), BotError> {
+        self.inner.set_reaction(reaction).await
+    }
+
+    async fn count_reactions(&self, omikuji_id: u32) -> Result<Vec<(String, i64)>, BotError> {
+        self.inner.count_reactions(omikuji_id).await
+    }
+
+    async fn find_reactions_by_user(&self, tg_id: i64) -> Result<Vec<(u32, String)>, BotError> {
+        self.inner.find_reactions_by_user(tg_id).await
+    }
+}
+
+#[async_trait(?Send)]
+impl<R: PollRepository> PollRepository for CachedRepository<R> {
+    async fn insert_weekly_poll(&self, poll: &NewWeeklyPoll) -> Result<(), BotError> {
+        self.inner.insert_weekly_poll(poll).await
+    }
+
+    async fn find_open_weekly_poll(&self) -> Result<Option<WeeklyPoll>, BotError> {
+        self.inner.find_open_weekly_poll().await
+    }
+
+    async fn close_weekly_poll(
+        &self,
+        poll_id: u32,
+        winner_id: Option<u32>,
+    ) -> Result<(), BotError> {
+        self.inner.close_weekly_poll(poll_id, winner_id).await
+    }
+
+    async fn find_weekly_winners(&self) -> Result<Vec<u32>, BotError> {
+        self.inner.find_weekly_winners().await
+    }
+
+    async fn migrate_poll_chat(&self, from: i64, to: i64) -> Result<(), BotError> {
+        self.inner.migrate_poll_chat(from, to).await
+    }
+}
+
+#[async_trait(?Send)]
+impl<R: UsageRepository> UsageRepository for CachedRepository<R> {
+    async fn record_usage(&self, event: &NewUsageEvent) -> Result<(), BotError> {
+        self.inner.record_usage(event).await
+    }
+
+    async fn aggregate_usage(&self, before: NaiveDateTime) -> Result<usize, BotError> {
+        self.inner.aggregate_usage(before).await
+    }
+
+    async fn usage_by_day(&self, since: NaiveDate) -> Result<Vec<UsageDay>, BotError> {
+        self.inner.usage_by_day(since).await
+    }
+}
+
+#[async_trait(?Send)]
+impl<R: LeaseRepository> LeaseRepository for CachedRepository<R> {
+    async fn acquire_lease(
+        &self,
+        name: &str,
+        holder: &str,
+        duration: Duration,
+    ) -> Result<bool, BotError> {
+        self.inner.acquire_lease(name, holder, duration).await
+    }
+
+    async fn find_job_runs(&self, job: &str) -> Result<Vec<JobRun>, BotError> {
+        self.inner.find_job_runs(job).await
+    }
+
+    async fn record_job_run(&self, run: &JobRun) -> Result<(), BotError> {
+        self.inner.record_job_run(run).await
+    }
+}
+
+#[async_trait(?Send)]
+impl<R: TrashRepository> TrashRepository for CachedRepository<R> {
+    async fn insert_trash(&self, item: &NewTrashItem) -> Result<u32, BotError> {
+        self.inner.insert_trash(item).await
+    }
+
+    async fn find_trash(
+        &self,
+        tg_id: i64,
+        since: NaiveDateTime,
+    ) -> Result<Vec<TrashItem>, BotError> {
+        self.inner.find_trash(tg_id, since).await
+    }
+
+    async fn take_trash(&self, tg_id: i64, trash_id: u32) -> Result<Option<TrashItem>, BotError> {
+        self.inner.take_trash(tg_id, trash_id).await
+    }
+
+    async fn purge_trash(&self, before: NaiveDateTime) -> Result<usize, BotError> {
+        self.inner.purge_trash(before).await
+    }
+}
+
+#[async_trait(?Send)]
+impl<R: ImportRepository> ImportRepository for CachedRepository<R> {
+    async fn import_omikujis(
+        &self,
+        batch: &NewImportBatch,
+        omikujis: &[NewOmikuji],
+    ) -> Result<u32, BotError> {
+        let batch_id = self.inner.import_omikujis(batch, omikujis).await?;
+        self.invalidate();
+        Ok(batch_id)
+    }
+}
+
+#[async_trait(?Send)]
+impl<R: DeferredRepository> DeferredRepository for CachedRepository<R> {
+    async fn defer_message(&self, message: &NewDeferredMessage) -> Result<(), BotError> {
+        self.inner.defer_message(message).await
+    }
+
+    async fn find_deferred_messages(&self) -> Result<Vec<DeferredMessage>, BotError> {
+        self.inner.find_deferred_messages().await
+    }
+
+    async fn delete_deferred_message(&self, id: u32) -> Result<(), BotError> {
+        self.inner.delete_deferred_message(id).await
+    }
+}
+
+#[async_trait(?Send)]
+impl<R: CommentRepository> CommentRepository for CachedRepository<R> {
+    async fn insert_comment(&self, comment: &NewComment) -> Result<u32, BotError> {
+        self.inner.insert_comment(comment).await
+    }
+
+    async fn find_comment(&self, comment_id: u32) -> Result<Option<Comment>, BotError> {
+        self.inner.find_comment(comment_id).await
+    }
+
+    async fn find_comments_for_author(
+        &self,
+        tg_id: i64,
+        limit: i64,
+    ) -> Result<Vec<Comment>, BotError> {
+        self.inner.find_comments_for_author(tg_id, limit).await
+    }
+
+    async fn find_recent_comments(&self, limit: i64) -> Result<Vec<Comment>, BotError> {
+        self.inner.find_recent_comments(limit).await
+    }
+
+    async fn find_comments_by_user(&self, tg_id: i64) -> Result<Vec<Comment>, BotError> {
+        self.inner.find_comments_by_user(tg_id).await
+    }
+
+    async fn delete_comment(&self, comment_id: u32) -> Result<bool, BotError> {
+        self.inner.delete_comment(comment_id).await
+    }
+}
+
+#[async_trait(?Send)]
+impl<R: AuditRepository> AuditRepository for CachedRepository<R> {
+    async fn record_audit(&self, entry: &NewAuditEntry) -> Result<(), BotError> {
+        self.inner.record_audit(entry).await
+    }
+
+    async fn find_audit_entries(
+        &self,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>, BotError> {
+        self.inner.find_audit_entries(offset, limit).await
+    }
+}
+
+//
+// In-memory implementation, for tests
+//
+
+// Only holds a single tenant's data
+
+#[derive(Default)]
+pub struct MemoryRepository {
+    pub omikujis: RefCell<Vec<Omikuji>>,
+    pub users: RefCell<HashMap<i64, User>>,
+    pub user_settings: RefCell<HashMap<i64, UserSettings>>,
+    pub draws: RefCell<Vec<Draw>>,
+    pub audit_log: RefCell<Vec<AuditEntry>>,
+    pub interpretations: RefCell<HashMap<String, String>>,
+    pub achievements: RefCell<HashMap<i64, Vec<String>>>,
+    // (tg_id, omikuji_id), in the order they were saved
+    pub favorites: RefCell<Vec<(i64, u32)>>,
+    pub comments: RefCell<Vec<Comment>>,
+    // Reaction (as serialized) by (tg_id, omikuji_id)
+    pub reactions: RefCell<HashMap<(i64, u32), String>>,
+    pub weekly_polls: RefCell<Vec<WeeklyPoll>>,
+    pub usage_events: RefCell<Vec<UsageEvent>>,
+    pub usage_daily: RefCell<Vec<UsageDay>>,
+    // Drafts (encoded like in the drafts table) by tg_id
+    pub drafts: RefCell<HashMap<i64, (NaiveDateTime, String)>>,
+    // (holder, expires_at) by name
+    pub leases: RefCell<HashMap<String, (String, NaiveDateTime)>>,
+    pub job_runs: RefCell<Vec<JobRun>>,
+    pub trash: RefCell<Vec<TrashItem>>,
+    // Sources of the import batches, by ID - 1
+    pub import_batches: RefCell<Vec<String>>,
+    // Draws at booths, with the time they were drawn
+    pub booth_draws: RefCell<Vec<(NewBoothDraw, NaiveDateTime)>>,
+    pub deferred_messages: RefCell<Vec<DeferredMessage>>,
+}
+
+impl MemoryRepository {
+    // Like DieselRepository::number_slips
+    fn number_slips(&self) {
+        let mut omikujis = self.omikujis.borrow_mut();
+        let mut last = omikujis
+            .iter()
+            .filter_map(|omikuji| omikuji.slip_number)
+            .max()
+            .unwrap_or(0);
+        for omikuji in omikujis.iter_mut() {
+            if !omikuji.quarantined && omikuji.slip_number.is_none() {
+                last += 1;
+                omikuji.slip_number = Some(last);
+            }
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl OmikujiRepository for MemoryRepository {
+    async fn insert_omikuji(&self, omikuji: &NewOmikuji) -> Result<u32, BotError> {
+        let mut omikujis = self.omikujis.borrow_mut();
+        let now = chrono::Local::now().naive_local();
+        // IDs are not reused after a strip is deleted
+        let id = omikujis.last().map_or(1, |last| last.id + 1);
+        omikujis.push(Omikuji {
+            id,
+            message: omikuji.message.to_string(),
+            vote_count: omikuji.vote_count,
+            tg_id: omikuji.tg_id,
+            tg_name: omikuji.tg_name.to_string(),
+            created_at: now,
+            updated_at: now,
+            reviewed_at: None,
+            tenant_id: DEFAULT_TENANT.to_string(),
+            community_id: omikuji.community_id,
+            anonymous: omikuji.anonymous,
+            expires_at: omikuji.expires_at,
+            archived: false,
+            quarantined: omikuji.quarantined,
+            draw_count: 0,
+            edited_at: None,
+            slip_number: None,
+            import_batch: None,
+        });
+        drop(omikujis);
+        self.number_slips();
+        Ok(id)
+    }
+
+    async fn find_omikuji(&self, omikuji_id: u32) -> Result<Option<Omikuji>, BotError> {
+        let omikujis = self.omikujis.borrow();
+        Ok(omikujis
+            .iter()
+            .find(|omikuji| omikuji.id == omikuji_id)
+            .cloned())
+    }
+
+    async fn find_omikuji_by_slip(&self, slip_number: u32) -> Result<Option<Omikuji>, BotError> {
+        let omikujis = self.omikujis.borrow();
+        Ok(omikujis
+            .iter()
+            .find(|omikuji| omikuji.slip_number == Some(slip_number))
+            .cloned())
+    }
+
+    async fn find_omikujis_by_author(&self, tg_id: i64) -> Result<Vec<Omikuji>, BotError> {
+        let omikujis = self.omikujis.borrow();
+        Ok(omikujis
+            .iter()
+            .filter(|omikuji| omikuji.tg_id == tg_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn all_omikujis(&self) -> Result<Vec<Omikuji>, BotError> {
+        Ok(self.omikujis.borrow().clone())
+    }
+
+    async fn random_omikuji(
+        &self,
+        pool: DrawPool,
+        community: Option<i64>,
+    ) -> Result<Option<Omikuji>, BotError> {
+        let omikujis = self.omikujis.borrow();
+        let now = chrono::Local::now().naive_local();
+        let visible: Vec<&Omikuji> = omikujis
+            .iter()
+            .filter(|omikuji| !omikuji.quarantined)
+            .filter(|omikuji| !omikuji.is_expired(now))
+            .filter(|omikuji| pool.includes(omikuji.community_id, community))
+            .collect();
+        if visible.is_empty() {
+            return Ok(None);
+        }
+        let x = thread_rng().gen_range(0, visible.len());
+        Ok(Some(visible[x].clone()))
+    }
+
+    async fn least_drawn_omikuji(
+        &self,
+        pool: DrawPool,
+        community: Option<i64>,
+    ) -> Result<Option<Omikuji>, BotError> {
+        let omikujis = self.omikujis.borrow();
+        let now = chrono::Local::now().naive_local();
+        let visible: Vec<&Omikuji> = omikujis
+            .iter()
+            .filter(|omikuji| !omikuji.quarantined)
+            .filter(|omikuji| !omikuji.is_expired(now))
+            .filter(|omikuji| pool.includes(omikuji.community_id, community))
+            .collect();
+        let fewest = match visible.iter().map(|omikuji| omikuji.draw_count).min() {
+            Some(fewest) => fewest,
+            None => return Ok(None),
+        };
+        let least_drawn: Vec<&&Omikuji> = visible
+            .iter()
+            .filter(|omikuji| omikuji.draw_count == fewest)
+            .collect();
+        let x = thread_rng().gen_range(0, least_drawn.len());
+        Ok(Some((*least_drawn[x]).clone()))
+    }
+
+    async fn weighted_omikuji(
+        &self,
+        pool: DrawPool,
+        community: Option<i64>,
+    ) -> Result<Option<Omikuji>, BotError> {
+        let omikujis = self.omikujis.borrow();
+        let now = chrono::Local::now().naive_local();
+        let visible: Vec<&Omikuji> = omikujis
+            .iter()
+            .filter(|omikuji| !omikuji.quarantined)
+            .filter(|omikuji| !omikuji.is_expired(now))
+            .filter(|omikuji| pool.includes(omikuji.community_id, community))
+            .collect();
+        let weights: Vec<f64> = visible
+            .iter()
+            .map(|omikuji| scoring::weight(scoring::score(omikuji, now, vote_half_life())))
+            .collect();
+        Ok(
+            scoring::weighted_index(&weights, &mut thread_rng())
+                .map(|index| visible[index].clone()),
+        )
+    }
+
+    async fn balanced_omikuji(
+        &self,
+        pool: DrawPool,
+        community: Option<i64>,
+    ) -> Result<Option<Omikuji>, BotError> {
+        let omikujis = self.omikujis.borrow();
+        let now = chrono::Local::now().naive_local();
+        let visible: Vec<&Omikuji> = omikujis
+            .iter()
+            .filter(|omikuji| !omikuji.quarantined)
+            .filter(|omikuji| !omikuji.is_expired(now))
+            .filter(|omikuji| pool.includes(omikuji.community_id, community))
+            .collect();
+        let classes: Vec<Option<String>> = visible
+            .iter()
+            .map(|omikuji| {
+                serde_json::from_str::<OmikujiMessage>(omikuji.message.as_str())
+                    .ok()
+                    .and_then(|message| message.class)
+                    .map(|class| format!("{:?}", class))
+            })
+            .collect();
+        Ok(
+            scoring::balanced_index(&classes, &mut thread_rng())
+                .map(|index| visible[index].clone()),
+        )
+    }
+
+    async fn visible_omikuji_ids(&self) -> Result<Vec<(u32, Option<i64>)>, BotError> {
+        let omikujis = self.omikujis.borrow();
+        let now = chrono::Local::now().naive_local();
+        Ok(omikujis
+            .iter()
+            .filter(|omikuji| !omikuji.quarantined)
+            .filter(|omikuji| !omikuji.is_expired(now))
+            .map(|omikuji| (omikuji.id, omikuji.community_id))
+            .collect())
+    }
+
+    async fn top_omikuji(&self, since: NaiveDateTime) -> Result<Option<Omikuji>, BotError> {
+        let omikujis = self.omikujis.borrow();
+        let now = chrono::Local::now().naive_local();
+        let candidates = omikujis
+            .iter()
+            .filter(|omikuji| omikuji.created_at >= since)
+            .filter(|omikuji| !omikuji.quarantined)
+            .filter(|omikuji| !omikuji.is_expired(now));
+        Ok(scoring::top(candidates, now, vote_half_life()).cloned())
+    }
+
+    async fn top_omikujis(
+        &self,
+        since: NaiveDateTime,
+        limit: usize,
+    ) -> Result<Vec<Omikuji>, BotError> {
+        let omikujis = self.omikujis.borrow();
+        let now = chrono::Local::now().naive_local();
+        let candidates = omikujis
+            .iter()
+            .filter(|omikuji| omikuji.created_at >= since)
+            .filter(|omikuji| !omikuji.quarantined)
+            .filter(|omikuji| !omikuji.is_expired(now));
+        Ok(scoring::top_n(candidates, now, vote_half_life(), limit)
+            .into_iter()
+            .cloned()
+            .collect())
+    }
+
+    async fn add_vote(&self, omikuji_id: u32, delta: i32) -> Result<(), BotError> {
+        let mut omikujis = self.omikujis.borrow_mut();
+        if let Some(stored) = omikujis.iter_mut().find(|stored| stored.id == omikuji_id) {
+            stored.vote_count += delta;
+            if stored.vote_count <= HIDE_THRESHOLD {
+                stored.quarantined = true;
+            }
+        }
+        Ok(())
+    }
+
+    async fn archive_expired(&self, now: NaiveDateTime) -> Result<usize, BotError> {
+        let mut archived = 0;
+        for omikuji in self.omikujis.borrow_mut().iter_mut() {
+            if !omikuji.archived && omikuji.is_expired(now) {
+                omikuji.archived = true;
+                archived += 1;
+            }
+        }
+        Ok(archived)
+    }
+
+    async fn update_omikuji(
+        &self,
+        omikuji_id: u32,
+        message: &str,
+        quarantine: bool,
+    ) -> Result<(), BotError> {
+        let mut omikujis = self.omikujis.borrow_mut();
+        if let Some(omikuji) = omikujis.iter_mut().find(|omikuji| omikuji.id == omikuji_id) {
+            omikuji.message = message.to_string();
+            omikuji.updated_at = chrono::Local::now().naive_local();
+            omikuji.edited_at = Some(omikuji.updated_at);
+            if quarantine {
+                omikuji.quarantined = true;
+                omikuji.reviewed_at = None;
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_anonymous(&self, omikuji_id: u32, anonymous: bool) -> Result<(), BotError> {
+        let mut omikujis = self.omikujis.borrow_mut();
+        if let Some(omikuji) = omikujis.iter_mut().find(|omikuji| omikuji.id == omikuji_id) {
+            omikuji.anonymous = anonymous;
+        }
+        Ok(())
+    }
+
+    async fn migrate_community(&self, from: i64, to: i64) -> Result<usize, BotError> {
+        let mut omikujis = self.omikujis.borrow_mut();
+        let mut moved = 0;
+        for omikuji in omikujis.iter_mut() {
+            if omikuji.community_id == Some(from) {
+                omikuji.community_id = Some(to);
+                moved += 1;
+            }
+        }
+        Ok(moved)
+    }
+}
+
+#[async_trait(?Send)]
+impl UserRepository for MemoryRepository {
+    async fn upsert_user(&self, user: &NewUser) -> Result<(), BotError> {
+        let now = chrono::Local::now().naive_local();
+        let mut users = self.users.borrow_mut();
+        let stored = users.entry(user.tg_id).or_insert_with(|| User {
+            tg_id: user.tg_id,
+            tg_name: String::new(),
+            tg_username: None,
+            language_code: None,
+            banned: false,
+            inactive: false,
+            referred_by: None,
+            created_at: now,
+            updated_at: now,
+            tenant_id: DEFAULT_TENANT.to_string(),
+        });
+        stored.tg_name = user.tg_name.to_string();
+        stored.tg_username = user.tg_username.map(String::from);
+        stored.language_code = user.language_code.map(String::from);
+        stored.inactive = false;
+        stored.updated_at = now;
+        Ok(())
+    }
+
+    async fn is_banned(&self, tg_id: i64) -> Result<bool, BotError> {
+        let users = self.users.borrow();
+        Ok(users.get(&tg_id).map(|user| user.banned).unwrap_or(false))
+    }
+
+    async fn find_user(&self, tg_id: i64) -> Result<Option<User>, BotError> {
+        let users = self.users.borrow();
+        Ok(users.get(&tg_id).cloned())
+    }
+
+    async fn get_user_settings(&self, tg_id: i64) -> Result<UserSettings, BotError> {
+        let now = chrono::Local::now().naive_local();
+        let code = self
+            .find_user(tg_id)
+            .await?
+            .and_then(|user| user.language_code);
+        let language = Language::detect(code.as_deref());
+        let mut user_settings = self.user_settings.borrow_mut();
+        let settings = user_settings.entry(tg_id).or_insert_with(|| UserSettings {
+            tg_id,
+            notifications: true,
+            language: format!("{:?}", language),
+            daily_subscription: false,
+            reply_keyboard: false,
+            voice: false,
+            pool: String::from("Both"),
+            credit: false,
+            weekly_digest: false,
+            quiet_start: None,
+            quiet_end: None,
+            time_zone: None,
+            created_at: now,
+            updated_at: now,
+            tenant_id: DEFAULT_TENANT.to_string(),
+        });
+        Ok(settings.clone())
+    }
+
+    async fn update_user_settings(&self, settings: &UserSettings) -> Result<(), BotError> {
+        let mut user_settings = self.user_settings.borrow_mut();
+        user_settings.insert(settings.tg_id, settings.clone());
+        Ok(())
+    }
+
+    async fn get_daily_subscribers(&self) -> Result<Vec<UserSettings>, BotError> {
+        let user_settings = self.user_settings.borrow();
+        let users = self.users.borrow();
+        Ok(user_settings
+            .values()
+            .filter(|settings| settings.daily_subscription)
+            .filter(|settings| !users.get(&settings.tg_id).is_some_and(|user| user.inactive))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_digest_subscribers(&self) -> Result<Vec<UserSettings>, BotError> {
+        let user_settings = self.user_settings.borrow();
+        let users = self.users.borrow();
+        Ok(user_settings
+            .values()
+            .filter(|settings| settings.weekly_digest)
+            .filter(|settings| !users.get(&settings.tg_id).is_some_and(|user| user.inactive))
+            .cloned()
+            .collect())
+    }
+
+    async fn set_inactive(&self, tg_id: i64, inactive: bool) -> Result<(), BotError> {
+        if let Some(user) = self.users.borrow_mut().get_mut(&tg_id) {
+            user.inactive = inactive;
+        }
+        Ok(())
+    }
+
+    async fn credit_name(&self, tg_id: i64) -> Result<Option<String>, BotError> {
+        let user_settings = self.user_settings.borrow();
+        if !user_settings
+            .get(&tg_id)
+            .is_some_and(|settings| settings.credit)
+        {
+            return Ok(None);
+        }
+        let users = self.users.borrow();
+        Ok(users.get(&tg_id).map(|user| user.tg_name.clone()))
+    }
+
+    async fn forget_user(&self, tg_id: i64) -> Result<(), BotError> {
+        for omikuji in self.omikujis.borrow_mut().iter_mut() {
+            if omikuji.tg_id == tg_id {
+                omikuji.tg_id = ANONYMOUS_ID;
+                omikuji.tg_name = String::from(ANONYMOUS_NAME);
+                omikuji.anonymous = true;
+            }
+        }
+        self.draws.borrow_mut().retain(|draw| draw.tg_id != tg_id);
+        self.achievements.borrow_mut().remove(&tg_id);
+        self.favorites
+            .borrow_mut()
+            .retain(|(user, _)| *user != tg_id);
+        self.comments
+            .borrow_mut()
+            .retain(|comment| comment.tg_id != tg_id);
+        self.reactions
+            .borrow_mut()
+            .retain(|(user, _), _| *user != tg_id);
+        self.trash.borrow_mut().retain(|item| item.tg_id != tg_id);
+        self.deferred_messages
+            .borrow_mut()
+            .retain(|message| message.tg_id != tg_id);
+        self.booth_draws
+            .borrow_mut()
+            .retain(|(draw, _)| draw.tg_id != tg_id);
+        self.usage_events
+            .borrow_mut()
+            .retain(|event| event.tg_id != tg_id);
+        self.job_runs.borrow_mut().retain(|run| run.tg_id != tg_id);
+        self.drafts.borrow_mut().remove(&tg_id);
+        let actor = format!("tg:{}", tg_id);
+        for entry in self.audit_log.borrow_mut().iter_mut() {
+            if entry.actor == actor {
+                entry.actor = String::from(ANONYMOUS_NAME);
+            }
+        }
+        self.user_settings.borrow_mut().remove(&tg_id);
+        let mut users = self.users.borrow_mut();
+        users.remove(&tg_id);
+        for user in users.values_mut() {
+            if user.referred_by == Some(tg_id) {
+                user.referred_by = None;
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_referrer(&self, tg_id: i64, referrer: i64) -> Result<bool, BotError> {
+        match self.users.borrow_mut().get_mut(&tg_id) {
+            Some(user) if user.referred_by.is_none() => {
+                user.referred_by = Some(referrer);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl StatsRepository for MemoryRepository {
+    async fn record_draw(&self, draw: &NewDraw) -> Result<(), BotError> {
+        let mut draws = self.draws.borrow_mut();
+        let id = draws.len() as u32 + 1;
+        draws.push(Draw {
+            id,
+            tg_id: draw.tg_id,
+            omikuji_id: draw.omikuji_id,
+            created_at: chrono::Local::now().naive_local(),
+            tenant_id: DEFAULT_TENANT.to_string(),
+            variant: draw.variant.clone(),
+            vote: None,
+            tied_at: None,
+        });
+        let mut omikujis = self.omikujis.borrow_mut();
+        if let Some(omikuji) = omikujis
+            .iter_mut()
+            .find(|omikuji| omikuji.id == draw.omikuji_id)
+        {
+            omikuji.draw_count += 1;
+        }
+        Ok(())
+    }
+
+    async fn find_draws_by_user(&self, tg_id: i64) -> Result<Vec<Draw>, BotError> {
+        let draws = self.draws.borrow();
+        Ok(draws
+            .iter()
+            .filter(|draw| draw.tg_id == tg_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn count_draws_by_user(&self, tg_id: i64, since: NaiveDateTime) -> Result<i64, BotError> {
+        let draws = self.draws.borrow();
+        Ok(draws
+            .iter()
+            .filter(|draw| draw.tg_id == tg_id && draw.created_at >= since)
+            .count() as i64)
+    }
+
+    async fn find_last_draw(&self, tg_id: i64) -> Result<Option<Draw>, BotError> {
+        let draws = self.draws.borrow();
+        Ok(draws
+            .iter()
+            .rev()
+            .find(|draw| draw.tg_id == tg_id && draw.tied_at.is_none())
+            .cloned())
+    }
+
+    async fn count_omikujis_by_class(&self) -> Result<Vec<(Option<String>, i64)>, BotError> {
+        let mut counts: Vec<(Option<String>, i64)> = Vec::new();
+        for omikuji in self.omikujis.borrow().iter() {
+            let class = serde_json::from_str::<OmikujiMessage>(omikuji.message.as_str())
+                .ok()
+                .and_then(|message| message.class)
+                .map(|class| format!("{:?}", class));
+            match counts.iter_mut().find(|(stored, _)| *stored == class) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((class, 1)),
+            }
+        }
+        Ok(counts)
+    }
+
+    async fn count_hidden_omikujis(&self) -> Result<i64, BotError> {
+        let omikujis = self.omikujis.borrow();
+        Ok(omikujis
+            .iter()
+            .filter(|omikuji| omikuji.quarantined)
+            .count() as i64)
+    }
+
+    async fn count_undrawn_omikujis(&self) -> Result<i64, BotError> {
+        let omikujis = self.omikujis.borrow();
+        Ok(omikujis
+            .iter()
+            .filter(|omikuji| !omikuji.quarantined && !omikuji.archived && omikuji.draw_count == 0)
+            .count() as i64)
+    }
+
+    async fn most_drawn_omikujis(&self, limit: i64) -> Result<Vec<(u32, u32)>, BotError> {
+        let omikujis = self.omikujis.borrow();
+        let mut counts: Vec<(u32, u32)> = omikujis
+            .iter()
+            .filter(|omikuji| omikuji.draw_count > 0)
+            .map(|omikuji| (omikuji.id, omikuji.draw_count))
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        counts.truncate(limit as usize);
+        Ok(counts)
+    }
+
+    async fn count_active_users(&self, since: NaiveDateTime) -> Result<i64, BotError> {
+        let users = self.users.borrow();
+        Ok(users
+            .values()
+            .filter(|user| user.updated_at >= since)
+            .count() as i64)
+    }
+
+    async fn count_draws_by_day(
+        &self,
+        since: NaiveDateTime,
+    ) -> Result<Vec<(NaiveDate, i64)>, BotError> {
+        let mut counts: Vec<(NaiveDate, i64)> = Vec::new();
+        for draw in self
+            .draws
+            .borrow()
+            .iter()
+            .filter(|draw| draw.created_at >= since)
+        {
+            let day = draw.created_at.date();
+            match counts.iter_mut().find(|(stored, _)| *stored == day) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((day, 1)),
+            }
+        }
+        counts.sort();
+        Ok(counts)
+    }
+
+    async fn top_authors(&self, limit: i64) -> Result<Vec<(String, i64)>, BotError> {
+        let mut counts: Vec<(i64, String, i64)> = Vec::new();
+        for omikuji in self.omikujis.borrow().iter() {
+            if omikuji.anonymous {
+                continue;
+            }
+            match counts
+                .iter_mut()
+                .find(|(tg_id, _, _)| *tg_id == omikuji.tg_id)
+            {
+                Some((_, _, count)) => *count += 1,
+                None => counts.push((omikuji.tg_id, omikuji.tg_name.clone(), 1)),
+            }
+        }
+        counts.sort_by_key(|(_, _, count)| std::cmp::Reverse(*count));
+        Ok(counts
+            .into_iter()
+            .take(limit as usize)
+            .map(|(_, tg_name, count)| (tg_name, count))
+            .collect())
+    }
+
+    async fn count_referrals(&self, tg_id: i64) -> Result<i64, BotError> {
+        let users = self.users.borrow();
+        Ok(users
+            .values()
+            .filter(|user| user.referred_by == Some(tg_id))
+            .count() as i64)
+    }
+
+    async fn top_referrers(&self, limit: i64) -> Result<Vec<(String, i64)>, BotError> {
+        let users = self.users.borrow();
+        let mut counts: HashMap<i64, i64> = HashMap::new();
+        for referrer in users.values().filter_map(|user| user.referred_by) {
+            *counts.entry(referrer).or_default() += 1;
+        }
+        let mut referrers: Vec<(String, i64)> = counts
+            .into_iter()
+            .filter_map(|(referrer, count)| Some((users.get(&referrer)?.tg_name.clone(), count)))
+            .collect();
+        referrers.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        referrers.truncate(limit as usize);
+        Ok(referrers)
+    }
+
+    async fn record_vote(&self, tg_id: i64, omikuji_id: u32, vote: i8) -> Result<(), BotError> {
+        let mut draws = self.draws.borrow_mut();
+        let latest = draws
+            .iter_mut()
+            .rev()
+            .find(|draw| draw.tg_id == tg_id && draw.omikuji_id == omikuji_id);
+        if let Some(draw) = latest {
+            draw.vote.get_or_insert(vote);
+        }
+        Ok(())
+    }
+
+    async fn tie_draw(&self, tg_id: i64, omikuji_id: u32) -> Result<bool, BotError> {
+        let mut draws = self.draws.borrow_mut();
+        let latest = draws
+            .iter_mut()
+            .rev()
+            .find(|draw| draw.tg_id == tg_id && draw.omikuji_id == omikuji_id);
+        match latest {
+            Some(draw) if draw.tied_at.is_none() => {
+                draw.tied_at = Some(chrono::Local::now().naive_local());
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn count_tied_draws(&self, since: NaiveDateTime) -> Result<i64, BotError> {
+        let draws = self.draws.borrow();
+        Ok(draws
+            .iter()
+            .filter(|draw| draw.tied_at.is_some_and(|tied_at| tied_at >= since))
+            .count() as i64)
+    }
+
+    async fn experiment_results(&self) -> Result<Vec<VariantResult>, BotError> {
+        let draws = self.draws.borrow();
+        let mut results: Vec<VariantResult> = Vec::new();
+        for draw in draws.iter() {
+            let variant = match &draw.variant {
+                Some(variant) => variant,
+                None => continue,
+            };
+            let index = match results.iter().position(|result| &result.variant == variant) {
+                Some(index) => index,
+                None => {
+                    results.push(VariantResult {
+                        variant: variant.clone(),
+                        users: 0,
+                        draws: 0,
+                        upvotes: 0,
+                        downvotes: 0,
+                    });
+                    results.len() - 1
+                }
+            };
+            let result = &mut results[index];
+            result.draws += 1;
+            match draw.vote {
+                Some(vote) if vote > 0 => result.upvotes += 1,
+                Some(vote) if vote < 0 => result.downvotes += 1,
+                _ => {}
+            }
+        }
+        for result in results.iter_mut() {
+            let mut users: Vec<i64> = draws
+                .iter()
+                .filter(|draw| draw.variant.as_ref() == Some(&result.variant))
+                .map(|draw| draw.tg_id)
+                .collect();
+            users.sort_unstable();
+            users.dedup();
+            result.users = users.len() as i64;
+        }
+        results.sort_by(|a, b| a.variant.cmp(&b.variant));
+        Ok(results)
+    }
+
+    async fn author_digest(
+        &self,
+        tg_id: i64,
+        since: NaiveDateTime,
+    ) -> Result<AuthorDigest, BotError> {
+        let strips: Vec<u32> = self
+            .omikujis
+            .borrow()
+            .iter()
+            .filter(|omikuji| omikuji.tg_id == tg_id)
+            .map(|omikuji| omikuji.id)
+            .collect();
+        let mut digest = AuthorDigest::default();
+        for draw in self.draws.borrow().iter() {
+            if !strips.contains(&draw.omikuji_id) || draw.tg_id == tg_id || draw.created_at < since
+            {
+                continue;
+            }
+            digest.draws += 1;
+            match draw.vote {
+                Some(vote) if vote > 0 => digest.upvotes += 1,
+                Some(vote) if vote < 0 => digest.downvotes += 1,
+                _ => {}
+            }
+        }
+        digest.comments = self
+            .comments
+            .borrow()
+            .iter()
+            .filter(|comment| strips.contains(&comment.omikuji_id))
+            .filter(|comment| comment.tg_id != tg_id && comment.parent_id.is_none())
+            .filter(|comment| comment.created_at >= since)
+            .count() as i64;
+        Ok(digest)
+    }
+
+    async fn record_booth_draw(&self, draw: &NewBoothDraw) -> Result<(), BotError> {
+        self.booth_draws
+            .borrow_mut()
+            .push((draw.clone(), chrono::Local::now().naive_local()));
+        let mut omikujis = self.omikujis.borrow_mut();
+        if let Some(omikuji) = omikujis
+            .iter_mut()
+            .find(|omikuji| omikuji.id == draw.omikuji_id)
+        {
+            omikuji.draw_count += 1;
+        }
+        Ok(())
+    }
+
+    async fn count_booth_draws(&self, since: NaiveDateTime) -> Result<i64, BotError> {
+        let booth_draws = self.booth_draws.borrow();
+        Ok(booth_draws
+            .iter()
+            .filter(|(_, created_at)| *created_at >= since)
+            .count() as i64)
+    }
+
+    async fn find_booth_draws_by_user(&self, tg_id: i64) -> Result<Vec<BoothDraw>, BotError> {
+        let booth_draws = self.booth_draws.borrow();
+        Ok(booth_draws
+            .iter()
+            .enumerate()
+            .filter(|(_, (draw, _))| draw.tg_id == tg_id)
+            .map(|(index, (draw, created_at))| BoothDraw {
+                id: index as u32 + 1,
+                chat_id: draw.chat_id,
+                tg_id: draw.tg_id,
+                visitor: draw.visitor.clone(),
+                omikuji_id: draw.omikuji_id,
+                created_at: *created_at,
+                tenant_id: DEFAULT_TENANT.to_string(),
+            })
+            .collect())
+    }
+}
+
+#[async_trait(?Send)]
+impl ModerationRepository for MemoryRepository {
+    async fn find_pending_omikujis(&self) -> Result<Vec<Omikuji>, BotError> {
+        let omikujis = self.omikujis.borrow();
+        Ok(omikujis
+            .iter()
+            .filter(|omikuji| omikuji.quarantined)
+            .filter(|omikuji| omikuji.reviewed_at.is_none())
+            .cloned()
+            .collect())
+    }
+
+    async fn find_quarantined_omikujis(&self) -> Result<Vec<Omikuji>, BotError> {
+        let omikujis = self.omikujis.borrow();
+        Ok(omikujis
+            .iter()
+            .filter(|omikuji| omikuji.quarantined)
+            .cloned()
+            .collect())
+    }
+
+    async fn search_omikujis(&self, query: &str, limit: i64) -> Result<Vec<Omikuji>, BotError> {
+        let omikujis = self.omikujis.borrow();
+        Ok(omikujis
+            .iter()
+            .rev()
+            .filter(|omikuji| omikuji.message.contains(query))
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn review_omikuji(&self, omikuji_id: u32, approve: bool) -> Result<(), BotError> {
+        let mut omikujis = self.omikujis.borrow_mut();
+        if let Some(stored) = omikujis.iter_mut().find(|stored| stored.id == omikuji_id) {
+            if approve {
+                stored.vote_count = 0;
+                stored.quarantined = false;
+            }
+            stored.reviewed_at = Some(chrono::Local::now().naive_local());
+        }
+        drop(omikujis);
+        self.number_slips();
+        Ok(())
+    }
+
+    async fn delete_omikuji(&self, omikuji_id: u32) -> Result<(), BotError> {
+        let mut omikujis = self.omikujis.borrow_mut();
+        omikujis.retain(|omikuji| omikuji.id != omikuji_id);
+        self.favorites
+            .borrow_mut()
+            .retain(|(_, id)| *id != omikuji_id);
+        self.comments
+            .borrow_mut()
+            .retain(|comment| comment.omikuji_id != omikuji_id);
+        self.reactions
+            .borrow_mut()
+            .retain(|(_, id), _| *id != omikuji_id);
+        Ok(())
+    }
+
+    async fn review_omikujis(&self, omikuji_ids: &[u32], approve: bool) -> Result<usize, BotError> {
+        let mut reviewed = 0;
+        for omikuji_id in omikuji_ids {
+            if self.find_omikuji(*omikuji_id).await?.is_some() {
+                self.review_omikuji(*omikuji_id, approve).await?;
+                reviewed += 1;
+            }
+        }
+        Ok(reviewed)
+    }
+
+    async fn delete_omikujis(&self, omikuji_ids: &[u32]) -> Result<usize, BotError> {
+        let mut deleted = 0;
+        for omikuji_id in omikuji_ids {
+            if self.find_omikuji(*omikuji_id).await?.is_some() {
+                self.delete_omikuji(*omikuji_id).await?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+}
+
+#[async_trait(?Send)]
+impl InterpretationRepository for MemoryRepository {
+    async fn find_interpretation(&self, class: &str) -> Result<Option<String>, BotError> {
+        Ok(self.interpretations.borrow().get(class).cloned())
+    }
+
+    async fn set_interpretation(&self, interpretation: &NewInterpretation) -> Result<(), BotError> {
+        let mut interpretations = self.interpretations.borrow_mut();
+        if interpretation.text.is_empty() {
+            interpretations.remove(interpretation.class);
+        } else {
+            interpretations.insert(
+                interpretation.class.to_string(),
+                interpretation.text.to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl AchievementRepository for MemoryRepository {
+    async fn award_badge(&self, achievement: &NewAchievement) -> Result<bool, BotError> {
+        let mut achievements = self.achievements.borrow_mut();
+        let badges = achievements.entry(achievement.tg_id).or_default();
+        if badges.contains(&achievement.badge) {
+            return Ok(false);
+        }
+        badges.push(achievement.badge.clone());
+        Ok(true)
+    }
+
+    async fn find_badges(&self, tg_id: i64) -> Result<Vec<String>, BotError> {
+        Ok(self
+            .achievements
+            .borrow()
+            .get(&tg_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+#[async_trait(?Send)]
+impl FavoriteRepository for MemoryRepository {
+    async fn add_favorite(&self, favorite: &NewFavorite) -> Result<bool, BotError> {
+        let mut favorites = self.favorites.borrow_mut();
+        let entry = (favorite.tg_id, favorite.omikuji_id);
+        if favorites.contains(&entry) {
+            return Ok(false);
+        }
+        favorites.push(entry);
+        Ok(true)
+    }
+
+    async fn find_favorites(
+        &self,
+        tg_id: i64,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<Omikuji>, BotError> {
+        let omikujis = self.omikujis.borrow();
+        Ok(self
+            .favorites
+            .borrow()
+            .iter()
+            .rev()
+            .filter(|(user, _)| *user == tg_id)
+            .filter_map(|(_, id)| omikujis.iter().find(|omikuji| omikuji.id == *id).cloned())
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect())
+    }
+
+    async fn count_favorites(&self, tg_id: i64) -> Result<i64, BotError> {
+        Ok(self
+            .favorites
+            .borrow()
+            .iter()
+            .filter(|(user, _)| *user == tg_id)
+            .count() as i64)
+    }
+}
+
+#[async_trait(?Send)]
+impl ReactionRepository for MemoryRepository {
+    async fn set_reaction(&self, reaction: &NewReaction) -> Result<(), BotError> {
+        self.reactions.borrow_mut().insert(
+            (reaction.tg_id, reaction.omikuji_id),
+            reaction.reaction.clone(),
+        );
+        Ok(())
+    }
+
+    async fn count_reactions(&self, omikuji_id: u32) -> Result<Vec<(String, i64)>, BotError> {
+        let mut counts: Vec<(String, i64)> = Vec::new();
+        for ((_, id), reaction) in self.reactions.borrow().iter() {
+            if *id != omikuji_id {
+                continue;
+            }
+            match counts.iter_mut().find(|(name, _)| name == reaction) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((reaction.clone(), 1)),
+            }
+        }
+        Ok(counts)
+    }
+
+    async fn find_reactions_by_user(&self, tg_id: i64) -> Result<Vec<(u32, String)>, BotError> {
+        let mut reactions: Vec<(u32, String)> = self
+            .reactions
+            .borrow()
+            .iter()
+            .filter(|((user, _), _)| *user == tg_id)
+            .map(|((_, omikuji_id), reaction)| (*omikuji_id, reaction.clone()))
+            .collect();
+        reactions.sort();
+        Ok(reactions)
+    }
+}
+
+#[async_trait(?Send)]
+impl PollRepository for MemoryRepository {
+    async fn insert_weekly_poll(&self, poll: &NewWeeklyPoll) -> Result<(), BotError> {
+        let mut weekly_polls = self.weekly_polls.borrow_mut();
+        let id = weekly_polls.len() as u32 + 1;
+        weekly_polls.push(WeeklyPoll {
+            id,
+            chat: poll.chat.to_string(),
+            message_id: poll.message_id,
+            candidates: poll.candidates.to_string(),
+            winner_id: None,
+            created_at: chrono::Local::now().naive_local(),
+            closed_at: None,
+            tenant_id: DEFAULT_TENANT.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn find_open_weekly_poll(&self) -> Result<Option<WeeklyPoll>, BotError> {
+        let weekly_polls = self.weekly_polls.borrow();
+        Ok(weekly_polls
+            .last()
+            .filter(|poll| poll.closed_at.is_none())
+            .cloned())
+    }
+
+    async fn close_weekly_poll(
+        &self,
+        poll_id: u32,
+        winner_id: Option<u32>,
+    ) -> Result<(), BotError> {
+        let mut weekly_polls = self.weekly_polls.borrow_mut();
+        if let Some(poll) = weekly_polls.iter_mut().find(|poll| poll.id == poll_id) {
+            poll.winner_id = winner_id;
+            poll.closed_at = Some(chrono::Local::now().naive_local());
+        }
+        Ok(())
+    }
+
+    async fn find_weekly_winners(&self) -> Result<Vec<u32>, BotError> {
+        let weekly_polls = self.weekly_polls.borrow();
+        Ok(weekly_polls
+            .iter()
+            .rev()
+            .filter_map(|poll| poll.winner_id)
+            .collect())
+    }
+
+    async fn migrate_poll_chat(&self, from: i64, to: i64) -> Result<(), BotError> {
+        let mut weekly_polls = self.weekly_polls.borrow_mut();
+        for poll in weekly_polls.iter_mut() {
+            if poll.chat == from.to_string() {
+                poll.chat = to.to_string();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl UsageRepository for MemoryRepository {
+    async fn record_usage(&self, event: &NewUsageEvent) -> Result<(), BotError> {
+        self.usage_events.borrow_mut().push(UsageEvent {
+            command: event.command.to_string(),
+            tg_id: event.tg_id,
+            latency_ms: event.latency_ms,
+            success: event.success,
+            created_at: chrono::Local::now().naive_local(),
+        });
+        Ok(())
+    }
+
+    async fn aggregate_usage(&self, before: NaiveDateTime) -> Result<usize, BotError> {
+        let (rolled_up, kept): (Vec<UsageEvent>, Vec<UsageEvent>) = self
+            .usage_events
+            .take()
+            .into_iter()
+            .partition(|event| event.created_at < before);
+        self.usage_events.replace(kept);
+        let totals = self.usage_daily.take();
+        self.usage_daily
+            .replace(merge_usage(totals, UsageDay::tally(&rolled_up)));
+        Ok(rolled_up.len())
+    }
+
+    async fn usage_by_day(&self, since: NaiveDate) -> Result<Vec<UsageDay>, BotError> {
+        let totals = self
+            .usage_daily
+            .borrow()
+            .iter()
+            .filter(|total| total.day >= since)
+            .cloned()
+            .collect();
+        let events = self.usage_events.borrow();
+        let events = events
+            .iter()
+            .filter(|event| event.created_at.date() >= since);
+        Ok(merge_usage(totals, UsageDay::tally(events)))
+    }
+}
+
+#[async_trait(?Send)]
+impl LeaseRepository for MemoryRepository {
+    async fn acquire_lease(
+        &self,
+        name: &str,
+        holder: &str,
+        duration: Duration,
+    ) -> Result<bool, BotError> {
+        let now = chrono::Local::now().naive_local();
+        let expires_at = now
+            + chrono::Duration::from_std(duration)
+                .map_err(|e| BotError::Validation(format!("Lease of {}: {}", name, e)))?;
+        let mut leases = self.leases.borrow_mut();
+        let free = match leases.get(name) {
+            Some((current, expires_at)) => current == holder || *expires_at < now,
+            None => true,
+        };
+        if free {
+            leases.insert(name.to_string(), (holder.to_string(), expires_at));
+        }
+        Ok(free)
+    }
+
+    async fn find_job_runs(&self, job: &str) -> Result<Vec<JobRun>, BotError> {
+        Ok(self
+            .job_runs
+            .borrow()
+            .iter()
+            .filter(|run| run.job == job)
+            .cloned()
+            .collect())
+    }
+
+    async fn record_job_run(&self, run: &JobRun) -> Result<(), BotError> {
+        let mut job_runs = self.job_runs.borrow_mut();
+        job_runs.retain(|stored| stored.job != run.job || stored.tg_id != run.tg_id);
+        job_runs.push(run.clone());
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl ImportRepository for MemoryRepository {
+    async fn import_omikujis(
+        &self,
+        batch: &NewImportBatch,
+        omikujis: &[NewOmikuji],
+    ) -> Result<u32, BotError> {
+        let batch_id = {
+            let mut import_batches = self.import_batches.borrow_mut();
+            import_batches.push(batch.source.to_string());
+            import_batches.len() as u32
+        };
+        for omikuji in omikujis {
+            self.insert_omikuji(omikuji).await?;
+            if let Some(last) = self.omikujis.borrow_mut().last_mut() {
+                last.import_batch = Some(batch_id);
+            }
+        }
+        Ok(batch_id)
+    }
+}
+
+#[async_trait(?Send)]
+impl DeferredRepository for MemoryRepository {
+    async fn defer_message(&self, message: &NewDeferredMessage) -> Result<(), BotError> {
+        let mut deferred_messages = self.deferred_messages.borrow_mut();
+        let id = deferred_messages.last().map_or(1, |last| last.id + 1);
+        deferred_messages.push(DeferredMessage {
+            id,
+            tg_id: message.tg_id,
+            message: message.message.to_string(),
+            created_at: chrono::Local::now().naive_local(),
+            tenant_id: DEFAULT_TENANT.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn find_deferred_messages(&self) -> Result<Vec<DeferredMessage>, BotError> {
+        Ok(self.deferred_messages.borrow().clone())
+    }
+
+    async fn delete_deferred_message(&self, id: u32) -> Result<(), BotError> {
+        self.deferred_messages
+            .borrow_mut()
+            .retain(|message| message.id != id);
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl TrashRepository for MemoryRepository {
+    async fn insert_trash(&self, item: &NewTrashItem) -> Result<u32, BotError> {
+        let mut trash = self.trash.borrow_mut();
+        let id = trash.iter().map(|item| item.id).max().unwrap_or(0) + 1;
+        trash.push(TrashItem {
+            id,
+            tg_id: item.tg_id,
+            kind: item.kind.clone(),
+            content: item.content.to_string(),
+            deleted_at: chrono::Local::now().naive_local(),
+            tenant_id: DEFAULT_TENANT.to_string(),
+        });
+        Ok(id)
+    }
+
+    async fn find_trash(
+        &self,
+        tg_id: i64,
+        since: NaiveDateTime,
+    ) -> Result<Vec<TrashItem>, BotError> {
+        let mut items: Vec<TrashItem> = self
+            .trash
+            .borrow()
+            .iter()
+            .filter(|item| item.tg_id == tg_id && item.deleted_at >= since)
+            .cloned()
+            .collect();
+        items.sort_by_key(|item| Reverse((item.deleted_at, item.id)));
+        Ok(items)
+    }
+
+    async fn take_trash(&self, tg_id: i64, trash_id: u32) -> Result<Option<TrashItem>, BotError> {
+        let mut trash = self.trash.borrow_mut();
+        Ok(trash
+            .iter()
+            .position(|item| item.id == trash_id && item.tg_id == tg_id)
+            .map(|index| trash.remove(index)))
+    }
+
+    async fn purge_trash(&self, before: NaiveDateTime) -> Result<usize, BotError> {
+        let mut trash = self.trash.borrow_mut();
+        let count = trash.len();
+        trash.retain(|item| item.deleted_at >= before);
+        Ok(count - trash.len())
+    }
+}
+
+#[async_trait(?Send)]
+impl DraftStore for MemoryRepository {
+    async fn load_draft(&self, tg_id: i64) -> Result<Option<OmikujiMessage>, BotError> {
+        self.drafts
+            .borrow()
+            .get(&tg_id)
+            .map(|(_, text)| crate::drafts::decode(text.as_str()))
+            .transpose()
+    }
+
+    async fn save_draft(&self, tg_id: i64, draft: &OmikujiMessage) -> Result<(), BotError> {
+        let text = crate::drafts::encode(draft)?;
+        let revision = chrono::Local::now().naive_local();
+        self.drafts.borrow_mut().insert(tg_id, (revision, text));
+        Ok(())
+    }
+
+    async fn delete_draft(&self, tg_id: i64) -> Result<(), BotError> {
+        self.drafts.borrow_mut().remove(&tg_id);
+        Ok(())
+    }
+
+    async fn load_drafts(&self) -> Result<Vec<(i64, NaiveDateTime, OmikujiMessage)>, BotError> {
+        self.drafts
+            .borrow()
+            .iter()
+            .map(|(tg_id, (revision, text))| {
+                Ok((*tg_id, *revision, crate::drafts::decode(text.as_str())?))
+            })
+            .collect()
+    }
+
+    async fn save_draft_if(
+        &self,
+        tg_id: i64,
+        draft: &OmikujiMessage,
+        revision: NaiveDateTime,
+    ) -> Result<bool, BotError> {
+        let unchanged = self
+            .drafts
+            .borrow()
+            .get(&tg_id)
+            .is_some_and(|(stored, _)| *stored == revision);
+        if unchanged {
+            self.save_draft(tg_id, draft).await?;
+        }
+        Ok(unchanged)
+    }
+}
+
+#[async_trait(?Send)]
+impl CommentRepository for MemoryRepository {
+    async fn insert_comment(&self, comment: &NewComment) -> Result<u32, BotError> {
+        let mut comments = self.comments.borrow_mut();
+        let id = comments.last().map_or(1, |last| last.id + 1);
+        comments.push(Comment {
+            id,
+            omikuji_id: comment.omikuji_id,
+            tg_id: comment.tg_id,
+            text: comment.text.to_string(),
+            created_at: chrono::Local::now().naive_local(),
+            tenant_id: DEFAULT_TENANT.to_string(),
+            parent_id: comment.parent_id,
+        });
+        Ok(id)
+    }
+
+    async fn find_comment(&self, comment_id: u32) -> Result<Option<Comment>, BotError> {
+        Ok(self
+            .comments
+            .borrow()
+            .iter()
+            .find(|comment| comment.id == comment_id)
+            .cloned())
+    }
+
+    async fn find_comments_for_author(
+        &self,
+        tg_id: i64,
+        limit: i64,
+    ) -> Result<Vec<Comment>, BotError> {
+        let omikujis = self.omikujis.borrow();
+        Ok(self
+            .comments
+            .borrow()
+            .iter()
+            .rev()
+            .filter(|comment| {
+                omikujis
+                    .iter()
+                    .any(|omikuji| omikuji.id == comment.omikuji_id && omikuji.tg_id == tg_id)
+            })
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn find_recent_comments(&self, limit: i64) -> Result<Vec<Comment>, BotError> {
+        Ok(self
+            .comments
+            .borrow()
+            .iter()
+            .rev()
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn find_comments_by_user(&self, tg_id: i64) -> Result<Vec<Comment>, BotError> {
+        Ok(self
+            .comments
+            .borrow()
+            .iter()
+            .filter(|comment| comment.tg_id == tg_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn delete_comment(&self, comment_id: u32) -> Result<bool, BotError> {
+        let mut comments = self.comments.borrow_mut();
+        let before = comments.len();
+        comments.retain(|comment| comment.id != comment_id);
+        Ok(comments.len() < before)
+    }
+}
+
+#[async_trait(?Send)]
+impl AuditRepository for MemoryRepository {
+    async fn record_audit(&self, entry: &NewAuditEntry) -> Result<(), BotError> {
+        let mut audit_log = self.audit_log.borrow_mut();
+        let id = audit_log.len() as u32 + 1;
+        audit_log.push(AuditEntry {
+            id,
+            actor: entry.actor.to_string(),
+            action: entry.action.clone(),
+            target: entry.target.clone(),
+            created_at: chrono::Local::now().naive_local(),
+            tenant_id: DEFAULT_TENANT.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn find_audit_entries(
+        &self,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>, BotError> {
+        let audit_log = self.audit_log.borrow();
+        Ok(audit_log
+            .iter()
+            .rev()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+}
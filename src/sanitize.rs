@@ -0,0 +1,40 @@
+use unicode_normalization::UnicodeNormalization;
+
+//
+// Normalization of text typed by users, applied before it is stored
+//
+
+// Invisible characters which are only ever used to sneak around filters or break layout
+// The zero-width joiner (U+200D) is kept, since emoji sequences such as 👨‍👩‍👧 depend on it
+const ZERO_WIDTH: [char; 4] = ['\u{200B}', '\u{200C}', '\u{2060}', '\u{FEFF}'];
+
+// Return the cleaned-up text, or None if nothing is left
+// - Unicode is normalized to NFC, so that the same text is always stored the same way
+// - Zero-width and control characters are removed, except for line breaks
+// - Runs of spaces are collapsed into one, lines are trimmed and blank lines are collapsed
+pub fn sanitize(text: &str) -> Option<String> {
+    // Characters are removed before normalizing, as they may separate ones to be combined
+    let text: String = text
+        .chars()
+        .filter(|c| !ZERO_WIDTH.contains(c))
+        .map(|c| if c == '\t' { ' ' } else { c })
+        .filter(|c| *c == '\n' || !c.is_control())
+        .nfc()
+        .collect();
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let line = line.split_whitespace().collect::<Vec<&str>>().join(" ");
+        // Keep at most one blank line between paragraphs
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|last| last.is_empty()) {
+        lines.pop();
+    }
+    if lines.is_empty() {
+        return None;
+    }
+    Some(lines.join("\n"))
+}
@@ -1,3 +1,127 @@
+table! {
+    achievements (tenant_id, tg_id, badge) {
+        tenant_id -> Varchar,
+        tg_id -> Bigint,
+        badge -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    audit_log (id) {
+        id -> Unsigned<Integer>,
+        actor -> Varchar,
+        action -> Varchar,
+        target -> Varchar,
+        created_at -> Timestamp,
+        tenant_id -> Varchar,
+    }
+}
+
+table! {
+    booth_draws (id) {
+        id -> Unsigned<Integer>,
+        chat_id -> Bigint,
+        tg_id -> Bigint,
+        visitor -> Varchar,
+        omikuji_id -> Unsigned<Integer>,
+        created_at -> Timestamp,
+        tenant_id -> Varchar,
+    }
+}
+
+table! {
+    comments (id) {
+        id -> Unsigned<Integer>,
+        omikuji_id -> Unsigned<Integer>,
+        tg_id -> Bigint,
+        text -> Varchar,
+        created_at -> Timestamp,
+        tenant_id -> Varchar,
+        parent_id -> Nullable<Unsigned<Integer>>,
+    }
+}
+
+table! {
+    deferred_messages (id) {
+        id -> Unsigned<Integer>,
+        tg_id -> Bigint,
+        message -> Text,
+        created_at -> Timestamp,
+        tenant_id -> Varchar,
+    }
+}
+
+table! {
+    drafts (tenant_id, tg_id) {
+        tenant_id -> Varchar,
+        tg_id -> Bigint,
+        draft -> Text,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    draws (id) {
+        id -> Unsigned<Integer>,
+        tg_id -> Bigint,
+        omikuji_id -> Unsigned<Integer>,
+        created_at -> Timestamp,
+        tenant_id -> Varchar,
+        variant -> Nullable<Varchar>,
+        vote -> Nullable<Tinyint>,
+        tied_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    favorites (tenant_id, tg_id, omikuji_id) {
+        tenant_id -> Varchar,
+        tg_id -> Bigint,
+        omikuji_id -> Unsigned<Integer>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    import_batches (id) {
+        id -> Unsigned<Integer>,
+        tg_id -> Bigint,
+        source -> Varchar,
+        created_at -> Timestamp,
+        tenant_id -> Varchar,
+    }
+}
+
+table! {
+    interpretations (tenant_id, class) {
+        tenant_id -> Varchar,
+        class -> Varchar,
+        text -> Text,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    job_runs (tenant_id, job, tg_id) {
+        tenant_id -> Varchar,
+        job -> Varchar,
+        tg_id -> Bigint,
+        ran_on -> Date,
+        message_id -> Nullable<Integer>,
+        count -> Nullable<Bigint>,
+    }
+}
+
+table! {
+    leases (tenant_id, name) {
+        tenant_id -> Varchar,
+        name -> Varchar,
+        holder -> Varchar,
+        expires_at -> Timestamp,
+    }
+}
+
 table! {
     omikujis (id) {
         id -> Unsigned<Integer>,
@@ -7,5 +131,131 @@ table! {
         tg_name -> Varchar,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        reviewed_at -> Nullable<Timestamp>,
+        tenant_id -> Varchar,
+        community_id -> Nullable<Bigint>,
+        anonymous -> Bool,
+        expires_at -> Nullable<Timestamp>,
+        archived -> Bool,
+        quarantined -> Bool,
+        draw_count -> Unsigned<Integer>,
+        edited_at -> Nullable<Timestamp>,
+        slip_number -> Nullable<Unsigned<Integer>>,
+        import_batch -> Nullable<Unsigned<Integer>>,
+    }
+}
+
+table! {
+    reactions (tenant_id, tg_id, omikuji_id) {
+        tenant_id -> Varchar,
+        tg_id -> Bigint,
+        omikuji_id -> Unsigned<Integer>,
+        reaction -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    user_settings (tenant_id, tg_id) {
+        tg_id -> Bigint,
+        notifications -> Bool,
+        language -> Varchar,
+        daily_subscription -> Bool,
+        reply_keyboard -> Bool,
+        voice -> Bool,
+        pool -> Varchar,
+        credit -> Bool,
+        weekly_digest -> Bool,
+        quiet_start -> Nullable<Time>,
+        quiet_end -> Nullable<Time>,
+        time_zone -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        tenant_id -> Varchar,
+    }
+}
+
+table! {
+    users (tenant_id, tg_id) {
+        tg_id -> Bigint,
+        tg_name -> Varchar,
+        tg_username -> Nullable<Varchar>,
+        language_code -> Nullable<Varchar>,
+        banned -> Bool,
+        inactive -> Bool,
+        referred_by -> Nullable<Bigint>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        tenant_id -> Varchar,
+    }
+}
+
+table! {
+    trash (id) {
+        id -> Unsigned<Integer>,
+        tg_id -> Bigint,
+        kind -> Varchar,
+        content -> Text,
+        deleted_at -> Timestamp,
+        tenant_id -> Varchar,
+    }
+}
+
+table! {
+    usage_daily (tenant_id, day, command) {
+        tenant_id -> Varchar,
+        day -> Date,
+        command -> Varchar,
+        invocations -> Bigint,
+        failures -> Bigint,
+        total_latency_ms -> Bigint,
+    }
+}
+
+table! {
+    usage_events (id) {
+        id -> Unsigned<Bigint>,
+        command -> Varchar,
+        tg_id -> Bigint,
+        latency_ms -> Unsigned<Integer>,
+        success -> Bool,
+        created_at -> Timestamp,
+        tenant_id -> Varchar,
+    }
+}
+
+table! {
+    weekly_polls (id) {
+        id -> Unsigned<Integer>,
+        chat -> Varchar,
+        message_id -> Integer,
+        candidates -> Varchar,
+        winner_id -> Nullable<Unsigned<Integer>>,
+        created_at -> Timestamp,
+        closed_at -> Nullable<Timestamp>,
+        tenant_id -> Varchar,
     }
 }
+
+allow_tables_to_appear_in_same_query!(
+    achievements,
+    audit_log,
+    booth_draws,
+    comments,
+    deferred_messages,
+    drafts,
+    draws,
+    favorites,
+    import_batches,
+    interpretations,
+    job_runs,
+    leases,
+    omikujis,
+    reactions,
+    trash,
+    usage_daily,
+    usage_events,
+    user_settings,
+    users,
+    weekly_polls,
+);
@@ -0,0 +1,141 @@
+use crate::models::{Omikuji, OmikujiClass};
+use chrono::{Duration, NaiveDateTime};
+use rand::Rng;
+use std::cmp::Ordering;
+use strum::IntoEnumIterator;
+
+//
+// Scores of strips, used to rank them on leaderboards and to weight draws
+//
+
+// Factor applied to a vote of the given age, halved every `half_life`
+pub fn decay(age: Duration, half_life: Duration) -> f64 {
+    if half_life <= Duration::zero() {
+        return 1.0;
+    }
+    let age = age.max(Duration::zero());
+    0.5f64.powf(age.num_seconds() as f64 / half_life.num_seconds() as f64)
+}
+
+// Votes are not timestamped, so they are decayed by the age of the strip
+// Without a half-life this is just the vote count
+pub fn score(omikuji: &Omikuji, now: NaiveDateTime, half_life: Option<Duration>) -> f64 {
+    decayed_votes(omikuji.vote_count, omikuji.created_at, now, half_life)
+}
+
+pub fn decayed_votes(
+    vote_count: i32,
+    created_at: NaiveDateTime,
+    now: NaiveDateTime,
+    half_life: Option<Duration>,
+) -> f64 {
+    let votes = vote_count as f64;
+    match half_life {
+        Some(half_life) => votes * decay(now - created_at, half_life),
+        None => votes,
+    }
+}
+
+//
+// Weighted draws
+//
+
+// Strips without votes still get drawn, downvoted ones only rarely
+const MIN_WEIGHT: f64 = 0.1;
+
+// Weight of a strip with the given score in weighted draws
+pub fn weight(score: f64) -> f64 {
+    (1.0 + score).max(MIN_WEIGHT)
+}
+
+// Index of a random element, each with a probability proportional to its weight
+pub fn weighted_index<R: Rng>(weights: &[f64], rng: &mut R) -> Option<usize> {
+    let total: f64 = weights.iter().sum();
+    if weights.is_empty() || total <= 0.0 {
+        return None;
+    }
+    let mut x = rng.gen_range(0.0, total);
+    for (index, weight) in weights.iter().enumerate() {
+        if x < *weight {
+            return Some(index);
+        }
+        x -= weight;
+    }
+    // Rounding errors can leave a tiny remainder
+    Some(weights.len() - 1)
+}
+
+// Index of a random strip, given the class of each (as serialized)
+// A class is picked by its target share among the classes there are strips of, then a strip of
+// that class, so that the odds do not depend on how many strips were written per class
+// Strips of other (or unknown) classes are only drawn if there are no others
+pub fn balanced_index<R: Rng>(classes: &[Option<String>], rng: &mut R) -> Option<usize> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut weights: Vec<f64> = Vec::new();
+    for class in OmikujiClass::iter() {
+        let share = match class.target_share() {
+            Some(share) => share,
+            None => continue,
+        };
+        let name = format!("{:?}", class);
+        let members: Vec<usize> = (0..classes.len())
+            .filter(|index| classes[*index].as_deref() == Some(name.as_str()))
+            .collect();
+        if !members.is_empty() {
+            groups.push(members);
+            weights.push(share as f64);
+        }
+    }
+    match weighted_index(&weights, rng) {
+        Some(group) => Some(groups[group][rng.gen_range(0, groups[group].len())]),
+        None if classes.is_empty() => None,
+        None => Some(rng.gen_range(0, classes.len())),
+    }
+}
+
+// Strip with the highest score, the one with the lowest ID on ties
+pub fn top<'a, I>(
+    omikujis: I,
+    now: NaiveDateTime,
+    half_life: Option<Duration>,
+) -> Option<&'a Omikuji>
+where
+    I: IntoIterator<Item = &'a Omikuji>,
+{
+    omikujis.into_iter().fold(None, |best, omikuji| match best {
+        Some(best)
+            if score(best, now, half_life) > score(omikuji, now, half_life)
+                || (score(best, now, half_life) == score(omikuji, now, half_life)
+                    && best.id < omikuji.id) =>
+        {
+            Some(best)
+        }
+        _ => Some(omikuji),
+    })
+}
+
+// Strips with the highest scores, best first, those with lower IDs first on ties
+pub fn top_n<'a, I>(
+    omikujis: I,
+    now: NaiveDateTime,
+    half_life: Option<Duration>,
+    n: usize,
+) -> Vec<&'a Omikuji>
+where
+    I: IntoIterator<Item = &'a Omikuji>,
+{
+    let mut ranked: Vec<(f64, &Omikuji)> = omikujis
+        .into_iter()
+        .map(|omikuji| (score(omikuji, now, half_life), omikuji))
+        .collect();
+    ranked.sort_by(|a, b| {
+        b.0.partial_cmp(&a.0)
+            .unwrap_or(Ordering::Equal)
+            .then(a.1.id.cmp(&b.1.id))
+    });
+    ranked
+        .into_iter()
+        .take(n)
+        .map(|(_, omikuji)| omikuji)
+        .collect()
+}
@@ -0,0 +1,51 @@
+use crate::config::get_callback_secret;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+// Separates the data of a button from its tag, never used in callback commands or payloads
+pub const TAG_SEPARATOR: char = '~';
+// Bytes of the HMAC kept, as callback data is limited to 64 bytes by Telegram
+const TAG_BYTES: usize = 6;
+
+// Button data with a tag appended, e.g. "vote/3/up~Xb2k9Qa1"
+pub fn sign_with(secret: &[u8], data: &str) -> String {
+    format!("{}{}{}", data, TAG_SEPARATOR, tag(secret, data))
+}
+
+// The data of a button without its tag, or None if the tag is missing or does not match
+pub fn verify_with<'a>(secret: &[u8], signed: &'a str) -> Option<&'a str> {
+    let (data, received) = signed.rsplit_once(TAG_SEPARATOR)?;
+    let received = URL_SAFE_NO_PAD
+        .decode(received)
+        .ok()
+        .filter(|received| received.len() == TAG_BYTES)?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(data.as_bytes());
+    // Compared in constant time
+    mac.verify_truncated_left(&received).ok()?;
+    Some(data)
+}
+
+// Signed with CALLBACK_SECRET, if configured
+pub(crate) fn sign(data: String) -> String {
+    match get_callback_secret() {
+        Some(secret) => sign_with(secret.as_bytes(), data.as_str()),
+        None => data,
+    }
+}
+
+// Checked against CALLBACK_SECRET, if configured
+pub(crate) fn verify(signed: &str) -> Option<&str> {
+    match get_callback_secret() {
+        Some(secret) => verify_with(secret.as_bytes(), signed),
+        None => Some(signed),
+    }
+}
+
+fn tag(secret: &[u8], data: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(data.as_bytes());
+    URL_SAFE_NO_PAD.encode(&mac.finalize().into_bytes()[..TAG_BYTES])
+}
@@ -0,0 +1,93 @@
+//
+// Detection of submissions which look like advertisements
+//
+
+// What a suspicious strip contains
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Spam {
+    Link,
+    Mention,
+    PhoneNumber,
+}
+
+// Numbers with this many digits (allowing separators) are taken for phone numbers
+const PHONE_DIGITS: usize = 8;
+
+// Look for links, @mentions and phone numbers in a text
+// Links to `allowed_domains` (or their subdomains) are not reported
+pub fn find_spam(text: &str, allowed_domains: &[String]) -> Option<Spam> {
+    for word in text.split_whitespace() {
+        // Markdown links are written as [label](url)
+        for token in word.split(['(', ')', '[', ']']) {
+            let token = token.trim_matches(|c: char| !c.is_alphanumeric() && c != '@' && c != '/');
+            if is_mention(token) {
+                return Some(Spam::Mention);
+            }
+            if let Some(host) = link_host(token) {
+                if !is_allowed(host.as_str(), allowed_domains) {
+                    return Some(Spam::Link);
+                }
+            }
+        }
+    }
+    if has_phone_number(text) {
+        return Some(Spam::PhoneNumber);
+    }
+    None
+}
+
+// Telegram usernames have at least 5 characters
+fn is_mention(token: &str) -> bool {
+    match token.strip_prefix('@') {
+        Some(name) => {
+            name.chars().count() >= 5 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+// Host of a token looking like a link, e.g. "https://example.com/a" or "t.me/channel"
+fn link_host(token: &str) -> Option<String> {
+    let token = token.to_lowercase();
+    let rest = match token.split_once("://") {
+        Some((_, rest)) => rest,
+        None => token.as_str(),
+    };
+    let host = rest.split(['/', '?', '#']).next()?;
+    let host = host.rsplit('@').next()?.split(':').next()?;
+    let labels: Vec<&str> = host.split('.').collect();
+    let tld = labels.last()?;
+    let is_domain = labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        })
+        && (2..=6).contains(&tld.len())
+        && tld.chars().all(|c| c.is_ascii_alphabetic());
+    if is_domain || (token.contains("://") && !host.is_empty()) {
+        return Some(host.to_string());
+    }
+    None
+}
+
+fn is_allowed(host: &str, allowed_domains: &[String]) -> bool {
+    allowed_domains.iter().any(|domain| {
+        let domain = domain.to_lowercase();
+        host == domain || host.ends_with(format!(".{}", domain).as_str())
+    })
+}
+
+// Runs of digits, possibly broken up by spaces, dashes, dots or parentheses
+fn has_phone_number(text: &str) -> bool {
+    let mut digits = 0;
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            digits += 1;
+            if digits >= PHONE_DIGITS {
+                return true;
+            }
+        } else if !matches!(c, ' ' | '-' | '.' | '(' | ')' | '+') {
+            digits = 0;
+        }
+    }
+    false
+}
@@ -0,0 +1,135 @@
+use crate::bot_api::BotApi;
+use crate::error::BotError;
+use crate::frontends::Sender;
+use crate::models::OmikujiMessage;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use teloxide_core::payloads::setters::*;
+use teloxide_core::payloads::SendMessage;
+use teloxide_core::types::{ChatId, ParseMode, User};
+
+//
+// Extensions / Syntax Sugars
+//
+
+#[async_trait(?Send)]
+pub(crate) trait ApiExtension {
+    async fn send_text(&self, to: &Sender, message: &str) -> Result<(), BotError>;
+}
+
+#[async_trait(?Send)]
+impl<T: BotApi + ?Sized> ApiExtension for T {
+    async fn send_text(&self, to: &Sender, message: &str) -> Result<(), BotError> {
+        for chunk in split_message(message) {
+            self.send_message(SendMessage::new(ChatId(to.id), chunk).parse_mode(MARKDOWN))
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) trait HashMapExtension {
+    fn get_user_data(&mut self, user: &Sender) -> Option<&mut OmikujiMessage>;
+    fn new_user_data(&mut self, user: &Sender, community_id: Option<i64>);
+    fn delete_user_data(&mut self, user: &Sender);
+}
+
+impl HashMapExtension for HashMap<i64, OmikujiMessage> {
+    // Drafts are considered touched whenever they are looked up for the user
+    fn get_user_data(&mut self, user: &Sender) -> Option<&mut OmikujiMessage> {
+        let omikuji_message = self.get_mut(&user.id)?;
+        omikuji_message.touched_at = Some(chrono::Local::now().naive_local());
+        omikuji_message.reminded = false;
+        Some(omikuji_message)
+    }
+
+    fn new_user_data(&mut self, user: &Sender, community_id: Option<i64>) {
+        let omikuji_message = OmikujiMessage {
+            photo: None,
+            class: None,
+            description: None,
+            poem: None,
+            sections: Vec::new(),
+            community_id,
+            touched_at: Some(chrono::Local::now().naive_local()),
+            reminded: false,
+            anonymous: false,
+            expires_in: None,
+            editing: None,
+        };
+        self.insert(user.id, omikuji_message);
+    }
+
+    fn delete_user_data(&mut self, user: &Sender) {
+        self.remove(&user.id);
+    }
+}
+
+// Omikuji strips (and the bot's own messages) are written in the legacy Markdown syntax
+#[allow(deprecated)]
+pub(crate) const MARKDOWN: ParseMode = ParseMode::Markdown;
+
+// Escape text from users (names, comments) before it is put into a Markdown message,
+// as an unbalanced `_` or `*` makes Telegram reject the whole message
+pub(crate) fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '_' | '*' | '`' | '[') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Telegram user IDs fit into i64, which is how they are stored in the database
+pub(crate) fn user_id(user: &User) -> i64 {
+    user.id.0 as i64
+}
+
+pub(crate) fn full_name(user: &User) -> String {
+    let mut user_name = user.first_name.clone();
+    if let Some(last_name) = &user.last_name {
+        user_name.push(' ');
+        user_name.push_str(last_name.as_str());
+    }
+    user_name
+}
+
+// Telegram rejects messages longer than this, counted in UTF-16 code units
+pub const MESSAGE_LIMIT: usize = 4096;
+
+// Split a text into messages that fit into MESSAGE_LIMIT
+// Texts are split at line breaks where possible, so that Markdown entities on a line stay intact
+pub fn split_message(text: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    let mut chunk_len = 0;
+    for line in text.split_inclusive('\n') {
+        let line_len = line.encode_utf16().count();
+        if chunk_len + line_len > MESSAGE_LIMIT && !chunk.is_empty() {
+            chunks.push(chunk);
+            chunk = String::new();
+            chunk_len = 0;
+        }
+        if line_len <= MESSAGE_LIMIT {
+            chunk.push_str(line);
+            chunk_len += line_len;
+            continue;
+        }
+        // This line alone is too long, so it has to be split in the middle
+        for c in line.chars() {
+            if chunk_len + c.len_utf16() > MESSAGE_LIMIT {
+                chunks.push(chunk);
+                chunk = String::new();
+                chunk_len = 0;
+            }
+            chunk.push(c);
+            chunk_len += c.len_utf16();
+        }
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
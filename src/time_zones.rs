@@ -0,0 +1,51 @@
+use crate::geo::Point;
+use chrono_tz::Tz;
+use std::str::FromStr;
+
+// Principal locations of the time zones, from the tz database (public domain)
+const ZONES: &str = include_str!("../data/zone1970.tab");
+
+// The time zone whose principal location is the closest to a point, e.g. of a location message
+// This is a guess for points near the border between two time zones, which can be corrected
+// with /timezone
+pub fn nearest_time_zone(point: Point) -> Option<Tz> {
+    ZONES
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut columns = line.split('\t').skip(1);
+            let location = parse_coordinates(columns.next()?)?;
+            let time_zone = Tz::from_str(columns.next()?).ok()?;
+            Some((point.distance(location), time_zone))
+        })
+        .min_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, time_zone)| time_zone)
+}
+
+// Coordinates in the ISO 6709 format of the tz database, i.e. ±DDMM±DDDMM or ±DDMMSS±DDDMMSS
+fn parse_coordinates(text: &str) -> Option<Point> {
+    let split = text[1..].find(['+', '-'])? + 1;
+    let (latitude, longitude) = text.split_at(split);
+    Some(Point::new(
+        parse_degrees(latitude, 2)?,
+        parse_degrees(longitude, 3)?,
+    ))
+}
+
+// A signed angle of `digits` digits of degrees, followed by minutes and optionally seconds
+fn parse_degrees(text: &str, digits: usize) -> Option<f64> {
+    let sign = match text.get(..1)? {
+        "+" => 1.0,
+        "-" => -1.0,
+        _ => return None,
+    };
+    let text = &text[1..];
+    let degrees: f64 = text.get(..digits)?.parse().ok()?;
+    let minutes: f64 = text.get(digits..digits + 2)?.parse().ok()?;
+    let seconds: f64 = match text.get(digits + 2..) {
+        Some("") => 0.0,
+        Some(seconds) => seconds.parse().ok()?,
+        None => return None,
+    };
+    Some(sign * (degrees + minutes / 60.0 + seconds / 3600.0))
+}
@@ -0,0 +1,27 @@
+use crate::config::{get_tts_token, get_tts_url};
+use crate::error::BotError;
+use crate::models::Language;
+use serde_json::json;
+
+// Synthesize a text into an OGG (Opus) voice note with the service configured in TTS_URL
+// The service receives {"text": ..., "language": ...} and responds with the audio file
+pub async fn synthesize(text: &str, language: Language) -> Result<Vec<u8>, BotError> {
+    let url = get_tts_url().ok_or_else(|| BotError::State(String::from("TTS_URL is not set")))?;
+    let mut request = reqwest::Client::new().post(url.as_str()).json(&json!({
+        "text": text,
+        "language": language.code(),
+    }));
+    if let Some(token) = get_tts_token() {
+        request = request.bearer_auth(token);
+    }
+    let response = request.send().await?.error_for_status()?;
+    Ok(response.bytes().await?.to_vec())
+}
+
+// Markdown marks would be read out literally, so they are dropped
+pub fn speech_text(markdown: &str) -> String {
+    markdown
+        .chars()
+        .filter(|c| !matches!(c, '*' | '_' | '`' | '[' | ']'))
+        .collect()
+}
@@ -0,0 +1,33 @@
+use anyhow::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use teloxide_core::types::Update;
+
+//
+// Recording of incoming updates, to be replayed with `omikuji_bot replay`
+//
+
+// Updates of each bot are appended to their own file in the directory, one JSON object per line
+pub fn log_file(dir: &str, tenant: &str) -> PathBuf {
+    Path::new(dir).join(format!("{}.jsonl", tenant))
+}
+
+pub fn record(dir: &str, tenant: &str, update: &Update) -> Result<(), Error> {
+    fs::create_dir_all(dir)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file(dir, tenant))?;
+    writeln!(file, "{}", serde_json::to_string(update)?)?;
+    Ok(())
+}
+
+// Updates recorded in a file, in the order they were received
+pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<Update>, Error> {
+    fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
@@ -0,0 +1,161 @@
+use crate::models::{Language, OmikujiMessage};
+use std::fmt;
+
+//
+// Checks of strips before they are accepted, with messages for their authors
+//
+
+// Limits in characters, counted after sanitizing
+pub const MAX_DESCRIPTION_LENGTH: usize = 300;
+pub const MAX_SECTION_LENGTH: usize = 200;
+pub const MAX_POEM_LENGTH: usize = 120;
+// The whole strip has to fit in a photo caption (1024 characters), with room for the extras
+pub const MAX_STRIP_LENGTH: usize = 900;
+
+// Strips are sent as Markdown, which Telegram refuses to parse with these left unmatched
+const MARKDOWN_MARKS: [char; 3] = ['*', '_', '`'];
+
+// Part of a strip an issue was found in
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    Description,
+    Poem,
+    // By the (English) name of the section
+    Section(String),
+    Strip,
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Field::Description => write!(f, "The description"),
+            Field::Poem => write!(f, "The poem"),
+            Field::Section(name) => write!(f, "The {} section", name),
+            Field::Strip => write!(f, "The strip"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    MissingClass,
+    NoSections,
+    EmptySection(String),
+    TooLong {
+        field: Field,
+        length: usize,
+        limit: usize,
+    },
+    UnmatchedMark {
+        field: Field,
+        mark: char,
+    },
+    // The poem is set in italics, where no other formatting can be nested
+    MarkInPoem(char),
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::MissingClass => write!(f, "It needs a fortune, e.g. Blessing."),
+            Issue::NoSections => write!(f, "It needs at least one section."),
+            Issue::EmptySection(name) => write!(f, "The {} section is empty.", name),
+            Issue::TooLong {
+                field,
+                length,
+                limit,
+            } => write!(
+                f,
+                "{} is {} characters long, please keep it to {}.",
+                field, length, limit
+            ),
+            Issue::UnmatchedMark { field, mark } => write!(
+                f,
+                "{} has an unmatched {}, which would break the formatting.",
+                field, mark
+            ),
+            Issue::MarkInPoem(mark) => write!(
+                f,
+                "The poem has a {}, but it cannot be formatted since it is set in italics.",
+                mark
+            ),
+        }
+    }
+}
+
+// Issues with a piece of text typed for a part of a strip
+pub fn check_text(field: Field, text: &str) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let limit = match field {
+        Field::Description => MAX_DESCRIPTION_LENGTH,
+        Field::Poem => MAX_POEM_LENGTH,
+        Field::Section(_) => MAX_SECTION_LENGTH,
+        Field::Strip => MAX_STRIP_LENGTH,
+    };
+    let length = text.chars().count();
+    if length > limit {
+        issues.push(Issue::TooLong {
+            field: field.clone(),
+            length,
+            limit,
+        });
+    }
+    for mark in MARKDOWN_MARKS {
+        if field == Field::Poem && text.contains(mark) {
+            issues.push(Issue::MarkInPoem(mark));
+        } else if text.matches(mark).count() % 2 == 1 {
+            issues.push(Issue::UnmatchedMark {
+                field: field.clone(),
+                mark,
+            });
+        }
+    }
+    issues
+}
+
+// Issues keeping a draft from being saved, none if it is ready
+pub fn check_strip(omikuji_message: &OmikujiMessage) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    if omikuji_message.class.is_none() {
+        issues.push(Issue::MissingClass);
+    }
+    if let Some(description) = &omikuji_message.description {
+        issues.extend(check_text(Field::Description, description));
+    }
+    if let Some(poem) = &omikuji_message.poem {
+        issues.extend(check_text(Field::Poem, poem));
+    }
+    if omikuji_message.sections.is_empty() {
+        issues.push(Issue::NoSections);
+    }
+    for (section, description) in &omikuji_message.sections {
+        let name = section.name(Language::English).to_string();
+        if description.is_empty() {
+            issues.push(Issue::EmptySection(name));
+        } else {
+            issues.extend(check_text(Field::Section(name), description));
+        }
+    }
+    // Only checked for length, the marks have been counted by part already
+    let length = omikuji_message.render(Language::English).chars().count();
+    if length > MAX_STRIP_LENGTH {
+        issues.push(Issue::TooLong {
+            field: Field::Strip,
+            length,
+            limit: MAX_STRIP_LENGTH,
+        });
+    }
+    issues
+}
+
+// A bulleted list of the issues, to be sent as plain text since it may quote Markdown marks
+pub fn render_issues(subject: &str, issues: &[Issue]) -> String {
+    let mut text = match issues.len() {
+        1 => format!("{} has 1 problem:", subject),
+        count => format!("{} has {} problems:", subject, count),
+    };
+    for issue in issues {
+        text += format!("\n• {}", issue).as_str();
+    }
+    text
+}
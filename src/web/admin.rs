@@ -0,0 +1,174 @@
+use super::{Request, Response};
+use crate::error::BotError;
+use crate::events;
+use crate::events::ModerationEvent;
+use crate::models::{AuditAction, Language, NewAuditEntry, Omikuji, OmikujiClass, OmikujiMessage};
+use crate::repository::Repository;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+// Number of strips shown for a search
+const SEARCH_LIMIT: i64 = 50;
+
+// Moderation pages, protected by HTTP basic auth with API_TOKEN as the password
+//   GET  /admin?q=...                  - pending strips, or the strips matching a search
+//   POST /admin/omikuji/{id}/{action}  - approve, reject or delete a strip
+pub async fn respond(request: &Request, repository: &dyn Repository, token: &str) -> Response {
+    let user_name = match authorized(request, token) {
+        Some(user_name) => user_name,
+        None => {
+            let mut response = Response::html(401, String::from("Unauthorized"));
+            response.headers.push((
+                "WWW-Authenticate",
+                String::from("Basic realm=\"Omikuji moderation\""),
+            ));
+            return response;
+        }
+    };
+    // Moderators are told apart in the audit log by the user name they logged in with
+    let actor = format!("web:{}", user_name);
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    let result = match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["admin"]) => index(request, repository)
+            .await
+            .map(|page| Response::html(200, page)),
+        ("POST", ["admin", "omikuji", id, action]) => match id.parse() {
+            Ok(id) => moderate(repository, actor.as_str(), id, action).await,
+            Err(_) => Ok(Response::html(400, String::from("Malformed omikuji ID"))),
+        },
+        _ => Ok(Response::html(404, String::from("Not found"))),
+    };
+    result.unwrap_or_else(|e| {
+        println!("Admin panel request to {} failed: {}", request.path, e);
+        Response::html(500, String::from("Internal server error"))
+    })
+}
+
+// Any user name is accepted, as long as the password is the API token
+// Returns the user name if authorized
+fn authorized(request: &Request, token: &str) -> Option<String> {
+    request
+        .authorization
+        .as_deref()
+        .and_then(|authorization| authorization.strip_prefix("Basic "))
+        .and_then(|credentials| STANDARD.decode(credentials).ok())
+        .and_then(|credentials| String::from_utf8(credentials).ok())
+        .and_then(|credentials| {
+            let (user_name, password) = credentials.split_once(':')?;
+            (password == token).then(|| user_name.to_string())
+        })
+}
+
+async fn moderate(
+    repository: &dyn Repository,
+    actor: &str,
+    id: u32,
+    action: &str,
+) -> Result<Response, BotError> {
+    let action = match action {
+        "approve" => {
+            repository.review_omikuji(id, true).await?;
+            AuditAction::Approve
+        }
+        "reject" => {
+            repository.review_omikuji(id, false).await?;
+            AuditAction::Reject
+        }
+        "delete" => {
+            repository.delete_omikuji(id).await?;
+            AuditAction::Delete
+        }
+        _ => return Ok(Response::html(404, String::from("Unknown action"))),
+    };
+    repository
+        .record_audit(&NewAuditEntry::new(
+            actor,
+            action,
+            format!("omikuji {}", id),
+        ))
+        .await?;
+    if action == AuditAction::Approve {
+        events::notify_moderators(ModerationEvent::Approved {
+            omikuji_id: id,
+            moderator: actor.to_string(),
+        });
+    }
+    Ok(Response::redirect("/admin"))
+}
+
+async fn index(request: &Request, repository: &dyn Repository) -> Result<String, BotError> {
+    let query = request.query_param("q").filter(|query| !query.is_empty());
+    let (title, omikujis) = match &query {
+        // Searching for the name of a class (e.g. 大吉) finds the strips of that class
+        Some(query) => match OmikujiClass::parse(query) {
+            Some(class) => (
+                format!("Strips of class {}", class.name(Language::English)),
+                repository
+                    .search_omikujis(&format!("\"class\":\"{:?}\"", class), SEARCH_LIMIT)
+                    .await?,
+            ),
+            None => (
+                format!("Search results for \"{}\"", escape(query)),
+                repository.search_omikujis(query, SEARCH_LIMIT).await?,
+            ),
+        },
+        None => (
+            String::from("Pending review"),
+            repository.find_pending_omikujis().await?,
+        ),
+    };
+    let mut page = String::from(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Omikuji moderation</title></head>\n\
+        <body>\n<h1>Omikuji moderation</h1>\n",
+    );
+    page += format!(
+        "<form method=\"get\" action=\"/admin\"><input name=\"q\" value=\"{}\"> \
+        <button>Search</button> <a href=\"/admin\">Pending</a></form>\n",
+        escape(query.as_deref().unwrap_or(""))
+    )
+    .as_str();
+    page += format!("<h2>{} ({})</h2>\n", title, omikujis.len()).as_str();
+    page += "<table border=\"1\">\n<tr><th>ID</th><th>Author</th><th>Votes</th><th>Strip</th><th>Actions</th></tr>\n";
+    for omikuji in &omikujis {
+        page += row(omikuji).as_str();
+    }
+    page += "</table>\n</body></html>\n";
+    Ok(page)
+}
+
+fn row(omikuji: &Omikuji) -> String {
+    let text = serde_json::from_str::<OmikujiMessage>(omikuji.message.as_str())
+        .map(|message| message.render(Language::English))
+        .unwrap_or_else(|_| omikuji.message.clone());
+    let mut actions = String::new();
+    for action in &["approve", "reject", "delete"] {
+        // Strips which are not quarantined have nothing to approve or reject
+        if *action != "delete" && !omikuji.quarantined {
+            continue;
+        }
+        actions += format!(
+            "<form method=\"post\" action=\"/admin/omikuji/{}/{}\"><button>{}</button></form>",
+            omikuji.id, action, action
+        )
+        .as_str();
+    }
+    format!(
+        "<tr><td>{}</td><td>{}</td><td>{}</td><td><pre>{}</pre></td><td>{}</td></tr>\n",
+        omikuji.id,
+        if omikuji.anonymous {
+            String::from("<i>anonymous</i>")
+        } else {
+            escape(omikuji.tg_name.as_str())
+        },
+        omikuji.vote_count,
+        escape(text.as_str()),
+        actions
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,85 @@
+use super::{Request, Response};
+use crate::error::BotError;
+use crate::models::{DrawPool, Language, Omikuji, OmikujiMessage};
+use crate::repository::Repository;
+use chrono::{Duration, Local};
+use serde_json::{json, Value};
+
+// Read-only endpoints, all of which require `Authorization: Bearer <API_TOKEN>`
+//   GET /omikuji/random - a random strip which is not hidden
+//   GET /omikuji/{id}   - the strip with the given ID, unless it is hidden
+//   GET /stats          - size of the library and number of active users
+pub async fn respond(request: &Request, repository: &dyn Repository, token: &str) -> Response {
+    let authorized = request
+        .authorization
+        .as_deref()
+        .and_then(|authorization| authorization.strip_prefix("Bearer "))
+        .is_some_and(|given| given == token);
+    if !authorized {
+        return error(401, "Missing or invalid API token");
+    }
+    if request.method != "GET" {
+        return error(405, "Only GET requests are supported");
+    }
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    let result = match segments.as_slice() {
+        ["omikuji", "random"] => repository
+            .random_omikuji(DrawPool::Global, None)
+            .await
+            .and_then(|omikuji| omikuji.map(|omikuji| omikuji_json(&omikuji)).transpose()),
+        ["omikuji", id] => match id.parse() {
+            Ok(id) => repository.find_omikuji(id).await.and_then(|omikuji| {
+                omikuji
+                    .filter(|omikuji| !omikuji.quarantined)
+                    .filter(|omikuji| !omikuji.is_expired(Local::now().naive_local()))
+                    .map(|omikuji| omikuji_json(&omikuji))
+                    .transpose()
+            }),
+            Err(_) => return error(400, "Malformed omikuji ID"),
+        },
+        ["stats"] => stats_json(repository).await.map(Some),
+        _ => return error(404, "Unknown endpoint"),
+    };
+    match result {
+        Ok(Some(body)) => Response::json(200, body),
+        Ok(None) => error(404, "Omikuji not found"),
+        Err(e) => {
+            println!("HTTP API request to {} failed: {}", request.path, e);
+            error(500, "Internal server error")
+        }
+    }
+}
+
+fn error(status: u16, message: &str) -> Response {
+    Response::json(status, json!({ "error": message }))
+}
+
+pub(super) fn omikuji_json(omikuji: &Omikuji) -> Result<Value, BotError> {
+    let message: OmikujiMessage = serde_json::from_str(omikuji.message.as_str())?;
+    Ok(json!({
+        "id": omikuji.id,
+        "author": if omikuji.anonymous { None } else { Some(&omikuji.tg_name) },
+        "vote_count": omikuji.vote_count,
+        "created_at": omikuji.created_at.format("%Y-%m-%dT%H:%M:%S").to_string(),
+        "class": message.class,
+        "description": message.description,
+        "poem": message.poem,
+        "sections": message.sections,
+        "text": message.render(Language::English),
+    }))
+}
+
+async fn stats_json(repository: &dyn Repository) -> Result<Value, BotError> {
+    let classes = repository.count_omikujis_by_class().await?;
+    let total: i64 = classes.iter().map(|(_, count)| count).sum();
+    let since = Local::now().naive_local() - Duration::days(1);
+    Ok(json!({
+        "total": total,
+        "hidden": repository.count_hidden_omikujis().await?,
+        "classes": classes
+            .into_iter()
+            .map(|(class, count)| json!({ "class": class, "count": count }))
+            .collect::<Vec<Value>>(),
+        "active_users": repository.count_active_users(since).await?,
+    }))
+}
@@ -0,0 +1,141 @@
+use crate::db::{establish_connection, Database};
+use crate::repository::{DieselRepository, Repository};
+use anyhow::Error;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Server};
+use serde_json::Value;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use url::form_urlencoded;
+
+pub mod admin;
+pub mod api;
+
+//
+// Embedded HTTP server, for the club website and moderators
+//
+
+// An HTTP request, independent from the server implementation
+#[derive(Default)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    // Value of the Authorization header
+    pub authorization: Option<String>,
+}
+
+impl Request {
+    // Value of a parameter in the query string
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        let query = self.query.as_deref()?;
+        form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: String,
+}
+
+impl Response {
+    pub fn json(status: u16, body: Value) -> Self {
+        Response {
+            status,
+            content_type: "application/json",
+            headers: Vec::new(),
+            body: body.to_string(),
+        }
+    }
+
+    pub fn html(status: u16, body: String) -> Self {
+        Response {
+            status,
+            content_type: "text/html; charset=utf-8",
+            headers: Vec::new(),
+            body,
+        }
+    }
+
+    // Send the browser to another page after a form has been submitted
+    pub fn redirect(location: &str) -> Self {
+        let mut response = Response::html(303, String::new());
+        response.headers.push(("Location", location.to_string()));
+        response
+    }
+}
+
+// Route a request to the admin panel or the API
+pub async fn respond(request: &Request, repository: &dyn Repository, token: &str) -> Response {
+    if request.path == "/admin" || request.path.starts_with("/admin/") {
+        admin::respond(request, repository, token).await
+    } else {
+        api::respond(request, repository, token).await
+    }
+}
+
+// Serve the HTTP API until an error occurs
+// The server has its own pool of database connections, so that it does not wait for the bot
+// Only the library of the given tenant is served
+pub async fn serve(addr: SocketAddr, token: String, tenant: String) -> Result<(), Error> {
+    let database = establish_connection().await;
+    let token = Arc::new(token);
+    let tenant = Arc::new(tenant);
+    let make_service = make_service_fn(move |_| {
+        let database = database.clone();
+        let token = token.clone();
+        let tenant = tenant.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle(request, database.clone(), token.clone(), tenant.clone())
+            }))
+        }
+    });
+    println!("HTTP API is listening on {}", addr);
+    Server::try_bind(&addr)?.serve(make_service).await?;
+    Ok(())
+}
+
+async fn handle(
+    request: hyper::Request<Body>,
+    database: Database,
+    token: Arc<String>,
+    tenant: Arc<String>,
+) -> Result<hyper::Response<Body>, Infallible> {
+    let request = Request {
+        method: request.method().to_string(),
+        path: request.uri().path().to_string(),
+        query: request.uri().query().map(String::from),
+        authorization: request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from),
+    };
+    // The futures of the repository are not Send, which hyper requires, so the request is
+    // answered on a thread of its own
+    let response = tokio::task::spawn_blocking(move || {
+        let repository = DieselRepository::new(&database, tenant.as_str());
+        tokio::runtime::Handle::current().block_on(respond(&request, &repository, token.as_str()))
+    })
+    .await
+    .unwrap_or_else(|e| {
+        println!("HTTP API request panicked: {}", e);
+        Response::html(500, String::from("Internal server error"))
+    });
+    let mut builder = hyper::Response::builder()
+        .status(response.status)
+        .header(CONTENT_TYPE, response.content_type);
+    for (name, value) in response.headers {
+        builder = builder.header(name, value);
+    }
+    Ok(builder
+        .body(Body::from(response.body))
+        .expect("status and headers are valid"))
+}
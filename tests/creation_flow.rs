@@ -0,0 +1,3887 @@
+use chrono::{Duration, Local, NaiveDate};
+use omikuji_bot::booklet;
+use omikuji_bot::bot_api::{is_blocked, BotApi, DryRunApi, RecordingApi};
+use omikuji_bot::config::{get_tts_token, in_maintenance, reload_from, validate};
+use omikuji_bot::drafts::{load_draft_into, load_drafts, save_changed_drafts, save_draft_from};
+use omikuji_bot::error::BotError;
+use omikuji_bot::fortune_extras::{daily_class, FortuneExtras};
+#[cfg(feature = "matrix")]
+use omikuji_bot::frontends::matrix::{matrix_user, MatrixClient};
+use omikuji_bot::frontends::telegram::{callback_event, message_event};
+use omikuji_bot::frontends::{handle, EventKind, IncomingEvent, OutgoingAction, Platform, Sender};
+use omikuji_bot::handlers::draw::{assign_strategy, pick_omikuji, post_to_channel};
+use omikuji_bot::handlers::poll::weekly_poll_round;
+use omikuji_bot::handlers::stats::streak;
+use omikuji_bot::import::{csv_url, guess_mapping, parse_csv, ColumnMapping};
+use omikuji_bot::middleware::{MaintenanceCheck, Middleware, Pipeline, UsageTracker, UserUpsert};
+use omikuji_bot::models::{
+    AuditAction, AuthorDigest, Draw, DrawPool, DrawStrategy, Language, NewAuditEntry, NewBoothDraw,
+    NewComment, NewDraw, NewInterpretation, NewOmikuji, NewReaction, NewUser, OmikujiClass,
+    OmikujiMessage,
+};
+use omikuji_bot::repository::{
+    AchievementRepository, AuditRepository, CachedRepository, CommentRepository, DraftStore,
+    InterpretationRepository, LeaseRepository, MemoryRepository, ModerationRepository,
+    OmikujiRepository, PollRepository, ReactionRepository, Repository, StatsRepository,
+    UsageRepository, UserRepository, ANONYMOUS_ID, HIDE_THRESHOLD,
+};
+use omikuji_bot::update_log;
+use omikuji_bot::{
+    callback_entry, message_entry, migration_entry, reminder_entry, shrine_entry, trash_entry,
+    user_data,
+};
+use printpdf::lopdf;
+use serde_json::json;
+use std::collections::HashMap;
+use teloxide_core::types::{CallbackQuery, Message, Recipient, UpdateKind};
+use teloxide_core::{ApiError, RequestError};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const USER_ID: i64 = 42;
+const OTHER_USER_ID: i64 = 43;
+
+fn user(id: i64) -> serde_json::Value {
+    json!({"id": id, "is_bot": false, "first_name": "Test", "last_name": "User"})
+}
+
+fn text(text: &str) -> Message {
+    serde_json::from_value(json!({
+        "message_id": 1,
+        "from": user(USER_ID),
+        "date": 0,
+        "chat": {"id": USER_ID, "type": "private", "first_name": "Test"},
+        "text": text,
+    }))
+    .unwrap()
+}
+
+fn location(latitude: f64, longitude: f64) -> Message {
+    serde_json::from_value(json!({
+        "message_id": 1,
+        "from": user(USER_ID),
+        "date": 0,
+        "chat": {"id": USER_ID, "type": "private", "first_name": "Test"},
+        "location": {"latitude": latitude, "longitude": longitude},
+    }))
+    .unwrap()
+}
+
+// A message sent from another user, replying to a message of the bot
+fn reply(from: i64, text: &str, replied: &str) -> Message {
+    serde_json::from_value(json!({
+        "message_id": 2,
+        "from": user(from),
+        "date": 0,
+        "chat": {"id": from, "type": "private", "first_name": "Test"},
+        "text": text,
+        "reply_to_message": {
+            "message_id": 1,
+            "from": {"id": 1, "is_bot": true, "first_name": "Omikuji"},
+            "date": 0,
+            "chat": {"id": from, "type": "private", "first_name": "Test"},
+            "text": replied,
+        },
+    }))
+    .unwrap()
+}
+
+fn callback(from: i64, data: &str) -> CallbackQuery {
+    serde_json::from_value(json!({
+        "id": "1",
+        "from": user(from),
+        "chat_instance": "test",
+        "data": data,
+    }))
+    .unwrap()
+}
+
+// Everything the handlers depend on, without Telegram or a database
+#[derive(Default)]
+struct Bot {
+    api: RecordingApi,
+    store: HashMap<i64, OmikujiMessage>,
+    repository: MemoryRepository,
+}
+
+impl Bot {
+    async fn text(&mut self, data: &str) {
+        message_entry(&text(data), &self.api, &mut self.store, &self.repository)
+            .await
+            .unwrap();
+    }
+
+    async fn callback(&mut self, data: &str) {
+        self.callback_from(USER_ID, data).await;
+    }
+
+    async fn callback_from(&mut self, from: i64, data: &str) {
+        callback_entry(
+            &callback(from, data),
+            &self.api,
+            &mut self.store,
+            &self.repository,
+        )
+        .await
+        .unwrap();
+    }
+
+    // Text of the last message sent
+    fn last_text(&self) -> String {
+        self.api.texts().pop().unwrap_or_default()
+    }
+}
+
+#[tokio::test]
+async fn create_draw_and_vote() {
+    let mut bot = Bot::default();
+
+    bot.text("/start").await;
+    let requests = bot.api.take();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].callbacks(), vec!["new", "draw"]);
+
+    bot.callback("new").await;
+    let requests = bot.api.take();
+    assert!(requests
+        .last()
+        .unwrap()
+        .callbacks()
+        .contains(&"class/GreatBlessing"));
+
+    bot.callback("class/GreatBlessing").await;
+    assert!(bot
+        .last_text()
+        .starts_with("Step 2/4: write a description\nSure! Can you write a brief description"));
+
+    bot.text("Everything goes well").await;
+    let requests = bot.api.take();
+    let callbacks = requests.last().unwrap().callbacks();
+    assert!(!callbacks.contains(&"section/Love"));
+    assert!(callbacks.contains(&"section/page:1"));
+
+    bot.callback("section/page:1").await;
+    let requests = bot.api.take();
+    let callbacks = requests.last().unwrap().callbacks();
+    assert!(callbacks.contains(&"section/Love"));
+    assert!(callbacks.contains(&"section/page:0"));
+
+    bot.callback("section/Love").await;
+    assert_eq!(
+        bot.last_text(),
+        "Step 3/4: add sections\nOK. Type your description for section Love below!"
+    );
+
+    bot.text("You will meet someone").await;
+    let requests = bot.api.take();
+    assert!(requests.last().unwrap().callbacks().contains(&"ask_photo"));
+
+    bot.text("/current").await;
+    assert!(bot.last_text().contains("*Great Blessing*"));
+    assert!(bot.last_text().contains("*Love*: You will meet someone"));
+    assert_eq!(
+        bot.api.take().last().unwrap().callbacks(),
+        vec!["section/page:0", "ask_photo", "save", "cancel"]
+    );
+
+    bot.callback("ask_photo").await;
+    assert_eq!(
+        bot.api.take().last().unwrap().callbacks(),
+        vec!["save", "anonymous", "expiry", "back/Photo"]
+    );
+
+    bot.callback("save").await;
+    let texts = bot.api.texts();
+    // Followed by the badge for the first strip
+    assert_eq!(
+        texts[texts.len() - 2],
+        "Nice! Your omikuji strip has been saved into our database."
+    );
+    assert!(bot.store.is_empty());
+    let omikujis = bot.repository.omikujis.borrow().clone();
+    assert_eq!(omikujis.len(), 1);
+    assert_eq!(omikujis[0].tg_id, USER_ID);
+    assert_eq!(omikujis[0].tg_name, "Test User");
+    bot.api.take();
+
+    bot.callback("draw").await;
+    let requests = bot.api.take();
+    let strip = requests.last().unwrap();
+    assert!(strip.text().unwrap().contains("Everything goes well"));
+    assert_eq!(
+        strip.callbacks(),
+        vec![
+            "vote/+1",
+            "vote/-1",
+            "favorite/1",
+            "comment/1",
+            "react/1/Pray",
+            "react/1/Laugh",
+            "react/1/Scream",
+            "react/1/Heart"
+        ]
+    );
+
+    bot.callback_from(OTHER_USER_ID, "vote/+1").await;
+    assert_eq!(bot.repository.omikujis.borrow()[0].vote_count, 1);
+    let texts = bot.api.texts();
+    assert!(texts.contains(&String::from("Successfully upvoted the omikuji slip!")));
+    // The author has notifications turned on by default
+    assert!(texts
+        .iter()
+        .any(|text| text.starts_with("Someone has just upvoted your omikuji slip #1!")));
+}
+
+#[tokio::test]
+async fn draw_from_empty_library() {
+    let mut bot = Bot::default();
+    bot.callback("draw").await;
+    assert_eq!(bot.last_text(), "Oops! Our omikuji library is empty.");
+}
+
+#[tokio::test]
+async fn unknown_inputs() {
+    let mut bot = Bot::default();
+
+    bot.text("/nonsense").await;
+    assert_eq!(bot.last_text(), "Command /nonsense is not recognized.");
+
+    bot.callback("nonsense").await;
+    assert_eq!(
+        bot.last_text(),
+        "Callback query nonsense is not recognized!"
+    );
+
+    bot.callback("class/GreatBlessing").await;
+    assert_eq!(
+        bot.last_text(),
+        "You have to create a new omikuji strip before calling `class` callback."
+    );
+
+    bot.callback("vote/+1").await;
+    assert_eq!(bot.last_text(), "Requested omikuji cannot be found.");
+}
+
+#[tokio::test]
+async fn template_sections() {
+    let mut bot = Bot::default();
+
+    bot.text("/template").await;
+    assert!(bot
+        .last_text()
+        .starts_with("You have to create a new omikuji strip"));
+
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("/template").await;
+    let requests = bot.api.take();
+    assert_eq!(
+        requests.last().unwrap().callbacks(),
+        vec!["template/Classic", "template/Short"]
+    );
+
+    bot.callback("template/Short").await;
+    assert!(bot.last_text().starts_with("Template added!"));
+
+    // Sections are asked for in the order of the template, right after the description
+    bot.text("Not bad").await;
+    assert_eq!(
+        bot.last_text(),
+        "Step 3/4: add sections\nOK. Type your description for section Desire below!"
+    );
+    bot.text("It will come true").await;
+    bot.text("Work hard").await;
+    assert_eq!(
+        bot.last_text(),
+        "Step 3/4: add sections\nOK. Type your description for section Love below!"
+    );
+    bot.text("Be patient").await;
+    let requests = bot.api.take();
+    assert!(requests.last().unwrap().callbacks().contains(&"ask_photo"));
+
+    let draft = &bot.store[&USER_ID];
+    let descriptions: Vec<&str> = draft
+        .sections
+        .iter()
+        .map(|(_, description)| description.as_str())
+        .collect();
+    assert_eq!(
+        descriptions,
+        vec!["It will come true", "Work hard", "Be patient"]
+    );
+}
+
+#[tokio::test]
+async fn wizard_navigation() {
+    let mut bot = Bot::default();
+
+    bot.callback("new").await;
+    assert!(bot.last_text().starts_with("Step 1/4: choose a class\n"));
+    bot.callback("class/Curse").await;
+    bot.callback("skip/Description").await;
+    assert!(bot.last_text().starts_with("Step 3/4: add sections\n"));
+
+    // Going back from the sections asks for the description again
+    bot.callback("back/Sections").await;
+    assert!(bot
+        .last_text()
+        .starts_with("Step 2/4: write a description\n"));
+    bot.text("Watch your steps").await;
+
+    bot.callback("section/Travel").await;
+    let requests = bot.api.take();
+    assert_eq!(
+        requests.last().unwrap().callbacks(),
+        vec!["back/Sections", "skip/Sections"]
+    );
+    bot.text("Stay at home").await;
+
+    // The last section entered is asked for again
+    bot.callback("back/Sections").await;
+    assert!(bot.last_text().ends_with("section Travel below!"));
+    bot.text("Stay at home for now").await;
+
+    // Buttons of an earlier step are ignored
+    bot.callback("skip/Description").await;
+    assert!(bot.last_text().starts_with("This button is out of date."));
+
+    let draft = &bot.store[&USER_ID];
+    assert_eq!(draft.description.as_deref(), Some("Watch your steps"));
+    assert_eq!(draft.sections.len(), 1);
+    assert_eq!(draft.sections[0].1, "Stay at home for now");
+}
+
+#[tokio::test]
+async fn stalled_draft_reminder() {
+    use omikuji_bot::quiet_entry;
+    let mut bot = Bot::default();
+
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.api.take();
+
+    // Recently touched drafts are left alone
+    reminder_entry(&bot.api, &mut bot.store, &bot.repository)
+        .await
+        .unwrap();
+    assert!(bot.api.take().is_empty());
+
+    // Reminders due in the quiet hours of the user are held back
+    let now = Local::now().naive_local().time();
+    let mut settings = bot.repository.get_user_settings(USER_ID).await.unwrap();
+    settings.quiet_start = Some(now - Duration::hours(1));
+    settings.quiet_end = Some(now + Duration::hours(1));
+    bot.repository
+        .update_user_settings(&settings)
+        .await
+        .unwrap();
+    let stalled = chrono::Local::now().naive_local() - chrono::Duration::minutes(11);
+    bot.store.get_mut(&USER_ID).unwrap().touched_at = Some(stalled);
+    reminder_entry(&bot.api, &mut bot.store, &bot.repository)
+        .await
+        .unwrap();
+    assert!(bot.api.take().is_empty());
+    assert_eq!(bot.repository.deferred_messages.borrow().len(), 1);
+
+    // Only one reminder is sent
+    reminder_entry(&bot.api, &mut bot.store, &bot.repository)
+        .await
+        .unwrap();
+    assert_eq!(bot.repository.deferred_messages.borrow().len(), 1);
+
+    settings.quiet_start = None;
+    settings.quiet_end = None;
+    bot.repository
+        .update_user_settings(&settings)
+        .await
+        .unwrap();
+    quiet_entry(&bot.api, &bot.repository).await.unwrap();
+    let requests = bot.api.take();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].callbacks(), vec!["resume", "cancel"]);
+
+    bot.callback("resume").await;
+    assert!(bot
+        .last_text()
+        .starts_with("Step 2/4: write a description\n"));
+
+    // Users who blocked the bot are noticed when they are reminded
+    bot.repository
+        .upsert_user(&NewUser {
+            tg_id: OTHER_USER_ID,
+            tg_name: "Other User",
+            tg_username: None,
+            language_code: None,
+        })
+        .await
+        .unwrap();
+    bot.callback_from(OTHER_USER_ID, "new").await;
+    bot.store.get_mut(&OTHER_USER_ID).unwrap().touched_at = Some(stalled);
+    bot.api.blocked.borrow_mut().push(OTHER_USER_ID);
+    let reminded = reminder_entry(&bot.api, &mut bot.store, &bot.repository)
+        .await
+        .unwrap();
+    assert_eq!(reminded, vec![OTHER_USER_ID]);
+    let other = bot
+        .repository
+        .find_user(OTHER_USER_ID)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(other.inactive);
+}
+
+#[tokio::test]
+async fn spam_is_held_for_moderation() {
+    std::env::set_var("ALLOWED_DOMAINS", "nus.edu.sg");
+    let mut bot = Bot::default();
+
+    for description in [
+        "Read the syllabus at https://www.nus.edu.sg/",
+        "Buy now at example.com!",
+        "Ask @someone",
+        "Call +65 9123 4567",
+    ] {
+        bot.callback("new").await;
+        bot.callback("class/Blessing").await;
+        bot.text(description).await;
+        bot.callback("section/Study").await;
+        bot.text("Keep going").await;
+        bot.callback("save").await;
+    }
+    let quarantined: Vec<bool> = bot
+        .repository
+        .omikujis
+        .borrow()
+        .iter()
+        .map(|omikuji| omikuji.quarantined)
+        .collect();
+    assert_eq!(quarantined, vec![false, true, true, true]);
+    assert!(bot.last_text().contains("once a moderator has approved it"));
+}
+
+#[tokio::test]
+async fn strip_info() {
+    let repository = MemoryRepository::default();
+    repository
+        .insert_omikuji(&NewOmikuji {
+            message: "{}",
+            tg_id: USER_ID,
+            tg_name: "Test User",
+            community_id: None,
+            vote_count: 0,
+            anonymous: false,
+            expires_at: None,
+            quarantined: false,
+        })
+        .await
+        .unwrap();
+    let today = Local::now().naive_local().format("%Y-%m-%d").to_string();
+    let omikuji = repository.find_omikuji(1).await.unwrap().unwrap();
+    assert_eq!(
+        omikuji.info(Language::English),
+        format!("#1 · Submitted {}", today)
+    );
+    repository.update_omikuji(1, "{}", false).await.unwrap();
+    let omikuji = repository.find_omikuji(1).await.unwrap().unwrap();
+    assert_eq!(
+        omikuji.info(Language::Japanese),
+        format!("#1 · 投稿 {} · 編集 {}", today, today)
+    );
+}
+
+#[tokio::test]
+async fn slip_numbers() {
+    let mut bot = Bot::default();
+    for quarantined in [false, true, false] {
+        bot.repository
+            .insert_omikuji(&NewOmikuji {
+                message: r#"{"class":"SmallBlessing","sections":[]}"#,
+                tg_id: USER_ID,
+                tg_name: "Test User",
+                community_id: None,
+                vote_count: 0,
+                anonymous: false,
+                expires_at: None,
+                quarantined,
+            })
+            .await
+            .unwrap();
+    }
+    let repository = &bot.repository;
+    let number = |id| async move {
+        repository
+            .find_omikuji(id)
+            .await
+            .unwrap()
+            .unwrap()
+            .slip_number
+    };
+    // Held strips are numbered once approved, after those drawn already
+    assert_eq!(
+        (number(1).await, number(2).await, number(3).await),
+        (Some(1), None, Some(2))
+    );
+    bot.repository.review_omikuji(2, true).await.unwrap();
+    assert_eq!(number(2).await, Some(3));
+    bot.text("/slip 3").await;
+    assert!(bot.last_text().starts_with("第三番 (No. 3)\n\n"));
+    // Numbers stay with hidden strips, which cannot be fetched any more
+    bot.repository.add_vote(2, HIDE_THRESHOLD).await.unwrap();
+    bot.text("/slip 3").await;
+    assert_eq!(bot.last_text(), "There is no omikuji slip number 3.");
+    bot.text("/slip twelve").await;
+    assert_eq!(
+        bot.last_text(),
+        "Please give the number of the slip, e.g. /slip 12."
+    );
+}
+
+#[tokio::test]
+async fn typed_class() {
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.text("Very lucky").await;
+    assert!(bot
+        .last_text()
+        .contains("There is no class called \"Very lucky\"."));
+    bot.text("中吉").await;
+    bot.text("Not bad").await;
+    assert_eq!(
+        format!("{:?}", bot.store[&USER_ID].class),
+        "Some(MiddleBlessing)"
+    );
+    assert_eq!(bot.store[&USER_ID].description.as_deref(), Some("Not bad"));
+}
+
+#[tokio::test]
+async fn strip_validation() {
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text(&"Long ".repeat(100)).await;
+    assert_eq!(
+        bot.last_text(),
+        "Your description has 1 problem:\n\
+        • The description is 499 characters long, please keep it to 300.\n\
+        Please send it again."
+    );
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep *going").await;
+    assert_eq!(
+        bot.last_text(),
+        "Your section has 1 problem:\n\
+        • The Study section has an unmatched *, which would break the formatting.\n\
+        Please send it again."
+    );
+    bot.text("Keep *going*").await;
+
+    // Strips which do not pass, e.g. edited from before the checks, cannot be saved
+    let draft = bot.store.get_mut(&USER_ID).unwrap();
+    draft.class = None;
+    draft.sections[0].1 = String::from("Keep_going");
+    bot.callback("ask_photo").await;
+    assert_eq!(
+        bot.last_text(),
+        "Your strip has 2 problems:\n\
+        • It needs a fortune, e.g. Blessing.\n\
+        • The Study section has an unmatched _, which would break the formatting.\n\
+        Go back to fix it, or /cancel."
+    );
+    bot.callback("save").await;
+    assert!(bot.last_text().starts_with("Your strip has 2 problems:"));
+    assert!(bot.repository.omikujis.borrow().is_empty());
+}
+
+#[tokio::test]
+async fn current_preview() {
+    let mut bot = Bot::default();
+    bot.text("/current").await;
+    assert_eq!(
+        bot.last_text(),
+        "You don't have an omikuji you are currently working on."
+    );
+
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.api.take();
+    bot.text("/current").await;
+    assert_eq!(
+        bot.api.take().last().unwrap().callbacks(),
+        vec!["resume", "cancel"]
+    );
+
+    // The photo of a strip being edited is shown before its text
+    bot.store.get_mut(&USER_ID).unwrap().photo = Some(String::from("photo-id"));
+    bot.text("/current").await;
+    let requests = bot.api.take();
+    assert_eq!(requests[0].method, "SendPhoto");
+    assert_eq!(
+        requests[1].text(),
+        Some("This is what you are currently working on:\n\n*Blessing*\n")
+    );
+}
+
+#[tokio::test]
+async fn cancel_and_undo() {
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("/cancel").await;
+    assert_eq!(
+        bot.api.take().last().unwrap().callbacks(),
+        vec!["cancel/confirm", "resume"]
+    );
+
+    bot.callback("cancel/confirm").await;
+    assert!(bot.store.is_empty());
+    let requests = bot.api.take();
+    assert_eq!(
+        requests.last().unwrap().callbacks(),
+        vec!["trash/restore/1"]
+    );
+
+    bot.callback("trash/restore/1").await;
+    assert!(bot
+        .last_text()
+        .starts_with("Step 2/4: write a description\n"));
+    assert_eq!(format!("{:?}", bot.store[&USER_ID].class), "Some(Blessing)");
+
+    // The item has left the trash with the draft restored
+    bot.callback("trash/restore/1").await;
+    assert_eq!(
+        bot.last_text(),
+        "Sorry, this item cannot be restored any more."
+    );
+}
+
+#[tokio::test]
+async fn trash() {
+    let mut bot = Bot::default();
+    bot.text("/trash").await;
+    assert!(bot.last_text().starts_with("Your trash is empty."));
+
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+    bot.callback("mystrip/confirm_delete/1").await;
+    assert!(bot.repository.omikujis.borrow().is_empty());
+
+    bot.callback("new").await;
+    bot.callback("class/Curse").await;
+    bot.callback("cancel/confirm").await;
+    bot.api.take();
+
+    bot.text("/trash").await;
+    let requests = bot.api.take();
+    let list = requests.last().unwrap();
+    assert!(list.text().unwrap().contains("1. Draft (Curse), deleted "));
+    assert!(list
+        .text()
+        .unwrap()
+        .contains("2. Strip #1 (Blessing), deleted "));
+    assert_eq!(list.callbacks(), vec!["trash/restore/2", "trash/restore/1"]);
+
+    // Strips come back as new ones, without their comments and reactions
+    bot.callback("trash/restore/1").await;
+    assert!(bot.last_text().starts_with("Your omikuji strip is back!"));
+    let omikujis = bot.repository.omikujis.borrow().clone();
+    assert_eq!(omikujis.len(), 1);
+    assert!(omikujis[0].message.contains("Keep going"));
+
+    // Drafts only when the user is not working on another one
+    bot.callback("new").await;
+    bot.callback("trash/restore/2").await;
+    assert!(bot
+        .last_text()
+        .starts_with("You have to complete (or /cancel)"));
+    bot.store.clear();
+    bot.callback_from(OTHER_USER_ID, "trash/restore/2").await;
+    assert_eq!(
+        bot.last_text(),
+        "Sorry, this item cannot be restored any more."
+    );
+
+    // Items older than TRASH_DAYS are emptied by the trash job
+    bot.repository.trash.borrow_mut()[0].deleted_at =
+        Local::now().naive_local() - Duration::days(8);
+    trash_entry(&bot.repository).await.unwrap();
+    assert!(bot.repository.trash.borrow().is_empty());
+}
+
+#[tokio::test]
+async fn poem() {
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    let requests = bot.api.take();
+    assert!(requests.last().unwrap().callbacks().contains(&"poem"));
+
+    bot.callback("poem").await;
+    bot.text("Spring *rain").await;
+    assert_eq!(
+        bot.last_text(),
+        "Your poem has 1 problem:\n\
+        • The poem has a *, but it cannot be formatted since it is set in italics.\n\
+        Please send it again."
+    );
+    bot.text("Spring rain falls\n\non the old shrine gate")
+        .await;
+    let requests = bot.api.take();
+    assert!(!requests.last().unwrap().callbacks().contains(&"poem"));
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+
+    let omikuji = bot.repository.find_omikuji(1).await.unwrap().unwrap();
+    let message: OmikujiMessage = serde_json::from_str(omikuji.message.as_str()).unwrap();
+    assert_eq!(
+        message.render(Language::English),
+        "*Blessing*\n▎_Spring rain falls_\n▎_on the old shrine gate_\nNot bad\n\n*Study*: Keep going"
+    );
+
+    // The poem can be given up on while being asked for
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("poem").await;
+    bot.callback("poem/none").await;
+    assert_eq!(bot.store[&USER_ID].poem, None);
+    assert!(bot.last_text().starts_with("Step 3/4: add sections\n"));
+}
+
+#[tokio::test]
+async fn anonymous_submission() {
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+
+    bot.callback("ask_photo").await;
+    let requests = bot.api.take();
+    let preview = &requests[requests.len() - 2];
+    assert!(preview.body["text"]
+        .as_str()
+        .unwrap()
+        .contains("*Study*: Keep going"));
+    let keyboard = requests.last().unwrap().body["reply_markup"].to_string();
+    assert!(keyboard.contains("Submit anonymously: Off"));
+
+    bot.callback("anonymous").await;
+    let requests = bot.api.take();
+    let keyboard = requests.last().unwrap().body["reply_markup"].to_string();
+    assert!(keyboard.contains("Submit anonymously: On"));
+
+    bot.callback("save").await;
+    let omikujis = bot.repository.omikujis.borrow().clone();
+    assert!(omikujis[0].anonymous);
+    assert!(bot.repository.top_authors(10).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn credited_authors() {
+    let mut bot = Bot::default();
+    bot.repository
+        .upsert_user(&NewUser {
+            tg_id: USER_ID,
+            tg_name: "Test User",
+            tg_username: None,
+            language_code: None,
+        })
+        .await
+        .unwrap();
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+
+    bot.callback("draw").await;
+    assert!(!bot.last_text().contains("by Test User"));
+
+    bot.callback("settings/credit").await;
+    bot.callback("draw").await;
+    assert!(bot.last_text().ends_with("\n\nby Test User"));
+
+    // Anonymous strips are never credited
+    bot.repository.omikujis.borrow_mut()[0].anonymous = true;
+    bot.callback("draw").await;
+    assert!(!bot.last_text().contains("by Test User"));
+}
+
+#[tokio::test]
+async fn credited_names_are_escaped() {
+    let mut bot = Bot::default();
+    bot.repository
+        .upsert_user(&NewUser {
+            tg_id: USER_ID,
+            tg_name: "foo_bar*",
+            tg_username: None,
+            language_code: None,
+        })
+        .await
+        .unwrap();
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+
+    bot.callback("settings/credit").await;
+    bot.callback("draw").await;
+    assert!(bot.last_text().ends_with("\n\nby foo\\_bar\\*"));
+}
+
+#[tokio::test]
+async fn interpretations() {
+    std::env::set_var("ADMIN_IDS", USER_ID.to_string());
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+
+    bot.text("/setinterpretation Fortune Good").await;
+    assert!(bot
+        .last_text()
+        .starts_with("Usage: /setinterpretation <class> <text>\nClasses: GreatBlessing"));
+
+    bot.text("/setinterpretation Blessing Steady effort pays off")
+        .await;
+    assert_eq!(
+        bot.last_text(),
+        "The interpretation of Blessing has been saved."
+    );
+    bot.callback("draw").await;
+    assert!(bot.last_text().ends_with("\n\nSteady effort pays off"));
+
+    bot.text("/setinterpretation Blessing").await;
+    assert_eq!(
+        bot.last_text(),
+        "The interpretation of Blessing has been removed."
+    );
+    bot.callback("draw").await;
+    assert!(!bot.last_text().contains("Steady effort pays off"));
+    let entries = bot.repository.find_audit_entries(0, 10).await.unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].target, "Blessing");
+}
+
+#[tokio::test]
+async fn comparative_luck() {
+    assert_eq!(OmikujiClass::GreatBlessing.rank(), Some(0));
+    assert_eq!(OmikujiClass::GreatCurse.rank(), Some(11));
+    assert_eq!(OmikujiClass::Other.rank(), None);
+    assert_eq!(OmikujiClass::GreatBlessing.better_than(), Some(100));
+    assert_eq!(OmikujiClass::GreatCurse.better_than(), Some(0));
+
+    let mut bot = Bot::default();
+    bot.text("/stats").await;
+    assert!(!bot.last_text().contains("Average luck"));
+
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+
+    bot.callback("draw").await;
+    assert!(bot
+        .last_text()
+        .starts_with("You draw a omikuji strip, better than 72% of possible fortunes:"));
+    bot.callback("draw").await;
+    bot.text("/stats").await;
+    assert!(bot.last_text().contains(
+        "\nAverage luck of your 2 draws: Blessing (better than 72% of possible fortunes)"
+    ));
+}
+
+#[tokio::test]
+async fn lucky_extras() {
+    let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+    let extras = FortuneExtras::generate(USER_ID, date, 1);
+    assert_eq!(extras, FortuneExtras::generate(USER_ID, date, 1));
+    assert!((1..100).contains(&extras.number));
+
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+
+    bot.callback("draw").await;
+    let first = bot.last_text();
+    assert!(first.contains("*Lucky number*: "));
+    assert!(first.contains("*Lucky color*: "));
+    assert!(first.contains("*Lucky direction*: "));
+    // Same user, strip and day
+    bot.callback("draw").await;
+    assert_eq!(bot.last_text(), first);
+}
+
+#[tokio::test]
+async fn daily_fortune() {
+    let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+    assert!(daily_class(USER_ID, date).rank().is_some());
+
+    // Works with an empty library, and gives the same fortune all day
+    let mut bot = Bot::default();
+    bot.text("/today").await;
+    let first = bot.last_text();
+    assert!(first.starts_with("Your fortune for "));
+    assert!(first.contains("*Lucky number*: "));
+    bot.text("/today").await;
+    assert_eq!(bot.last_text(), first);
+
+    let today = Local::now().naive_local().date();
+    let class = format!("{:?}", daily_class(USER_ID, today));
+    bot.repository
+        .set_interpretation(&NewInterpretation {
+            class: class.as_str(),
+            text: "Take it easy",
+        })
+        .await
+        .unwrap();
+    bot.text("/today").await;
+    assert!(bot.last_text().contains("\n\nTake it easy\n\n"));
+}
+
+#[tokio::test]
+async fn draw_streak() {
+    let mut bot = Bot::default();
+    bot.text("/streak").await;
+    assert!(bot.last_text().starts_with("You are not on a streak."));
+
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+
+    // Draws on the four previous days, and one with a gap before them
+    let now = Local::now().naive_local();
+    for days in &[1, 2, 3, 4, 6] {
+        bot.repository.draws.borrow_mut().push(Draw {
+            id: 0,
+            tg_id: USER_ID,
+            omikuji_id: 1,
+            created_at: now - Duration::days(*days),
+            tenant_id: String::from("default"),
+            variant: None,
+            vote: None,
+            tied_at: None,
+        });
+    }
+    // The streak has not been broken yet today
+    bot.text("/streak").await;
+    assert!(bot.last_text().starts_with("🔥 4-day streak"));
+
+    bot.callback("draw").await;
+    assert!(bot.last_text().ends_with("\n\n🔥 5-day streak"));
+    assert_eq!(streak(&bot.repository, USER_ID).await.unwrap(), 5);
+}
+
+#[tokio::test]
+async fn achievements() {
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+    assert_eq!(
+        bot.last_text(),
+        "🏅 You have earned a new badge: ✍️ First Strip! (See all your badges in /stats)"
+    );
+    assert_eq!(
+        bot.repository.find_badges(USER_ID).await.unwrap(),
+        vec!["FirstStrip"]
+    );
+
+    // Badges are only awarded once
+    bot.callback("draw").await;
+    assert!(!bot.last_text().starts_with("🏅"));
+
+    bot.repository.omikujis.borrow_mut()[0].vote_count = 9;
+    bot.callback_from(OTHER_USER_ID, "vote/+1").await;
+    assert!(bot.api.texts().contains(&String::from(
+        "🏅 You have earned a new badge: ⭐ Popular Strip! (See all your badges in /stats)"
+    )));
+
+    bot.text("/stats").await;
+    assert!(bot
+        .last_text()
+        .ends_with("\nBadges: ✍️ First Strip, ⭐ Popular Strip"));
+}
+
+#[tokio::test]
+async fn referrals() {
+    let mut bot = Bot::default();
+    for (tg_id, tg_name) in &[(USER_ID, "Test User"), (OTHER_USER_ID, "Other User")] {
+        bot.repository
+            .upsert_user(&NewUser {
+                tg_id: *tg_id,
+                tg_name,
+                tg_username: None,
+                language_code: None,
+            })
+            .await
+            .unwrap();
+    }
+
+    // Users can't refer themselves
+    bot.text(format!("/start ref_{}", USER_ID).as_str()).await;
+    assert_eq!(
+        bot.repository
+            .find_user(USER_ID)
+            .await
+            .unwrap()
+            .unwrap()
+            .referred_by,
+        None
+    );
+
+    bot.text(format!("/start ref_{}", OTHER_USER_ID).as_str())
+        .await;
+    assert_eq!(
+        bot.repository
+            .find_user(USER_ID)
+            .await
+            .unwrap()
+            .unwrap()
+            .referred_by,
+        Some(OTHER_USER_ID)
+    );
+    assert!(bot
+        .api
+        .texts()
+        .iter()
+        .any(|text| text.starts_with("Someone has just joined through your invite link!")));
+    // The main menu is shown as usual
+    assert_eq!(
+        bot.api.take().last().unwrap().callbacks(),
+        vec!["new", "draw"]
+    );
+
+    // Only the first referrer is credited
+    bot.text("/start ref_44").await;
+    assert_eq!(
+        bot.repository
+            .find_user(USER_ID)
+            .await
+            .unwrap()
+            .unwrap()
+            .referred_by,
+        Some(OTHER_USER_ID)
+    );
+    assert_eq!(
+        bot.repository.count_referrals(OTHER_USER_ID).await.unwrap(),
+        1
+    );
+
+    bot.text("/invites").await;
+    let text = bot.last_text();
+    assert!(text.contains("Users you have invited: 0\n"));
+    assert!(text.ends_with("\nTop inviters\n1. Other User: 1\n"));
+}
+
+#[tokio::test]
+async fn expiring_strips() {
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Happy new year").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("ask_photo").await;
+    bot.callback("expiry").await;
+    let requests = bot.api.take();
+    let buttons = requests.last().unwrap().body["reply_markup"]["inline_keyboard"].to_string();
+    assert!(buttons.contains("Expires: after 1 days"));
+    // Cycles through all the options, back to 1 day
+    for _ in 0..4 {
+        bot.callback("expiry").await;
+    }
+    bot.callback("save").await;
+    let expires_at = bot.repository.omikujis.borrow()[0].expires_at.unwrap();
+    let now = Local::now().naive_local();
+    assert!(expires_at > now && expires_at <= now + Duration::days(1));
+
+    bot.callback("draw").await;
+    assert!(bot.last_text().contains("Happy new year"));
+
+    // Expired strips are no longer drawn, even before they are archived
+    bot.repository.omikujis.borrow_mut()[0].expires_at = Some(now - Duration::minutes(1));
+    bot.callback("draw").await;
+    assert_eq!(bot.last_text(), "Oops! Our omikuji library is empty.");
+
+    assert_eq!(bot.repository.archive_expired(now).await.unwrap(), 1);
+    assert!(bot.repository.omikujis.borrow()[0].archived);
+    assert_eq!(bot.repository.archive_expired(now).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn quarantine_review() {
+    std::env::set_var("ADMIN_IDS", USER_ID.to_string());
+    let mut bot = Bot::default();
+    for description in ["Not bad", "Not good"] {
+        bot.callback("new").await;
+        bot.callback("class/Blessing").await;
+        bot.text(description).await;
+        bot.callback("section/Study").await;
+        bot.text("Keep going").await;
+        bot.callback("save").await;
+    }
+    bot.text("/review hidden").await;
+    assert_eq!(bot.last_text(), "There are no quarantined strips.");
+
+    // Downvoted strips are quarantined rather than silently dropped
+    bot.repository.add_vote(1, HIDE_THRESHOLD).await.unwrap();
+    bot.repository
+        .add_vote(2, HIDE_THRESHOLD - 1)
+        .await
+        .unwrap();
+    assert!(
+        bot.repository
+            .find_omikuji(1)
+            .await
+            .unwrap()
+            .unwrap()
+            .quarantined
+    );
+    bot.callback("draw").await;
+    assert_eq!(bot.last_text(), "Oops! Our omikuji library is empty.");
+    bot.api.take();
+
+    bot.text("/review hidden").await;
+    let requests = bot.api.take();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(
+        requests[0].text().unwrap(),
+        "2 quarantined strips, showing the first 2:"
+    );
+    assert!(requests[1]
+        .text()
+        .unwrap()
+        .starts_with("#1 (-3 votes, pending)"));
+    assert_eq!(
+        requests[1].callbacks(),
+        vec!["review/restore/1", "review/delete/1", "review/author/42"]
+    );
+
+    bot.callback("review/restore/1").await;
+    assert_eq!(bot.last_text(), "Omikuji #1 has been restored.");
+    let restored = bot.repository.find_omikuji(1).await.unwrap().unwrap();
+    assert!(!restored.quarantined);
+    assert_eq!(restored.vote_count, 0);
+    bot.callback("draw").await;
+    assert!(bot.last_text().contains("Not bad"));
+
+    bot.callback("review/delete/2").await;
+    assert_eq!(bot.last_text(), "Omikuji #2 has been deleted permanently.");
+    assert!(bot.repository.find_omikuji(2).await.unwrap().is_none());
+
+    // Only admins can use the buttons
+    bot.callback_from(OTHER_USER_ID, "review/restore/1").await;
+    assert_eq!(bot.last_text(), "This is only available to admins.");
+    let entries = bot.repository.find_audit_entries(0, 10).await.unwrap();
+    assert_eq!(entries[0].action, "Delete");
+    assert_eq!(entries[1].action, "Approve");
+}
+
+#[tokio::test]
+async fn bulk_review() {
+    std::env::set_var("ADMIN_IDS", USER_ID.to_string());
+    let mut bot = Bot::default();
+    // An imported batch of one author, and a strip of another
+    for index in 0..10 {
+        let author = if index < 9 { OTHER_USER_ID } else { 44 };
+        bot.repository
+            .insert_omikuji(&NewOmikuji {
+                message: r#"{"class":"SmallBlessing","sections":[]}"#,
+                tg_id: author,
+                tg_name: "Test User",
+                community_id: None,
+                vote_count: 0,
+                anonymous: false,
+                expires_at: None,
+                quarantined: true,
+            })
+            .await
+            .unwrap();
+    }
+    bot.text("/review hidden").await;
+    let requests = bot.api.take();
+    assert_eq!(
+        requests[0].callbacks(),
+        vec!["review/select/0/0", "review/reject_pending"]
+    );
+    assert_eq!(
+        requests[1].callbacks(),
+        vec!["review/restore/1", "review/delete/1", "review/author/43"]
+    );
+
+    bot.callback("review/select/0/0").await;
+    let requests = bot.api.take();
+    let page = requests.last().unwrap();
+    assert!(page
+        .text()
+        .unwrap()
+        .contains("\n#1 Small Blessing by Test User (0 votes, pending)"));
+    let callbacks = page.callbacks();
+    assert_eq!(callbacks.len(), 11);
+    assert_eq!(callbacks[2], "review/select/0/4");
+    assert_eq!(callbacks[8], "review/select/8/0");
+    assert_eq!(callbacks[9], "review/restore_selected/0/0");
+
+    bot.callback("review/delete_selected/0/0").await;
+    assert!(bot.api.texts()[0].starts_with("Nothing is selected"));
+    bot.api.take();
+
+    // The selection is carried by the buttons, across taps
+    bot.callback("review/select/0/5").await;
+    let requests = bot.api.take();
+    let callbacks = requests.last().unwrap().callbacks();
+    assert_eq!(callbacks[0], "review/select/0/4");
+    assert_eq!(callbacks[10], "review/delete_selected/0/5");
+    bot.callback("review/delete_selected/0/5").await;
+    let requests = bot.api.take();
+    assert_eq!(
+        requests[0].text().unwrap(),
+        "2 strips have been deleted permanently."
+    );
+    assert!(bot.repository.find_omikuji(1).await.unwrap().is_none());
+    assert!(bot.repository.find_omikuji(3).await.unwrap().is_none());
+    // The page is shown again without them, and it is the last one now
+    let callbacks = requests[1].callbacks();
+    assert_eq!(callbacks[0], "review/select/0/1");
+    assert_eq!(callbacks.len(), 10);
+
+    bot.callback("review/author/43").await;
+    assert_eq!(
+        bot.last_text(),
+        "7 strips of this author have been restored."
+    );
+    assert_eq!(
+        bot.repository
+            .find_quarantined_omikujis()
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+
+    bot.callback("review/reject_pending").await;
+    assert!(bot
+        .last_text()
+        .starts_with("1 pending strips have been rejected."));
+    let rejected = bot.repository.find_omikuji(10).await.unwrap().unwrap();
+    assert!(rejected.quarantined && rejected.reviewed_at.is_some());
+    bot.callback("review/reject_pending").await;
+    assert_eq!(bot.last_text(), "There are no pending strips.");
+
+    let entries = bot.repository.find_audit_entries(0, 20).await.unwrap();
+    assert_eq!(entries.len(), 10);
+    assert_eq!(entries[0].action, "Reject");
+    assert_eq!(entries[9].action, "Delete");
+}
+
+// Serve a CSV over HTTP, answering every request with it
+async fn serve_csv(csv: &'static str) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 4096];
+            let _ = socket.read(&mut request).await;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/csv\r\nContent-Length: {}\r\n\
+                Connection: close\r\n\r\n{}",
+                csv.len(),
+                csv
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+    format!("http://{}/strips.csv", address)
+}
+
+#[test]
+fn csv_parsing() {
+    assert_eq!(
+        parse_csv("a,\"b, \"\"c\"\"\"\r\n\r\n\"multi\nline\",d"),
+        vec![vec!["a", "b, \"c\""], vec!["multi\nline", "d"]]
+    );
+    assert_eq!(
+        ColumnMapping::parse("class=1 description=2 sections=3:4,5:6"),
+        Some(ColumnMapping {
+            class: 1,
+            description: Some(2),
+            sections: vec![(3, 4), (5, 6)],
+        })
+    );
+    assert_eq!(ColumnMapping::parse("description=2"), None);
+    assert_eq!(ColumnMapping::parse("class=0"), None);
+    let header: Vec<String> = ["Fortune", "Description", "Section", "Text"]
+        .iter()
+        .map(|cell| cell.to_string())
+        .collect();
+    assert_eq!(
+        guess_mapping(&header).to_string(),
+        "class=1 description=2 sections=3:4"
+    );
+    assert_eq!(
+        csv_url("https://docs.google.com/spreadsheets/d/abc/edit#gid=7")
+            .unwrap()
+            .as_str(),
+        "https://docs.google.com/spreadsheets/d/abc/export?format=csv&gid=7"
+    );
+    assert!(csv_url("ftp://example.com/strips.csv").is_none());
+}
+
+#[tokio::test]
+async fn import_url() {
+    std::env::set_var("ADMIN_IDS", USER_ID.to_string());
+    let mut bot = Bot::default();
+    let url = serve_csv(
+        "Class,Description,Section,Text,Section,Text\n\
+        大吉,Very good,Study,Keep going,Love,Be bold\n\
+        Curse,Careful,学問,Rest,,\n\
+        Fortune?,Unknown,Study,Hmm,,\n\
+        Blessing,Odd,Cooking,Yes,,\n",
+    )
+    .await;
+    bot.text(format!("/importurl {}", url).as_str()).await;
+    let prompt = bot.last_text();
+    assert!(prompt.starts_with(format!("📥 Import from {}\n4 rows", url).as_str()));
+    assert!(prompt.contains("\n1. Class\n"));
+    assert!(prompt.contains("class=1 description=2 sections=3:4,5:6"));
+
+    // Only replies of admins to the prompt are taken as the mapping
+    let message = reply(OTHER_USER_ID, "class=1", prompt.as_str());
+    message_entry(&message, &bot.api, &mut bot.store, &bot.repository)
+        .await
+        .unwrap();
+    assert!(bot.repository.omikujis.borrow().is_empty());
+    let message = reply(USER_ID, "class=9", prompt.as_str());
+    message_entry(&message, &bot.api, &mut bot.store, &bot.repository)
+        .await
+        .unwrap();
+    assert_eq!(bot.last_text(), "The CSV only has 6 columns.");
+
+    let message = reply(
+        USER_ID,
+        "class=1 description=2 sections=3:4,5:6",
+        prompt.as_str(),
+    );
+    message_entry(&message, &bot.api, &mut bot.store, &bot.repository)
+        .await
+        .unwrap();
+    assert_eq!(
+        bot.last_text(),
+        "2 strips have been imported as batch #1. \
+        They are pending until approved with /review hidden.\n\n\
+        2 rows have been skipped:\n\
+        • Row 4: \"Fortune?\" is not a fortune.\n\
+        • Row 5: \"Cooking\" is not a section."
+    );
+    let omikujis = bot.repository.omikujis.borrow().clone();
+    assert_eq!(omikujis.len(), 2);
+    assert!(omikujis
+        .iter()
+        .all(|omikuji| omikuji.quarantined && omikuji.import_batch == Some(1)));
+    let message: OmikujiMessage = serde_json::from_str(omikujis[0].message.as_str()).unwrap();
+    assert_eq!(format!("{:?}", message.class), "Some(GreatBlessing)");
+    assert_eq!(message.sections.len(), 2);
+    assert_eq!(bot.repository.import_batches.borrow()[0], url);
+    let entries = bot.repository.find_audit_entries(0, 1).await.unwrap();
+    assert_eq!(entries[0].target, "import batch 1 (2 strips)");
+}
+
+#[tokio::test]
+async fn export_pdf() {
+    std::env::set_var("ADMIN_IDS", USER_ID.to_string());
+    let mut bot = Bot::default();
+    bot.text("/exportpdf").await;
+    assert_eq!(bot.last_text(), "There are no approved strips to export.");
+
+    for index in 0..8 {
+        bot.repository
+            .insert_omikuji(&NewOmikuji {
+                message: r#"{"class":"GreatBlessing","description":"とても良い *運勢* です","sections":[["Study","Keep going"]]}"#,
+                tg_id: USER_ID,
+                tg_name: "Test User",
+                community_id: None,
+                vote_count: 0,
+                anonymous: false,
+                expires_at: None,
+                quarantined: index == 7,
+            })
+            .await.unwrap();
+    }
+    bot.api.take();
+    bot.text("/exportpdf").await;
+    assert_eq!(
+        bot.last_text(),
+        "The booklet needs a TrueType font with Japanese glyphs, set BOOKLET_FONT to its path."
+    );
+
+    // The rest runs only where DejaVu Sans is installed (which has no CJK glyphs)
+    const LATIN_FONT: &str = "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf";
+    if !std::path::Path::new(LATIN_FONT).exists() {
+        return;
+    }
+    std::env::set_var("BOOKLET_FONT", LATIN_FONT);
+    bot.api.take();
+    bot.text("/exportpdf").await;
+    std::env::remove_var("BOOKLET_FONT");
+    let requests = bot.api.take();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "SendDocument");
+    assert_eq!(
+        requests[0].body["caption"],
+        "7 omikuji slips on 2 pages, ready to print."
+    );
+
+    let omikujis = bot.repository.omikujis.borrow().clone();
+    let font = std::fs::read(LATIN_FONT).unwrap();
+    let pdf = booklet::render_with(&font, &omikujis[..7], Language::English).unwrap();
+    assert!(pdf.starts_with(b"%PDF-"));
+    let document = lopdf::Document::load_mem(&pdf).unwrap();
+    assert_eq!(document.get_pages().len(), 2);
+    // The font is embedded, not left to the PDF reader
+    assert!(document.objects.values().any(|object| object
+        .as_dict()
+        .is_ok_and(|dictionary| dictionary.has(b"FontFile2"))));
+    // Text is written as glyphs of the font, e.g. the class, without the Markdown marks of the
+    // description
+    let face = ttf_parser::Face::parse(&font, 0).unwrap();
+    let page = document.get_pages()[&1];
+    let content =
+        lopdf::content::Content::decode(&document.get_page_content(page).unwrap()).unwrap();
+    let lines: Vec<String> = content
+        .operations
+        .iter()
+        .filter(|operation| operation.operator == "Tj")
+        .map(|operation| {
+            let glyphs = operation.operands[0].as_str().unwrap();
+            glyphs
+                .chunks(2)
+                .map(|glyph| ttf_parser::GlyphId(u16::from_be_bytes([glyph[0], glyph[1]])))
+                .filter_map(|glyph| (' '..='~').find(|&c| face.glyph_index(c) == Some(glyph)))
+                .collect()
+        })
+        .collect();
+    assert!(
+        lines.iter().any(|line| line == "Great Blessing"),
+        "{:?}",
+        lines
+    );
+    assert!(lines.iter().all(|line| !line.contains('*')));
+}
+
+#[tokio::test]
+async fn booth_printer() {
+    const BOOTH_ID: i64 = -300;
+    let printer = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    std::env::set_var(
+        "BOOTH_PRINTERS",
+        format!("{}={}", BOOTH_ID, printer.local_addr().unwrap()),
+    );
+    let mut bot = Bot::default();
+    bot.repository
+        .insert_omikuji(&NewOmikuji {
+            message: r#"{"class":"Blessing","description":"Not *bad*","sections":[["Study","Keep going"]]}"#,
+            tg_id: USER_ID,
+            tg_name: "Test User",
+            community_id: Some(BOOTH_ID),
+            vote_count: 0,
+            anonymous: false,
+            expires_at: None,
+            quarantined: false,
+        })
+        .await.unwrap();
+    let message: Message = serde_json::from_value(json!({
+        "message_id": 1,
+        "from": user(USER_ID),
+        "date": 0,
+        "chat": {"id": BOOTH_ID, "type": "group", "title": "Booth"},
+        "text": "/draw",
+    }))
+    .unwrap();
+    message_entry(&message, &bot.api, &mut bot.store, &bot.repository)
+        .await
+        .unwrap();
+    assert!(bot.api.texts()[0].contains("*Blessing*"));
+
+    // The strip is printed in the background, as a receipt which is cut at its end
+    let (mut connection, _) = printer.accept().unwrap();
+    let mut receipt = Vec::new();
+    std::io::Read::read_to_end(&mut connection, &mut receipt).unwrap();
+    assert!(receipt.starts_with(&[0x1B, b'@']));
+    assert!(receipt.ends_with(&[0x1D, b'V', 66, 0]));
+    let text = String::from_utf8_lossy(&receipt);
+    assert!(text.contains("Blessing\n"));
+    assert!(text.contains("\nNot bad\n"));
+    assert!(text.contains("【Study】Keep going\n"));
+    std::env::remove_var("BOOTH_PRINTERS");
+}
+
+#[tokio::test]
+async fn booth_draw() {
+    const BOOTH_ID: i64 = -400;
+    std::env::set_var("ADMIN_IDS", USER_ID.to_string());
+    let mut bot = Bot::default();
+    bot.repository
+        .insert_omikuji(&NewOmikuji {
+            message: r#"{"class":"GreatCurse","sections":[["Study","Rest"]]}"#,
+            tg_id: OTHER_USER_ID,
+            tg_name: "Author",
+            community_id: None,
+            vote_count: 0,
+            anonymous: false,
+            expires_at: None,
+            quarantined: false,
+        })
+        .await
+        .unwrap();
+    let booth = |text: &str| -> Message {
+        serde_json::from_value(json!({
+            "message_id": 1,
+            "from": user(USER_ID),
+            "date": 0,
+            "chat": {"id": BOOTH_ID, "type": "group", "title": "Booth"},
+            "text": text,
+        }))
+        .unwrap()
+    };
+    message_entry(
+        &booth("/boothdraw Hanako"),
+        &bot.api,
+        &mut bot.store,
+        &bot.repository,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        bot.last_text(),
+        "/boothdraw only works in the chats of booths."
+    );
+
+    std::env::set_var("BOOTH_CHATS", format!("1,{}", BOOTH_ID));
+    message_entry(
+        &booth("/boothdraw"),
+        &bot.api,
+        &mut bot.store,
+        &bot.repository,
+    )
+    .await
+    .unwrap();
+    assert!(bot.last_text().starts_with("Who is drawing?"));
+    bot.api.take();
+
+    // Visitors draw one after another, and even a great curse is shown
+    for visitor in ["Hanako", "Taro"] {
+        let command = format!("/boothdraw {}", visitor);
+        message_entry(&booth(&command), &bot.api, &mut bot.store, &bot.repository)
+            .await
+            .unwrap();
+    }
+    let requests = bot.api.take();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].body["chat_id"], BOOTH_ID);
+    let text = requests[1].body["text"].as_str().unwrap();
+    assert!(text.starts_with("🎐 Taro draws a omikuji strip:\n\n"));
+    assert!(text.contains("Great Curse"));
+
+    // The draws are not the ones of the user at the booth
+    assert!(bot.repository.draws.borrow().is_empty());
+    let booth_draws = bot.repository.booth_draws.borrow().clone();
+    assert_eq!(booth_draws.len(), 2);
+    assert_eq!(booth_draws[0].0.visitor, "Hanako");
+    assert_eq!(booth_draws[0].0.tg_id, USER_ID);
+    assert_eq!(
+        bot.repository
+            .find_omikuji(1)
+            .await
+            .unwrap()
+            .unwrap()
+            .draw_count,
+        2
+    );
+    bot.text("/adminstats").await;
+    assert!(bot
+        .last_text()
+        .contains("Booth draws (7 days)           2\n"));
+    std::env::remove_var("BOOTH_CHATS");
+}
+
+// A NATS server which answers every PING, sending what it received on each connection once the
+// client has closed it
+fn serve_nats() -> (String, std::sync::mpsc::Receiver<String>) {
+    use std::io::{BufRead, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            stream
+                .write_all(b"INFO {\"max_payload\":1048576}\r\n")
+                .unwrap();
+            let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+            let mut received = String::new();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                if line == "PING\r\n" {
+                    stream.write_all(b"PONG\r\n").unwrap();
+                } else {
+                    received += line.as_str();
+                }
+                line.clear();
+            }
+            sender.send(received).unwrap();
+        }
+    });
+    (format!("nats://bot:p%2Fss@{}/club", addr), receiver)
+}
+
+// An MQTT broker which accepts every client, sending what it received after connecting
+fn serve_mqtt() -> (String, std::sync::mpsc::Receiver<Vec<u8>>) {
+    use std::io::{Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut header = [0u8; 2];
+            stream.read_exact(&mut header).unwrap();
+            let mut connect = vec![0u8; header[1] as usize];
+            stream.read_exact(&mut connect).unwrap();
+            stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap();
+            sender
+                .send([header.to_vec(), connect, received].concat())
+                .unwrap();
+        }
+    });
+    (format!("mqtt://bot:se%40cret@{}/club", addr), receiver)
+}
+
+#[tokio::test]
+async fn event_sink() {
+    let timeout = std::time::Duration::from_secs(10);
+    let (sink, received) = serve_nats();
+    std::env::set_var("EVENT_SINK", sink);
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+    bot.callback("draw").await;
+    bot.callback("vote/-1").await;
+
+    // Other tests may publish events at the same time, so only ours are looked for
+    let mut expected = vec![
+        "PUB club.strip_created ",
+        r#""class":"Blessing","event":"strip_created","omikuji_id":1,"pending":false}"#,
+        "PUB club.strip_drawn ",
+        r#""booth":false,"class":"Blessing","event":"strip_drawn","omikuji_id":1}"#,
+        "PUB club.vote_cast ",
+        r#""event":"vote_cast","omikuji_id":1,"vote":-1}"#,
+    ];
+    while !expected.is_empty() {
+        let publication = received.recv_timeout(timeout).unwrap();
+        assert!(publication.starts_with("CONNECT {"));
+        assert!(publication.contains(r#""pass":"p/ss""#));
+        expected.retain(|part| !publication.contains(part));
+    }
+
+    let (sink, received) = serve_mqtt();
+    std::env::set_var("EVENT_SINK", sink);
+    bot.callback("vote/+1").await;
+    loop {
+        let packets = received.recv_timeout(timeout).unwrap();
+        // Connecting with a clean session, the user name and the password (decoded from the URL)
+        assert_eq!(&packets[2..10], &[0, 4, b'M', b'Q', b'T', b'T', 4, 0xC2]);
+        assert!(packets.ends_with(&[0xE0, 0]));
+        let text = String::from_utf8_lossy(&packets);
+        assert!(text.contains("\0\x03bot\0\x07se@cret"));
+        if text.contains("\0\x0eclub/vote_cast{") && text.contains(r#""vote":1}"#) {
+            break;
+        }
+    }
+    std::env::remove_var("EVENT_SINK");
+}
+
+// A webhook which takes every POST, sending its body
+fn serve_webhook() -> (String, std::sync::mpsc::Receiver<serde_json::Value>) {
+    use std::io::{BufRead, Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+            let mut length = 0;
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+                if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+                line.clear();
+            }
+            let mut body = vec![0u8; length];
+            reader.read_exact(&mut body).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            sender.send(serde_json::from_slice(&body).unwrap()).unwrap();
+        }
+    });
+    (format!("http://{}/hook", addr), receiver)
+}
+
+#[tokio::test]
+async fn moderation_webhooks() {
+    std::env::set_var("ADMIN_IDS", USER_ID.to_string());
+    let (webhook, received) = serve_webhook();
+    std::env::set_var("MODERATION_WEBHOOKS", webhook);
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Buy now at example.com!").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+    bot.callback("review/restore/1").await;
+    bot.callback_from(OTHER_USER_ID, "vote/-1").await;
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Sold at anonymous.example.com").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("anonymous").await;
+    bot.callback("save").await;
+
+    // Other tests may post events at the same time, so only ours are looked for
+    let mut submitted = None;
+    let mut anonymous = None;
+    let mut approved = None;
+    let mut reported = None;
+    while submitted.is_none() || anonymous.is_none() || approved.is_none() || reported.is_none() {
+        let payload = received
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .unwrap();
+        let text = payload["text"].as_str().unwrap_or_default().to_string();
+        match payload["event"].as_str() {
+            Some("strip_submitted") if text.contains("anonymous.example.com") => {
+                anonymous = Some(payload)
+            }
+            Some("strip_submitted") if text.contains("example.com") => submitted = Some(payload),
+            Some("strip_approved") if payload["moderator"] == "Test User" => {
+                approved = Some(payload)
+            }
+            Some("strip_reported") if payload["vote_count"] == -1 => reported = Some(payload),
+            _ => {}
+        }
+    }
+    let submitted = submitted.unwrap();
+    assert_eq!(submitted["omikuji_id"], 1);
+    assert_eq!(submitted["author"], "Test User");
+    assert_eq!(submitted["pending"], true);
+    assert!(submitted["text"]
+        .as_str()
+        .unwrap()
+        .starts_with("📝 Strip #1 has been submitted by Test User, and is pending review\n\n"));
+    // Discord shows the summary from "content", Slack from "text"
+    assert_eq!(submitted["content"], submitted["text"]);
+    // The author of an anonymous strip is not named
+    let anonymous = anonymous.unwrap();
+    assert_eq!(anonymous["omikuji_id"], 2);
+    assert!(anonymous.get("author").is_none());
+    assert!(anonymous["text"]
+        .as_str()
+        .unwrap()
+        .starts_with("📝 Strip #2 has been submitted anonymously, and is pending review\n\n"));
+    assert_eq!(
+        approved.unwrap()["text"],
+        "✅ Strip #1 has been approved by Test User"
+    );
+    let reported = reported.unwrap();
+    assert_eq!(reported["quarantined"], false);
+    assert_eq!(
+        reported["content"],
+        "🚩 Strip #1 has been reported as insulting, its score is now -1"
+    );
+    std::env::remove_var("MODERATION_WEBHOOKS");
+}
+
+#[cfg(feature = "matrix")]
+#[tokio::test]
+async fn matrix_frontend() {
+    let (homeserver, received) = serve_webhook();
+    let client = MatrixClient::new(
+        url::Url::parse(homeserver.as_str()).unwrap(),
+        String::from("token"),
+        String::from("@omikuji:example.org"),
+    );
+    let bot = Bot::default();
+    bot.repository
+        .insert_omikuji(&NewOmikuji {
+            message: r#"{"class":"Blessing","sections":[["Study","Keep <going>"]]}"#,
+            tg_id: OTHER_USER_ID,
+            tg_name: "Author",
+            community_id: None,
+            vote_count: 0,
+            anonymous: false,
+            expires_at: None,
+            quarantined: false,
+        })
+        .await
+        .unwrap();
+    let message = |sender: &str, body: &str| json!({"type": "m.room.message", "sender": sender, "content": {"msgtype": "m.text", "body": body}});
+    let sync = json!({"rooms": {
+        "invite": {"!new:example.org": {}},
+        "join": {"!club:example.org": {"timeline": {"events": [
+            message("@alice:example.org", "hello"),
+            message("@alice:example.org", "!unknown"),
+            message("@omikuji:example.org", "!draw"),
+            message("@alice:example.org", "!draw"),
+        ]}}},
+    }});
+    client.join_invited(&sync).await;
+    assert_eq!(
+        received
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .unwrap(),
+        json!({})
+    );
+    client.answer(&sync, &bot.repository).await;
+    let answer = received
+        .recv_timeout(std::time::Duration::from_secs(10))
+        .unwrap();
+    assert_eq!(answer["msgtype"], "m.notice");
+    let body = answer["body"].as_str().unwrap();
+    assert!(body.starts_with("@alice:example.org draws a omikuji strip:\n\n"));
+    assert!(body.contains("\nBlessing\n"));
+    let formatted = answer["formatted_body"].as_str().unwrap();
+    assert!(formatted.contains("<strong>Study</strong>: Keep &lt;going&gt;"));
+    assert!(formatted.contains("<p><strong>Blessing</strong></p>"));
+    assert!(!formatted.contains('*'));
+    // Only alice asked for a draw, the bot does not answer itself
+    assert!(received
+        .recv_timeout(std::time::Duration::from_millis(200))
+        .is_err());
+    let draws = bot.repository.draws.borrow();
+    assert_eq!(draws.len(), 1);
+    assert_eq!(draws[0].tg_id, matrix_user("@alice:example.org"));
+    assert!(draws[0].tg_id < 0);
+}
+
+#[tokio::test]
+async fn other_platforms() {
+    let bot = Bot::default();
+    let event = |name: &str| IncomingEvent {
+        from: Sender {
+            platform: Platform::Discord,
+            id: 1234567890123456789,
+            name: String::from("<@1234567890123456789>"),
+            username: None,
+            language_code: Some(String::from("ja")),
+        },
+        community: Some(1),
+        kind: EventKind::Command {
+            name: String::from(name),
+            args: String::new(),
+        },
+    };
+    let actions = handle(&event("today"), &bot.repository).await.unwrap();
+    assert_eq!(actions.len(), 1);
+    let OutgoingAction::Reply { text } = &actions[0];
+    assert!(text.starts_with("Your fortune for "));
+    // Drawing from an empty library is told, like on Telegram
+    let actions = handle(&event("draw"), &bot.repository).await.unwrap();
+    assert_eq!(
+        actions,
+        vec![OutgoingAction::Reply {
+            text: String::from("Oops! Our omikuji library is empty.")
+        }]
+    );
+
+    // Commands which need buttons or drafts, and those of other bots, are left alone
+    for name in &["new", "settings", "ban", "unknown"] {
+        assert!(handle(&event(name), &bot.repository)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+    let mut text = event("draw");
+    text.kind = EventKind::Text {
+        text: String::from("Hello"),
+        reply_to: None,
+    };
+    assert!(handle(&text, &bot.repository).await.unwrap().is_empty());
+    assert!(bot.api.take().is_empty());
+}
+
+#[tokio::test]
+async fn quiet_hours() {
+    use omikuji_bot::quiet_entry;
+    let mut bot = Bot::default();
+    bot.repository
+        .insert_omikuji(&NewOmikuji {
+            message: r#"{"class":"Blessing","sections":[["Study","Keep going"]]}"#,
+            tg_id: USER_ID,
+            tg_name: "Test User",
+            community_id: None,
+            vote_count: 0,
+            anonymous: false,
+            expires_at: None,
+            quarantined: false,
+        })
+        .await
+        .unwrap();
+    bot.text("/quiet").await;
+    assert!(bot.last_text().starts_with("You have no quiet hours."));
+    bot.text("/quiet 23:00").await;
+    assert_eq!(
+        bot.last_text(),
+        "Please give your quiet hours like /quiet 23:00-08:00."
+    );
+
+    // Quiet from an hour ago to an hour from now, whatever time it is
+    let now = Local::now().naive_local().time();
+    let start = (now - Duration::hours(1)).format("%H:%M").to_string();
+    let end = (now + Duration::hours(1)).format("%H:%M").to_string();
+    bot.text(format!("/quiet {}-{}", start, end).as_str()).await;
+    assert_eq!(
+        bot.last_text(),
+        format!(
+            "Got it, you will not be disturbed from {} to {}.",
+            start, end
+        )
+    );
+    bot.api.take();
+    bot.callback_from(OTHER_USER_ID, "vote/+1").await;
+    let to_author = |requests: &[omikuji_bot::bot_api::SentRequest]| {
+        requests
+            .iter()
+            .filter(|request| request.body["chat_id"] == USER_ID)
+            .filter_map(|request| request.text().map(String::from))
+            .collect::<Vec<String>>()
+    };
+    assert!(to_author(&bot.api.take()).is_empty());
+    // The vote notification, followed by the badge for the first upvote
+    let deferred = bot.repository.deferred_messages.borrow().len();
+    assert!(deferred >= 1);
+    quiet_entry(&bot.api, &bot.repository).await.unwrap();
+    assert!(bot.api.take().is_empty());
+
+    bot.text("/quiet off").await;
+    assert_eq!(bot.last_text(), "Your quiet hours are turned off.");
+    bot.api.take();
+    quiet_entry(&bot.api, &bot.repository).await.unwrap();
+    let texts = to_author(&bot.api.take());
+    assert_eq!(texts.len(), deferred);
+    assert!(texts[0].starts_with("Someone has just upvoted your omikuji slip #1!"));
+    assert!(bot.repository.deferred_messages.borrow().is_empty());
+}
+
+#[tokio::test]
+async fn time_zones() {
+    use chrono::{Timelike, Utc};
+    use omikuji_bot::daily_entry;
+    let mut bot = Bot::default();
+    bot.text("/timezone").await;
+    assert!(bot
+        .last_text()
+        .starts_with("You follow the time of the bot"));
+    bot.text("/timezone Mars/Olympus_Mons").await;
+    assert!(bot.last_text().starts_with("Unknown time zone."));
+    bot.text("/timezone Asia/Singapore").await;
+    assert!(bot
+        .last_text()
+        .starts_with("Your time zone is now Asia/Singapore, where it is "));
+
+    // A location in Kyoto only offers Japan time
+    message_entry(
+        &location(35.0116, 135.7681),
+        &bot.api,
+        &mut bot.store,
+        &bot.repository,
+    )
+    .await
+    .unwrap();
+    let settings = bot.repository.user_settings.borrow()[&USER_ID].clone();
+    assert_eq!(settings.time_zone.as_deref(), Some("Asia/Singapore"));
+    bot.callback("timezone/Asia/Tokyo").await;
+    let settings = bot.repository.user_settings.borrow()[&USER_ID].clone();
+    assert_eq!(settings.time_zone.as_deref(), Some("Asia/Tokyo"));
+
+    // The daily omikuji comes at noon for one subscriber, but not yet at 4 am for the other
+    bot.repository
+        .insert_omikuji(&NewOmikuji {
+            message: r#"{"class":"Blessing","sections":[["Study","Keep going"]]}"#,
+            tg_id: USER_ID,
+            tg_name: "Test User",
+            community_id: None,
+            vote_count: 0,
+            anonymous: false,
+            expires_at: None,
+            quarantined: false,
+        })
+        .await
+        .unwrap();
+    let zone_at = |hour: i64| {
+        // Etc/GMT-9 is nine hours ahead of UTC
+        let offset = (hour - Utc::now().hour() as i64 + 36) % 24 - 12;
+        format!("/timezone Etc/GMT{:+}", -offset)
+    };
+    bot.text(zone_at(12).as_str()).await;
+    bot.callback("settings/daily").await;
+    message_entry(
+        &reply(OTHER_USER_ID, zone_at(4).as_str(), ""),
+        &bot.api,
+        &mut bot.store,
+        &bot.repository,
+    )
+    .await
+    .unwrap();
+    bot.callback_from(OTHER_USER_ID, "settings/daily").await;
+    bot.api.take();
+    daily_entry(&bot.api, &bot.repository).await.unwrap();
+    let requests = bot.api.take();
+    assert!(requests
+        .iter()
+        .any(|request| request.body["chat_id"] == USER_ID));
+    assert!(requests
+        .iter()
+        .all(|request| request.body["chat_id"] != OTHER_USER_ID));
+    // Once a day only, also when another instance (sharing the database) takes the jobs over
+    daily_entry(&bot.api, &bot.repository).await.unwrap();
+    assert!(bot.api.take().is_empty());
+    let runs = bot.repository.find_job_runs("daily").await.unwrap();
+    assert_eq!(runs.len(), 1);
+    assert_eq!(runs[0].tg_id, USER_ID);
+}
+
+#[tokio::test]
+async fn place_fortune() {
+    std::env::set_var(
+        "SHRINE_LOCATIONS",
+        "Kiyomizu-dera=34.9949,135.7850;Fushimi Inari=34.9671,135.7727",
+    );
+    let mut bot = Bot::default();
+    // Yasaka Shrine, where the way to Kiyomizu-dera goes south-east
+    let yasaka = location(35.0037, 135.7785);
+    message_entry(&yasaka, &bot.api, &mut bot.store, &bot.repository)
+        .await
+        .unwrap();
+    let requests = bot.api.take();
+    assert_eq!(requests.len(), 1);
+    let text = requests[0].text().unwrap().to_string();
+    assert!(text.starts_with("⛩ The kami of this place have read your fortune:"));
+    assert!(text.ends_with("The nearest shrine is Kiyomizu-dera, 1.1 km to the Southeast."));
+    // The time zone of the place is offered, but not taken without asking
+    assert_eq!(requests[0].callbacks(), vec!["timezone/Asia/Tokyo"]);
+    assert_eq!(
+        bot.repository
+            .get_user_settings(USER_ID)
+            .await
+            .unwrap()
+            .time_zone,
+        None
+    );
+    bot.callback("timezone/Asia/Tokyo").await;
+    assert!(bot
+        .last_text()
+        .starts_with("Your time zone is now Asia/Tokyo"));
+    bot.api.take();
+
+    // The place keeps its fortune for the day, and the time zone is not offered again
+    message_entry(&yasaka, &bot.api, &mut bot.store, &bot.repository)
+        .await
+        .unwrap();
+    let requests = bot.api.take();
+    assert_eq!(requests[0].text(), Some(text.as_str()));
+    assert!(requests[0].callbacks().is_empty());
+    std::env::remove_var("SHRINE_LOCATIONS");
+}
+
+#[tokio::test]
+async fn addressed_commands() {
+    use omikuji_bot::commands::Command;
+    let bot_username = Some("OmikujiBot");
+    assert_eq!(Command::addressed_name("draw", bot_username), Some("draw"));
+    assert_eq!(
+        Command::addressed_name("draw@omikujibot", bot_username),
+        Some("draw")
+    );
+    assert_eq!(Command::addressed_name("draw@OtherBot", bot_username), None);
+    assert_eq!(Command::addressed_name("draw@OtherBot", None), Some("draw"));
+
+    // Commands in groups are sent like /help@OmikujiBot
+    let mut bot = Bot::default();
+    bot.text("/help@OmikujiBot").await;
+    let texts = bot.api.texts();
+    assert!(!texts.is_empty());
+    assert!(texts.iter().all(|text| !text.contains("is not recognized")));
+}
+
+#[tokio::test]
+async fn my_data() {
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+    bot.callback("draw").await;
+    bot.api.take();
+
+    bot.text("/mydata").await;
+    let requests = bot.api.take();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "SendDocument");
+    assert_eq!(
+        requests[0].body["caption"],
+        "Here is everything we have stored about you."
+    );
+    assert_eq!(
+        bot.repository
+            .find_draws_by_user(USER_ID)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+
+    // Votes, comments, reactions and booth draws are part of it as well
+    bot.repository.record_vote(USER_ID, 1, 1).await.unwrap();
+    bot.repository
+        .insert_comment(&NewComment {
+            omikuji_id: 1,
+            tg_id: USER_ID,
+            text: "Made my day!",
+            parent_id: None,
+        })
+        .await
+        .unwrap();
+    bot.repository
+        .set_reaction(&NewReaction {
+            tg_id: USER_ID,
+            omikuji_id: 1,
+            reaction: String::from("Pray"),
+        })
+        .await
+        .unwrap();
+    bot.repository
+        .record_booth_draw(&NewBoothDraw {
+            chat_id: -300,
+            tg_id: USER_ID,
+            visitor: String::from("Hanako"),
+            omikuji_id: 1,
+        })
+        .await
+        .unwrap();
+    let data = user_data(&bot.repository, &bot.store, USER_ID)
+        .await
+        .unwrap();
+    assert_eq!(data["draws"][0]["vote"], 1);
+    assert_eq!(data["comments"][0]["text"], "Made my day!");
+    assert_eq!(
+        data["reactions"],
+        json!([{"omikuji_id": 1, "reaction": "Pray"}])
+    );
+    assert_eq!(data["booth_draws"][0]["visitor"], "Hanako");
+}
+
+#[tokio::test]
+async fn forget_me() {
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+    bot.callback("draw").await;
+    bot.callback("settings/daily").await;
+    bot.callback("new").await;
+
+    bot.text("/forgetme").await;
+    assert_eq!(
+        bot.api.take().last().unwrap().callbacks(),
+        vec!["forgetme/confirm", "forgetme/cancel"]
+    );
+    bot.callback("forgetme/cancel").await;
+    assert_eq!(bot.last_text(), "OK, nothing has been deleted.");
+    assert!(!bot.store.is_empty());
+
+    bot.callback("forgetme/confirm").await;
+    assert!(bot.store.is_empty());
+    assert!(bot.repository.draws.borrow().is_empty());
+    assert!(bot.repository.user_settings.borrow().is_empty());
+    let omikujis = bot.repository.omikujis.borrow().clone();
+    assert_eq!(omikujis[0].tg_id, ANONYMOUS_ID);
+    assert!(omikujis[0].anonymous);
+}
+
+#[tokio::test]
+async fn submission_quota() {
+    // Other tests save fewer strips than this
+    std::env::set_var("QUOTA_DAILY", "5");
+    let mut bot = Bot::default();
+    for _ in 0..5 {
+        bot.repository
+            .insert_omikuji(&NewOmikuji {
+                message: "{}",
+                tg_id: USER_ID,
+                tg_name: "Test User",
+                community_id: None,
+                vote_count: 0,
+                anonymous: false,
+                expires_at: None,
+                quarantined: false,
+            })
+            .await
+            .unwrap();
+    }
+
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+    let requests = bot.api.take();
+    let reply = requests.last().unwrap();
+    assert!(reply.body["text"]
+        .as_str()
+        .unwrap()
+        .starts_with("You can submit up to 5 omikuji strips a day."));
+    assert_eq!(reply.callbacks(), vec!["save"]);
+
+    // The draft is kept for later
+    assert_eq!(bot.repository.omikujis.borrow().len(), 5);
+    assert!(bot.store.contains_key(&USER_ID));
+}
+
+#[tokio::test]
+async fn toggle_settings() {
+    let mut bot = Bot::default();
+
+    bot.text("/settings").await;
+    let requests = bot.api.take();
+    assert_eq!(
+        requests[0].callbacks(),
+        vec![
+            "settings/notifications",
+            "settings/language",
+            "settings/daily",
+            "settings/keyboard",
+            "settings/pool",
+            "settings/credit",
+            "settings/digest",
+            "settings/quiet",
+            "settings/timezone"
+        ]
+    );
+
+    bot.callback("settings/daily").await;
+    bot.callback("settings/language").await;
+    bot.callback("settings/pool").await;
+    let settings = bot.repository.user_settings.borrow()[&USER_ID].clone();
+    assert!(settings.daily_subscription);
+    assert_eq!(settings.language, "Japanese");
+    assert_eq!(settings.pool, "Local");
+}
+
+#[tokio::test]
+async fn language_detection() {
+    let mut bot = Bot::default();
+    bot.repository
+        .upsert_user(&NewUser {
+            tg_id: USER_ID,
+            tg_name: "Test User",
+            tg_username: None,
+            language_code: Some("ja-JP"),
+        })
+        .await
+        .unwrap();
+    // New users get the language of their Telegram client, if supported
+    assert_eq!(
+        bot.repository
+            .get_user_settings(USER_ID)
+            .await
+            .unwrap()
+            .language,
+        "Japanese"
+    );
+    assert_eq!(
+        bot.repository
+            .get_user_settings(OTHER_USER_ID)
+            .await
+            .unwrap()
+            .language,
+        "English"
+    );
+
+    bot.text("/language").await;
+    assert_eq!(
+        bot.api.take().last().unwrap().callbacks(),
+        vec!["language/English", "language/Japanese", "language/auto"]
+    );
+    bot.callback("language/English").await;
+    assert_eq!(bot.last_text(), "The bot is now in English.");
+    assert_eq!(
+        bot.repository
+            .get_user_settings(USER_ID)
+            .await
+            .unwrap()
+            .language(),
+        Language::English
+    );
+}
+
+#[tokio::test]
+async fn quick_action_keyboard() {
+    let mut bot = Bot::default();
+
+    bot.callback("settings/keyboard").await;
+    let requests = bot.api.take();
+    let keyboard = requests[0]
+        .body
+        .pointer("/reply_markup/keyboard/0")
+        .unwrap();
+    assert_eq!(
+        keyboard,
+        &json!([{"text": "🎴 Draw"}, {"text": "✍️ Create"}, {"text": "📊 Stats"}])
+    );
+
+    bot.text("🎴 Draw").await;
+    assert_eq!(bot.last_text(), "Oops! Our omikuji library is empty.");
+
+    bot.text("📊 Stats").await;
+    assert!(bot
+        .last_text()
+        .starts_with("You have not written any omikuji strip yet."));
+
+    bot.text("✍️ Create").await;
+    assert!(bot
+        .api
+        .take()
+        .last()
+        .unwrap()
+        .callbacks()
+        .contains(&"class/GreatBlessing"));
+}
+
+#[tokio::test]
+async fn concurrent_votes() {
+    let repository = MemoryRepository::default();
+    repository
+        .insert_omikuji(&NewOmikuji {
+            message: "{}",
+            tg_id: USER_ID,
+            tg_name: "Test User",
+            community_id: None,
+            vote_count: 0,
+            anonymous: false,
+            expires_at: None,
+            quarantined: false,
+        })
+        .await
+        .unwrap();
+
+    // Two voters load the strip before either of their votes is written
+    let first = repository.find_omikuji(1).await.unwrap().unwrap();
+    let second = repository.find_omikuji(1).await.unwrap().unwrap();
+    repository.add_vote(first.id, 1).await.unwrap();
+    repository.add_vote(second.id, 1).await.unwrap();
+    assert_eq!(
+        repository
+            .find_omikuji(1)
+            .await
+            .unwrap()
+            .unwrap()
+            .vote_count,
+        2
+    );
+
+    // Votes handled at the same time by the handlers
+    let api = RecordingApi::default();
+    let (mut first_store, mut second_store) = (HashMap::new(), HashMap::new());
+    let first_vote = callback(OTHER_USER_ID, "vote/-1");
+    let second_vote = callback(OTHER_USER_ID + 1, "vote/-1");
+    let (first, second) = tokio::join!(
+        callback_entry(&first_vote, &api, &mut first_store, &repository),
+        callback_entry(&second_vote, &api, &mut second_store, &repository),
+    );
+    first.unwrap();
+    second.unwrap();
+    assert_eq!(
+        repository
+            .find_omikuji(1)
+            .await
+            .unwrap()
+            .unwrap()
+            .vote_count,
+        0
+    );
+}
+
+#[tokio::test]
+async fn cached_draws() {
+    let repository = CachedRepository::new(MemoryRepository::default());
+    assert!(repository
+        .random_omikuji(DrawPool::Global, None)
+        .await
+        .unwrap()
+        .is_none());
+
+    // Saving a strip refreshes the cached IDs
+    let omikuji = NewOmikuji {
+        message: "{}",
+        tg_id: USER_ID,
+        tg_name: "Test User",
+        community_id: None,
+        vote_count: 0,
+        anonymous: false,
+        expires_at: None,
+        quarantined: false,
+    };
+    repository.insert_omikuji(&omikuji).await.unwrap();
+    assert_eq!(
+        repository
+            .random_omikuji(DrawPool::Global, None)
+            .await
+            .unwrap()
+            .unwrap()
+            .id,
+        1
+    );
+
+    // So does hiding it
+    repository.add_vote(1, HIDE_THRESHOLD).await.unwrap();
+    assert!(repository
+        .random_omikuji(DrawPool::Global, None)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(repository.visible_omikuji_ids().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn community_pools() {
+    let repository = MemoryRepository::default();
+    const GROUP_ID: i64 = -100;
+    for community_id in [None, Some(GROUP_ID)] {
+        let omikuji = NewOmikuji {
+            message: "{}",
+            tg_id: USER_ID,
+            tg_name: "Test User",
+            community_id,
+            vote_count: 0,
+            anonymous: false,
+            expires_at: None,
+            quarantined: false,
+        };
+        repository.insert_omikuji(&omikuji).await.unwrap();
+    }
+    let repository = &repository;
+    let draw = |pool, community| async move {
+        let mut drawn = std::collections::HashSet::new();
+        for _ in 0..20 {
+            drawn.insert(
+                repository
+                    .random_omikuji(pool, community)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .id,
+            );
+        }
+        drawn
+    };
+
+    // Strip 1 was created privately, strip 2 in the group
+    assert_eq!(draw(DrawPool::Local, Some(GROUP_ID)).await, [2].into());
+    assert_eq!(draw(DrawPool::Local, None).await, [1].into());
+    assert_eq!(draw(DrawPool::Both, None).await, [1].into());
+    assert!(repository
+        .random_omikuji(DrawPool::Local, Some(-200))
+        .await
+        .unwrap()
+        .is_none());
+    assert!(draw(DrawPool::Global, None).await.contains(&2));
+    assert!(draw(DrawPool::Both, Some(GROUP_ID)).await.contains(&2));
+}
+
+#[tokio::test]
+async fn chat_migration() {
+    let mut bot = Bot::default();
+    const GROUP_ID: i64 = -100;
+    const SUPERGROUP_ID: i64 = -1001555296434;
+    let omikuji = NewOmikuji {
+        message: "{}",
+        tg_id: USER_ID,
+        tg_name: "Test User",
+        community_id: Some(GROUP_ID),
+        vote_count: 0,
+        anonymous: false,
+        expires_at: None,
+        quarantined: false,
+    };
+    bot.repository.insert_omikuji(&omikuji).await.unwrap();
+    let migration: Message = serde_json::from_value(json!({
+        "message_id": 16,
+        "from": user(USER_ID),
+        "date": 0,
+        "chat": {"id": GROUP_ID, "type": "group", "title": "Test"},
+        "migrate_to_chat_id": SUPERGROUP_ID,
+    }))
+    .unwrap();
+    // A draft started in the group before the upgrade
+    bot.callback("new").await;
+    bot.store.get_mut(&USER_ID).unwrap().community_id = Some(GROUP_ID);
+    assert!(migration_entry(&migration, &mut bot.store, &bot.repository)
+        .await
+        .unwrap());
+    let omikuji = bot.repository.find_omikuji(1).await.unwrap().unwrap();
+    assert_eq!(omikuji.community_id, Some(SUPERGROUP_ID));
+    assert_eq!(bot.store[&USER_ID].community_id, Some(SUPERGROUP_ID));
+    assert!(bot
+        .repository
+        .random_omikuji(DrawPool::Local, Some(GROUP_ID))
+        .await
+        .unwrap()
+        .is_none());
+    assert!(bot
+        .repository
+        .random_omikuji(DrawPool::Local, Some(SUPERGROUP_ID))
+        .await
+        .unwrap()
+        .is_some());
+
+    // Other messages are left to message_entry
+    assert!(
+        !migration_entry(&text("/draw"), &mut bot.store, &bot.repository)
+            .await
+            .unwrap()
+    );
+}
+
+#[tokio::test]
+async fn admin_stats() {
+    std::env::set_var("ADMIN_IDS", USER_ID.to_string());
+    let mut bot = Bot::default();
+    bot.repository
+        .insert_omikuji(&NewOmikuji {
+            message: r#"{"photo":null,"class":"GreatBlessing","description":null,"sections":[]}"#,
+            tg_id: OTHER_USER_ID,
+            tg_name: "Author",
+            community_id: None,
+            vote_count: 0,
+            anonymous: false,
+            expires_at: None,
+            quarantined: false,
+        })
+        .await
+        .unwrap();
+    bot.callback("draw").await;
+    assert_eq!(bot.repository.draws.borrow().len(), 1);
+
+    bot.text("/adminstats").await;
+    let text = bot.last_text();
+    assert!(text.starts_with("*Admin statistics*"));
+    assert!(text.contains("Total strips"));
+    assert!(text.contains("Great Blessing"));
+    assert!(text.contains("Author"));
+    assert!(text.contains("Never drawn                    0\n"));
+    assert!(text.contains("Most drawn strips\n  #1                           1\n"));
+    assert_eq!(
+        bot.repository
+            .find_omikuji(1)
+            .await
+            .unwrap()
+            .unwrap()
+            .draw_count,
+        1
+    );
+}
+
+#[tokio::test]
+async fn usage_analytics() {
+    std::env::set_var("ADMIN_IDS", USER_ID.to_string());
+    let mut bot = Bot::default();
+    // Only the usage tracker, which records whatever reaches the handlers
+    let mut pipeline = Pipeline::new().with(UsageTracker::default());
+    let updates = [text("/draw"), text("/draw extra"), text("Hello")];
+    for message in &updates {
+        pipeline
+            .handle(
+                &message_event(message).unwrap(),
+                &bot.api,
+                &mut bot.store,
+                &bot.repository,
+            )
+            .await
+            .unwrap();
+    }
+    let query = callback(USER_ID, "vote/1/+1");
+    pipeline
+        .handle(
+            &callback_event(&query),
+            &bot.api,
+            &mut bot.store,
+            &bot.repository,
+        )
+        .await
+        .unwrap();
+
+    let today = Local::now().naive_local().date();
+    let usage = bot.repository.usage_by_day(today).await.unwrap();
+    let calls: Vec<(&str, i64)> = usage
+        .iter()
+        .map(|total| (total.command.as_str(), total.invocations))
+        .collect();
+    assert_eq!(calls, vec![("/draw", 2), ("message", 1), ("vote", 1)]);
+    assert!(usage.iter().all(|total| total.failures == 0));
+
+    // Rolled up events are still counted, but no longer kept one by one
+    let tomorrow = Local::now().naive_local() + Duration::days(1);
+    assert_eq!(bot.repository.aggregate_usage(tomorrow).await.unwrap(), 4);
+    assert!(bot.repository.usage_events.borrow().is_empty());
+    assert_eq!(bot.repository.usage_by_day(today).await.unwrap(), usage);
+    pipeline
+        .handle(
+            &message_event(&updates[0]).unwrap(),
+            &bot.api,
+            &mut bot.store,
+            &bot.repository,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        bot.repository.usage_by_day(today).await.unwrap()[0].invocations,
+        3
+    );
+
+    bot.text("/adminstats").await;
+    let text = bot.last_text();
+    assert!(text.contains("\nUsage (7 days)"));
+    assert!(text.contains("  /draw             █      3      0"));
+}
+
+#[tokio::test]
+async fn class_balance() {
+    std::env::set_var("ADMIN_IDS", USER_ID.to_string());
+    let mut bot = Bot::default();
+    bot.text("/balance").await;
+    assert_eq!(bot.last_text(), "There are no strips yet.");
+
+    for class in ["GreatBlessing", "GreatBlessing", "Blessing", "Curse"] {
+        let message = format!(
+            r#"{{"photo":null,"class":"{}","description":null,"sections":[]}}"#,
+            class
+        );
+        bot.repository
+            .insert_omikuji(&NewOmikuji {
+                message: message.as_str(),
+                tg_id: OTHER_USER_ID,
+                tg_name: "Author",
+                community_id: None,
+                vote_count: 0,
+                anonymous: false,
+                expires_at: None,
+                quarantined: false,
+            })
+            .await
+            .unwrap();
+    }
+    bot.text("/balance").await;
+    let text = bot.last_text();
+    assert!(text.contains("Great Blessing                2    50%     16%\n"));
+    assert!(text.contains("Curse                         1    25%     10%\n"));
+    assert!(text.contains("Great Curse                   0     0%      4% !\n"));
+    assert!(text.contains("Other                         0     0%       -\n"));
+    assert!(text.contains("Under-represented: Middle Blessing, Small Blessing, "));
+}
+
+#[tokio::test]
+async fn audit_log() {
+    std::env::set_var("ADMIN_IDS", USER_ID.to_string());
+    let mut bot = Bot::default();
+    bot.text("/audit").await;
+    assert_eq!(bot.last_text(), "The audit log is empty.");
+
+    for id in 1..=12 {
+        bot.repository
+            .record_audit(&NewAuditEntry::new(
+                "web:admin",
+                AuditAction::Approve,
+                format!("omikuji {}", id),
+            ))
+            .await
+            .unwrap();
+    }
+    bot.text("/audit").await;
+    let requests = bot.api.take();
+    let text = requests.last().unwrap().text().unwrap();
+    assert!(text.starts_with("*Audit log* (page 1)"));
+    assert!(text.contains("web:admin Approve omikuji 12\n"));
+    assert!(!text.contains("omikuji 2\n"));
+    assert_eq!(requests.last().unwrap().callbacks(), vec!["audit/1"]);
+
+    bot.callback("audit/1").await;
+    let requests = bot.api.take();
+    assert!(requests
+        .last()
+        .unwrap()
+        .text()
+        .unwrap()
+        .contains("omikuji 1\n"));
+    assert_eq!(requests.last().unwrap().callbacks(), vec!["audit/0"]);
+
+    bot.callback_from(OTHER_USER_ID, "audit/0").await;
+    assert_eq!(bot.last_text(), "This is only available to admins.");
+}
+
+#[tokio::test]
+async fn maintenance_mode() {
+    std::env::set_var("ADMIN_IDS", USER_ID.to_string());
+    let mut bot = Bot::default();
+    // Only the maintenance check, so that other tests using the handlers are not affected
+    let mut pipeline = Pipeline::new().with(MaintenanceCheck);
+
+    bot.text("/maintenance on").await;
+    assert!(in_maintenance());
+    let requests = bot.api.take();
+    assert!(requests
+        .last()
+        .unwrap()
+        .text()
+        .unwrap()
+        .starts_with("Maintenance mode is on."));
+
+    let query = callback(OTHER_USER_ID, "draw");
+    pipeline
+        .handle(
+            &callback_event(&query),
+            &bot.api,
+            &mut bot.store,
+            &bot.repository,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        bot.last_text(),
+        "The bot is under maintenance. Please try again later."
+    );
+
+    // Users who cannot be written to are turned away all the same, without an error
+    bot.api.blocked.borrow_mut().push(OTHER_USER_ID);
+    pipeline
+        .handle(
+            &callback_event(&query),
+            &bot.api,
+            &mut bot.store,
+            &bot.repository,
+        )
+        .await
+        .unwrap();
+    bot.api.blocked.borrow_mut().clear();
+
+    // Admins are still served
+    let query = callback(USER_ID, "draw");
+    pipeline
+        .handle(
+            &callback_event(&query),
+            &bot.api,
+            &mut bot.store,
+            &bot.repository,
+        )
+        .await
+        .unwrap();
+    assert_eq!(bot.last_text(), "Oops! Our omikuji library is empty.");
+
+    bot.text("/maintenance off").await;
+    assert!(!in_maintenance());
+    assert_eq!(bot.repository.audit_log.borrow().len(), 2);
+}
+
+#[test]
+fn reload_config() {
+    let path = std::env::temp_dir().join("omikuji_bot_reload.env");
+    std::fs::write(&path, "TTS_TOKEN=reloaded\n").unwrap();
+    std::env::set_var("TTS_TOKEN", "started");
+    // Values already in the environment are overridden, without changing the environment
+    assert_eq!(reload_from(&path).unwrap(), 1);
+    assert_eq!(get_tts_token().as_deref(), Some("reloaded"));
+    assert_eq!(std::env::var("TTS_TOKEN").unwrap(), "started");
+    // Settings no longer in the file fall back to the environment
+    std::fs::write(&path, "").unwrap();
+    assert_eq!(reload_from(&path).unwrap(), 0);
+    assert_eq!(get_tts_token().as_deref(), Some("started"));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn inspect_strip() {
+    std::env::set_var("ADMIN_IDS", USER_ID.to_string());
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+    bot.callback("draw").await;
+
+    bot.text("/inspect").await;
+    assert_eq!(bot.last_text(), "Usage: /inspect <id>");
+    bot.text("/inspect 2").await;
+    assert_eq!(bot.last_text(), "Requested omikuji cannot be found.");
+    bot.text("/inspect #1").await;
+    let text = bot.last_text();
+    assert!(text.starts_with("#1\nAuthor: Test User (42)\nVotes: 0\n"));
+    assert!(text.contains("\nStatus: visible\nDrawn: 1 times\n\n{"));
+    assert!(text.contains("\"Keep going\""));
+}
+
+#[tokio::test]
+async fn fair_exposure() {
+    // Only FairExposure draws depend on the rate, no other test uses them
+    std::env::set_var("DRAW_EXPLORATION", "1");
+    let repository = MemoryRepository::default();
+    for _ in 0..3 {
+        repository
+            .insert_omikuji(&NewOmikuji {
+                message: "{}",
+                tg_id: OTHER_USER_ID,
+                tg_name: "Author",
+                community_id: None,
+                vote_count: 0,
+                anonymous: false,
+                expires_at: None,
+                quarantined: false,
+            })
+            .await
+            .unwrap();
+    }
+    for omikuji_id in [1, 1, 2, 3] {
+        repository
+            .record_draw(&NewDraw {
+                tg_id: USER_ID,
+                omikuji_id,
+                variant: None,
+            })
+            .await
+            .unwrap();
+    }
+    for _ in 0..10 {
+        let omikuji = pick_omikuji(
+            &repository,
+            DrawStrategy::FairExposure,
+            DrawPool::Both,
+            None,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_ne!(omikuji.id, 1);
+    }
+    assert!(
+        pick_omikuji(&repository, DrawStrategy::Uniform, DrawPool::Local, Some(1))
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn draw_experiment() {
+    std::env::set_var("ADMIN_IDS", USER_ID.to_string());
+    let mut bot = Bot::default();
+    bot.text("/experiment").await;
+    assert!(bot.last_text().starts_with("No draws have been recorded"));
+
+    // Users are split by their ID, so USER_ID (42) and OTHER_USER_ID (43) get different variants
+    std::env::set_var("DRAW_EXPERIMENT", "Uniform,Weighted");
+    assert_eq!(
+        assign_strategy(USER_ID),
+        (DrawStrategy::Uniform, Some("Uniform".to_string()))
+    );
+    assert_eq!(assign_strategy(OTHER_USER_ID).0, DrawStrategy::Weighted);
+    std::env::remove_var("DRAW_EXPERIMENT");
+
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+    for (tg_id, variant) in [(USER_ID, "Uniform"), (OTHER_USER_ID, "Weighted")] {
+        bot.repository
+            .record_draw(&NewDraw {
+                tg_id,
+                omikuji_id: 1,
+                variant: Some(variant.to_string()),
+            })
+            .await
+            .unwrap();
+    }
+    bot.callback_from(OTHER_USER_ID, "vote/+1").await;
+    // Only the first vote on a draw counts
+    bot.callback_from(OTHER_USER_ID, "vote/-1").await;
+
+    let results = bot.repository.experiment_results().await.unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!((results[0].draws, results[0].upvotes), (1, 0));
+    assert_eq!(
+        (results[1].variant.as_str(), results[1].upvotes),
+        ("Weighted", 1)
+    );
+    assert_eq!(results[1].downvotes, 0);
+
+    bot.text("/experiment").await;
+    let text = bot.last_text();
+    assert!(text.contains("Weighted            1      1      1      0    100%\n"));
+    assert!(text.ends_with("The experiment is not running (DRAW_EXPERIMENT is not set)."));
+}
+
+#[tokio::test]
+async fn last_strip() {
+    let mut bot = Bot::default();
+    bot.text("/last").await;
+    assert_eq!(
+        bot.last_text(),
+        "You have not drawn any omikuji yet. Try /draw!"
+    );
+
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+    bot.callback("draw").await;
+    let drawn = bot.last_text();
+    bot.api.take();
+
+    // The same strip with the same extras, without recording another draw
+    bot.text("/last").await;
+    let requests = bot.api.take();
+    let text = requests.last().unwrap().text().unwrap();
+    assert!(text.starts_with("Your last omikuji strip, drawn on "));
+    let extras = &drawn[drawn.find("*Lucky number*").unwrap()..];
+    assert!(text.contains(extras.split("\n\n").next().unwrap()));
+    assert_eq!(
+        requests.last().unwrap().callbacks(),
+        vec![
+            "vote/+1",
+            "vote/-1",
+            "favorite/1",
+            "comment/1",
+            "draw",
+            "react/1/Pray",
+            "react/1/Laugh",
+            "react/1/Scream",
+            "react/1/Heart"
+        ]
+    );
+    assert_eq!(bot.repository.draws.borrow().len(), 1);
+
+    // Hidden strips are not shown again either
+    bot.repository.omikujis.borrow_mut()[0].quarantined = true;
+    bot.text("/last").await;
+    assert_eq!(
+        bot.last_text(),
+        "Your last omikuji strip is no longer available."
+    );
+
+    bot.repository.delete_omikuji(1).await.unwrap();
+    bot.text("/last").await;
+    assert_eq!(
+        bot.last_text(),
+        "Your last omikuji strip is no longer available."
+    );
+}
+
+#[tokio::test]
+async fn favorites() {
+    let mut bot = Bot::default();
+    bot.text("/favorites").await;
+    assert_eq!(
+        bot.last_text(),
+        "You have no favorites yet. Save a strip with ⭐ after drawing it!"
+    );
+
+    for text in ["Not bad", "Quite good", "Very good"] {
+        bot.callback("new").await;
+        bot.callback("class/Blessing").await;
+        bot.text(text).await;
+        bot.callback("section/Study").await;
+        bot.text("Keep going").await;
+        bot.callback("save").await;
+    }
+    bot.callback("favorite/1").await;
+    assert_eq!(
+        bot.last_text(),
+        "Saved to your favorites. Read them again with /favorites."
+    );
+    bot.callback("favorite/1").await;
+    assert_eq!(
+        bot.last_text(),
+        "This omikuji strip is already in your favorites."
+    );
+    bot.callback("favorite/3").await;
+    bot.callback("favorite/9").await;
+    assert_eq!(bot.last_text(), "Requested omikuji cannot be found.");
+    bot.api.take();
+
+    // Most recently saved first, one strip per page
+    bot.text("/favorites").await;
+    let requests = bot.api.take();
+    let strip = requests.last().unwrap();
+    assert!(strip.text().unwrap().starts_with("⭐ Favorite 1 of 2:"));
+    assert!(strip.text().unwrap().contains("Very good"));
+    assert_eq!(strip.callbacks(), vec!["favorites/1"]);
+    bot.callback("favorites/1").await;
+    let requests = bot.api.take();
+    let strip = requests.last().unwrap();
+    assert!(strip.text().unwrap().contains("Not bad"));
+    assert_eq!(strip.callbacks(), vec!["favorites/0"]);
+
+    bot.repository.delete_omikuji(3).await.unwrap();
+    bot.text("/favorites").await;
+    assert!(bot.last_text().starts_with("⭐ Favorite 1 of 1:"));
+}
+
+#[tokio::test]
+async fn comments() {
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+    bot.text("/comments").await;
+    assert_eq!(
+        bot.last_text(),
+        "There are no comments on your omikuji strips yet."
+    );
+
+    // Only strips which have been drawn can be commented on
+    bot.callback_from(OTHER_USER_ID, "comment/1").await;
+    assert_eq!(
+        bot.last_text(),
+        "You can only comment on omikuji strips you have drawn."
+    );
+    bot.callback_from(OTHER_USER_ID, "draw").await;
+    bot.callback_from(OTHER_USER_ID, "comment/1").await;
+    let prompt = bot.last_text();
+    assert!(prompt.starts_with("💬 Comment on omikuji #1\n"));
+    bot.api.take();
+
+    let message = reply(OTHER_USER_ID, "Made my  day!", prompt.as_str());
+    message_entry(&message, &bot.api, &mut bot.store, &bot.repository)
+        .await
+        .unwrap();
+    let texts = bot.api.texts();
+    assert_eq!(
+        texts[0],
+        "Thank you! Your comment has been sent to the author."
+    );
+    // The author is notified without learning who commented
+    assert!(texts[1].starts_with("💬 Comment #1 on your omikuji slip #1:\n\nMade my day!"));
+    let long = "a".repeat(281);
+    let message = reply(OTHER_USER_ID, long.as_str(), prompt.as_str());
+    message_entry(&message, &bot.api, &mut bot.store, &bot.repository)
+        .await
+        .unwrap();
+    assert!(bot
+        .last_text()
+        .starts_with("Comments are limited to 280 characters"));
+    assert_eq!(bot.repository.comments.borrow().len(), 1);
+
+    bot.text("/comments").await;
+    assert!(bot.last_text().ends_with(": Made my day!"));
+
+    bot.text("/moderatecomments").await;
+    let requests = bot.api.take();
+    assert_eq!(
+        requests.last().unwrap().callbacks(),
+        vec!["delete_comment/1"]
+    );
+    bot.callback("delete_comment/1").await;
+    assert_eq!(bot.last_text(), "Comment 1 has been deleted.");
+    assert!(bot.repository.comments.borrow().is_empty());
+    let audit_log = bot.repository.audit_log.borrow();
+    assert_eq!(audit_log.last().unwrap().target, "comment 1");
+}
+
+#[tokio::test]
+async fn comment_replies() {
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+    bot.callback_from(OTHER_USER_ID, "draw").await;
+    bot.api.take();
+    let message = reply(OTHER_USER_ID, "Made my day!", "💬 Comment on omikuji #1\n");
+    message_entry(&message, &bot.api, &mut bot.store, &bot.repository)
+        .await
+        .unwrap();
+    let requests = bot.api.take();
+    let notification = requests.last().unwrap();
+    assert_eq!(notification.callbacks(), vec!["thank/1"]);
+    let notification = notification.text().unwrap().to_string();
+
+    // The author answers the commenter, and the commenter the author, through the bot
+    let message = reply(USER_ID, "Glad to hear it", notification.as_str());
+    message_entry(&message, &bot.api, &mut bot.store, &bot.repository)
+        .await
+        .unwrap();
+    let texts = bot.api.texts();
+    assert_eq!(texts[0], "Your reply has been sent.");
+    assert!(texts[1].starts_with("💬 Reply #2 about omikuji slip #1:\n\nGlad to hear it\n\n"));
+    assert!(!texts[1].contains("Test User"));
+    let message = reply(OTHER_USER_ID, "Thanks!", texts[1].as_str());
+    message_entry(&message, &bot.api, &mut bot.store, &bot.repository)
+        .await
+        .unwrap();
+    assert!(bot
+        .last_text()
+        .starts_with("💬 Reply #3 about omikuji slip #1:\n\nThanks!"));
+
+    // Nobody else can join the conversation
+    let message = reply(44, "Me too", notification.as_str());
+    message_entry(&message, &bot.api, &mut bot.store, &bot.repository)
+        .await
+        .unwrap();
+    assert_eq!(bot.last_text(), "This conversation is no longer available.");
+
+    bot.callback("thank/1").await;
+    assert!(bot
+        .last_text()
+        .starts_with("💬 Reply #4 about omikuji slip #1:\n\n🙏 Thank you for your comment!"));
+    let parents: Vec<Option<u32>> = bot
+        .repository
+        .comments
+        .borrow()
+        .iter()
+        .map(|comment| comment.parent_id)
+        .collect();
+    assert_eq!(parents, vec![None, Some(1), Some(2), Some(1)]);
+
+    // The author's own replies are not listed with the comments they received
+    bot.text("/comments").await;
+    let text = bot.last_text();
+    assert!(text.contains("Thanks!"));
+    assert!(!text.contains("Glad to hear it"));
+}
+
+#[tokio::test]
+async fn weekly_digest() {
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+    // The author's own draws are left out
+    bot.callback("draw").await;
+    bot.callback_from(OTHER_USER_ID, "draw").await;
+    bot.callback_from(OTHER_USER_ID, "vote/+1").await;
+    let message = reply(OTHER_USER_ID, "Made my day!", "💬 Comment on omikuji #1\n");
+    message_entry(&message, &bot.api, &mut bot.store, &bot.repository)
+        .await
+        .unwrap();
+
+    let since = Local::now().naive_local() - Duration::days(7);
+    let digest = bot.repository.author_digest(USER_ID, since).await.unwrap();
+    assert_eq!(
+        digest,
+        AuthorDigest {
+            draws: 1,
+            upvotes: 1,
+            downvotes: 0,
+            comments: 1,
+        }
+    );
+    let later = Local::now().naive_local() + Duration::days(1);
+    let digest = bot.repository.author_digest(USER_ID, later).await.unwrap();
+    assert_eq!(digest, AuthorDigest::default());
+
+    assert!(bot
+        .repository
+        .get_digest_subscribers()
+        .await
+        .unwrap()
+        .is_empty());
+    bot.callback("settings/digest").await;
+    let subscribers = bot.repository.get_digest_subscribers().await.unwrap();
+    assert_eq!(subscribers.len(), 1);
+    assert_eq!(subscribers[0].tg_id, USER_ID);
+}
+
+#[tokio::test]
+async fn blocked_users() {
+    let mut bot = Bot::default();
+    // Only the user upsert, which brings users back when they write to the bot again
+    let mut pipeline = Pipeline::new().with(UserUpsert);
+    for tg_id in [USER_ID, OTHER_USER_ID] {
+        bot.repository
+            .upsert_user(&NewUser {
+                tg_id,
+                tg_name: "Test",
+                tg_username: None,
+                language_code: None,
+            })
+            .await
+            .unwrap();
+        bot.callback_from(tg_id, "settings/daily").await;
+    }
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+    bot.callback_from(OTHER_USER_ID, "draw").await;
+
+    // The author blocked the bot, which is noticed when a comment is delivered to them
+    bot.api.blocked.borrow_mut().push(USER_ID);
+    let message = reply(OTHER_USER_ID, "Made my day!", "💬 Comment on omikuji #1\n");
+    message_entry(&message, &bot.api, &mut bot.store, &bot.repository)
+        .await
+        .unwrap();
+    assert!(
+        bot.repository
+            .find_user(USER_ID)
+            .await
+            .unwrap()
+            .unwrap()
+            .inactive
+    );
+    let subscribers = bot.repository.get_daily_subscribers().await.unwrap();
+    assert_eq!(subscribers.len(), 1);
+    assert_eq!(subscribers[0].tg_id, OTHER_USER_ID);
+
+    // Failing to reply to a blocked user does not stop the bot
+    let message = text("/help");
+    pipeline
+        .handle(
+            &message_event(&message).unwrap(),
+            &bot.api,
+            &mut bot.store,
+            &bot.repository,
+        )
+        .await
+        .unwrap();
+    assert!(
+        bot.repository
+            .find_user(USER_ID)
+            .await
+            .unwrap()
+            .unwrap()
+            .inactive
+    );
+
+    bot.api.blocked.borrow_mut().clear();
+    pipeline
+        .handle(
+            &message_event(&message).unwrap(),
+            &bot.api,
+            &mut bot.store,
+            &bot.repository,
+        )
+        .await
+        .unwrap();
+    assert!(
+        !bot.repository
+            .find_user(USER_ID)
+            .await
+            .unwrap()
+            .unwrap()
+            .inactive
+    );
+    let mut subscribers: Vec<i64> = bot
+        .repository
+        .get_daily_subscribers()
+        .await
+        .unwrap()
+        .iter()
+        .map(|settings| settings.tg_id)
+        .collect();
+    subscribers.sort();
+    assert_eq!(subscribers, vec![USER_ID, OTHER_USER_ID]);
+}
+
+#[test]
+fn error_kinds() {
+    let error = BotError::NotFound(String::from("Omikuji 3"));
+    assert_eq!(error.kind(), "not_found");
+    assert_eq!(error.to_string(), "Omikuji 3 cannot be found");
+    let error = BotError::from(RequestError::Api(ApiError::BotBlocked));
+    assert_eq!(error.kind(), "telegram");
+    assert!(is_blocked(&error));
+    let error = BotError::from(serde_json::from_str::<OmikujiMessage>("{").unwrap_err());
+    assert_eq!(error.kind(), "state");
+    assert!(!is_blocked(&error));
+}
+
+#[tokio::test]
+async fn reactions() {
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+
+    bot.callback("react/1/Laugh").await;
+    assert_eq!(bot.last_text(), "You reacted 😂 to the omikuji slip #1.");
+    // A new reaction replaces the previous one of the same user
+    bot.callback("react/1/Pray").await;
+    bot.callback_from(OTHER_USER_ID, "react/1/Pray").await;
+    bot.callback("react/1/Sleepy").await;
+    assert_eq!(bot.last_text(), "Malformed callback request.");
+    bot.callback("react/9/Pray").await;
+    assert_eq!(bot.last_text(), "Requested omikuji cannot be found.");
+    bot.api.take();
+
+    bot.callback("draw").await;
+    let requests = bot.api.take();
+    let keyboard = requests.last().unwrap().labels();
+    assert!(keyboard.ends_with(&["🙏 2", "😂", "😱", "❤️"]));
+}
+
+#[tokio::test]
+async fn tie_great_curse() {
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.callback("class/GreatCurse").await;
+    bot.text("Stay home").await;
+    bot.callback("section/Study").await;
+    bot.text("Do not bother").await;
+    bot.callback("save").await;
+    bot.api.take();
+
+    // A great curse asks before revealing itself
+    bot.callback("draw").await;
+    let requests = bot.api.take();
+    let prompt = requests.last().unwrap();
+    assert!(prompt
+        .text()
+        .unwrap()
+        .ends_with("Are you sure you want to know?"));
+    assert_eq!(prompt.callbacks(), vec!["reveal/1", "tie/1"]);
+    bot.callback("reveal/1").await;
+    let requests = bot.api.take();
+    let strip = requests.last().unwrap();
+    assert!(strip.text().unwrap().contains("Stay home"));
+    assert!(strip.callbacks().contains(&"tie/1"));
+    bot.callback_from(OTHER_USER_ID, "reveal/1").await;
+    assert_eq!(
+        bot.last_text(),
+        "This omikuji strip is no longer waiting to be revealed."
+    );
+
+    // Tied, it is gone from the history
+    bot.callback("tie/1").await;
+    assert!(bot.last_text().starts_with("🪢 You tied the bad fortune"));
+    assert!(bot.repository.draws.borrow()[0].tied_at.is_some());
+    bot.callback("tie/1").await;
+    assert_eq!(
+        bot.last_text(),
+        "There is no bad fortune of yours left to tie here."
+    );
+    bot.text("/last").await;
+    assert_eq!(
+        bot.last_text(),
+        "You have not drawn any omikuji yet. Try /draw!"
+    );
+    bot.callback("reveal/1").await;
+    assert_eq!(
+        bot.last_text(),
+        "This omikuji strip is no longer waiting to be revealed."
+    );
+}
+
+#[tokio::test]
+async fn my_strips() {
+    let mut bot = Bot::default();
+    bot.text("/mystrips").await;
+    assert_eq!(
+        bot.last_text(),
+        "You have not written any omikuji strips yet. Create one with /new!"
+    );
+    bot.callback("new").await;
+    bot.callback("class/Blessing").await;
+    bot.text("Not bad").await;
+    bot.callback("section/Study").await;
+    bot.text("Keep going").await;
+    bot.callback("save").await;
+    bot.callback_from(OTHER_USER_ID, "draw").await;
+    bot.api.take();
+
+    bot.text("/mystrips").await;
+    let requests = bot.api.take();
+    let list = requests.last().unwrap();
+    assert!(list.text().unwrap().contains("#1 "));
+    assert!(list
+        .text()
+        .unwrap()
+        .contains("drawn 1 times, 0 votes (score 0.0), visible"));
+    assert_eq!(
+        list.callbacks(),
+        vec!["mystrip/edit/1", "mystrip/delete/1", "mystrip/anonymous/1"]
+    );
+
+    // Only the author can manage a strip
+    bot.callback_from(OTHER_USER_ID, "mystrip/delete/1").await;
+    assert_eq!(bot.last_text(), "Requested omikuji cannot be found.");
+    bot.callback("mystrip/anonymous/1").await;
+    assert!(bot.repository.omikujis.borrow()[0].anonymous);
+
+    // Edits are saved over the original strip
+    bot.callback("mystrip/edit/1").await;
+    assert_eq!(bot.store[&USER_ID].editing, Some(1));
+    bot.callback("section/Love").await;
+    bot.text("Be bold").await;
+    bot.callback("save").await;
+    assert_eq!(bot.last_text(), "Your omikuji strip #1 has been updated.");
+    assert_eq!(bot.repository.omikujis.borrow().len(), 1);
+    assert!(bot.repository.omikujis.borrow()[0]
+        .message
+        .contains("Be bold"));
+    assert!(bot.repository.omikujis.borrow()[0].anonymous);
+
+    bot.callback("mystrip/delete/1").await;
+    let requests = bot.api.take();
+    assert_eq!(
+        requests.last().unwrap().callbacks(),
+        vec!["mystrip/confirm_delete/1", "mystrip/keep/1"]
+    );
+    bot.callback("mystrip/confirm_delete/1").await;
+    assert_eq!(bot.last_text(), "Your omikuji strip #1 has been deleted.");
+    assert!(bot.repository.omikujis.borrow().is_empty());
+}
+
+#[tokio::test]
+async fn shared_drafts() {
+    // Two instances of the bot, with the drafts shared through a third repository
+    let drafts = MemoryRepository::default();
+    let mut first = Bot::default();
+    let mut second = Bot::default();
+
+    first.callback("new").await;
+    first.callback("class/Blessing").await;
+    first.store.get_mut(&USER_ID).unwrap().community_id = Some(-100);
+    first.store.get_mut(&USER_ID).unwrap().editing = Some(7);
+    save_draft_from(&drafts, &first.store, USER_ID)
+        .await
+        .unwrap();
+
+    load_draft_into(&drafts, &mut second.store, USER_ID)
+        .await
+        .unwrap();
+    let draft = &second.store[&USER_ID];
+    assert_eq!(draft.community_id, Some(-100));
+    assert_eq!(draft.editing, Some(7));
+    assert_eq!(draft.touched_at, first.store[&USER_ID].touched_at);
+    second.store.get_mut(&USER_ID).unwrap().editing = None;
+    second.text("Not bad").await;
+    second.callback("section/Study").await;
+    second.text("Keep going").await;
+    second.callback("save").await;
+    assert_eq!(second.repository.omikujis.borrow().len(), 1);
+
+    // Saving the strip deletes the draft for every instance
+    save_draft_from(&drafts, &second.store, USER_ID)
+        .await
+        .unwrap();
+    assert!(drafts.load_draft(USER_ID).await.unwrap().is_none());
+    load_draft_into(&drafts, &mut first.store, USER_ID)
+        .await
+        .unwrap();
+    assert!(first.store.is_empty());
+}
+
+#[tokio::test]
+async fn shared_draft_reminders() {
+    let drafts = MemoryRepository::default();
+    let mut bot = Bot::default();
+    let stalled = chrono::Local::now().naive_local() - chrono::Duration::minutes(11);
+    bot.callback("new").await;
+    bot.callback_from(OTHER_USER_ID, "new").await;
+    for tg_id in [USER_ID, OTHER_USER_ID] {
+        bot.store.get_mut(&tg_id).unwrap().touched_at = Some(stalled);
+        save_draft_from(&drafts, &bot.store, tg_id).await.unwrap();
+    }
+    let (mut stored, revisions) = load_drafts(&drafts).await.unwrap();
+
+    // Other instances handle the users while the reminders are sent
+    bot.callback("class/Blessing").await;
+    save_draft_from(&drafts, &bot.store, USER_ID).await.unwrap();
+    drafts.delete_draft(OTHER_USER_ID).await.unwrap();
+    let reminded = reminder_entry(&bot.api, &mut stored, &bot.repository)
+        .await
+        .unwrap();
+    assert_eq!(reminded.len(), 2);
+    save_changed_drafts(&drafts, &stored, &revisions, &reminded)
+        .await
+        .unwrap();
+    let draft = drafts.load_draft(USER_ID).await.unwrap().unwrap();
+    assert!(draft.class.is_some());
+    assert!(!draft.reminded);
+    assert!(drafts.load_draft(OTHER_USER_ID).await.unwrap().is_none());
+
+    // Drafts nobody touched in between get the flag, so they are only reminded once
+    bot.store.get_mut(&USER_ID).unwrap().touched_at = Some(stalled);
+    save_draft_from(&drafts, &bot.store, USER_ID).await.unwrap();
+    let (mut stored, revisions) = load_drafts(&drafts).await.unwrap();
+    let reminded = reminder_entry(&bot.api, &mut stored, &bot.repository)
+        .await
+        .unwrap();
+    assert_eq!(reminded, vec![USER_ID]);
+    save_changed_drafts(&drafts, &stored, &revisions, &reminded)
+        .await
+        .unwrap();
+    assert!(drafts.load_draft(USER_ID).await.unwrap().unwrap().reminded);
+    let (mut stored, _) = load_drafts(&drafts).await.unwrap();
+    assert!(reminder_entry(&bot.api, &mut stored, &bot.repository)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn scheduler_lease() {
+    let repository = MemoryRepository::default();
+    let duration = std::time::Duration::from_secs(180);
+    assert!(repository
+        .acquire_lease("scheduler", "first", duration)
+        .await
+        .unwrap());
+    assert!(!repository
+        .acquire_lease("scheduler", "second", duration)
+        .await
+        .unwrap());
+    assert!(repository
+        .acquire_lease("scheduler", "first", duration)
+        .await
+        .unwrap());
+    assert!(repository
+        .acquire_lease("other", "second", duration)
+        .await
+        .unwrap());
+
+    // The lease of a stopped instance expires
+    repository
+        .leases
+        .borrow_mut()
+        .get_mut("scheduler")
+        .unwrap()
+        .1 -= Duration::minutes(5);
+    assert!(repository
+        .acquire_lease("scheduler", "second", duration)
+        .await
+        .unwrap());
+    assert!(!repository
+        .acquire_lease("scheduler", "first", duration)
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn shrine_board() {
+    std::env::set_var("SHRINE_BOARD_ID", "@shrine");
+    let mut bot = Bot::default();
+    bot.callback("new").await;
+    bot.callback("class/Curse").await;
+    bot.text("Stay home").await;
+    bot.callback("section/Study").await;
+    bot.text("Do not bother").await;
+    bot.callback("save").await;
+    bot.callback("draw").await;
+    bot.api.take();
+
+    shrine_entry(&bot.api, &bot.repository).await.unwrap();
+    let requests = bot.api.take();
+    assert_eq!(requests[0].method, "SendMessage");
+    assert!(requests[0].text().unwrap().contains("0 bad fortunes tied"));
+
+    // The tally is edited in place, without telling who tied
+    bot.callback("tie/1").await;
+    bot.api.take();
+    shrine_entry(&bot.api, &bot.repository).await.unwrap();
+    let requests = bot.api.take();
+    assert_eq!(requests[0].method, "EditMessageText");
+    assert!(requests[0].text().unwrap().contains("1 bad fortune tied"));
+    // The board is kept in the database, so an instance taking the jobs over edits it as well
+    shrine_entry(&bot.api, &bot.repository).await.unwrap();
+    assert!(bot.api.take().is_empty());
+    let runs = bot.repository.find_job_runs("shrine_board").await.unwrap();
+    assert_eq!(runs[0].count, Some(1));
+
+    // Last month's ties are cleared from the board
+    let since = Local::now().naive_local() + Duration::days(1);
+    assert_eq!(bot.repository.count_tied_draws(since).await.unwrap(), 0);
+    std::env::remove_var("SHRINE_BOARD_ID");
+}
+
+#[tokio::test]
+async fn weekly_poll() {
+    let mut bot = Bot::default();
+    let chat = Recipient::ChannelUsername(String::from("@omikuji"));
+    for description in &["First", "Second"] {
+        bot.callback("new").await;
+        bot.callback("class/Blessing").await;
+        bot.text(description).await;
+        bot.callback("section/Study").await;
+        bot.text("Keep going").await;
+        bot.callback("save").await;
+    }
+    bot.callback_from(OTHER_USER_ID, "draw").await;
+    bot.api.take();
+
+    weekly_poll_round(&bot.api, &bot.repository, chat.clone())
+        .await
+        .unwrap();
+    let requests = bot.api.take();
+    assert_eq!(requests[0].method, "SendPoll");
+    assert_eq!(
+        requests[0].body["options"],
+        json!(["#1 Blessing: First", "#2 Blessing: Second"])
+    );
+    // Only one round a day
+    weekly_poll_round(&bot.api, &bot.repository, chat.clone())
+        .await
+        .unwrap();
+    assert!(bot.api.take().is_empty());
+
+    // A week later, the second strip wins and its author gets a badge
+    bot.repository.weekly_polls.borrow_mut()[0].created_at -= Duration::days(7);
+    bot.api.poll_votes.replace(vec![1, 3]);
+    weekly_poll_round(&bot.api, &bot.repository, chat)
+        .await
+        .unwrap();
+    let texts = bot.api.texts();
+    assert!(texts[0].starts_with("🏆 The omikuji of the week is #2, with 3 votes"));
+    assert!(texts[1].contains("Omikuji of the Week"));
+    assert_eq!(bot.repository.find_weekly_winners().await.unwrap(), vec![2]);
+    assert!(bot
+        .repository
+        .find_open_weekly_poll()
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[test]
+fn config_validation() {
+    std::env::set_var("DATABASE_URL", "");
+    std::env::set_var("TELEGRAM_BOT_TOKEN", "not a token");
+    std::env::set_var("QUOTA_TOTAL", "ten");
+    let problems = validate();
+    assert!(problems[0].starts_with("DATABASE_URL is not set"));
+    assert!(problems
+        .iter()
+        .any(|problem| problem.starts_with("The bot token of default")));
+    assert!(problems.iter().any(|problem| problem.contains("\"ten\"")));
+    std::env::remove_var("QUOTA_TOTAL");
+}
+
+#[tokio::test]
+async fn channel_post() {
+    std::env::set_var("CHANNEL_ID", "@omikuji");
+    std::env::set_var("CHANNEL_PIN", "true");
+    std::env::set_var("BOT_USERNAME", "omikuji_bot");
+    let bot = Bot::default();
+    bot.repository
+        .insert_omikuji(&NewOmikuji {
+            message: r#"{"photo":null,"class":"Blessing","description":"Good","sections":[]}"#,
+            tg_id: USER_ID,
+            tg_name: "Test User",
+            community_id: None,
+            vote_count: 0,
+            anonymous: false,
+            expires_at: None,
+            quarantined: false,
+        })
+        .await
+        .unwrap();
+
+    post_to_channel(&bot.api, &bot.repository).await.unwrap();
+    let requests = bot.api.take();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].body["chat_id"], "@omikuji");
+    assert!(requests[0]
+        .text()
+        .unwrap()
+        .starts_with("Omikuji of the day:"));
+    assert_eq!(
+        requests[0].body["reply_markup"]["inline_keyboard"][0][0]["url"],
+        "https://t.me/omikuji_bot?start=draw"
+    );
+    assert_eq!(requests[1].method, "PinChatMessage");
+    assert_eq!(requests[1].body["message_id"], 1);
+    // Channel posts are not counted as draws
+    assert!(bot.repository.draws.borrow().is_empty());
+}
+
+#[tokio::test]
+async fn dry_run() {
+    // Replies are only logged, but the handlers work as usual
+    let api = DryRunApi::default();
+    let mut store = HashMap::new();
+    let repository = MemoryRepository::default();
+    for command in ["/start", "/today"] {
+        message_entry(&text(command), &api, &mut store, &repository)
+            .await
+            .unwrap();
+    }
+    assert!(api.sent() >= 2);
+}
+
+#[tokio::test]
+async fn replay_updates() {
+    let dir = std::env::temp_dir().join("omikuji_bot_updates");
+    let dir = dir.to_str().unwrap();
+    let path = update_log::log_file(dir, "replay");
+    std::fs::remove_file(&path).ok();
+
+    for (id, command) in ["/start", "/today"].iter().enumerate() {
+        // Parsed from text like updates from Telegram, which does not work from a Value
+        let update = serde_json::from_str(
+            &json!({
+            "update_id": id,
+            "message": {
+                "message_id": id,
+                "from": user(USER_ID),
+                "date": 0,
+                "chat": {"id": USER_ID, "type": "private", "first_name": "Test"},
+                "text": command,
+            },
+            })
+            .to_string(),
+        )
+        .unwrap();
+        update_log::record(dir, "replay", &update).unwrap();
+    }
+
+    let updates = update_log::read(&path).unwrap();
+    assert_eq!(updates.len(), 2);
+    assert_eq!(updates[1].id, 1);
+
+    let mut bot = Bot::default();
+    for update in updates {
+        if let UpdateKind::Message(message) = update.kind {
+            message_entry(&message, &bot.api, &mut bot.store, &bot.repository)
+                .await
+                .unwrap();
+        }
+    }
+    assert!(bot
+        .api
+        .texts()
+        .iter()
+        .any(|text| text.starts_with("Your fortune for")));
+}
+
+// Counts the updates it has seen the end of, e.g. like the logger
+struct Ending(std::rc::Rc<std::cell::Cell<usize>>);
+
+#[async_trait::async_trait(?Send)]
+impl Middleware for Ending {
+    async fn before(
+        &mut self,
+        _event: &IncomingEvent,
+        _api: &dyn BotApi,
+        _repository: &dyn Repository,
+    ) -> Result<bool, BotError> {
+        Ok(true)
+    }
+
+    async fn after(
+        &mut self,
+        _event: &IncomingEvent,
+        _repository: &dyn Repository,
+        result: Option<&Result<(), BotError>>,
+    ) {
+        assert!(result.is_none());
+        self.0.set(self.0.get() + 1);
+    }
+}
+
+struct Failing;
+
+#[async_trait::async_trait(?Send)]
+impl Middleware for Failing {
+    async fn before(
+        &mut self,
+        _event: &IncomingEvent,
+        _api: &dyn BotApi,
+        _repository: &dyn Repository,
+    ) -> Result<bool, BotError> {
+        Err(BotError::State(String::from("the database is gone")))
+    }
+}
+
+#[tokio::test]
+async fn failing_middleware() {
+    let mut bot = Bot::default();
+    let ended = std::rc::Rc::new(std::cell::Cell::new(0));
+    let mut pipeline = Pipeline::new()
+        .with(Ending(ended.clone()))
+        .with(Failing)
+        .with(Ending(ended.clone()));
+    let result = pipeline
+        .handle(
+            &message_event(&text("/draw")).unwrap(),
+            &bot.api,
+            &mut bot.store,
+            &bot.repository,
+        )
+        .await;
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Unexpected state: the database is gone"
+    );
+    // Only the middleware before the failing one has passed, the update never reached the handlers
+    assert_eq!(ended.get(), 1);
+    assert!(bot.api.take().is_empty());
+}
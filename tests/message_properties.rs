@@ -0,0 +1,162 @@
+use omikuji_bot::models::{kanji_numeral, Language, OmikujiClass, OmikujiMessage, OmikujiSection};
+use omikuji_bot::sanitize::sanitize;
+use omikuji_bot::signing::{sign_with, verify_with};
+use omikuji_bot::telegram_ext::{split_message, MESSAGE_LIMIT};
+use proptest::prelude::*;
+use strum::IntoEnumIterator;
+
+// Free text as users might type it: Unicode, emoji and Markdown metacharacters
+fn text() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "\\PC{0,64}",
+        "[a-z 大吉凶恋愛🎋🍀👍🏽*_`\\[\\]()\\\\\n]{0,256}",
+        // Huge sections, longer than a single Telegram message
+        "[a-z 🎋\n]{4000,6000}",
+    ]
+}
+
+fn class() -> impl Strategy<Value = OmikujiClass> {
+    (0..OmikujiClass::iter().count()).prop_map(|i| OmikujiClass::iter().nth(i).unwrap())
+}
+
+fn section() -> impl Strategy<Value = OmikujiSection> {
+    (0..OmikujiSection::iter().count()).prop_map(|i| OmikujiSection::iter().nth(i).unwrap())
+}
+
+fn language() -> impl Strategy<Value = Language> {
+    (0..Language::iter().count()).prop_map(|i| Language::iter().nth(i).unwrap())
+}
+
+fn omikuji_message() -> impl Strategy<Value = OmikujiMessage> {
+    (
+        proptest::option::of("[A-Za-z0-9_-]{1,64}"),
+        proptest::option::of(class()),
+        proptest::option::of(text()),
+        proptest::option::of(text()),
+        proptest::collection::vec((section(), text()), 0..8),
+    )
+        .prop_map(
+            |(photo, class, description, poem, sections)| OmikujiMessage {
+                photo,
+                class,
+                description,
+                poem,
+                sections,
+                community_id: None,
+                touched_at: None,
+                reminded: false,
+                anonymous: false,
+                expires_in: None,
+                editing: None,
+            },
+        )
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn serde_round_trip(message in omikuji_message(), language in language()) {
+        let json = serde_json::to_string(&message).unwrap();
+        let decoded: OmikujiMessage = serde_json::from_str(json.as_str()).unwrap();
+        prop_assert_eq!(serde_json::to_string(&decoded).unwrap(), json);
+        prop_assert_eq!(decoded.render(language), message.render(language));
+    }
+
+    #[test]
+    fn rendered_chunks_fit_telegram_limit(message in omikuji_message(), language in language()) {
+        let text = message.render(language);
+        let chunks = split_message(text.as_str());
+        for chunk in &chunks {
+            prop_assert!(!chunk.is_empty());
+            prop_assert!(chunk.encode_utf16().count() <= MESSAGE_LIMIT);
+        }
+        // Nothing is lost or reordered
+        prop_assert_eq!(chunks.concat(), text);
+    }
+
+    #[test]
+    fn short_texts_are_not_split(text in "\\PC{1,1024}") {
+        prop_assert_eq!(split_message(text.as_str()), vec![text]);
+    }
+
+    #[test]
+    fn sanitized_text_is_clean(text in prop_oneof![text(), "[ \t\n\u{200B}\u{FEFF}\u{7}a]{0,64}"]) {
+        if let Some(sanitized) = sanitize(text.as_str()) {
+            prop_assert!(!sanitized.is_empty());
+            prop_assert_eq!(sanitized.trim(), sanitized.as_str());
+            prop_assert!(!sanitized.chars().any(|c| c != '\n' && c.is_control()));
+            prop_assert!(!sanitized.contains("  ") && !sanitized.contains("\n\n\n"));
+            // Sanitizing twice changes nothing
+            prop_assert_eq!(sanitize(sanitized.as_str()), Some(sanitized.clone()));
+        }
+    }
+
+    #[test]
+    fn signed_callbacks_verify(data in "[a-z_]{1,16}(/[A-Za-z0-9:_]{1,12}){0,2}") {
+        let signed = sign_with(b"secret", data.as_str());
+        // Telegram limits callback data to 64 bytes
+        prop_assert!(signed.len() <= 64);
+        prop_assert_eq!(verify_with(b"secret", signed.as_str()), Some(data.as_str()));
+        prop_assert_eq!(verify_with(b"another secret", signed.as_str()), None);
+        prop_assert_eq!(verify_with(b"secret", data.as_str()), None);
+        // The tag of one button does not fit another
+        let forged = signed.replacen(data.as_str(), format!("{}0", data).as_str(), 1);
+        prop_assert_eq!(verify_with(b"secret", forged.as_str()), None);
+    }
+
+    #[test]
+    fn class_names_parse_back(class in class(), language in language()) {
+        let parsed = OmikujiClass::parse(class.name(language));
+        prop_assert_eq!(parsed.map(|parsed| format!("{:?}", parsed)), Some(format!("{:?}", class)));
+    }
+
+    #[test]
+    fn section_names_parse_back(section in section(), language in language()) {
+        prop_assert_eq!(OmikujiSection::parse(section.name(language)), Some(section));
+    }
+}
+
+#[test]
+fn class_aliases() {
+    for (input, expected) in [
+        ("大吉", "GreatBlessing"),
+        ("great blessing", "GreatBlessing"),
+        (" Daikichi ", "GreatBlessing"),
+        ("halfblessing", "HalfBlessing"),
+        ("chūkichi", "MiddleBlessing"),
+        ("末小吉", "FutureSmallBlessing"),
+        ("其他", "Other"),
+    ] {
+        let parsed = OmikujiClass::parse(input).map(|class| format!("{:?}", class));
+        assert_eq!(parsed.as_deref(), Some(expected), "{}", input);
+    }
+    assert!(OmikujiClass::parse("Great").is_none());
+    assert!(OmikujiClass::parse(" ").is_none());
+}
+
+#[test]
+fn kanji_numerals() {
+    for (number, expected) in [
+        (1, "一"),
+        (10, "十"),
+        (12, "十二"),
+        (100, "百"),
+        (305, "三百五"),
+        (1024, "千二十四"),
+        (10_000, "一万"),
+        (10_500, "一万五百"),
+        (2_000_000_001, "二十億一"),
+    ] {
+        assert_eq!(kanji_numeral(number), expected, "{}", number);
+    }
+}
+
+#[test]
+fn sanitize_examples() {
+    assert_eq!(
+        sanitize("  Good\u{200B} luck\t ahead \n\n\n\nCafe\u{301}  ").as_deref(),
+        Some("Good luck ahead\n\nCafé")
+    );
+    assert_eq!(sanitize(" \u{FEFF}\n\u{200B} "), None);
+}
@@ -0,0 +1,35 @@
+#![cfg(feature = "image-render")]
+
+use omikuji_bot::render::{load_font, render_with};
+
+// Tests of the images of strips, run only where DejaVu Sans is installed (which has no CJK glyphs)
+const LATIN_FONT: &str = "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf";
+
+#[test]
+fn strips_on_paper() {
+    let font = match std::path::Path::new(LATIN_FONT).exists() {
+        true => load_font(LATIN_FONT).unwrap(),
+        false => return,
+    };
+    let strip = "Alice draws a omikuji strip:\n\n*Blessing*\n▎_Quiet water runs deep_\nGood\n\n\
+                 *Study*: Keep going, and the results will follow in their own time 🎉";
+    let png = render_with(&font, strip).unwrap().unwrap();
+    let image = image::load_from_memory(&png).unwrap().to_rgb8();
+    assert_eq!(image.width(), 720);
+    assert!(image.height() > image.width() / 2);
+    // Paper at the edge, then the vermilion frame
+    assert_eq!(image.get_pixel(0, 0).0, [250, 244, 228]);
+    assert_eq!(image.get_pixel(21, 21).0, [196, 48, 36]);
+    // Something is printed between the margins
+    let inked = image
+        .enumerate_pixels()
+        .filter(|(x, y, pixel)| (72..648).contains(x) && *y > 72 && pixel.0 == [40, 32, 28])
+        .count();
+    assert!(inked > 0);
+
+    // Letters the font lacks, and strips too long for a photo, are sent as text instead
+    assert!(render_with(&font, "*大吉*").unwrap().is_none());
+    assert!(render_with(&font, "word ".repeat(5000).as_str())
+        .unwrap()
+        .is_none());
+}
@@ -0,0 +1,400 @@
+use chrono::Local;
+use diesel::dsl::sql;
+use diesel::sql_types::{BigInt, Text};
+use diesel_async::{RunQueryDsl, SimpleAsyncConnection};
+use omikuji_bot::db::Database;
+use omikuji_bot::establish_connection;
+use omikuji_bot::models::{
+    AuditAction, JobRun, NewAchievement, NewAuditEntry, NewBoothDraw, NewComment,
+    NewDeferredMessage, NewDraw, NewFavorite, NewOmikuji, NewReaction, NewTrashItem, NewUsageEvent,
+    NewUser, OmikujiMessage, TrashKind,
+};
+use omikuji_bot::repository::{
+    DieselRepository, DraftStore, MemoryRepository, OmikujiRepository, Repository, UserRepository,
+    ANONYMOUS_ID, ANONYMOUS_NAME,
+};
+
+// Tests of the repositories themselves
+// Those against DieselRepository are ignored unless asked for, with DATABASE_URL pointing at a
+// test database (the rows of TENANT are deleted by the tests):
+// DATABASE_URL=mysql://... cargo test --test repositories -- --ignored
+const TENANT: &str = "repository-tests";
+const USER_ID: i64 = 42;
+const OTHER_USER_ID: i64 = 43;
+
+async fn test_database() -> Database {
+    match std::env::var("DATABASE_URL") {
+        Ok(url) if !url.is_empty() => establish_connection().await,
+        _ => panic!("Tests against the database need DATABASE_URL of a test database"),
+    }
+}
+
+// Tables (of the database the connection is to) with the given column
+async fn tables_with(database: &Database, column: &str) -> Vec<String> {
+    sql::<Text>(&format!(
+        "SELECT table_name FROM information_schema.columns \
+         WHERE table_schema = DATABASE() AND column_name = '{}'",
+        column
+    ))
+    .load(&mut database.connection().await.unwrap())
+    .await
+    .unwrap()
+}
+
+async fn clear_tenant(database: &Database, tenant: &str) {
+    let mut connection = database.connection().await.unwrap();
+    for table in tables_with(database, "tenant_id").await {
+        connection
+            .batch_execute(&format!(
+                "DELETE FROM `{}` WHERE tenant_id = '{}'",
+                table, tenant
+            ))
+            .await
+            .unwrap();
+    }
+}
+
+// Something about USER_ID in every place a user ID is kept
+async fn fill_user_data<R: Repository + DraftStore>(repository: &R) {
+    for &tg_id in &[USER_ID, OTHER_USER_ID] {
+        repository
+            .upsert_user(&NewUser {
+                tg_id,
+                tg_name: "Test User",
+                tg_username: None,
+                language_code: None,
+            })
+            .await
+            .unwrap();
+    }
+    let mut settings = repository.get_user_settings(USER_ID).await.unwrap();
+    settings.daily_subscription = true;
+    repository.update_user_settings(&settings).await.unwrap();
+    assert!(repository
+        .set_referrer(OTHER_USER_ID, USER_ID)
+        .await
+        .unwrap());
+    let omikuji_id = repository
+        .insert_omikuji(&NewOmikuji {
+            message: r#"{"photo":null,"class":"Blessing","description":"Good","sections":[]}"#,
+            tg_id: USER_ID,
+            tg_name: "Test User",
+            community_id: None,
+            vote_count: 0,
+            anonymous: false,
+            expires_at: None,
+            quarantined: false,
+        })
+        .await
+        .unwrap();
+    repository
+        .record_draw(&NewDraw {
+            tg_id: USER_ID,
+            omikuji_id,
+            variant: None,
+        })
+        .await
+        .unwrap();
+    repository
+        .record_vote(USER_ID, omikuji_id, 1)
+        .await
+        .unwrap();
+    repository
+        .award_badge(&NewAchievement {
+            tg_id: USER_ID,
+            badge: String::from("FirstStrip"),
+        })
+        .await
+        .unwrap();
+    repository
+        .add_favorite(&NewFavorite {
+            tg_id: USER_ID,
+            omikuji_id,
+        })
+        .await
+        .unwrap();
+    repository
+        .insert_comment(&NewComment {
+            omikuji_id,
+            tg_id: USER_ID,
+            text: "Nice",
+            parent_id: None,
+        })
+        .await
+        .unwrap();
+    repository
+        .set_reaction(&NewReaction {
+            tg_id: USER_ID,
+            omikuji_id,
+            reaction: String::from("Pray"),
+        })
+        .await
+        .unwrap();
+    repository
+        .insert_trash(&NewTrashItem::new(USER_ID, TrashKind::Draft, "{}"))
+        .await
+        .unwrap();
+    repository
+        .defer_message(&NewDeferredMessage {
+            tg_id: USER_ID,
+            message: "{}",
+        })
+        .await
+        .unwrap();
+    repository
+        .record_booth_draw(&NewBoothDraw {
+            chat_id: -1,
+            tg_id: USER_ID,
+            visitor: String::from("Visitor"),
+            omikuji_id,
+        })
+        .await
+        .unwrap();
+    repository
+        .record_usage(&NewUsageEvent {
+            command: "draw",
+            tg_id: USER_ID,
+            latency_ms: 10,
+            success: true,
+        })
+        .await
+        .unwrap();
+    let draft: OmikujiMessage =
+        serde_json::from_str(r#"{"photo":null,"class":null,"description":"Draft","sections":[]}"#)
+            .unwrap();
+    repository.save_draft(USER_ID, &draft).await.unwrap();
+    repository
+        .record_job_run(&JobRun::new(
+            "daily",
+            USER_ID,
+            Local::now().naive_local().date(),
+        ))
+        .await
+        .unwrap();
+    let actor = format!("tg:{}", USER_ID);
+    repository
+        .record_audit(&NewAuditEntry::new(
+            actor.as_str(),
+            AuditAction::Ban,
+            OTHER_USER_ID.to_string(),
+        ))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn forget_user_in_memory() {
+    let repository = MemoryRepository::default();
+    fill_user_data(&repository).await;
+    repository.forget_user(USER_ID).await.unwrap();
+
+    for omikuji in repository.omikujis.borrow().iter() {
+        assert_eq!(omikuji.tg_id, ANONYMOUS_ID);
+        assert_eq!(omikuji.tg_name, ANONYMOUS_NAME);
+    }
+    assert!(!repository.users.borrow().contains_key(&USER_ID));
+    assert!(repository
+        .users
+        .borrow()
+        .values()
+        .all(|user| user.referred_by != Some(USER_ID)));
+    assert!(!repository.user_settings.borrow().contains_key(&USER_ID));
+    assert!(repository.draws.borrow().iter().all(|d| d.tg_id != USER_ID));
+    assert!(!repository.achievements.borrow().contains_key(&USER_ID));
+    assert!(repository
+        .favorites
+        .borrow()
+        .iter()
+        .all(|&(tg_id, _)| tg_id != USER_ID));
+    assert!(repository
+        .comments
+        .borrow()
+        .iter()
+        .all(|c| c.tg_id != USER_ID));
+    assert!(repository
+        .reactions
+        .borrow()
+        .keys()
+        .all(|&(tg_id, _)| tg_id != USER_ID));
+    assert!(repository
+        .usage_events
+        .borrow()
+        .iter()
+        .all(|e| e.tg_id != USER_ID));
+    assert!(!repository.drafts.borrow().contains_key(&USER_ID));
+    assert!(repository
+        .job_runs
+        .borrow()
+        .iter()
+        .all(|r| r.tg_id != USER_ID));
+    assert!(repository.trash.borrow().iter().all(|t| t.tg_id != USER_ID));
+    assert!(repository
+        .booth_draws
+        .borrow()
+        .iter()
+        .all(|(d, _)| d.tg_id != USER_ID));
+    assert!(repository
+        .deferred_messages
+        .borrow()
+        .iter()
+        .all(|m| m.tg_id != USER_ID));
+    let audit_log = repository.audit_log.borrow();
+    assert_eq!(audit_log.len(), 1);
+    assert_eq!(audit_log[0].actor, ANONYMOUS_NAME);
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL of a test database"]
+async fn forget_user_in_database() {
+    let database = test_database().await;
+    clear_tenant(&database, TENANT).await;
+    let repository = DieselRepository::new(&database, TENANT);
+    fill_user_data(&repository).await;
+    repository.forget_user(USER_ID).await.unwrap();
+
+    let tables = tables_with(&database, "tg_id").await;
+    assert!(tables.iter().any(|table| table == "usage_events"));
+    for table in tables {
+        let count: i64 = sql::<BigInt>(&format!(
+            "SELECT COUNT(*) FROM `{}` WHERE tenant_id = '{}' AND tg_id = {}",
+            table, TENANT, USER_ID
+        ))
+        .get_result(&mut database.connection().await.unwrap())
+        .await
+        .unwrap();
+        assert_eq!(count, 0, "{} still holds the user", table);
+    }
+    let referred: i64 = sql::<BigInt>(&format!(
+        "SELECT COUNT(*) FROM users WHERE tenant_id = '{}' AND referred_by = {}",
+        TENANT, USER_ID
+    ))
+    .get_result(&mut database.connection().await.unwrap())
+    .await
+    .unwrap();
+    assert_eq!(referred, 0);
+    let actors: Vec<String> = sql::<Text>(&format!(
+        "SELECT actor FROM audit_log WHERE tenant_id = '{}'",
+        TENANT
+    ))
+    .load(&mut database.connection().await.unwrap())
+    .await
+    .unwrap();
+    assert_eq!(actors, vec![String::from(ANONYMOUS_NAME)]);
+    clear_tenant(&database, TENANT).await;
+}
+
+// Votes cast at the same time on separate connections all count, as the database adds them up
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs DATABASE_URL of a test database"]
+async fn concurrent_votes_in_database() {
+    // Its own tenant, as the other tests clear theirs while they run
+    const VOTE_TENANT: &str = "repository-tests-votes";
+    const VOTERS: usize = 8;
+    const VOTES: i32 = 10;
+    let database = test_database().await;
+    clear_tenant(&database, VOTE_TENANT).await;
+    let omikuji_id = DieselRepository::new(&database, VOTE_TENANT)
+        .insert_omikuji(&NewOmikuji {
+            message: r#"{"photo":null,"class":"Blessing","description":"Good","sections":[]}"#,
+            tg_id: USER_ID,
+            tg_name: "Test User",
+            community_id: None,
+            vote_count: 0,
+            anonymous: false,
+            expires_at: None,
+            quarantined: false,
+        })
+        .await
+        .unwrap();
+    // Like the web server, each voter on a blocking thread with its own repository
+    let voters: Vec<_> = (0..VOTERS)
+        .map(|_| {
+            let database = database.clone();
+            tokio::task::spawn_blocking(move || {
+                let repository = DieselRepository::new(&database, VOTE_TENANT);
+                tokio::runtime::Handle::current().block_on(async {
+                    for _ in 0..VOTES {
+                        repository.add_vote(omikuji_id, 1).await.unwrap();
+                    }
+                })
+            })
+        })
+        .collect();
+    for voter in voters {
+        voter.await.unwrap();
+    }
+
+    let omikuji = DieselRepository::new(&database, VOTE_TENANT)
+        .find_omikuji(omikuji_id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(omikuji.vote_count, VOTERS as i32 * VOTES);
+    assert!(!omikuji.quarantined);
+    clear_tenant(&database, VOTE_TENANT).await;
+}
+
+// Two draws of USER_ID and one of OTHER_USER_ID, counted from before and after they were made
+async fn count_draws<R: Repository>(repository: &R) {
+    let omikuji_id = repository
+        .insert_omikuji(&NewOmikuji {
+            message: r#"{"photo":null,"class":"Blessing","description":"Good","sections":[]}"#,
+            tg_id: OTHER_USER_ID,
+            tg_name: "Other User",
+            community_id: None,
+            vote_count: 0,
+            anonymous: false,
+            expires_at: None,
+            quarantined: false,
+        })
+        .await
+        .unwrap();
+    for tg_id in [USER_ID, USER_ID, OTHER_USER_ID] {
+        repository
+            .record_draw(&NewDraw {
+                tg_id,
+                omikuji_id,
+                variant: None,
+            })
+            .await
+            .unwrap();
+    }
+    let hour_ago = Local::now().naive_local() - chrono::Duration::hours(1);
+    let in_an_hour = Local::now().naive_local() + chrono::Duration::hours(1);
+    assert_eq!(
+        repository
+            .count_draws_by_user(USER_ID, hour_ago)
+            .await
+            .unwrap(),
+        2
+    );
+    assert_eq!(
+        repository
+            .count_draws_by_user(OTHER_USER_ID, hour_ago)
+            .await
+            .unwrap(),
+        1
+    );
+    assert_eq!(
+        repository
+            .count_draws_by_user(USER_ID, in_an_hour)
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn count_draws_in_memory() {
+    count_draws(&MemoryRepository::default()).await;
+}
+
+#[tokio::test]
+#[ignore = "needs DATABASE_URL of a test database"]
+async fn count_draws_in_database() {
+    const DRAW_TENANT: &str = "repository-tests-draws";
+    let database = test_database().await;
+    clear_tenant(&database, DRAW_TENANT).await;
+    count_draws(&DieselRepository::new(&database, DRAW_TENANT)).await;
+    clear_tenant(&database, DRAW_TENANT).await;
+}
@@ -0,0 +1,109 @@
+use chrono::{Duration, Local, NaiveDateTime};
+use omikuji_bot::models::Omikuji;
+use omikuji_bot::scoring::{balanced_index, decay, score, top, top_n, weight, weighted_index};
+use rand::thread_rng;
+
+fn strip(id: u32, vote_count: i32, created_at: NaiveDateTime) -> Omikuji {
+    Omikuji {
+        id,
+        message: String::from("{}"),
+        vote_count,
+        tg_id: 42,
+        tg_name: String::from("Test User"),
+        created_at,
+        updated_at: created_at,
+        reviewed_at: None,
+        tenant_id: String::from("default"),
+        community_id: None,
+        anonymous: false,
+        expires_at: None,
+        archived: false,
+        quarantined: false,
+        draw_count: 0,
+        edited_at: None,
+        slip_number: None,
+        import_batch: None,
+    }
+}
+
+#[test]
+fn decay_halves_every_half_life() {
+    let half_life = Duration::days(30);
+    assert_eq!(decay(Duration::zero(), half_life), 1.0);
+    assert!((decay(Duration::days(30), half_life) - 0.5).abs() < 1e-9);
+    assert!((decay(Duration::days(60), half_life) - 0.25).abs() < 1e-9);
+    // Clock skew doesn't make votes count more
+    assert_eq!(decay(Duration::days(-1), half_life), 1.0);
+    // No decay without a positive half-life
+    assert_eq!(decay(Duration::days(60), Duration::zero()), 1.0);
+}
+
+#[test]
+fn score_without_half_life_is_vote_count() {
+    let now = Local::now().naive_local();
+    let omikuji = strip(1, 8, now - Duration::days(30));
+    assert_eq!(score(&omikuji, now, None), 8.0);
+    assert!((score(&omikuji, now, Some(Duration::days(30))) - 4.0).abs() < 1e-9);
+}
+
+#[test]
+fn newer_strips_can_beat_older_ones() {
+    let now = Local::now().naive_local();
+    let old = strip(1, 10, now - Duration::days(90));
+    let new = strip(2, 3, now - Duration::days(1));
+    let half_life = Some(Duration::days(30));
+    assert_eq!(top(vec![&old, &new], now, half_life).unwrap().id, 2);
+    assert_eq!(top(vec![&old, &new], now, None).unwrap().id, 1);
+
+    // Ties go to the lower ID, whatever the order
+    let twin = strip(3, 3, now - Duration::days(1));
+    assert_eq!(top(vec![&twin, &new], now, half_life).unwrap().id, 2);
+    assert!(top(Vec::new(), now, half_life).is_none());
+
+    let ranked: Vec<u32> = top_n(vec![&old, &twin, &new], now, half_life, 2)
+        .iter()
+        .map(|omikuji| omikuji.id)
+        .collect();
+    assert_eq!(ranked, vec![2, 3]);
+}
+
+#[test]
+fn weighted_draws_favour_higher_scores() {
+    assert_eq!(weight(0.0), 1.0);
+    assert_eq!(weight(-5.0), 0.1);
+
+    let mut rng = thread_rng();
+    assert_eq!(weighted_index(&[], &mut rng), None);
+    assert_eq!(weighted_index(&[0.0, 2.0], &mut rng), Some(1));
+    let mut counts = [0; 2];
+    for _ in 0..1000 {
+        counts[weighted_index(&[1.0, 9.0], &mut rng).unwrap()] += 1;
+    }
+    assert!(counts[1] > counts[0] * 3);
+}
+
+#[test]
+fn balanced_draws_follow_class_shares() {
+    let mut rng = thread_rng();
+    assert_eq!(balanced_index(&[], &mut rng), None);
+    // Other (and unknown) classes are a fallback only
+    let other = Some(String::from("Other"));
+    assert!(balanced_index(&[other.clone(), None], &mut rng).is_some());
+    let curse = Some(String::from("Curse"));
+    assert_eq!(
+        balanced_index(&[other, curse.clone(), None], &mut rng),
+        Some(1)
+    );
+
+    // Nine blessings against one great blessing: 20% and 16% of the target shares
+    let mut classes = vec![Some(String::from("Blessing")); 9];
+    classes.push(Some(String::from("GreatBlessing")));
+    let mut great = 0;
+    for _ in 0..1000 {
+        if balanced_index(&classes, &mut rng) == Some(9) {
+            great += 1;
+        }
+    }
+    // 16 / 36 of the draws are expected, far more than a tenth
+    assert!(great > 300 && great < 600);
+}
@@ -0,0 +1,176 @@
+use omikuji_bot::models::NewOmikuji;
+use omikuji_bot::repository::{AuditRepository, MemoryRepository, OmikujiRepository};
+use omikuji_bot::web;
+use omikuji_bot::web::api::respond;
+use omikuji_bot::web::{Request, Response};
+use serde_json::Value;
+
+const TOKEN: &str = "secret";
+
+async fn get(repository: &MemoryRepository, path: &str, token: Option<&str>) -> (u16, Value) {
+    let request = Request {
+        method: String::from("GET"),
+        path: String::from(path),
+        authorization: token.map(|token| format!("Bearer {}", token)),
+        ..Request::default()
+    };
+    let Response { status, body, .. } = respond(&request, repository, TOKEN).await;
+    (status, serde_json::from_str(body.as_str()).unwrap())
+}
+
+async fn library() -> MemoryRepository {
+    let repository = MemoryRepository::default();
+    for message in &[
+        r#"{"photo":null,"class":"GreatBlessing","description":"Good","sections":[["Love","Yes"]]}"#,
+        r#"{"photo":null,"class":"Curse","description":"Bad","sections":[]}"#,
+    ] {
+        repository
+            .insert_omikuji(&NewOmikuji {
+                message,
+                tg_id: 42,
+                tg_name: "Test User",
+                community_id: None,
+                vote_count: 0,
+                anonymous: false,
+                expires_at: None,
+                quarantined: false,
+            })
+            .await
+            .unwrap();
+    }
+    repository
+}
+
+#[tokio::test]
+async fn requires_token() {
+    let repository = library().await;
+    assert_eq!(get(&repository, "/stats", None).await.0, 401);
+    assert_eq!(get(&repository, "/stats", Some("wrong")).await.0, 401);
+
+    let request = Request {
+        method: String::from("POST"),
+        path: String::from("/stats"),
+        authorization: Some(format!("Bearer {}", TOKEN)),
+        ..Request::default()
+    };
+    assert_eq!(respond(&request, &repository, TOKEN).await.status, 405);
+}
+
+#[tokio::test]
+async fn omikuji_endpoints() {
+    let repository = library().await;
+
+    let (status, body) = get(&repository, "/omikuji/1", Some(TOKEN)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["class"], "GreatBlessing");
+    assert_eq!(body["author"], "Test User");
+    assert_eq!(body["sections"][0][1], "Yes");
+    assert!(body["text"].as_str().unwrap().contains("*Love*: Yes"));
+
+    let (status, body) = get(&repository, "/omikuji/random", Some(TOKEN)).await;
+    assert_eq!(status, 200);
+    assert!(body["id"] == 1 || body["id"] == 2);
+
+    // Hidden strips are not published
+    repository.add_vote(2, -5).await.unwrap();
+    assert_eq!(get(&repository, "/omikuji/2", Some(TOKEN)).await.0, 404);
+    assert_eq!(get(&repository, "/omikuji/3", Some(TOKEN)).await.0, 404);
+    assert_eq!(get(&repository, "/omikuji/abc", Some(TOKEN)).await.0, 400);
+    assert_eq!(get(&repository, "/nothing", Some(TOKEN)).await.0, 404);
+
+    let (status, body) = get(&repository, "/stats", Some(TOKEN)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["total"], 2);
+    assert_eq!(body["hidden"], 1);
+}
+
+async fn admin(
+    repository: &MemoryRepository,
+    method: &str,
+    path: &str,
+    query: Option<&str>,
+) -> Response {
+    let request = Request {
+        method: String::from(method),
+        path: String::from(path),
+        query: query.map(String::from),
+        // "admin:secret"
+        authorization: Some(String::from("Basic YWRtaW46c2VjcmV0")),
+    };
+    web::respond(&request, repository, TOKEN).await
+}
+
+#[tokio::test]
+async fn admin_panel() {
+    let repository = library().await;
+    repository.add_vote(2, -5).await.unwrap();
+
+    let request = Request {
+        method: String::from("GET"),
+        path: String::from("/admin"),
+        ..Request::default()
+    };
+    let response = web::respond(&request, &repository, TOKEN).await;
+    assert_eq!(response.status, 401);
+    assert!(response
+        .headers
+        .iter()
+        .any(|(name, _)| *name == "WWW-Authenticate"));
+
+    // Only the hidden strip is pending
+    let page = admin(&repository, "GET", "/admin", None).await.body;
+    assert!(page.contains("Pending review (1)"));
+    assert!(page.contains("/admin/omikuji/2/approve"));
+    assert!(!page.contains("/admin/omikuji/1/"));
+
+    let page = admin(&repository, "GET", "/admin", Some("q=Good"))
+        .await
+        .body;
+    assert!(page.contains("Search results for \"Good\" (1)"));
+    assert!(page.contains("/admin/omikuji/1/delete"));
+
+    let response = admin(&repository, "POST", "/admin/omikuji/2/approve", None).await;
+    assert_eq!(response.status, 303);
+    assert_eq!(
+        repository
+            .find_omikuji(2)
+            .await
+            .unwrap()
+            .unwrap()
+            .vote_count,
+        0
+    );
+    assert!(admin(&repository, "GET", "/admin", None)
+        .await
+        .body
+        .contains("Pending review (0)"));
+
+    admin(&repository, "POST", "/admin/omikuji/1/delete", None).await;
+    assert!(repository.find_omikuji(1).await.unwrap().is_none());
+    assert_eq!(
+        admin(&repository, "POST", "/admin/omikuji/2/nothing", None)
+            .await
+            .status,
+        404
+    );
+
+    // Actions are recorded with the user name the moderator logged in with
+    let entries = repository.find_audit_entries(0, 10).await.unwrap();
+    let entries: Vec<(&str, &str, &str)> = entries
+        .iter()
+        .map(|entry| {
+            (
+                entry.actor.as_str(),
+                entry.action.as_str(),
+                entry.target.as_str(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        entries,
+        vec![
+            ("web:admin", "Delete", "omikuji 1"),
+            ("web:admin", "Approve", "omikuji 2")
+        ]
+    );
+}